@@ -0,0 +1,88 @@
+//! Conditions Example: Regular expressions against host and path prefix lookups
+//!
+//! Filters the 500 links of a generated page with two sets of 5 `FilterUrls` rules: one of
+//! `Condition::new` regular expressions, one of `Condition::hosts` and
+//! `Condition::path_prefixes`. Both follow the same links. The time the rules take is
+//! printed, and the time a page takes to parse with them, most of which goes to the HTML.
+//!
+//! Usage: `cargo run --release --example conditions`
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+
+use vortex::{
+    crawler::Crawler,
+    spider::{Condition, ParseRule, Spider, SpiderBuilder},
+};
+
+const RUNS: u32 = 200;
+
+fn regex_conditions() -> Vec<Condition> {
+    let allow = |exp: &'static str| Condition::new(vec![exp], vec![]);
+    vec![
+        allow(r"^https?://(en|de)\.site\.test/"),
+        allow(r"^https?://[^/]+/(wiki|docs)/"),
+        allow(r"^https?://[^/]+/(wiki/A|wiki/B|docs/)"),
+        allow(r"^https?://[^/]+/(wiki/|docs/current/)"),
+        Condition::any_except(vec![r"\?action=edit"]),
+    ]
+}
+
+/// The rules of `regex_conditions`, the first four without regular expressions
+fn fast_conditions() -> Vec<Condition> {
+    vec![
+        Condition::hosts(vec!["en.site.test", "de.site.test"]),
+        Condition::path_prefixes(vec!["/wiki/", "/docs/"]),
+        Condition::path_prefixes(vec!["/wiki/A", "/wiki/B", "/docs/"]),
+        Condition::path_prefixes(vec!["/wiki/", "/docs/current/"]),
+        Condition::any_except(vec![r"\?action=edit"]),
+    ]
+}
+
+/// The links of a page over 4 hosts and 5 sections
+fn links() -> Vec<String> {
+    let hosts = ["en", "de", "fr", "ja"];
+    let paths = ["wiki/A", "wiki/B", "wiki/Special:Search", "docs/current", "docs/archive"];
+    (0..500)
+        .map(|i| format!("http://{}.site.test/{}/{}", hosts[i % hosts.len()],
+                         paths[i % paths.len()], i))
+        .collect()
+}
+
+/// The number of links `conditions` allow, and the average time they take
+fn filter(conditions: &[Condition], urls: &[Url]) -> (usize, Duration) {
+    let allowed = |url: &&Url| conditions.iter().all(|condition| condition.allows_url(url));
+    let started = Instant::now();
+    let mut followed = 0;
+    for _ in 0..RUNS {
+        followed = urls.iter().filter(allowed).count();
+    }
+    (followed, started.elapsed() / RUNS)
+}
+
+/// The average time `spider` takes to parse `html`
+fn parse(spider: &Spider, html: &str) -> Duration {
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        Crawler::dry_run(spider, html, "http://en.site.test/");
+    }
+    started.elapsed() / RUNS
+}
+
+fn main() {
+    let links = links();
+    let urls: Vec<Url> = links.iter().map(|link| Url::parse(link).unwrap()).collect();
+    let html: String = links.iter().map(|link| format!(r#"<a href="{}">Link</a>"#, link)).collect();
+
+    let runs = [("regex", regex_conditions()), ("hosts and prefixes", fast_conditions())];
+    for (name, conditions) in runs {
+        let (followed, elapsed) = filter(&conditions, &urls);
+        let spider = conditions.into_iter()
+            .fold(SpiderBuilder::default(), |builder, condition| {
+                builder.crawl_rule(condition, ParseRule::FilterUrls)
+            })
+            .build();
+        println!("{:>18}: {} links followed, {:?} for the rules, {:?} for the page",
+                 name, followed, elapsed, parse(&spider, &html));
+    }
+}
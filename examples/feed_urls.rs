@@ -0,0 +1,96 @@
+//! Feed Example: Start urls submitted while the crawl runs
+//!
+//! Tails a file of urls (one per line) and submits every line appended to it to the
+//! running crawl. Only the submitted pages are crawled, links are not followed.
+//!
+//! Usage: `cargo run --example feed_urls -- urls.txt`, then append to the file, e.g.
+//! `echo https://en.wikipedia.org/wiki/Rust >> urls.txt`. Append `stop` to end the crawl.
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use vortex::{
+    crawler::Crawler,
+    settings::Settings,
+    spider::{Condition, ParseRule, Pattern, SpiderBuilder},
+};
+
+fn main() {
+    // Initialize logger
+    env::set_var("RUST_LOG", "vortex=info");
+    pretty_env_logger::init();
+
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("Usage: feed_urls <file>");
+        std::process::exit(1);
+    });
+    let file = File::open(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    // A crawl needs at least one start url, so it is started once the file has one
+    let mut lines = BufReader::new(file);
+    let first = next_line(&mut lines);
+
+    // The spider is built on the crawl thread
+    let handle = Crawler::start(move || {
+        let mut settings = Settings::default();
+        // Keep the spider open for an hour while waiting for urls
+        settings.scheduler.idle_timeout = 3_600_000;
+
+        SpiderBuilder::default()
+            .start_urls(vec![first.as_str()])
+            .settings(settings)
+
+            // Don't follow any links
+            .crawl_rule(Condition::new(vec![], vec![".*"]), ParseRule::FilterUrls)
+
+            // Add a crawl rule for the 'title' field
+            .crawl_rule(
+                Condition::new(vec![r".*"], vec![]),
+                ParseRule::pattern(
+                    "title",
+                    Pattern::CssSelector("title"),
+                    |s| {
+                        Some(Value::String(s.first().unwrap().clone()))
+                    }))
+            .build()
+    }).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    // Submit the urls appended to the file, until `stop`
+    loop {
+        let line = next_line(&mut lines);
+        if line == "stop" {
+            break;
+        }
+        if let Err(errors) = handle.submit_urls(vec![line]) {
+            errors.iter().for_each(|e| eprintln!("{}", e));
+        }
+    }
+
+    // Wait for the submitted urls to be crawled
+    let report = handle.stop();
+    println!("{:#?}", report);
+}
+
+/// Returns the next non-empty line, waiting for it to be appended to the file
+fn next_line(lines: &mut BufReader<File>) -> String {
+    let mut line = String::new();
+    loop {
+        if lines.read_line(&mut line).unwrap() == 0 {
+            thread::sleep(Duration::from_millis(500));
+        } else if line.ends_with('\n') && !line.trim().is_empty() {
+            return line.trim().to_string();
+        } else if line.ends_with('\n') {
+            line.clear();
+        }
+    }
+}
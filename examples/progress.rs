@@ -0,0 +1,69 @@
+//! Progress Example: A terminal progress line fed by stats snapshots
+//!
+//! Crawls up to 200 english wiki pages and keeps a single progress line up to date while
+//! doing so, using `CrawlHandle::subscribe_stats`. The log is limited to warnings, so that
+//! it doesn't get in the way of the line.
+use std::env;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use vortex::{
+    crawler::{Crawler, StatsSnapshot},
+    settings::Settings,
+    spider::{Condition, ParseRule, Pattern, SpiderBuilder},
+};
+
+fn main() {
+    // Initialize logger
+    env::set_var("RUST_LOG", "vortex=warn");
+    pretty_env_logger::init();
+
+    // The spider is built on the crawl thread
+    let handle = Crawler::start(|| {
+        let mut settings = Settings::default();
+        settings.scheduler.max_requests = 200;
+
+        let cnd = Condition::new(vec![r"en.wikipedia.org/wiki"], vec![r":[A-Za-z]|\?|#"]);
+        SpiderBuilder::default()
+            .start_urls(vec!["http://en.wikipedia.org"])
+            .settings(settings)
+            .crawl_rule(cnd.clone(), ParseRule::FilterUrls)
+            .crawl_rule(
+                cnd,
+                ParseRule::pattern(
+                    "title",
+                    Pattern::CssSelector(".firstHeading"),
+                    |s| {
+                        Some(Value::String(s.first().unwrap().clone()))
+                    }))
+            .build()
+    }).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    handle.stop_on_signals();
+
+    // Redraw the line twice a second, and end it with the last snapshot
+    handle.subscribe_stats(Duration::from_millis(500), |snapshot| {
+        eprint!("\r{}", progress_line(&snapshot));
+        if !snapshot.running {
+            eprintln!();
+        }
+        let _ = io::stderr().flush();
+    });
+
+    let report = handle.wait();
+    println!("{:#?}", report);
+}
+
+/// e.g. `[00:42] 120 requests (2.9/s), 118 items (2.8/s), 3 errors, 80 queued, 4 in flight,
+/// 60.0% done, ETA 0m27s`
+fn progress_line(s: &StatsSnapshot) -> String {
+    let secs = s.elapsed_ms / 1000;
+    format!("[{:02}:{:02}] {} requests ({:.1}/s), {} items ({:.1}/s), {} errors, {} queued, \
+             {} in flight, {}",
+            secs / 60, secs % 60, s.requests, s.request_rate, s.items, s.item_rate,
+            s.requests_failed + s.error_responses, s.queue_len, s.in_flight, s.estimate)
+}
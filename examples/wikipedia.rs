@@ -3,76 +3,117 @@
 //! This crawler starts at the main english wiki page and crawls until terminated
 //!
 //! This example uses a TOML file (wikipedia.toml) to set user-specific settings
-use std::env;
+//!
+//! Run with `--dry-run` to try the crawl rules on two saved pages (examples/fixtures)
+//! instead, which prints a JSON report of what would be crawled and extracted, or with
+//! `--check` to check that the fields are still extracted from the saved article
+use std::{env, fs};
 
 use serde_json::Value;
 
 use vortex::{
     crawler::Crawler,
+    downloader::MockFetch,
     settings::Settings,
-    spider::{Condition, ParseRule, Pattern, SpiderBuilder},
+    spider::{Condition, CrawlRule, ParseRule, Pattern, SpiderBuilder, SpiderDef},
 };
 
-fn main() {
-    // Initialize logger
-    env::set_var("RUST_LOG", "vortex=info");
-    pretty_env_logger::init();
+/// The spider, defined as a type so that it can be reused and registered by name
+struct Wikipedia;
 
-    // Create a new instance of a spider builder
-    let mut builder = SpiderBuilder::default();
+impl SpiderDef for Wikipedia {
+    fn name(&self) -> &str {
+        "wikipedia"
+    }
 
     // Initialize the spider with a vector of start urls
-    builder = builder.start_urls(vec!["http://en.wikipedia.org"]);
+    fn start_urls(&self) -> Vec<&str> {
+        vec!["http://en.wikipedia.org"]
+    }
+
+    fn rules(&self) -> Vec<CrawlRule> {
+        // Specify a condition - a combo of regex expressions for:
+        // - which URLs to consider: allow
+        // - which URLs to filter out: deny
+        let cnd = Condition::new(
+            vec![r"en.wikipedia.org/wiki"],
+            vec![r":[A-Za-z]|\?|#"],
+        );
 
-    // Specify a condition - a combo of regex expressions for:
-    // - which URLs to consider: allow
-    // - which URLs to filter out: deny
-    let cnd = Condition::new(
-        vec![r"en.wikipedia.org/wiki"],
-        vec![r":[A-Za-z]|\?|#"],
-    );
+        vec![
+            // Initial crawl rule filters out all urls that don't satisfy
+            // the condition
+            CrawlRule::new(
+                cnd.clone(),
+                ParseRule::FilterUrls,
+            ),
 
-    // Initial crawl rule filters out all urls that don't satisfy
-    // the condition
-    builder = builder.crawl_rule(
-        cnd.clone(),
-        ParseRule::FilterUrls,
-    );
+            // Add a crawl rule for the 'title' field.
+            // Use a CSS selector to extract the field from the HTML and
+            // a closure to return the extracted text inside of a json 'Value' type
+            CrawlRule::new(
+                cnd.clone(),
+                ParseRule::pattern(
+                    "title",
+                    Pattern::CssSelector(".firstHeading"),
+                    |s| {
+                        Some(Value::String(s.first().unwrap().clone()))
+                    })),
 
-    // Add a crawl rule for the 'title' field.
-    // Use a CSS selector to extract the field from the HTML and
-    // a closure to return the extracted text inside of a json 'Value' type
-    builder = builder.crawl_rule(
-        cnd.clone(),
-        ParseRule::pattern(
-            "title",
-            Pattern::CssSelector(".firstHeading"),
-            |s| {
-                Some(Value::String(s.first().unwrap().clone()))
-            }));
+            // Add a crawl rule for the 'categories' field
+            // Use a CSS selector to extract the field from the HTML and
+            // a closure to return the extracted text inside of a json 'Value' type
+            CrawlRule::new(
+                cnd,
+                ParseRule::pattern(
+                    "categories",
+                    Pattern::CssSelector("#mw-normal-catlinks a[href*='/wiki/Category:']"),
+                    |s| {
+                        Some(Value::Array(s.iter()
+                            .map(|c| { Value::String(c.to_string()) })
+                            .collect()))
+                    })),
+        ]
+    }
 
-    // Add a crawl rule for the 'categories' field
-    // Use a CSS selector to extract the field from the HTML and
-    // a closure to return the extracted text inside of a json 'Value' type
-    builder = builder.crawl_rule(
-        cnd.clone(),
-        ParseRule::pattern(
-            "categories",
-            Pattern::CssSelector("#mw-normal-catlinks a[href*='/wiki/Category:']"),
-            |s| {
-                Some(Value::Array(s.iter()
-                    .map(|c| { Value::String(c.to_string()) })
-                    .collect()))
-            }));
+    fn configure(&self, builder: SpiderBuilder) -> SpiderBuilder {
+        // Use TOML file in directory to set user settings
+        let path = env::current_dir().unwrap().join("examples/wikipedia.toml");
+        builder.settings(Settings::from_file(path))
+    }
+}
+
+fn main() {
+    // Initialize logger
+    env::set_var("RUST_LOG", "vortex=info");
+    pretty_env_logger::init();
 
+    // Build spider from its definition
+    let spider = SpiderBuilder::from_def(&Wikipedia).build();
 
-    // Use TOML file in directory to set user settings
-    let path = env::current_dir().unwrap().join("examples/wikipedia.toml");
-    builder = builder.settings(Settings::from_file(path));
+    // Dry run against the saved pages, without touching the network
+    if env::args().any(|arg| arg == "--dry-run") {
+        let fixtures = env::current_dir().unwrap().join("examples/fixtures");
+        let fetcher = MockFetch::default()
+            .page_file("http://en.wikipedia.org/", fixtures.join("wikipedia_main.html"))
+            .page_file(
+                "http://en.wikipedia.org/wiki/Rust_(programming_language)",
+                fixtures.join("wikipedia_rust.html"));
+        let report = Crawler::dry_run_pages_with_fetcher(&spider, &fetcher, 2);
+        println!("{}", report.to_json());
+        return;
+    }
 
-    // Build spider
-    let spider = builder.build();
+    // Check the crawl rules against the saved article
+    if env::args().any(|arg| arg == "--check") {
+        let html = fs::read_to_string("examples/fixtures/wikipedia_rust.html").unwrap();
+        let url = "http://en.wikipedia.org/wiki/Rust_(programming_language)";
+        let result = spider.check(url, &html);
+        result.assert_fields(&["title", "categories"]);
+        println!("{}", serde_json::to_string_pretty(&result.items).unwrap());
+        return;
+    }
 
     // Run crawler, initialized with spider
-    Crawler::run(spider);
+    let _ = Crawler::run(spider);
 }
@@ -0,0 +1,71 @@
+//! Wikipedia Example 3: Basic Article Crawler configured from TOML
+//!
+//! This crawler starts at the main english wiki page and crawls until terminated
+//!
+//! Unlike the other examples, the start urls and the url filtering condition
+//! are not compiled into the binary, but read from a TOML file (wikipedia3.toml),
+//! so the crawl can be re-targeted without a rebuild.
+//!
+//! The spider is defined as a type and kept in a registry, which selects it by the name
+//! given as the first argument (`wikipedia` by default).
+use std::env;
+
+use serde_json::Value;
+
+use vortex::{
+    crawler::Crawler,
+    settings::Settings,
+    spider::{Condition, CrawlRule, ParseRule, Pattern, SpiderBuilder, SpiderDef, SpiderRegistry},
+};
+
+/// The spider, defined as a type and registered by name. It has no start urls of its own,
+/// they are read from the TOML file.
+struct Wikipedia;
+
+impl SpiderDef for Wikipedia {
+    fn name(&self) -> &str {
+        "wikipedia"
+    }
+
+    fn rules(&self) -> Vec<CrawlRule> {
+        // Only article pages are parsed
+        let cnd = Condition::new(vec![r"en.wikipedia.org/wiki"], vec![]);
+
+        // Add a crawl rule for the 'title' field
+        vec![CrawlRule::new(
+            cnd,
+            ParseRule::pattern(
+                "title",
+                Pattern::CssSelector(".firstHeading"),
+                |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))]
+    }
+
+    fn configure(&self, builder: SpiderBuilder) -> SpiderBuilder {
+        // Use TOML file in directory to set the start urls, the url filter and user settings
+        let path = env::current_dir().unwrap().join("examples/wikipedia3.toml");
+        builder.settings(Settings::from_file(path))
+    }
+}
+
+fn main() {
+    // Initialize logger
+    env::set_var("RUST_LOG", "vortex=info");
+    pretty_env_logger::init();
+
+    // The spiders that can be run, selected by the first argument
+    let registry = SpiderRegistry::default().register(Wikipedia);
+    let name = env::args().nth(1).unwrap_or_else(|| "wikipedia".to_string());
+
+    // Run crawler with the spider of that name, reporting an unknown name, or invalid urls
+    // or regexes from the TOML file. The exit status tells whether the crawl was stopped
+    // cleanly, e.g. after Ctrl+C
+    match Crawler::run_by_name(&registry, &name) {
+        Ok(report) => std::process::exit(report.exit_code()),
+        Err(e) => {
+            eprintln!("{} (spiders: {})", e, registry.names().join(", "));
+            std::process::exit(1);
+        }
+    }
+}
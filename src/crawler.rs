@@ -5,18 +5,31 @@
 //!
 //! The `crawler` also defines all the data types that are used to transfer information
 //! between the components (actors).
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::panic;
 use std::rc::Rc;
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use actix::{Actor, Addr, Arbiter, dev::ToEnvelope, Handler, Message, Recipient, System};
-use reqwest::{header::HeaderMap, Url};
-use serde_json::Value;
+use actix::{Actor, Addr, AsyncContext, dev::ToEnvelope, Handler, Message, Recipient, System};
+use futures::{Future, sync::oneshot};
+use reqwest::{header::HeaderMap, StatusCode, Url};
+use serde_json::{Map, Value};
 
-use crate::downloader::Downloader;
-use crate::parser::Parser;
-use crate::pipeline::Pipeline;
+use crate::downloader::{self, Downloader, Fetch, HttpFetch, RequestErrors};
+use crate::dry_run::{self, DryRunReport};
+use crate::events::EventLog;
+use crate::parser::{self, Parser, RelLinks};
+use crate::pipeline::{self, Pipeline};
 use crate::scheduler::Scheduler;
-use crate::spider::Spider;
+use crate::settings::Settings;
+use crate::shutdown::Shutdown;
+use crate::spider::{BuildError, Spider, SpiderRegistry};
 use crate::stats::Stats;
 
 /// Contains a `Vec` of `Requests. This is used as the interface to send `Requests`
@@ -50,9 +63,12 @@ impl RequestVec {
 ///
 /// `Request` also contains priority and depth fields so that the `Scheduler` knows how to
 /// establish priority dependencies.
-#[derive(Clone, Debug, Message, Eq)]
+///
+/// `Requests` are serialized to be shared by crawler processes, see `RedisQueue`.
+#[derive(Clone, Debug, Message, Eq, Serialize, Deserialize)]
 pub struct Request {
     /// The URL of the request
+    #[serde(with = "url_serde")]
     pub url: Url,
 
     /// The distance from the initial `start_urls`. The URLs from the `start_urls`
@@ -62,6 +78,64 @@ pub struct Request {
 
     /// The priority is calculated based on the crawling strategy.
     pub priority: u32,
+
+    /// Number of times the `Request` was retried, see `DownloaderSettings::retry_after_retries`
+    pub retries: u32,
+
+    /// Given by the `Scheduler` when the `Request` is queued, to tell `Requests` apart in the
+    /// event log (see `StatsSettings::event_log_path`). 0 until then.
+    pub id: u64,
+
+    /// Data about the `Request` that is passed along to its `Items`, e.g. the `lastmod` of
+    /// the sitemap entry it was seeded from
+    pub meta: Map<String, Value>,
+
+    /// The URL the `Scheduler` deduplicates the `Request` by, if it isn't `url`, see
+    /// `CanonicalizeSettings::param_value_rules`
+    #[serde(with = "url_serde::option")]
+    pub dedup_url: Option<Url>,
+
+    /// Interval after which the URL can be fetched again, from the crawl rule that governs
+    /// it (see `SpiderBuilder::crawl_rule_with_revisit`). `None` if it is fetched once.
+    pub revisit_after: Option<Duration>,
+}
+
+/// (De)serializes `Urls` as strings
+mod url_serde {
+    use reqwest::Url;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(url: &Url, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(url.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
+        Url::parse(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    /// Same for `Option<Url>`
+    pub mod option {
+        use reqwest::Url;
+        use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+        pub fn serialize<S: Serializer>(url: &Option<Url>, serializer: S)
+            -> Result<S::Ok, S::Error>
+        {
+            match url {
+                Some(url) => serializer.serialize_some(url.as_str()),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+            -> Result<Option<Url>, D::Error>
+        {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(url) => Url::parse(&url).map(Some).map_err(D::Error::custom),
+                None => Ok(None),
+            }
+        }
+    }
 }
 
 impl Ord for Request {
@@ -88,8 +162,18 @@ impl Request {
             url,
             depth,
             priority,
+            retries: 0,
+            id: 0,
+            meta: Map::new(),
+            dedup_url: None,
+            revisit_after: None,
         }
     }
+
+    /// The URL the `Request` is deduplicated by: `dedup_url`, or else `url`
+    pub fn dedup_key(&self) -> &Url {
+        self.dedup_url.as_ref().unwrap_or(&self.url)
+    }
 }
 
 /// Contains the result of a `Request` fulfilled by the `Downloader`.
@@ -98,6 +182,9 @@ pub struct Response {
     /// The `Request` that generated this `Response`.
     pub request: Request,
 
+    /// `Response` status code
+    pub status: StatusCode,
+
     /// `Response` headers
     pub headers: HeaderMap,
 
@@ -109,6 +196,7 @@ impl Response {
     pub fn new(request: Request) -> Self {
         Self {
             request,
+            status: StatusCode::OK,
             headers: HeaderMap::new(),
             body: String::new(),
         }
@@ -124,11 +212,368 @@ pub struct Item {
     /// A JSON, obtained by assigning the result of CSS-selector or RegEx queries on the
     /// `Response` body to pre-determined fields.
     pub data: Value,
+
+    /// The pagination links of the page, see `Page::rel_links`
+    pub rel_links: RelLinks,
+
+    /// The name of the crawl rule that extracted the `Item`, if it has one
+    pub rule: Option<String>,
 }
 
 impl Item {
     pub fn new(request: Request, data: Value) -> Self {
-        Self { request, data }
+        Self { request, data, rel_links: RelLinks::default(), rule: None }
+    }
+}
+
+/// Why a spider was closed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CloseReason {
+    /// There was nothing left to crawl
+    #[default]
+    Finished,
+
+    /// `SchedulerSettings::max_requests` was reached
+    RequestLimit,
+
+    /// `PipelineSettings::max_items` was reached
+    ItemLimit,
+
+    /// The crawl was stopped with `CrawlHandle::stop`
+    Stopped,
+
+    /// The process received a shutdown signal (SIGINT/SIGTERM, Ctrl+C on Windows)
+    Interrupted,
+
+    /// `SpiderSettings::max_duration_secs` was reached
+    TimeLimit,
+
+    /// A component of the crawl failed and couldn't go on, e.g. a `ParseWorker` stopped
+    Error,
+}
+
+/// Tells the `Scheduler` to stop dispatching `Request`s. The spider is closed once the
+/// `Request`s in flight have been processed, or after `SchedulerSettings::shutdown_timeout`.
+#[derive(Clone, Debug, Message)]
+pub struct StopSpider {
+    pub reason: CloseReason,
+}
+
+/// Tells the components to wrap up the crawl.
+///
+/// The `Scheduler` stops dispatching `Request`s and, once none are in flight, sends the
+/// message to the `Parser`, which passes it on to the `Pipeline`, which passes it on to
+/// `Stats`. As every component handles its messages in order, the `Response`s and `Item`s
+/// sent before have been processed by the time a component receives it. The `Scheduler`
+/// also sends it to the `Downloader`, and every component stops once it has passed through.
+#[derive(Clone, Debug, Message)]
+pub struct CloseSpider {
+    pub reason: CloseReason,
+
+    /// `false` if the spider was closed with `Request`s still in flight
+    pub clean: bool,
+}
+
+/// Tells the `Scheduler` to stop dispatching `Request`s until `ResumeSpider` is received.
+/// Discovered `Request`s are still added to the queue.
+#[derive(Clone, Debug, Message)]
+pub struct PauseSpider;
+
+/// Tells the `Scheduler` to resume dispatching `Request`s after `PauseSpider`
+#[derive(Clone, Debug, Message)]
+pub struct ResumeSpider;
+
+/// Tells the `Scheduler` to stop the crawl gracefully on SIGINT/SIGTERM, see `Shutdown`
+#[derive(Clone, Debug, Message)]
+pub struct HandleSignals;
+
+/// Tells the `Scheduler` to stop the actix system right away, without closing the spider
+#[derive(Clone, Debug, Message)]
+pub struct AbortSpider;
+
+/// Asks the `Scheduler` and `Stats` for their part of the `CrawlStatus`
+#[derive(Clone, Debug)]
+pub struct GetStatus;
+
+impl Message for GetStatus {
+    type Result = CrawlStatus;
+}
+
+/// A snapshot of a running crawl, returned by `CrawlHandle::status`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CrawlStatus {
+    /// Number of `Request`s waiting to be dispatched
+    pub queue_len: usize,
+
+    /// Number of `Request`s sent to the `Downloader` that haven't been processed yet
+    pub in_flight: usize,
+
+    /// Number of `Item`s processed by the `Pipeline`
+    pub items: usize,
+
+    /// `false` once the spider is closed
+    pub running: bool,
+}
+
+/// A consolidated view of a running crawl, passed to the callbacks of
+/// `CrawlHandle::subscribe_stats`. Rates are averages since the start of the crawl, but for
+/// the `estimate`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StatsSnapshot {
+    /// The name of the spider
+    pub spider: String,
+
+    /// Time (in milliseconds) since the start of the crawl
+    pub elapsed_ms: u64,
+
+    /// Number of `Request`s waiting to be dispatched
+    pub queue_len: usize,
+
+    /// Number of `Request`s sent to the `Downloader` that haven't been processed yet
+    pub in_flight: usize,
+
+    /// Number of `Request`s processed by the `Downloader`
+    pub requests: usize,
+
+    /// Number of `Request`s that failed, without a `Response`
+    pub requests_failed: usize,
+
+    /// `requests_failed` by the category of the error
+    pub request_errors: RequestErrors,
+
+    /// Number of `Response`s with a 4xx or 5xx status code
+    pub error_responses: usize,
+
+    /// Number of `Item`s processed by the `Pipeline`
+    pub items: usize,
+
+    /// Number of `Item`s dropped by a pipeline element
+    pub items_dropped: usize,
+
+    /// Total size (in bytes) of the `Response` bodies received
+    pub bytes_downloaded: u64,
+
+    /// `Request`s per second
+    pub request_rate: f64,
+
+    /// `Item`s per second
+    pub item_rate: f64,
+
+    /// How far along the crawl is, from the rates of the last few seconds
+    pub estimate: CrawlEstimate,
+
+    /// `false` for the last snapshot, taken once the spider is closed
+    pub running: bool,
+}
+
+/// How far along a crawl is.
+///
+/// Only crawls bounded by `max_requests` or `max_depth` have an end to estimate. For the
+/// others, comparing how fast `Request`s are discovered and completed tells whether the
+/// crawl is catching up with its frontier.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CrawlEstimate {
+    Bounded {
+        /// Share of the `Request`s of the crawl that are completed, from 0.0 to 100.0
+        percent: f64,
+
+        /// Time (in seconds) left at the current request rate. `None` while no `Request`s
+        /// are completed
+        eta_secs: Option<u64>,
+    },
+    Unbounded {
+        /// `Request`s queued per second
+        discovered_rate: f64,
+
+        /// `Request`s completed per second
+        completed_rate: f64,
+    },
+}
+
+impl Default for CrawlEstimate {
+    fn default() -> Self {
+        CrawlEstimate::Unbounded { discovered_rate: 0.0, completed_rate: 0.0 }
+    }
+}
+
+/// e.g. `42.0% done, ETA 3m12s` or `frontier +12.5/s vs 4.0/s done`
+impl fmt::Display for CrawlEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrawlEstimate::Bounded { percent, eta_secs: Some(secs) } => {
+                write!(f, "{:.1}% done, ETA {}m{:02}s", percent, secs / 60, secs % 60)
+            }
+            CrawlEstimate::Bounded { percent, eta_secs: None } => {
+                write!(f, "{:.1}% done", percent)
+            }
+            CrawlEstimate::Unbounded { discovered_rate, completed_rate } => {
+                write!(f, "frontier +{:.1}/s vs {:.1}/s done", discovered_rate, completed_rate)
+            }
+        }
+    }
+}
+
+/// Subscribes `callback` to a `StatsSnapshot` every `interval`, see
+/// `CrawlHandle::subscribe_stats`
+pub struct SubscribeStats {
+    pub interval: Duration,
+    pub callback: Box<dyn Fn(StatsSnapshot) + Send>,
+}
+
+impl Message for SubscribeStats {
+    type Result = ();
+}
+
+/// Summary of a finished crawl, returned by `Crawler::run`.
+#[derive(Clone, Debug, Default)]
+pub struct CrawlReport {
+    /// The name of the spider
+    pub spider: String,
+
+    /// Number of `Request`s made by the `Downloader`
+    pub requests_total: usize,
+
+    /// Number of `Request`s that received a `Response`, regardless of its status code
+    pub requests_success: usize,
+
+    /// Number of `Request`s that failed
+    pub requests_failed: usize,
+
+    /// `requests_failed` by the category of the error: connection, timeout, TLS, decoding,
+    /// HTTP status or other
+    pub request_errors: RequestErrors,
+
+    /// Number of `Request`s whose `Response` asked to retry them later (see
+    /// `DownloaderSettings::retry_after_retries`). They aren't counted in `requests_success`.
+    pub requests_retried: usize,
+
+    /// Number of `Request`s refused as their host resolves to a private address (see
+    /// `DownloaderSettings::allow_private_addresses`). They aren't counted in
+    /// `requests_failed`.
+    pub requests_refused: usize,
+
+    /// Number of `Item`s processed by the `Pipeline`
+    pub items_scraped: usize,
+
+    /// Number of `Item`s dropped by a pipeline element
+    pub items_dropped: usize,
+
+    /// Number of `Item`s processed by the `Pipeline` by crawl rule name, see `Item::rule`.
+    /// `Item`s without a rule are not counted
+    pub items_by_rule: HashMap<String, usize>,
+
+    /// Total size (in bytes) of the `Response` bodies received
+    pub bytes_downloaded: u64,
+
+    /// Time elapsed between the start and the end of the crawl
+    pub duration: Duration,
+
+    /// Number of `Response`s per status code
+    pub status_counts: HashMap<u16, usize>,
+
+    /// Average time it took to retrieve a network resource
+    pub average_latency: Duration,
+
+    /// Distribution of the time it took to retrieve the `Response`s
+    pub latency_histogram: LatencyHistogram,
+
+    /// Breakdown of the crawl by host. Once `StatsSettings::max_hosts` hosts are tracked,
+    /// further hosts are counted together under `HostStats::OTHER_HOSTS`
+    pub hosts: HashMap<String, HostStats>,
+
+    /// Highest number of `Request`s waiting to be dispatched during the crawl
+    pub max_queue_len: usize,
+
+    /// Highest estimate of the memory (in bytes) taken by the queue during the crawl, see
+    /// `SchedulerSettings::queue_warn_bytes`
+    pub max_queue_bytes: u64,
+
+    /// Number of URLs taken from the queue
+    pub visited: usize,
+
+    /// Why the crawl was stopped
+    pub stop_reason: CloseReason,
+
+    /// Whether all work in flight was completed before the crawl was stopped
+    pub clean: bool,
+}
+
+impl CrawlReport {
+    /// Exit status for a process that ran the crawl: `0` if the crawl was stopped
+    /// cleanly, and not because of an error, `1` otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.clean && self.stop_reason != CloseReason::Error { 0 } else { 1 }
+    }
+}
+
+/// Metrics of the crawl for a single host
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HostStats {
+    /// Number of `Request`s made to the host
+    pub requests: usize,
+
+    /// Number of `Request`s to the host that failed
+    pub errors: usize,
+
+    /// Number of `Item`s constructed from `Response`s of the host
+    pub items: usize,
+
+    /// Total size (in bytes) of the `Response` bodies received from the host
+    pub bytes_downloaded: u64,
+
+    /// Average time it took to retrieve a network resource from the host
+    pub average_latency: Duration,
+}
+
+impl HostStats {
+    /// Key under which the hosts past `StatsSettings::max_hosts` are counted together
+    pub const OTHER_HOSTS: &'static str = "(other)";
+}
+
+/// Number of `Response`s by the time it took to retrieve them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyHistogram {
+    /// `counts[i]` is the number of `Response`s that took less than `BOUNDS_MS[i]` (and at
+    /// least the bound before). The last bucket counts the slower ones.
+    pub counts: [usize; 7],
+}
+
+impl LatencyHistogram {
+    /// Upper bounds (in milliseconds) of the buckets
+    pub const BOUNDS_MS: [u64; 6] = [100, 250, 500, 1000, 2000, 5000];
+
+    /// Counts a `Response` that took `latency`
+    pub fn record(&mut self, latency: Duration) {
+        let ms = latency.as_secs() * 1000 + u64::from(latency.subsec_millis());
+        let bucket = LatencyHistogram::BOUNDS_MS.iter()
+            .position(|bound| ms < *bound)
+            .unwrap_or(LatencyHistogram::BOUNDS_MS.len());
+        self.counts[bucket] += 1;
+    }
+
+    /// The label and count of every bucket, e.g. `("<250ms", 3)` or `(">=5s", 1)`
+    pub fn buckets(&self) -> Vec<(String, usize)> {
+        let label = |ms: u64| {
+            if ms < 1000 { format!("{}ms", ms) } else { format!("{}s", ms / 1000) }
+        };
+        let bounds = LatencyHistogram::BOUNDS_MS;
+        bounds.iter()
+            .map(|bound| format!("<{}", label(*bound)))
+            .chain(Some(format!(">={}", label(bounds[bounds.len() - 1]))))
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+}
+
+/// Lists the buckets that aren't empty, e.g. `<100ms:12 <250ms:3 >=5s:1`
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let buckets: Vec<String> = self.buckets().into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(label, count)| format!("{}:{}", label, count))
+            .collect();
+        write!(f, "{}", buckets.join(" "))
     }
 }
 
@@ -166,54 +611,2100 @@ impl<T> Message for Listener<T>
     type Result = ();
 }
 
+/// A problem that prevents a crawl from being started
+#[derive(Debug)]
+pub enum CrawlError {
+    /// The `Spider` could not be built, see `SpiderBuilder::try_build`
+    Build(Vec<BuildError>),
+
+    /// The `Spider` has neither start URLs nor sitemaps to seed the crawl from, so there is
+    /// nothing to crawl
+    NoStartUrls,
+
+    /// No spider of that name is registered, see `Crawler::run_by_name`
+    UnknownSpider(String),
+}
+
+impl fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrawlError::Build(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "failed to build spider: {}", errors.join("; "))
+            }
+            CrawlError::NoStartUrls => write!(f, "spider has no start urls"),
+            CrawlError::UnknownSpider(name) => write!(f, "no spider named {:?}", name),
+        }
+    }
+}
+
+impl error::Error for CrawlError {}
+
+impl From<Vec<BuildError>> for CrawlError {
+    fn from(errors: Vec<BuildError>) -> Self {
+        CrawlError::Build(errors)
+    }
+}
+
 /// Contains a pointer to a spider template.
 ///
 /// The `Crawler` contains the actix event loop.
 pub struct Crawler;
 
 impl Crawler {
-    pub fn run(spider: Spider) {
+    /// Runs the crawl until the spider is closed, and returns a summary of the crawl.
+    /// Returns an error if the crawl can't be started.
+    ///
+    /// The spider is closed once the `Scheduler` has been idle for longer than
+    /// `SchedulerSettings::idle_timeout`.
+    pub fn run(spider: Spider) -> Result<CrawlReport, CrawlError> {
+        let fetcher = HttpFetch::from_settings(&spider.settings().downloader);
+        Crawler::run_with_fetcher(spider, fetcher)
+    }
+
+    /// Same as `run`, with the spider of `registry` named `name`, e.g. given on the command
+    /// line
+    pub fn run_by_name(registry: &SpiderRegistry, name: &str)
+        -> Result<CrawlReport, CrawlError>
+    {
+        let builder = registry.builder(name)
+            .ok_or_else(|| CrawlError::UnknownSpider(name.to_string()))?;
+        Crawler::run(builder.try_build()?)
+    }
+
+    /// Same as `run`, but network resources are retrieved with the supplied fetcher.
+    /// Use a `MockFetch` to run a spider against pages served from memory.
+    pub fn run_with_fetcher<F: Fetch + 'static>(spider: Spider, fetcher: F)
+        -> Result<CrawlReport, CrawlError>
+    {
+        let fetcher: Arc<dyn Fetch> = Arc::new(fetcher);
+        let mut reports = Crawler::run_all(vec![(spider, fetcher)], Crawler::stop_on_signals)?;
+        Ok(reports.remove(0))
+    }
+
+    /// Runs several spiders side by side in the same actix system, until all of them are
+    /// closed. Returns a `CrawlReport` per spider, in the order of `spiders`.
+    ///
+    /// Every spider has its own `Scheduler`, `Downloader`, `Parser`, `Pipeline` and `Stats`,
+    /// wired to each other by address, so spiders are isolated from each other:
+    /// - URLs are deduplicated per spider; two spiders may crawl the same URL
+    /// - `download_delay`, `concurrent_requests` and robots.txt delays apply per spider,
+    ///   so two spiders crawling the same host each send requests at their own pace
+    /// - `Item`s only pass through the pipeline elements of the spider that scraped them
+    /// - budgets (`max_requests`, `max_items`, ...) and stats are counted per spider
+    ///
+    /// What is shared is the thread the actix system runs on, and the shutdown: Ctrl+C
+    /// stops all spiders. To run spiders one after the other instead, call `run` for each.
+    pub fn run_many(spiders: Vec<Spider>) -> Result<Vec<CrawlReport>, CrawlError> {
+        let spiders = spiders.into_iter()
+            .map(|spider| {
+                let fetcher = HttpFetch::from_settings(&spider.settings().downloader);
+                (spider, Arc::new(fetcher) as Arc<dyn Fetch>)
+            })
+            .collect();
+        Crawler::run_all(spiders, Crawler::stop_on_signals)
+    }
+
+    /// Starts the crawl in the actix system of the current thread, e.g. from within an
+    /// actix application, and returns a future that resolves to the summary of the crawl.
+    /// Returns an error if the crawl can't be started.
+    ///
+    /// Unlike `run`, the system is neither created, run nor stopped by the crawl, and
+    /// handling Ctrl+C is left to the application.
+    pub fn spawn(spider: Spider) -> Result<oneshot::Receiver<CrawlReport>, CrawlError> {
+        let fetcher = HttpFetch::from_settings(&spider.settings().downloader);
+        Crawler::spawn_with_fetcher(spider, fetcher)
+    }
+
+    /// Same as `spawn`, but network resources are retrieved with the supplied fetcher
+    pub fn spawn_with_fetcher<F: Fetch + 'static>(spider: Spider, fetcher: F)
+        -> Result<oneshot::Receiver<CrawlReport>, CrawlError>
+    {
+        if !spider.has_start_urls() {
+            return Err(CrawlError::NoStartUrls);
+        }
+
+        let (report_sender, report_receiver) = oneshot::channel();
+        let on_close = Box::new(move |report: CrawlReport| {
+            let _ = report_sender.send(report);
+        });
+        Crawler::start_spider(spider, Arc::new(fetcher), on_close);
+        Ok(report_receiver)
+    }
+
+    /// Starts the crawl on a dedicated thread and returns a `CrawlHandle` to control it.
+    /// Returns an error if the crawl can't be started.
+    ///
+    /// A `Spider` can't be sent to another thread, so it is built on the crawl thread by
+    /// calling `build`.
+    ///
+    /// Unlike `run`, the crawl is left running on Ctrl+C, as the signals belong to the caller.
+    /// Call `CrawlHandle::stop_on_signals` to stop it gracefully on them instead.
+    pub fn start<B>(build: B) -> Result<CrawlHandle, CrawlError>
+        where
+            B: FnOnce() -> Spider + Send + 'static,
+    {
+        Crawler::start_with_fetcher(move || {
+            let spider = build();
+            let fetcher: Arc<dyn Fetch> =
+                Arc::new(HttpFetch::from_settings(&spider.settings().downloader));
+            (spider, fetcher)
+        })
+    }
+
+    /// Same as `start`, but `build` also returns the fetcher the crawl uses
+    pub(crate) fn start_with_fetcher<B>(build: B) -> Result<CrawlHandle, CrawlError>
+        where
+            B: FnOnce() -> (Spider, Arc<dyn Fetch>) + Send + 'static,
+    {
+        let (started_sender, started_receiver) = mpsc::channel();
+        let error_sender = started_sender.clone();
+
+        let thread = thread::spawn(move || {
+            let result = Crawler::run_all(vec![build()], move |actors| {
+                let SpiderActors { scheduler, stats, start_priority, .. } = &actors[0];
+                let started = (scheduler.clone(), stats.clone(), *start_priority);
+                let _ = started_sender.send(Ok(started));
+            });
+            match result {
+                Ok(mut reports) => reports.remove(0),
+                Err(e) => {
+                    let _ = error_sender.send(Err(e));
+                    CrawlReport::default()
+                }
+            }
+        });
+
+        match started_receiver.recv() {
+            Ok(Ok((scheduler, stats, start_priority))) => {
+                Ok(CrawlHandle { scheduler, stats, start_priority, thread })
+            }
+            Ok(Err(e)) => Err(e),
+            // The crawl thread panicked before the crawl was started
+            Err(_) => panic::resume_unwind(thread.join().unwrap_err()),
+        }
+    }
+
+    /// Stops the crawl of every spider gracefully on Ctrl+C. Passed to `run_all` as `started`
+    /// by the crawls that own the process.
+    fn stop_on_signals(actors: &[SpiderActors]) {
+        Shutdown::new(actors.iter().map(|a| a.scheduler.clone()).collect()).start();
+    }
+
+    /// Runs the spiders, each with its own fetcher. `started` is called with the actors of
+    /// every spider before the system is run.
+    pub(crate) fn run_all<F>(spiders: Vec<(Spider, Arc<dyn Fetch>)>, started: F)
+        -> Result<Vec<CrawlReport>, CrawlError>
+        where
+            F: FnOnce(&[SpiderActors]),
+    {
+        if spiders.iter().any(|(spider, _)| !spider.has_start_urls()) {
+            return Err(CrawlError::NoStartUrls);
+        }
+        if spiders.is_empty() {
+            return Ok(Vec::new());
+        }
+
         info!("Run Vortex v{}", env!("CARGO_PKG_VERSION"));
 
         let sys = System::new("crawler");
 
+        // The system is stopped once every spider is closed
+        let running = Rc::new(Cell::new(spiders.len()));
+
+        let mut names = Vec::new();
+        let mut receivers = Vec::new();
+        let mut actors = Vec::new();
+        for (spider, fetcher) in spiders {
+            let (report_sender, report_receiver) = mpsc::channel();
+            names.push(spider.name().to_string());
+            receivers.push(report_receiver);
+            let running = Rc::clone(&running);
+            let on_close = Box::new(move |report: CrawlReport| {
+                let _ = report_sender.send(report);
+                running.set(running.get() - 1);
+                if running.get() == 0 {
+                    System::current().stop();
+                }
+            });
+            actors.push(Crawler::start_spider(spider, fetcher, on_close));
+        }
+
+        started(&actors);
+
+        sys.run();
+
+        Ok(names.into_iter().zip(receivers)
+            .map(|(name, receiver)| {
+                receiver.try_recv().unwrap_or_else(|_| {
+                    warn!("The crawl was stopped before a crawl report was gathered");
+                    CrawlReport { spider: name, ..Default::default() }
+                })
+            })
+            .collect())
+    }
+
+    /// Creates the actors of a spider, wired to each other, and seeds its start requests.
+    /// `on_close` is called with the `CrawlReport` once the spider is closed.
+    fn start_spider(
+        spider: Spider,
+        fetcher: Arc<dyn Fetch>,
+        on_close: Box<dyn FnOnce(CrawlReport)>,
+    ) -> SpiderActors {
+        info!("Settings of {}:\n{}", spider.name(), spider.settings().to_redacted_toml_string());
+
         let spider = Rc::new(spider);
 
+        // The `on_close` hook sees the report before it is handed over
         let s = Rc::clone(&spider);
-        let scheduler = Scheduler::create(|_| Scheduler::new(s));
-        Arbiter::registry().set::<Scheduler>(scheduler.clone());
+        let closed = Rc::new(Cell::new(false));
+        let c = Rc::clone(&closed);
+        let on_close: Box<dyn FnOnce(CrawlReport)> = Box::new(move |report| {
+            c.set(true);
+            if let Some(hook) = &s.hooks().on_close {
+                hook(&report);
+            }
+            on_close(report);
+        });
 
-        let s = Rc::clone(&spider);
-        let downloader = Downloader::create(|_| Downloader::new(s));
-        Arbiter::registry().set::<Downloader>(downloader.clone());
+        let name = spider.name().to_string();
+        let settings = spider.settings().clone();
+        let stats = Stats::create(move |_| Stats::new(&name, &settings, on_close));
+        let events = EventLog::from_settings(&spider.settings().stats);
 
-        let s = Rc::clone(&spider);
-        let parser = Parser::create(|_| Parser::new(s));
-        Arbiter::registry().set::<Parser>(parser);
+        // The `Scheduler` is sent messages by the `Downloader`, `Parser` and `Pipeline`, so
+        // they are created once the address of the `Scheduler` is known
+        let addrs = Rc::new(RefCell::new(None));
+        let created = Rc::clone(&addrs);
+        let sp = Rc::clone(&spider);
+        let st = stats.clone();
+        let scheduler = Scheduler::create(move |ctx| {
+            let (s, sc, e) = (Rc::clone(&sp), ctx.address(), events.clone());
+            let pipeline = Pipeline::create(move |_| Pipeline::new(s, sc, st, e));
 
-        let s = Rc::clone(&spider);
-        let pipeline = Pipeline::create(|_| Pipeline::new(s));
-        Arbiter::registry().set::<Pipeline>(pipeline);
+            let (s, sc, p, e) = (Rc::clone(&sp), ctx.address(), pipeline.clone(), events.clone());
+            let parser = Parser::create(move |_| Parser::new(s, sc, p, e));
 
-        let stats = Stats::create(|_| Stats::default());
-        Arbiter::registry().set::<Stats>(stats.clone());
+            let (s, sc, p, e) = (Rc::clone(&sp), ctx.address(), parser.clone(), events.clone());
+            let downloader = Downloader::create(move |_| Downloader::new(s, fetcher, sc, p, e));
+
+            *created.borrow_mut() = Some((downloader.clone(), parser.clone(), pipeline));
+            Scheduler::new(sp, downloader, parser, events)
+        });
+        let (downloader, parser, pipeline) = addrs.borrow_mut().take().unwrap();
 
         // Add listeners
         scheduler.do_send(Listener::new(stats.clone()));
-        downloader.do_send(Listener::new(scheduler.clone()));
-        downloader.do_send(Listener::new(stats.clone()));
+        downloader.do_send(Listener::<downloader::State>::new(scheduler.clone()));
+        downloader.do_send(Listener::<downloader::State>::new(stats.clone()));
+        downloader.do_send(Listener::<downloader::Outcome>::new(stats.clone()));
+        parser.do_send(Listener::<parser::State>::new(scheduler.clone()));
+        parser.do_send(Listener::<parser::State>::new(stats.clone()));
+        pipeline.do_send(Listener::<pipeline::State>::new(stats.clone()));
+        pipeline.do_send(Listener::<pipeline::Outcome>::new(stats.clone()));
+
+        if let Some(path) = spider.settings_watch() {
+            let recipients = vec![scheduler.clone().recipient(), downloader.clone().recipient()];
+            Settings::watch(path, recipients, closed);
+        }
+
+        if let Some(on_start) = &spider.hooks().on_start {
+            on_start();
+        }
 
         // Start point
         scheduler.do_send(spider.start_requests().clone());
 
-        sys.run();
+        SpiderActors {
+            scheduler,
+            stats,
+            start_priority: spider.start_priority(),
+            #[cfg(test)]
+            downloader,
+            #[cfg(test)]
+            parser,
+            #[cfg(test)]
+            pipeline,
+        }
+    }
+
+    /// Fetches up to `max_pages` pages, starting from the spider's start urls and following
+    /// the links its crawl rules allow, and reports what the spider made of each page.
+    /// `Item`s are not sent to the `Pipeline`. See `DryRunReport`.
+    pub fn dry_run_pages(spider: &Spider, max_pages: usize) -> DryRunReport {
+        let fetcher = HttpFetch::from_settings(&spider.settings().downloader);
+        Crawler::dry_run_pages_with_fetcher(spider, &fetcher, max_pages)
+    }
+
+    /// Same as `dry_run_pages`, but network resources are retrieved with the supplied
+    /// fetcher. Use a `MockFetch` to dry run a spider against saved pages.
+    pub fn dry_run_pages_with_fetcher<F: Fetch>(spider: &Spider, fetcher: &F, max_pages: usize)
+        -> DryRunReport
+    {
+        dry_run::run(spider, fetcher, max_pages)
+    }
+
+    /// Applies the spider's crawl rules to a sample page without starting the actix system
+    /// or making any network requests. Returns the links that would be followed and the
+    /// `Item`s that would be sent to the `Pipeline`.
+    ///
+    /// Useful for testing `Condition`s and `ParseRule`s. Panics if `sample_url` is not a
+    /// valid URL.
+    pub fn dry_run(spider: &Spider, sample_html: &str, sample_url: &str) -> (Vec<Url>, Vec<Item>) {
+        let url = Url::parse(sample_url).unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = sample_html.to_string();
+
+        let (urls, data) = Parser::parse(spider, &res);
+        let items = data.into_iter()
+            .map(|d| Item::new(res.request.clone(), d))
+            .collect();
+        (urls, items)
+    }
+}
+
+/// The actors of a spider that are addressed from outside the crawl
+pub(crate) struct SpiderActors {
+    pub scheduler: Addr<Scheduler>,
+    pub stats: Addr<Stats>,
+    /// Priority of the URLs submitted to the crawl
+    pub start_priority: u32,
+    #[cfg(test)]
+    pub downloader: Addr<Downloader>,
+    #[cfg(test)]
+    pub parser: Addr<Parser>,
+    #[cfg(test)]
+    pub pipeline: Addr<Pipeline>,
+}
+
+/// Controls a crawl started with `Crawler::start` from another thread
+pub struct CrawlHandle {
+    scheduler: Addr<Scheduler>,
+    stats: Addr<Stats>,
+    start_priority: u32,
+    thread: JoinHandle<CrawlReport>,
+}
+
+impl CrawlHandle {
+    /// Stops dispatching `Request`s. The links found in the `Response`s of the `Request`s in
+    /// flight are still added to the queue.
+    pub fn pause(&self) {
+        self.scheduler.do_send(PauseSpider);
+    }
+
+    /// Resumes dispatching `Request`s after `pause`
+    pub fn resume(&self) {
+        self.scheduler.do_send(ResumeSpider);
+    }
+
+    /// Stops the crawl gracefully on Ctrl+C (SIGINT/SIGTERM), as `Crawler::run` does
+    pub fn stop_on_signals(&self) {
+        self.scheduler.do_send(HandleSignals);
+    }
+
+    /// Returns a snapshot of the crawl. Blocks until the `Scheduler` and `Stats` have
+    /// answered. `running` is `false` once the spider is closed.
+    pub fn status(&self) -> CrawlStatus {
+        let status = self.scheduler.send(GetStatus).wait().unwrap_or_default();
+        let items = self.stats.send(GetStatus).wait().map(|s| s.items).unwrap_or_default();
+        CrawlStatus { items, ..status }
+    }
+
+    /// Calls `callback` with a `StatsSnapshot` of the crawl every `interval`, e.g. to render
+    /// a progress bar, and once more when the spider is closed. The callback is called on
+    /// the crawl thread, so it should return quickly.
+    pub fn subscribe_stats<F>(&self, interval: Duration, callback: F)
+        where
+            F: Fn(StatsSnapshot) + Send + 'static,
+    {
+        self.stats.do_send(SubscribeStats { interval, callback: Box::new(callback) });
+    }
+
+    /// Adds start URLs to the running crawl. They are treated like the `start_urls` of the
+    /// spider: crawled at depth 0, and skipped if they were crawled already. Nothing is
+    /// submitted if any of the URLs is invalid.
+    ///
+    /// The spider is closed once it has been idle for `SchedulerSettings::idle_timeout`,
+    /// so the timeout should cover the gaps between submissions.
+    pub fn submit_urls(&self, urls: Vec<String>) -> Result<(), Vec<BuildError>> {
+        let mut requests = Vec::new();
+        let mut errors = Vec::new();
+        for url in urls {
+            match Url::parse(&url) {
+                Ok(u) => requests.push(Request::new(u, 0, self.start_priority)),
+                Err(e) => errors.push(BuildError::InvalidUrl { url, error: e.to_string() }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.scheduler.do_send(RequestVec::new(requests));
+        Ok(())
+    }
+
+    /// Stops the crawl gracefully, like Ctrl+C does, and blocks until the spider is closed.
+    /// Returns the summary of the crawl.
+    pub fn stop(self) -> CrawlReport {
+        self.scheduler.do_send(StopSpider { reason: CloseReason::Stopped });
+        self.wait()
+    }
+
+    /// Stops the crawl right away, without waiting for the `Request`s in flight or closing
+    /// the pipeline elements, and blocks until the crawl thread has ended.
+    pub fn abort(self) {
+        self.scheduler.do_send(AbortSpider);
+        self.wait();
+    }
+
+    /// Blocks until the crawl has ended and returns the summary of the crawl
+    pub fn wait(self) -> CrawlReport {
+        match self.thread.join() {
+            Ok(report) => report,
+            Err(e) => panic::resume_unwind(e),
+        }
     }
 }
 
-/// A macro that sends an asynchronous message to an Actor
-macro_rules! send {
-    ($actor:path, $msg:expr) => {{
-        let addr = Arbiter::registry().get::<$actor>().send($msg);
-        Arbiter::spawn(addr.map(|_| {}).map_err(|e| error!("Send error: {:?}", e)));
-    }};
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashSet;
+    use std::{fs, process};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::time::Instant;
+
+    use actix::Arbiter;
+    use flate2::{Compression, write::GzEncoder};
+    use futures::{future, Stream};
+    use tokio_timer::Interval;
+    use valico::json_schema::Scope;
+
+    use crate::downloader::{FetchError, FetchFuture, MockFetch};
+    use crate::downloader::middleware::DownloaderMiddleware;
+    use crate::pipeline::elements::{Pagination, PipelineElement};
+    use crate::scheduler::{MemorySeenStore, SeenStore};
+    use crate::settings::{DownloaderMiddlewareType, PipelineElementType, Update};
+    use crate::spider::{Condition, CrawlRule, ParseRule, Pattern, SpiderBuilder};
+
+    use super::*;
+
+    /// Collects the `Item`s that reach the `Pipeline`
+    struct Collect(Rc<RefCell<Vec<Item>>>);
+
+    impl PipelineElement for Collect {
+        fn process_item(&self, item: Item) -> Option<Item> {
+            self.0.borrow_mut().push(item.clone());
+            Some(item)
+        }
+    }
+
+    #[test]
+    fn test_request_serde() {
+        let mut req = Request::new(Url::parse("http://site.test/a?b=1").unwrap(), 2, 7);
+        req.dedup_url = Some(Url::parse("http://site.test/a").unwrap());
+        req.revisit_after = Some(Duration::from_secs(60));
+        req.meta.insert("lastmod".to_string(), json!("2019-03-01"));
+
+        let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(json["url"], "http://site.test/a?b=1");
+        assert_eq!(json["dedup_url"], "http://site.test/a");
+        let de: Request = serde_json::from_value(json).unwrap();
+        assert_eq!((de.url, de.depth, de.priority), (req.url, 2, 7));
+        assert_eq!(de.dedup_url, req.dedup_url);
+        assert_eq!(de.revisit_after, req.revisit_after);
+        assert_eq!(de.meta, req.meta);
+
+        let req = Request::new(Url::parse("http://site.test/").unwrap(), 0, 1);
+        assert_eq!(serde_json::to_value(req).unwrap()["dedup_url"], Value::Null);
+        assert!(serde_json::from_value::<Request>(json!({ "url": "not a url" })).is_err());
+    }
+
+    #[test]
+    fn test_run_with_mock_fetch() {
+        let home = r#"<h1>Home</h1><a href="/a">A</a><a href="/b">B</a>"#;
+        let a = r#"<h1>A</h1><a href="/">Home</a><a href="/b">B</a>"#;
+        let b = r#"<h1>B</h1><a href="/a">A</a><a href="/c">C</a>"#;
+        let fetcher = MockFetch::default()
+            .page("http://site.test/", 200, home)
+            .page("http://site.test/a", 200, a)
+            .page("http://site.test/b", 200, b);
+
+        let settings = test_settings();
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .pipeline_element(Collect(Rc::clone(&items)))
+            .build();
+
+        let report = Crawler::run_with_fetcher(spider, fetcher).unwrap();
+
+        let mut titles: Vec<String> = items.borrow().iter()
+            .map(|i| i.data["title"].as_str().unwrap().to_string())
+            .collect();
+        titles.sort();
+        assert_eq!(titles, vec!["A", "B", "Home"]);
+
+        assert_eq!(report.requests_total, 4);
+        assert_eq!(report.requests_success, 4);
+        assert_eq!(report.requests_failed, 0);
+        assert_eq!(report.items_scraped, 3);
+        assert_eq!(report.bytes_downloaded, (home.len() + a.len() + b.len()) as u64);
+        assert_eq!(report.status_counts.get(&200), Some(&3));
+        assert_eq!(report.status_counts.get(&404), Some(&1));
+    }
+
+    #[test]
+    fn test_parse_on_worker_thread() {
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let t = Arc::clone(&threads);
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/1"])
+            .settings(test_settings())
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::callback(move |page| {
+                    t.lock().unwrap().push(thread::current().id());
+                    Some(vec![json!({ "links": page.urls().len() })])
+                }))
+            .build();
+
+        // Pages /1 to /10, and the 404 page of /11
+        let report = Crawler::run_with_fetcher(spider, chain_site()).unwrap();
+        assert_eq!(report.items_scraped, 11);
+
+        // The actors run on this thread, the pages are parsed on another one
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 11);
+        assert!(!threads.contains(&thread::current().id()));
+    }
+
+    #[test]
+    fn test_parser_workers() {
+        let mut settings = test_settings();
+        settings.parser.workers = 3;
+
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let t = Arc::clone(&threads);
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::callback(move |_| {
+                    t.lock().unwrap().push(thread::current().id());
+                    // Slow enough for the `Responses` in flight to reach the other workers
+                    thread::sleep(Duration::from_millis(50));
+                    Some(vec![json!({})])
+                }))
+            .build();
+
+        let started = Instant::now();
+        let report = Crawler::run_with_fetcher(spider, star_site()).unwrap();
+        assert_eq!(report.items_scraped, 9);
+
+        let mut threads = threads.lock().unwrap().clone();
+        threads.sort_by_key(|id| format!("{:?}", id));
+        threads.dedup();
+        assert_eq!(threads.len(), 3);
+        // The 8 leaves take 400ms to parse one after the other
+        assert!(started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_incremental() {
+        let name = format!("vortex-incremental-crawl-{}.json", process::id());
+        let path = std::env::temp_dir().join(name);
+        let mut settings = test_settings();
+        settings.parser.incremental = true;
+        settings.parser.incremental_path = path.to_str().unwrap().to_string();
+
+        let crawl = |fetcher: MockFetch| {
+            let items = Rc::new(RefCell::new(Vec::new()));
+            let spider = SpiderBuilder::default()
+                .start_urls(vec!["http://site.test/1"])
+                .settings(settings.clone())
+                .crawl_rule(
+                    Condition::new(vec!["site.test"], vec![]),
+                    ParseRule::callback(|_| Some(vec![json!({ "page": true })])))
+                .pipeline_element(Collect(Rc::clone(&items)))
+                .build();
+            let report = Crawler::run_with_fetcher(spider, fetcher).unwrap();
+            let mut urls: Vec<String> = items.borrow().iter()
+                .map(|item| item.request.url.path().to_string())
+                .collect();
+            urls.sort();
+            (report.requests_total, urls)
+        };
+
+        assert_eq!(crawl(chain_site()).1.len(), 11);
+
+        // Unchanged pages are crawled for their links, without making items of them
+        assert_eq!(crawl(chain_site()), (11, vec![]));
+
+        let changed = chain_site()
+            .page("http://site.test/4", 200, r#"<h1>New</h1><a href="/5">Next</a>"#);
+        assert_eq!(crawl(changed), (11, vec!["/4".to_string()]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_summary_file() {
+        let path = std::env::temp_dir().join(format!("vortex-summary-{}.json", process::id()));
+        let mut settings = test_settings();
+        settings.stats.summary_path = path.to_str().unwrap().to_string();
+
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/1"])
+            .settings(settings)
+            .rule(CrawlRule::builder()
+                .name("page")
+                .condition(Condition::new(vec!["site.test"], vec![]))
+                .parse(ParseRule::callback(|_| Some(vec![json!({})])))
+                .build())
+            .build();
+        Crawler::run_with_fetcher(spider, chain_site()).unwrap();
+
+        let summary: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let count = json!({ "type": "integer", "minimum": 0 });
+        let counts = json!({ "type": "object", "additionalProperties": count });
+        let schema = json!({
+            "type": "object",
+            "required": ["spider", "started_at", "finished_at", "duration_ms", "stop_reason",
+                         "clean", "requests", "bytes_downloaded", "items", "queue", "top_hosts"],
+            "properties": {
+                "started_at": { "type": "string" },
+                "finished_at": { "type": "string" },
+                "stop_reason": { "type": "string" },
+                "clean": { "type": "boolean" },
+                "requests": {
+                    "type": "object",
+                    "required": ["total", "success", "failed", "by_status_class", "by_status"],
+                    "properties": { "by_status_class": counts, "by_status": counts },
+                },
+                "items": {
+                    "type": "object",
+                    "required": ["scraped", "dropped", "by_rule"],
+                    "properties": { "by_rule": counts },
+                },
+                "queue": {
+                    "type": "object",
+                    "properties": { "max_len": count, "max_bytes": count, "visited": count },
+                },
+                "top_hosts": {
+                    "type": "array",
+                    "items": { "type": "object", "required": ["host", "requests", "errors"] },
+                },
+            },
+        });
+        let mut scope = Scope::new();
+        let schema = scope.compile_and_return(schema, false).unwrap();
+        assert!(schema.validate(&summary).is_valid(), "{}", summary);
+
+        assert_eq!(summary["stop_reason"], "Finished");
+        assert_eq!(summary["requests"]["total"], 11);
+        assert_eq!(summary["requests"]["by_status_class"], json!({ "2xx": 10, "4xx": 1 }));
+        assert_eq!(summary["items"]["by_rule"], json!({ "page": 11 }));
+        assert_eq!(summary["top_hosts"][0]["host"], "site.test");
+        // A single link is queued at a time, with URLs of 18 characters on average
+        assert_eq!(summary["queue"], json!({ "max_len": 1, "max_bytes": 18, "visited": 11 }));
+    }
+
+    /// Counts the `Item`s that reach the `Pipeline`, slowly
+    struct SlowCount(Arc<AtomicUsize>);
+
+    impl PipelineElement for SlowCount {
+        fn process_item(&self, item: Item) -> Option<Item> {
+            thread::sleep(Duration::from_millis(1));
+            self.0.fetch_add(1, AtomicOrdering::SeqCst);
+            Some(item)
+        }
+    }
+
+    #[test]
+    fn test_pipeline_backpressure() {
+        let mut settings = test_settings();
+        settings.pipeline.max_inflight = 10;
+
+        // Every page yields 20 `Items` and takes a while to parse, so that `Responses` queue up.
+        // Before a page is parsed, the number of `Items` not processed yet is recorded.
+        let extracted = Arc::new(AtomicUsize::new(0));
+        let processed = Arc::new(AtomicUsize::new(0));
+        let backlog = Arc::new(Mutex::new(Vec::new()));
+        let (e, p, b) = (Arc::clone(&extracted), Arc::clone(&processed), Arc::clone(&backlog));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::callback(move |_page| {
+                    let pending = e.load(AtomicOrdering::SeqCst) - p.load(AtomicOrdering::SeqCst);
+                    b.lock().unwrap().push(pending);
+                    e.fetch_add(20, AtomicOrdering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    Some(vec![json!({}); 20])
+                }))
+            .pipeline_element(SlowCount(Arc::clone(&processed)))
+            .build();
+
+        let report = Crawler::run_with_fetcher(spider, star_site()).unwrap();
+        assert_eq!(report.items_scraped, 180);
+
+        // No page is parsed while the `Pipeline` is `max_inflight` `Items` behind
+        let backlog = backlog.lock().unwrap();
+        assert_eq!(backlog.len(), 9);
+        assert!(backlog.iter().all(|&pending| pending < 10), "{:?}", backlog);
+    }
+
+    /// A site where every page links to the next one: /1 -> /2 -> ... -> /10
+    fn chain_site() -> MockFetch {
+        (1..=10).fold(MockFetch::default(), |fetcher, i| {
+            let url = format!("http://site.test/{}", i);
+            let body = format!(r#"<a href="/{}">Next</a>"#, i + 1);
+            fetcher.page(&url, 200, &body)
+        })
+    }
+
+    fn chain_spider(settings: Settings) -> Spider {
+        SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/1"])
+            .settings(settings)
+            .crawl_rule(Condition::new(vec!["site.test"], vec![]), ParseRule::FilterUrls)
+            .build()
+    }
+
+    fn test_settings() -> Settings {
+        let mut settings = Settings::default();
+        settings.scheduler.download_delay = 1;
+        settings.scheduler.idle_timeout = 50;
+        // The test servers listen on 127.0.0.1
+        settings.downloader.allow_private_addresses = true;
+        settings.downloader.middleware_list = vec![];
+        settings.pipeline.element_list = vec![];
+        settings
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut settings = test_settings();
+        settings.scheduler.max_depth = 3;
+
+        let report = Crawler::run_with_fetcher(chain_spider(settings), chain_site()).unwrap();
+        assert_eq!(report.requests_total, 4);
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let mut settings = test_settings();
+        settings.scheduler.max_requests = 5;
+
+        let report = Crawler::run_with_fetcher(chain_spider(settings), chain_site()).unwrap();
+        assert_eq!(report.requests_total, 5);
+    }
+
+    /// The `Scheduler` of the spider a test fetcher fetches for, set by `run_with_scheduler`
+    type SchedulerSlot = Arc<Mutex<Option<Addr<Scheduler>>>>;
+
+    /// Same as `Crawler::run_with_fetcher`, but the `Scheduler` is put into `slot`
+    fn run_with_scheduler<F>(spider: Spider, fetcher: F, slot: &SchedulerSlot) -> CrawlReport
+        where
+            F: Fetch + 'static,
+    {
+        let fetcher: Arc<dyn Fetch> = Arc::new(fetcher);
+        let mut reports = Crawler::run_all(vec![(spider, fetcher)], |actors| {
+            *slot.lock().unwrap() = Some(actors[0].scheduler.clone());
+        }).unwrap();
+        reports.remove(0)
+    }
+
+    /// Same as `star_spider`, but every page yields an `Item`
+    fn item_spider(settings: Settings) -> Spider {
+        SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::callback(|_| Some(vec![json!({})])))
+            .build()
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let report = |stop_reason, clean| CrawlReport { stop_reason, clean, ..Default::default() };
+        assert_eq!(report(CloseReason::Finished, true).exit_code(), 0);
+        assert_eq!(report(CloseReason::Stopped, true).exit_code(), 0);
+        assert_eq!(report(CloseReason::Finished, false).exit_code(), 1);
+        assert_eq!(report(CloseReason::Error, true).exit_code(), 1);
+    }
+
+    #[test]
+    fn test_max_items() {
+        let mut settings = test_settings();
+        settings.pipeline.max_items = 3;
+        let concurrent_requests = settings.scheduler.concurrent_requests;
+
+        let report = Crawler::run_with_fetcher(item_spider(settings), star_site()).unwrap();
+        assert_eq!(report.stop_reason, CloseReason::ItemLimit);
+        assert!(report.clean);
+        assert_eq!(report.exit_code(), 0);
+        // Every page yields one `Item`, so only the `Requests` in flight once the limit is
+        // reached may push the count over it
+        assert!(report.items_scraped >= 3, "{}", report.items_scraped);
+        assert!(report.items_scraped <= 3 + concurrent_requests, "{}", report.items_scraped);
+        assert!(report.requests_total < 9, "{}", report.requests_total);
+    }
+
+    #[test]
+    fn test_max_items_per_rule() {
+        let mut settings = test_settings();
+        settings.pipeline.max_items_per_rule.insert("page".to_string(), 3);
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .rule(CrawlRule::builder()
+                .name("page")
+                .condition(Condition::new(vec!["site.test"], vec![]))
+                .parse(ParseRule::callback(|_| Some(vec![json!({ "page": true })])))
+                .build())
+            .rule(CrawlRule::builder()
+                .name("other")
+                .condition(Condition::new(vec!["site.test"], vec![]))
+                .parse(ParseRule::callback(|_| Some(vec![json!({ "page": false })])))
+                .build())
+            .pipeline_element(Collect(Rc::clone(&items)))
+            .build();
+
+        // The `Item`s of `page` are dropped past the limit, the crawl goes on for `other`.
+        // The rules are named, but the `Item`s are not tagged with `_rule`
+        let report = Crawler::run_with_fetcher(spider, star_site()).unwrap();
+        assert_eq!(report.stop_reason, CloseReason::Finished);
+        assert_eq!((report.requests_total, report.items_scraped), (9, 3 + 9));
+        assert_eq!(report.items_by_rule.get("page"), Some(&3));
+        let items = items.borrow();
+        assert!(items.iter().all(|item| item.data.get("_rule").is_none()));
+        let count = |name: &str| items.iter()
+            .filter(|item| item.rule.as_deref() == Some(name))
+            .count();
+        assert_eq!((count("page"), count("other")), (3, 9));
+    }
+
+    /// Records when `Request`s are fetched, and sends an `Update` to the `Scheduler` once
+    /// the given number of `Request`s were fetched
+    struct Timed {
+        pages: MockFetch,
+        fetched: Mutex<Vec<(String, Instant)>>,
+        update: Option<(usize, Update)>,
+        scheduler: SchedulerSlot,
+    }
+
+    impl Timed {
+        fn new(pages: MockFetch) -> Self {
+            Self { pages, fetched: Mutex::default(), update: None, scheduler: Arc::default() }
+        }
+
+        /// The gaps between fetching the `Request`s, without the robots.txt `Request`s
+        fn gaps(&self) -> Vec<Duration> {
+            let fetched: Vec<Instant> = self.fetched.lock().unwrap().iter()
+                .filter(|(url, _)| !url.ends_with("/robots.txt"))
+                .map(|(_, instant)| *instant)
+                .collect();
+            fetched.windows(2).map(|w| w[1] - w[0]).collect()
+        }
+    }
+
+    impl Fetch for Timed {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            let mut fetched = self.fetched.lock().unwrap();
+            fetched.push((req.url.to_string(), Instant::now()));
+            if let Some((after, ref update)) = self.update {
+                if let (true, Some(scheduler)) =
+                    (fetched.len() == after, &*self.scheduler.lock().unwrap())
+                {
+                    scheduler.do_send(update.clone());
+                }
+            }
+            self.pages.fetch(req, middleware)
+        }
+    }
+
+    /// Lets the test inspect a `Timed` after it was moved into the crawl
+    impl Fetch for Arc<Timed> {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            (**self).fetch(req, middleware)
+        }
+    }
+
+    #[test]
+    fn test_update_download_delay() {
+        let mut fetcher = Timed::new(star_site());
+        let update = toml::from_str("[scheduler]\ndownload_delay = 60").unwrap();
+        fetcher.update = Some((4, Update { settings: update }));
+        let fetcher = Arc::new(fetcher);
+
+        let mut settings = test_settings();
+        settings.scheduler.download_delay = 5;
+
+        run_with_scheduler(star_spider(settings), Arc::clone(&fetcher), &fetcher.scheduler);
+
+        let gaps = fetcher.gaps();
+        assert_eq!(gaps.len(), 8);
+        // The gaps around the update are skipped, as a tick of the old delay may be in flight
+        assert!(gaps[..3].iter().all(|g| *g < Duration::from_millis(60)), "{:?}", gaps);
+        assert!(gaps[5..].iter().all(|g| *g >= Duration::from_millis(60)), "{:?}", gaps);
+    }
+
+    #[test]
+    fn test_crawl_delay() {
+        let pages = star_site()
+            .page("http://site.test/robots.txt", 200, "User-agent: *\nCrawl-delay: 0.1");
+        let fetcher = Arc::new(Timed::new(pages));
+
+        let mut settings = test_settings();
+        settings.scheduler.download_delay = 5;
+        settings.downloader.respect_crawl_delay = true;
+
+        Crawler::run_with_fetcher(star_spider(settings), Arc::clone(&fetcher)).unwrap();
+
+        let robots = fetcher.fetched.lock().unwrap().iter()
+            .filter(|(url, _)| url.ends_with("/robots.txt"))
+            .count();
+        assert_eq!(robots, 1);
+
+        let gaps = fetcher.gaps();
+        assert_eq!(gaps.len(), 8);
+        assert!(gaps.iter().all(|g| *g >= Duration::from_millis(100)), "{:?}", gaps);
+    }
+
+    #[test]
+    fn test_max_crawl_delay() {
+        let pages = star_site()
+            .page("http://site.test/robots.txt", 200, "User-agent: *\nCrawl-delay: 3600");
+        let fetcher = Arc::new(Timed::new(pages));
+
+        let mut settings = test_settings();
+        settings.scheduler.download_delay = 5;
+        settings.scheduler.max_crawl_delay = 50;
+        settings.downloader.respect_crawl_delay = true;
+
+        Crawler::run_with_fetcher(star_spider(settings), Arc::clone(&fetcher)).unwrap();
+
+        // The hour asked for is capped
+        let gaps = fetcher.gaps();
+        assert_eq!(gaps.len(), 8);
+        assert!(gaps.iter().all(|g| *g >= Duration::from_millis(50)), "{:?}", gaps);
+        assert!(gaps.iter().all(|g| *g < Duration::from_millis(1000)), "{:?}", gaps);
+    }
+
+    /// Sends a `StopSpider` to the `Scheduler` once `stop_after` `Request`s were fetched.
+    /// `Request`s to `hang` never complete.
+    struct Interrupting {
+        pages: MockFetch,
+        fetched: AtomicUsize,
+        stop_after: usize,
+        hang: Option<&'static str>,
+        scheduler: SchedulerSlot,
+    }
+
+    impl Fetch for Interrupting {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            let fetched = self.fetched.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+            if let (true, Some(scheduler)) =
+                (fetched == self.stop_after, &*self.scheduler.lock().unwrap())
+            {
+                scheduler.do_send(StopSpider { reason: CloseReason::Interrupted });
+            }
+            if self.hang == Some(req.url.as_str()) {
+                return Box::new(future::empty());
+            }
+            self.pages.fetch(req, middleware)
+        }
+    }
+
+    fn star_site() -> MockFetch {
+        let links: String = (1..=8).map(|i| format!(r#"<a href="/{}">{}</a>"#, i, i)).collect();
+        (1..=8).fold(
+            MockFetch::default().page("http://site.test/", 200, &links),
+            |pages, i| pages.page(&format!("http://site.test/{}", i), 200, ""))
+    }
+
+    fn star_spider(settings: Settings) -> Spider {
+        SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .build()
+    }
+
+    /// Answers the first `Request` to `throttled` from `limited`, e.g. with a 429 status code
+    /// and a `Retry-After` of 5 seconds
+    struct Throttling {
+        pages: MockFetch,
+        limited: MockFetch,
+        throttled: &'static str,
+        fetched: Mutex<Vec<(String, Instant)>>,
+    }
+
+    impl Fetch for Throttling {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            let mut fetched = self.fetched.lock().unwrap();
+            let first = !fetched.iter().any(|(url, _)| url == req.url.as_str());
+            fetched.push((req.url.to_string(), Instant::now()));
+            if first && req.url.as_str() == self.throttled {
+                return self.limited.fetch(req, middleware);
+            }
+            self.pages.fetch(req, middleware)
+        }
+    }
+
+    impl Fetch for Arc<Throttling> {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            (**self).fetch(req, middleware)
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let throttled = "http://site.test/3";
+        let fetcher = Arc::new(Throttling {
+            pages: star_site(),
+            limited: MockFetch::default()
+                .page_with_headers(throttled, 429, &[("Retry-After", "5")], ""),
+            throttled,
+            fetched: Mutex::default(),
+        });
+
+        let report = Crawler::run_with_fetcher(star_spider(test_settings()), Arc::clone(&fetcher))
+            .unwrap();
+        assert_eq!((report.requests_total, report.requests_retried), (10, 1));
+        assert_eq!(report.status_counts[&429], 1);
+        assert_eq!(report.status_counts[&200], 9);
+
+        // The request was deferred for the Retry-After
+        let fetched = fetcher.fetched.lock().unwrap();
+        let times: Vec<Instant> = fetched.iter()
+            .filter(|(url, _)| url == throttled)
+            .map(|(_, instant)| *instant)
+            .collect();
+        assert_eq!(times.len(), 2);
+        // The scheduler works with timestamps in whole milliseconds
+        let deferred = times[1] - times[0];
+        assert!(deferred >= Duration::from_millis(4999), "{:?}", deferred);
+        assert!(deferred < Duration::from_millis(5500), "{:?}", deferred);
+    }
+
+    #[test]
+    fn test_process_response() {
+        let fetcher = MockFetch::default().page_with_headers(
+            "http://api.test/items?callback=cb", 200, &[("Content-Type", "application/javascript")],
+            r#"/**/ cb({"items": [{"id": 1}, {"id": 2}]});"#);
+
+        let mut settings = test_settings();
+        settings.downloader.middleware_list = vec![DownloaderMiddlewareType::Jsonp];
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://api.test/items?callback=cb"])
+            .settings(settings)
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::callback(|page| {
+                    let json: Value = serde_json::from_str(&page.doc().text_contents()).ok()?;
+                    Some(json["items"].as_array()?.clone())
+                }))
+            .pipeline_element(Collect(Rc::clone(&items)))
+            .build();
+        Crawler::run_with_fetcher(spider, fetcher).unwrap();
+
+        // The parser received the unwrapped body
+        let items: Vec<Value> = items.borrow().iter().map(|item| item.data.clone()).collect();
+        assert_eq!(items, vec![json!({ "id": 1 }), json!({ "id": 2 })]);
+    }
+
+    #[test]
+    fn test_min_body_bytes() {
+        let truncated = "http://site.test/3";
+        let leaves = |body: &str| (1..=8).fold(star_site(), |pages, i| {
+            pages.page(&format!("http://site.test/{}", i), 200, body)
+        });
+        let fetcher = Throttling {
+            pages: leaves("<h1>Leaf</h1>"),
+            limited: MockFetch::default().page(truncated, 200, ""),
+            throttled: truncated,
+            fetched: Mutex::default(),
+        };
+        let mut settings = test_settings();
+        settings.downloader.min_body_bytes = 10;
+
+        let report = Crawler::run_with_fetcher(star_spider(settings.clone()), fetcher).unwrap();
+        assert_eq!((report.requests_total, report.requests_retried), (10, 1));
+
+        // A body that stays empty is parsed once the retries are spent
+        let fetcher = leaves("<h1>Leaf</h1>").page(truncated, 200, "");
+        let report = Crawler::run_with_fetcher(star_spider(settings), fetcher).unwrap();
+        assert_eq!((report.requests_total, report.requests_retried), (12, 3));
+        assert_eq!(report.status_counts[&200], 12);
+    }
+
+    #[test]
+    fn test_stop_spider() {
+        let fetcher = Interrupting {
+            pages: star_site(),
+            fetched: AtomicUsize::new(0),
+            stop_after: 3,
+            hang: None,
+            scheduler: Arc::default(),
+        };
+
+        let slot = Arc::clone(&fetcher.scheduler);
+        let report = run_with_scheduler(star_spider(test_settings()), fetcher, &slot);
+        assert_eq!(report.stop_reason, CloseReason::Interrupted);
+        assert!(report.clean);
+        assert_eq!(report.exit_code(), 0);
+        assert!(report.requests_total < 9);
+        assert_eq!(report.requests_total, report.requests_success);
+    }
+
+    #[test]
+    fn test_stop_spider_timeout() {
+        let fetcher = Interrupting {
+            pages: star_site(),
+            fetched: AtomicUsize::new(0),
+            stop_after: 2,
+            hang: Some("http://site.test/1"),
+            scheduler: Arc::default(),
+        };
+
+        let mut settings = test_settings();
+        settings.scheduler.shutdown_timeout = 100;
+
+        let slot = Arc::clone(&fetcher.scheduler);
+        let report = run_with_scheduler(star_spider(settings), fetcher, &slot);
+        assert_eq!(report.stop_reason, CloseReason::Interrupted);
+        assert!(!report.clean);
+        assert_eq!(report.exit_code(), 1);
+    }
+
+    /// Two spiders crawl the same URLs, each through its own fetcher. A shared frontier
+    /// would skip the URLs already visited by the other spider.
+    #[test]
+    fn test_run_many() {
+        let site = |name: &str| {
+            let links = r#"<a href="/1">1</a><a href="/2">2</a>"#;
+            MockFetch::default()
+                .page("http://site.test/", 200, &format!("<h1>{}</h1>{}", name, links))
+                .page("http://site.test/1", 200, &format!("<h1>{}1</h1>", name))
+                .page("http://site.test/2", 200, &format!("<h1>{}2</h1>", name))
+        };
+        let spider = |name: &str, items: &Rc<RefCell<Vec<Item>>>| {
+            SpiderBuilder::default()
+                .start_urls(vec!["http://site.test/"])
+                .settings(test_settings())
+                .name(name)
+                .crawl_rule(
+                    Condition::new(vec!["site.test"], vec![]),
+                    ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                        Some(Value::String(s.first().unwrap().clone()))
+                    }))
+                .pipeline_element(Collect(Rc::clone(items)))
+                .build()
+        };
+
+        let (items_a, items_b) = (Rc::default(), Rc::default());
+        let spiders: Vec<(Spider, Arc<dyn Fetch>)> = vec![
+            (spider("a", &items_a), Arc::new(site("a"))),
+            (spider("b", &items_b), Arc::new(site("b"))),
+        ];
+        let reports = Crawler::run_all(spiders, |_| {}).unwrap();
+
+        let titles = |items: &Rc<RefCell<Vec<Item>>>| {
+            let mut titles: Vec<String> = items.borrow().iter()
+                .map(|i| i.data["title"].as_str().unwrap().to_string())
+                .collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(titles(&items_a), vec!["a", "a1", "a2"]);
+        assert_eq!(titles(&items_b), vec!["b", "b1", "b2"]);
+
+        assert_eq!(reports.len(), 2);
+        for (report, name) in reports.iter().zip(&["a", "b"]) {
+            assert_eq!(report.spider, *name);
+            assert_eq!(report.requests_total, 3);
+            assert_eq!(report.items_scraped, 3);
+            assert!(report.clean);
+        }
+    }
+
+    #[test]
+    fn test_crawl_handle() {
+        let handle = Crawler::start_with_fetcher(|| {
+            let mut settings = test_settings();
+            settings.scheduler.download_delay = 20;
+            settings.scheduler.idle_timeout = 10_000;
+            let fetcher: Arc<dyn Fetch> = Arc::new(star_site());
+            (item_spider(settings), fetcher)
+        }).unwrap();
+
+        // Nothing is dispatched while paused
+        handle.pause();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(handle.status(), CrawlStatus {
+            queue_len: 1,
+            in_flight: 0,
+            items: 0,
+            running: true,
+        });
+
+        handle.resume();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.status().items < 9 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let status = handle.status();
+        assert_eq!(status.items, 9);
+        assert!(status.running);
+
+        let report = handle.stop();
+        assert_eq!(report.stop_reason, CloseReason::Stopped);
+        assert!(report.clean);
+        assert_eq!(report.items_scraped, 9);
+    }
+
+    #[test]
+    fn test_subscribe_stats() {
+        let handle = Crawler::start_with_fetcher(|| {
+            let mut settings = test_settings();
+            settings.scheduler.idle_timeout = 10_000;
+            let fetcher: Arc<dyn Fetch> = Arc::new(star_site());
+            (item_spider(settings), fetcher)
+        }).unwrap();
+
+        let snapshots = Arc::new(Mutex::new(Vec::new()));
+        let s = Arc::clone(&snapshots);
+        handle.subscribe_stats(Duration::from_millis(10), move |snapshot| {
+            s.lock().unwrap().push(snapshot);
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.status().items < 9 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+        handle.stop();
+
+        // Snapshots are taken while the crawl runs, and once more when it's closed
+        let snapshots = snapshots.lock().unwrap();
+        let (last, running) = snapshots.split_last().unwrap();
+        assert!(!running.is_empty());
+        assert!(running.iter().all(|s| s.running));
+        assert!(running.windows(2).all(|w| w[0].items <= w[1].items));
+        assert_eq!((last.running, last.requests, last.items), (false, 9, 9));
+        assert_eq!((last.queue_len, last.in_flight), (0, 0));
+        assert!(last.request_rate > 0.0);
+
+        let json = serde_json::to_value(last).unwrap();
+        assert_eq!(json["spider"], "spider");
+        assert_eq!(json["items"], 9);
+    }
+
+    /// Records the URLs that are fetched. Unlike `Timed`, it can be shared with the test
+    /// when the crawl runs on another thread.
+    struct Recorded {
+        pages: MockFetch,
+        fetched: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Fetch for Recorded {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            self.fetched.lock().unwrap().push(req.url.to_string());
+            self.pages.fetch(req, middleware)
+        }
+    }
+
+    /// Blocks the thread it fetches on for a while, and records which one that is
+    struct Blocking {
+        pages: MockFetch,
+        threads: Arc<Mutex<Vec<thread::ThreadId>>>,
+    }
+
+    impl Fetch for Blocking {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            self.threads.lock().unwrap().push(thread::current().id());
+            thread::sleep(Duration::from_millis(30));
+            self.pages.fetch(req, middleware)
+        }
+    }
+
+    #[test]
+    fn test_downloader_workers() {
+        let mut settings = test_settings();
+        settings.downloader.workers = 3;
+
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let fetcher = Blocking { pages: star_site(), threads: Arc::clone(&threads) };
+        let started = Instant::now();
+        let report = Crawler::run_with_fetcher(star_spider(settings), fetcher).unwrap();
+        assert_eq!((report.requests_total, report.requests_success), (9, 9));
+
+        // The actors run on this thread, the `Requests` are fetched on the workers in turn
+        let mut threads = threads.lock().unwrap().clone();
+        assert!(!threads.contains(&thread::current().id()));
+        threads.sort_by_key(|id| format!("{:?}", id));
+        threads.dedup();
+        assert_eq!(threads.len(), 3);
+        // The 9 `Requests` take 270ms to fetch one after the other
+        assert!(started.elapsed() < Duration::from_millis(250), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_submit_urls() {
+        let fetched = Arc::new(Mutex::new(Vec::new()));
+        let f = Arc::clone(&fetched);
+        let handle = Crawler::start_with_fetcher(move || {
+            let mut settings = test_settings();
+            settings.scheduler.idle_timeout = 10_000;
+            // Links are not followed, only the submitted URLs are crawled
+            let spider = SpiderBuilder::default()
+                .start_urls(vec!["http://site.test/"])
+                .settings(settings)
+                .crawl_rule(Condition::new(vec![], vec![".*"]), ParseRule::FilterUrls)
+                .build();
+            let fetcher: Arc<dyn Fetch> = Arc::new(Recorded { pages: star_site(), fetched: f });
+            (spider, fetcher)
+        }).unwrap();
+
+        let wait_for = |n: usize| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while fetched.lock().unwrap().len() < n && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        let urls = |urls: &[&str]| urls.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+
+        wait_for(1);
+        handle.submit_urls(urls(&["http://site.test/1", "http://site.test/2"])).unwrap();
+        wait_for(3);
+        handle.submit_urls(urls(&["http://site.test/", "http://site.test/2"])).unwrap();
+        assert!(handle.submit_urls(urls(&["http://site.test/3", "not a url"])).is_err());
+        thread::sleep(Duration::from_millis(100));
+
+        let report = handle.stop();
+        assert_eq!(report.requests_total, 3);
+        let mut fetched = fetched.lock().unwrap().clone();
+        fetched.sort();
+        let expected = urls(&["http://site.test/", "http://site.test/1", "http://site.test/2"]);
+        assert_eq!(fetched, expected);
+    }
+
+    /// Each run gets a fresh system, so the second run crawls the URLs of the first again
+    #[test]
+    fn test_run_sequentially() {
+        for _ in 0..2 {
+            let report = Crawler::run_with_fetcher(star_spider(test_settings()), star_site());
+            assert_eq!(report.unwrap().requests_total, 9);
+        }
+    }
+
+    /// The crawl runs in a system owned by the test, which outlives the spider
+    #[test]
+    fn test_spawn() {
+        let sys = System::new("app");
+
+        let report = Rc::new(RefCell::new(None));
+        let r = Rc::clone(&report);
+        let actors = Crawler::start_spider(star_spider(test_settings()), Arc::new(star_site()),
+                                           Box::new(move |report| { r.replace(Some(report)); }));
+
+        // The system is kept running after the report, to see the actors stop
+        let connected = Rc::new(Cell::new(true));
+        let (c, scheduler) = (Rc::clone(&connected), actors.scheduler.clone());
+        Arbiter::spawn(Interval::new_interval(Duration::from_millis(100))
+            .skip_while(move |_| Ok(scheduler.connected()))
+            .take(2)
+            .for_each(|_| Ok(()))
+            .map(move |_| {
+                c.set(actors.stats.connected() || actors.downloader.connected()
+                    || actors.parser.connected() || actors.pipeline.connected());
+                System::current().stop();
+            })
+            .map_err(|e| error!("Timer error: {:?}", e)));
+
+        sys.run();
+
+        let report = report.borrow_mut().take().unwrap();
+        assert_eq!(report.requests_total, 9);
+        assert!(report.clean);
+        assert!(!connected.get());
+    }
+
+    /// A local server that answers every request after `delay`. Page `/n` links to
+    /// `/n+1` and `/n+2`, so there is always something left to crawl.
+    fn slow_server(delay: Duration) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut buf = [0; 4096];
+                    let n = stream.read(&mut buf).unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let page: u32 = request.split(' ').nth(1)
+                        .and_then(|path| path.trim_start_matches('/').parse().ok())
+                        .unwrap_or(0);
+                    let body = format!(r#"<a href="/{}">a</a><a href="/{}">b</a>"#,
+                                       page + 1, page + 2);
+                    thread::sleep(delay);
+                    let _ = write!(stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(), body);
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_event_log() {
+        let path = std::env::temp_dir().join(format!("vortex-events-{}.ndjson", process::id()));
+        let addr = slow_server(Duration::from_millis(0));
+        let mut settings = test_settings();
+        settings.scheduler.max_depth = 1;
+        settings.pipeline.element_list = vec![PipelineElementType::Timestamping];
+        settings.stats.event_log_path = path.to_str().unwrap().to_string();
+        let start_url = format!("http://{}/0", addr);
+        let spider = SpiderBuilder::default()
+            .start_urls(vec![start_url.as_str()])
+            .settings(settings)
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::callback(|_| Some(vec![json!({ "title": "page" })])))
+            .build();
+        let report = Crawler::run_with_fetcher(spider, HttpFetch::default()).unwrap();
+        assert_eq!(report.requests_total, 3);
+
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let events: Vec<Value> = log.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|event: &Value| event["url"] == start_url.as_str())
+            .collect();
+
+        // Every event of the start URL carries its id
+        assert!(events.iter().all(|event| event["id"] == 1), "{:?}", events);
+        let names: Vec<&str> = events.iter().map(|e| e["event"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["enqueued", "dispatched", "downloaded", "parsed", "piped"]);
+        assert_eq!(events[0]["depth"], 0);
+        assert_eq!(events[2]["status"], 200);
+        assert!(events[2]["ms"].is_u64());
+        assert_eq!((&events[3]["rules_matched"], &events[3]["links_found"], &events[3]["items"]),
+                   (&json!([0]), &json!(2), &json!(1)));
+        assert_eq!((&events[4]["elements"], &events[4]["dropped_by"]),
+                   (&json!(["Timestamping"]), &Value::Null));
+        assert!(events.iter().all(|event| event["at"].is_string()));
+
+        // The links of the start URL are queued with ids of their own
+        let enqueued = log.lines().filter(|line| line.contains(r#""event":"enqueued""#)).count();
+        assert_eq!(enqueued, 3);
+    }
+
+    #[test]
+    fn test_link_graph() {
+        let path = std::env::temp_dir().join(format!("vortex-graph-{}.ndjson", process::id()));
+        let mut settings = test_settings();
+        settings.parser.link_graph.path = path.to_str().unwrap().to_string();
+        let fetcher = MockFetch::default()
+            .page("http://site.test/", 200,
+                  r##"<a href="/a">Page <b>A</b></a> <a href="/b" rel="nofollow">B</a>
+                      <a href="/a">A again</a> <a href="/logo.png">Logo</a>
+                      <a href="#top">Top</a>"##)
+            .page("http://site.test/a", 200,
+                  r#"<a href="/b">  Page
+                         B </a> <a href="http://site.test/">Home</a>"#)
+            .page("http://site.test/b", 200,
+                  r#"<a href="/a">A</a> <a href="http://other.test/x">Elsewhere</a>
+                     <a href="http://exa mple.com/">Broken</a>"#);
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .crawl_rule(Condition::hosts(vec!["site.test"]), ParseRule::FilterUrls)
+            .build();
+        let report = Crawler::run_with_fetcher(spider, fetcher).unwrap();
+        assert_eq!(report.requests_total, 3);
+
+        // Flushed once the spider is closed
+        let graph = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let edges: HashSet<(String, String, String, bool)> = graph.lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .map(|edge| {
+                let field = |name: &str| edge[name].as_str().unwrap().to_string();
+                (field("source"), field("target"), field("text"), edge["nofollow"] == true)
+            })
+            .collect();
+        let edge = |source: &str, target: &str, text: &str, nofollow| {
+            (format!("http://site.test/{}", source), format!("http://site.test/{}", target),
+             text.to_string(), nofollow)
+        };
+        assert_eq!(graph.lines().count(), 5);
+        assert_eq!(edges, vec![
+            edge("", "a", "Page A", false),
+            edge("", "b", "B", true),
+            edge("a", "b", "Page B", false),
+            edge("a", "", "Home", false),
+            edge("b", "a", "A", false),
+        ].into_iter().collect());
+    }
+
+    #[test]
+    fn test_max_duration() {
+        let addr = slow_server(Duration::from_millis(300));
+        let mut settings = test_settings();
+        settings.scheduler.shutdown_timeout = 1000;
+        let spider = SpiderBuilder::default()
+            .start_urls(vec![format!("http://{}/0", addr).as_str()])
+            .settings(settings)
+            .max_duration(Duration::from_secs(1))
+            .build();
+
+        let started = Instant::now();
+        let report = Crawler::run_with_fetcher(spider, HttpFetch::default()).unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(report.stop_reason, CloseReason::TimeLimit);
+        assert!(report.clean);
+        assert!(report.requests_total > 1);
+        // The limit plus at most the drain timeout, with some slack for the setup
+        assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(2500), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_hooks() {
+        let started = Rc::new(Cell::new(0));
+        let idle = Rc::new(Cell::new(0));
+        let closed = Rc::new(Cell::new(0));
+        let (st, id, cl) = (Rc::clone(&started), Rc::clone(&idle), Rc::clone(&closed));
+
+        // Links are not followed, the pages returned by `on_idle` are crawled instead
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(test_settings())
+            .crawl_rule(Condition::new(vec![], vec![".*"]), ParseRule::FilterUrls)
+            .on_start(move || st.set(st.get() + 1))
+            .on_idle(move || {
+                id.set(id.get() + 1);
+                if id.get() == 1 {
+                    let urls = vec!["http://site.test/1", "http://site.test/2"];
+                    Some(RequestVec::from_strs(urls, 0, 1))
+                } else {
+                    None
+                }
+            })
+            .on_close(move |report| cl.set(report.requests_total))
+            .build();
+
+        let report = Crawler::run_with_fetcher(spider, star_site()).unwrap();
+        assert_eq!(report.requests_total, 3);
+        assert_eq!(started.get(), 1);
+        assert_eq!(idle.get(), 2);
+        assert_eq!(closed.get(), 3);
+    }
+
+    #[test]
+    fn test_no_start_urls() {
+        let spider = SpiderBuilder::default().settings(test_settings()).build();
+        match Crawler::run_with_fetcher(spider, MockFetch::default()) {
+            Err(CrawlError::NoStartUrls) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_start_urls_from_iter() {
+        // Read lazily, with a duplicate and an invalid line
+        let urls = (1..=8).map(|i| format!("http://site.test/{}", i))
+            .chain(vec!["http://site.test/1".to_string(), "not a url".to_string()]);
+        let spider = SpiderBuilder::default()
+            .start_urls_from_iter(urls)
+            .settings(test_settings())
+            .build();
+        assert!(spider.has_start_urls());
+        let report = Crawler::run_with_fetcher(spider, star_site()).unwrap();
+        assert_eq!(report.requests_total, 8);
+    }
+
+    #[test]
+    fn test_run_unknown_spider() {
+        match Crawler::run_by_name(&SpiderRegistry::default(), "news") {
+            Err(CrawlError::UnknownSpider(name)) => assert_eq!(name, "news"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_seen_store() {
+        // The URLs another crawler has seen aren't crawled
+        let mut store = MemorySeenStore::default();
+        store.insert(Url::parse("http://site.test/3").unwrap());
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(test_settings())
+            .seen_store(store)
+            .build();
+        let report = Crawler::run_with_fetcher(spider, star_site()).unwrap();
+        assert_eq!(report.requests_total, 8);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let html = r#"
+            <html><body>
+                <h1 class="firstHeading">Rust</h1>
+                <a href="/wiki/Ferris">Ferris</a>
+                <a href="/wiki/Special:Random">Random</a>
+                <a href="https://www.rust-lang.org">Rust</a>
+            </body></html>"#;
+
+        let cnd = Condition::new(vec![r"en.wikipedia.org/wiki"], vec![r":[A-Za-z]"]);
+        let spider = SpiderBuilder::default()
+            .crawl_rule(cnd.clone(), ParseRule::FilterUrls)
+            .crawl_rule(
+                cnd.clone(),
+                ParseRule::pattern("title", Pattern::CssSelector(".firstHeading"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .build();
+
+        let (urls, items) = Crawler::dry_run(&spider, html, "http://en.wikipedia.org/wiki/Rust");
+
+        let urls: Vec<&str> = urls.iter().map(|u| u.as_str()).collect();
+        assert_eq!(urls, vec!["http://en.wikipedia.org/wiki/Ferris"]);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].data, json!({ "title": "Rust" }));
+    }
+
+    /// A local server that serves `files`, a list of paths and bodies, and answers 404 for
+    /// other paths. `files` is given the address of the server.
+    fn file_server<F>(files: F) -> SocketAddr
+        where F: FnOnce(SocketAddr) -> Vec<(&'static str, Vec<u8>)>
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let files: HashMap<&str, Vec<u8>> = files(addr).into_iter().collect();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = match files.get(path) {
+                    Some(body) => ("200 OK", body.as_slice()),
+                    None => ("404 Not Found", &b""[..]),
+                };
+                let _ = write!(stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status, body.len());
+                let _ = stream.write_all(body);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_refuse_private_addresses() {
+        let addr = file_server(|addr| vec![
+            ("/", format!(r#"<h1>Home</h1><a href="/a">A</a>
+                             <a href="http://{0}/admin">Admin</a>
+                             <a href="http://[::1]:{1}/admin">Admin</a>
+                             <a href="http://169.254.169.254/latest/meta-data/">Metadata</a>"#,
+                          addr, addr.port()).into_bytes()),
+            ("/a", b"<h1>A</h1>".to_vec()),
+            ("/admin", b"<h1>Admin</h1>".to_vec()),
+        ]);
+
+        // site.test is routed to the server by `resolve`, the links to its address aren't
+        // followed
+        let mut settings = test_settings();
+        settings.downloader.allow_private_addresses = false;
+        settings.downloader.resolve = vec![("site.test".to_string(), addr.to_string())];
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .build();
+        let report = Crawler::run(spider).unwrap();
+        assert_eq!(report.stop_reason, CloseReason::Finished);
+        assert_eq!((report.requests_total, report.requests_success), (5, 2));
+        assert_eq!((report.requests_refused, report.requests_failed), (3, 0));
+    }
+
+    /// A local server whose pages link to ever-deeper paths and ever-longer queries, like
+    /// calendars and faceted search
+    fn trap_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let (path, query) = match target.find('?') {
+                    Some(i) => (&target[..i], &target[i + 1..]),
+                    None => (target, ""),
+                };
+                let params = query.split('&').filter(|p| !p.is_empty()).count();
+                let body = format!(r#"<a href="{0}day/">Next day</a>
+                                      <a href="{0}?{1}&f{2}=1">Filter</a>"#,
+                                   path.trim_end_matches('/').to_string() + "/", query, params);
+                let _ = write!(stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_trap_links() {
+        let addr = trap_server();
+        let spider = |settings: Settings| SpiderBuilder::default()
+            .start_urls(vec![format!("http://{}/cal/", addr).as_str()])
+            .settings(settings)
+            .build();
+
+        // The crawl ends: 3 nested days of /cal/ (and /cal/ itself), each with up to 2
+        // filters
+        let mut settings = test_settings();
+        settings.parser.max_query_params = 2;
+        let report = Crawler::run(spider(settings)).unwrap();
+        assert_eq!(report.stop_reason, CloseReason::Finished);
+        assert_eq!(report.requests_total, 4 * 3);
+
+        // Without the guards, only the request budget ends it
+        let mut settings = test_settings();
+        settings.scheduler.max_requests = 30;
+        settings.parser.max_url_length = 0;
+        settings.parser.max_query_params = 0;
+        settings.parser.max_repeated_segments = 0;
+        let report = Crawler::run(spider(settings)).unwrap();
+        assert_eq!(report.stop_reason, CloseReason::RequestLimit);
+    }
+
+    /// Fails the https `Requests` to `hosts`, as if they had no https
+    struct NoHttps {
+        pages: MockFetch,
+        hosts: Vec<&'static str>,
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl Fetch for Arc<NoHttps> {
+        fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+            self.fetched.lock().unwrap().push(req.url.to_string());
+            let host = req.url.host_str().unwrap_or_default();
+            if req.url.scheme() == "https" && self.hosts.contains(&host) {
+                return Box::new(future::err(FetchError::Timeout));
+            }
+            self.pages.fetch(req, middleware)
+        }
+    }
+
+    #[test]
+    fn test_url_policy() {
+        let home = r#"<a href="http://a.test/1">1</a><a href="https://a.test/1">1</a>
+                      <a href="http://b.test/1">1</a><a href="http://b.test/2">2</a>
+                      <a href="http://a.test:8080/x">X</a><a href="https://a.test:8443/y">Y</a>"#;
+        let fetcher = Arc::new(NoHttps {
+            pages: MockFetch::default()
+                .page("http://a.test/", 200, home)
+                .page("https://a.test/1", 200, "<h1>1</h1>")
+                .page("http://b.test/1", 200, "<h1>1</h1>")
+                .page("http://b.test/2", 200, "<h1>2</h1>"),
+            hosts: vec!["b.test"],
+            fetched: Mutex::default(),
+        });
+        let mut settings = test_settings();
+        settings.parser.url_policy.upgrade_http = true;
+        settings.parser.url_policy.allowed_ports = vec![80, 443];
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://a.test/"])
+            .settings(settings)
+            .build();
+
+        // Both links to a.test/1 are fetched once over https, the b.test pages over http once
+        // their https probe fails, and the links to other ports aren't followed
+        Crawler::run_with_fetcher(spider, Arc::clone(&fetcher)).unwrap();
+        let mut fetched = fetcher.fetched.lock().unwrap().clone();
+        fetched.sort();
+        fetched.dedup();
+        assert_eq!(fetched, vec!["http://a.test/", "http://b.test/1", "http://b.test/2",
+                                 "https://a.test/1", "https://b.test/1", "https://b.test/2"]);
+        let fetched = fetcher.fetched.lock().unwrap();
+        assert_eq!(fetched.iter().filter(|url| *url == "https://a.test/1").count(), 1);
+    }
+
+    #[test]
+    fn test_start_from_sitemaps() {
+        let addr = file_server(|addr| {
+            let mut pages = GzEncoder::new(Vec::new(), Compression::default());
+            write!(pages, r#"<?xml version="1.0" encoding="UTF-8"?>
+                <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                  <url><loc>http://{0}/a</loc><lastmod>2019-03-01</lastmod></url>
+                  <url><loc>http://{0}/b</loc></url>
+                  <url><loc>http://{0}/private/c</loc></url>
+                </urlset>"#, addr).unwrap();
+            vec![
+                ("/robots.txt", format!("User-agent: *\nDisallow:\n\n\
+                                         Sitemap: http://{0}/index.xml\n\
+                                         Sitemap: http://{0}/news.xml\n", addr).into_bytes()),
+                ("/index.xml", format!(r#"
+                    <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                      <sitemap><loc>http://{0}/pages.xml.gz</loc></sitemap>
+                      <sitemap><loc>http://{0}/news.xml</loc></sitemap>
+                      <sitemap><loc>http://{0}/missing.xml</loc></sitemap>
+                    </sitemapindex>"#, addr).into_bytes()),
+                ("/pages.xml.gz", pages.finish().unwrap()),
+                ("/news.xml", format!(r#"
+                    <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                      <url><loc>http://{}/d</loc><lastmod>2019-03-02</lastmod></url>
+                    </urlset>"#, addr).into_bytes()),
+                ("/a", b"<h1>A</h1>".to_vec()),
+                ("/b", b"<h1>B</h1>".to_vec()),
+                ("/d", b"<h1>D</h1>".to_vec()),
+            ]
+        });
+
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let spider = SpiderBuilder::default()
+            .start_from_sitemaps(vec![format!("http://{}", addr).as_str()])
+            .settings(test_settings())
+            .crawl_rule(Condition::any_except(vec!["/private/"]), ParseRule::FilterUrls)
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                    Some(Value::String(s[0].clone()))
+                }))
+            .pipeline_element(Collect(Rc::clone(&items)))
+            .build();
+        let report = Crawler::run_with_fetcher(spider, HttpFetch::default()).unwrap();
+
+        // The sitemap listed twice is fetched once, the denied page isn't crawled
+        assert_eq!(report.requests_total, 3);
+        let mut items: Vec<(String, u32, Value)> = items.borrow().iter()
+            .map(|item| {
+                let lastmod = item.request.meta.get("lastmod").cloned().unwrap_or(Value::Null);
+                (item.data["title"].as_str().unwrap().to_string(), item.request.depth, lastmod)
+            })
+            .collect();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(items, vec![
+            ("A".to_string(), 0, json!("2019-03-01")),
+            ("B".to_string(), 0, Value::Null),
+            ("D".to_string(), 0, json!("2019-03-02")),
+        ]);
+    }
+
+    #[test]
+    fn test_follow_rel_next() {
+        let page = |page: u32, next: &str| {
+            let next = if next.is_empty() {
+                String::new()
+            } else {
+                format!(r#"<link rel="next" href="{}">"#, next)
+            };
+            format!(r#"<html><head>{}</head><body><h1>{}</h1></body></html>"#, next, page)
+        };
+        let fetcher = MockFetch::default()
+            .page("http://site.test/?page=1", 200, &page(1, "?page=2"))
+            .page("http://site.test/?page=2", 200, &page(2, "/?page=3"))
+            .page("http://site.test/?page=3", 200, &page(3, ""));
+
+        let mut settings = test_settings();
+        settings.parser.follow_rel_next = true;
+        let pagination = Pagination::from_settings(settings.pipeline.element.pagination.clone());
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/?page=1"])
+            .settings(settings)
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::pattern("page", Pattern::CssSelector("h1"), |s| {
+                    Some(Value::String(s[0].clone()))
+                }))
+            .pipeline_element(pagination)
+            .pipeline_element(Collect(Rc::clone(&items)))
+            .build();
+        let report = Crawler::run_with_fetcher(spider, fetcher).unwrap();
+
+        assert_eq!(report.requests_total, 3);
+        let items = items.borrow();
+        assert!(items.iter().all(|item| item.request.depth == 0));
+        let items: Vec<Value> = items.iter().map(|item| item.data.clone()).collect();
+        assert_eq!(items, vec![
+            json!({ "page": "1", "_page_next": "http://site.test/?page=2", "_page_prev": null }),
+            json!({ "page": "2", "_page_next": "http://site.test/?page=3", "_page_prev": null }),
+            json!({ "page": "3", "_page_next": null, "_page_prev": null }),
+        ]);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let home = r#"<a href="/a">A</a><a href="/a#section">A</a><a href="//site.test//a">A</a>
+                      <a href="HTTP://SITE.test:80/%61">A</a><a href="/a/">A</a>
+                      <a href="/a?utm_source=feed&fbclid=1">A</a>"#;
+        let fetcher = || MockFetch::default()
+            .page("http://site.test/", 200, home)
+            .page("http://site.test/a", 200, "<h1>A</h1>")
+            .page("http://site.test/a/", 200, "<h1>A</h1>");
+
+        let report = Crawler::run_with_fetcher(star_spider(test_settings()), fetcher()).unwrap();
+        assert_eq!(report.requests_total, 3);
+
+        let mut settings = test_settings();
+        settings.parser.canonicalize.strip_trailing_slash = true;
+        let report = Crawler::run_with_fetcher(star_spider(settings), fetcher()).unwrap();
+        assert_eq!(report.requests_total, 2);
+    }
+
+    #[test]
+    fn test_param_value_rules() {
+        let home = r#"<a href="/a?page=2&sid=3f2a">A</a><a href="/a?page=2&sid=9bc1">A</a>"#;
+        let fetcher = || Arc::new(Throttling {
+            pages: MockFetch::default()
+                .page("http://site.test/", 200, home)
+                .page("http://site.test/a?page=2&sid=3f2a", 200, "<h1>A</h1>")
+                .page("http://site.test/a?page=2&sid=9bc1", 200, "<h1>A</h1>"),
+            limited: MockFetch::default(),
+            throttled: "",
+            fetched: Mutex::default(),
+        });
+        let fetched = |fetcher: &Throttling| -> Vec<String> {
+            fetcher.fetched.lock().unwrap().iter().map(|(url, _)| url.clone()).collect()
+        };
+
+        let fetcher_1 = fetcher();
+        let report = Crawler::run_with_fetcher(star_spider(test_settings()), Arc::clone(&fetcher_1))
+            .unwrap();
+        assert_eq!(report.requests_total, 3);
+
+        // The session ids are ignored, and the URL found first is fetched as it is
+        let mut settings = test_settings();
+        settings.parser.canonicalize.param_value_rules =
+            vec![("sid".to_string(), "[0-9a-f]+".to_string(), String::new())];
+        let fetcher_2 = fetcher();
+        let report = Crawler::run_with_fetcher(star_spider(settings), Arc::clone(&fetcher_2))
+            .unwrap();
+        assert_eq!(report.requests_total, 2);
+        assert_eq!(fetched(&fetcher_2),
+                   vec!["http://site.test/", "http://site.test/a?page=2&sid=3f2a"]);
+        assert_eq!(fetched(&fetcher_1).len(), 3);
+    }
+
+    #[test]
+    fn test_revisit() {
+        // A chain of pages that each link to /news, which is found again as the crawl goes on
+        let pages = (1..=6).fold(
+            MockFetch::default()
+                .page("http://site.test/", 200, r#"<a href="/news">News</a><a href="/1">1</a>"#)
+                .page("http://site.test/news", 200, "<h1>Headline</h1>"),
+            |pages, i| {
+                let body = format!(r#"<a href="/news">News</a><a href="/{}">Next</a>"#, i + 1);
+                pages.page(&format!("http://site.test/{}", i), 200, &body)
+            });
+        let fetcher = Arc::new(Throttling {
+            pages,
+            limited: MockFetch::default(),
+            throttled: "",
+            fetched: Mutex::default(),
+        });
+        let mut settings = test_settings();
+        settings.scheduler.download_delay = 20;
+        let headline = |matches: Vec<String>| Some(json!(matches[0]));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .settings(settings)
+            .crawl_rule_with_revisit(Condition::path_prefixes(vec!["/news"]),
+                                     ParseRule::pattern("headline", Pattern::CssSelector("h1"),
+                                                        headline),
+                                     Duration::from_millis(50))
+            .build();
+
+        Crawler::run_with_fetcher(spider, Arc::clone(&fetcher)).unwrap();
+        let fetched: Vec<String> = fetcher.fetched.lock().unwrap().iter()
+            .map(|(url, _)| url.clone())
+            .collect();
+        let count = |url: &str| fetched.iter().filter(|fetched| *fetched == url).count();
+        assert!(count("http://site.test/news") >= 2, "{:?}", fetched);
+        assert_eq!(count("http://site.test/"), 1);
+        assert!((1..=7).all(|i| count(&format!("http://site.test/{}", i)) == 1), "{:?}", fetched);
+    }
+
+    #[test]
+    fn test_skip_amp() {
+        let home = r#"<h1>News</h1><a href="/news/a">A</a>"#;
+        let a = r#"<html><head><link rel="amphtml" href="/amp-news/a"></head>
+                   <body><h1>A</h1><a href="/news/b">B</a></body></html>"#;
+        let b = r#"<h1>B</h1><a href="/amp-news/a">A (AMP)</a><a href="/news/b/amp">B (AMP)</a>"#;
+        let fetcher = || MockFetch::default()
+            .page("http://site.test/", 200, home)
+            .page("http://site.test/news/a", 200, a)
+            .page("http://site.test/news/b", 200, b)
+            .page("http://site.test/amp-news/a", 200, "<h1>A</h1>")
+            .page("http://site.test/news/b/amp", 200, "<h1>B</h1>");
+
+        // The AMP version of /news/a is marked as visited once /news/a is fetched, before
+        // /news/b links to it
+        let report = Crawler::run_with_fetcher(star_spider(test_settings()), fetcher()).unwrap();
+        assert_eq!(report.requests_total, 3);
+
+        let mut settings = test_settings();
+        settings.parser.skip_amp = false;
+        settings.parser.skip_amp_urls = false;
+        let report = Crawler::run_with_fetcher(star_spider(settings), fetcher()).unwrap();
+        assert_eq!(report.requests_total, 5);
+    }
+
+    #[test]
+    fn test_prefer_canonical() {
+        let addr = file_server(|_| vec![
+            ("/a?ref=home", br#"<link rel="canonical" href="/a"><h1>A</h1>
+                               <a href="/a">A</a><a href="/b">B</a>"#.to_vec()),
+            ("/a", b"<h1>A again</h1>".to_vec()),
+            ("/b", br#"<h1>B</h1><a href="/a">A</a><a href="/a?ref=home">A</a>"#.to_vec()),
+        ]);
+
+        let mut settings = test_settings();
+        settings.parser.prefer_canonical = true;
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let spider = SpiderBuilder::default()
+            .start_urls(vec![format!("http://{}/a?ref=home", addr).as_str()])
+            .settings(settings)
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                    Some(Value::String(s[0].clone()))
+                }))
+            .pipeline_element(Collect(Rc::clone(&items)))
+            .build();
+        let report = Crawler::run_with_fetcher(spider, HttpFetch::default()).unwrap();
+
+        // The links to the canonical URL of the start page aren't followed
+        assert_eq!(report.requests_total, 2);
+        let items: Vec<Value> = items.borrow().iter().map(|item| item.data.clone()).collect();
+        assert_eq!(items, vec![
+            json!({ "title": "A", "_canonical": format!("http://{}/a", addr) }),
+            json!({ "title": "B" }),
+        ]);
+    }
 }
@@ -0,0 +1,92 @@
+//! Private addresses
+//!
+//! Unless `DownloaderSettings::allow_private_addresses` is set, `HttpFetch` resolves the host
+//! of a `Request` before fetching it, and refuses it if one of its addresses is private. A
+//! page could otherwise point the crawler at the services of the network it runs in, e.g.
+//! `http://169.254.169.254/` or `http://10.0.0.5:8500/`. Redirects are checked the same way.
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use futures::{future, Future};
+use hyper::client::connect::dns::{GaiResolver, Name, Resolve};
+use reqwest::Url;
+
+/// Whether `ip` is a loopback, link-local, private (RFC 1918), unique local or unspecified
+/// address. IPv4-mapped IPv6 addresses are checked as IPv4 ones.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_v4(ip),
+            None => is_private_v6(ip),
+        },
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    ip.is_loopback() || ip.is_link_local() || ip.is_private() || ip.octets()[0] == 0
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    ip.is_loopback() || ip.is_unspecified() || ip.is_unicast_link_local() || ip.is_unique_local()
+}
+
+/// The addresses the host of `url` resolves to. Domains are looked up on the threads of
+/// `resolver`, so that the event loop isn't blocked, IP addresses are returned as is.
+pub fn lookup(resolver: &GaiResolver, url: &Url)
+    -> Box<dyn Future<Item=Vec<IpAddr>, Error=io::Error>>
+{
+    // IPv6 hosts are in brackets, e.g. `[::1]`
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Box::new(future::ok(Vec::new())),
+    };
+    if let Ok(ip) = host.parse() {
+        return Box::new(future::ok(vec![ip]));
+    }
+    match host.parse::<Name>() {
+        Ok(name) => Box::new(resolver.resolve(name).map(|addrs| addrs.collect())),
+        Err(e) => Box::new(future::err(io::Error::new(io::ErrorKind::InvalidInput, e))),
+    }
+}
+
+/// The first private address of `addrs`, if any. A host with both public and private
+/// addresses is refused, its DNS records point at the network the crawler runs in.
+pub fn private_address(addrs: &[IpAddr]) -> Option<IpAddr> {
+    addrs.iter().copied().find(|ip| is_private(*ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_private() {
+        let private = |ip: &str| is_private(ip.parse().unwrap());
+        for ip in ["127.0.0.1", "10.0.0.5", "172.16.0.1", "172.31.255.255", "192.168.1.1",
+                   "169.254.169.254", "0.0.0.0", "::1", "::", "fe80::1", "fc00::1", "fd12::1",
+                   "::ffff:10.0.0.1", "::ffff:127.0.0.1"] {
+            assert!(private(ip), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "172.32.0.1", "192.169.0.1", "100.64.0.1", "2001:db8::1",
+                   "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(!private(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_private_address() {
+        let resolver = GaiResolver::new(1);
+        let address = |url: &str| {
+            let addrs = lookup(&resolver, &Url::parse(url).unwrap()).wait().unwrap_or_default();
+            private_address(&addrs)
+        };
+        assert_eq!(address("http://169.254.169.254/latest/"),
+                   Some("169.254.169.254".parse().unwrap()));
+        assert_eq!(address("http://10.0.0.5:8500/v1/kv"), Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(address("http://[::1]:8080/"), Some("::1".parse().unwrap()));
+        assert_eq!(address("http://localhost/").map(|ip| ip.is_loopback()), Some(true));
+        assert_eq!(address("https://93.184.216.34/"), None);
+        assert_eq!(address("http://unresolvable.invalid/"), None);
+    }
+}
@@ -0,0 +1,576 @@
+//! Fetchers
+//!
+//! A fetcher performs the actual retrieval of a network resource for the `Downloader`.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::MailboxError;
+use flate2::read::GzDecoder;
+use futures::{future::{self, Either, Loop}, Future, Stream};
+use hyper::client::connect::dns::GaiResolver;
+use reqwest::{header::{AUTHORIZATION, COOKIE, HeaderMap, HeaderName, HeaderValue, HOST, LOCATION,
+                       PROXY_AUTHORIZATION},
+              r#async::{Client, ClientBuilder, Request as AsyncRequest, Response as AsyncResponse},
+              RedirectPolicy, StatusCode, Url};
+use tokio_timer::Timeout;
+
+use crate::crawler::{Request, Response};
+use crate::downloader::address;
+use crate::downloader::middleware::DownloaderMiddleware;
+use crate::settings::DownloaderSettings;
+
+/// Number of threads the hosts are looked up on, when private addresses are refused
+const DNS_THREADS: usize = 4;
+
+/// Number of redirects followed, when private addresses are refused
+const MAX_REDIRECTS: usize = 10;
+
+/// The future returned by a `Fetch`
+pub type FetchFuture = Box<dyn Future<Item=Response, Error=FetchError>>;
+
+/// The reasons a `Fetch` can fail
+#[derive(Debug)]
+pub enum FetchError {
+    /// The request could not be completed
+    Request(reqwest::Error),
+
+    /// The response body could not be read
+    Body(io::Error),
+
+    /// The `Request` was not completed within `DownloaderSettings::timeout`
+    Timeout,
+
+    /// The body of the `Response` was not received within
+    /// `DownloaderSettings::body_read_timeout_ms`
+    BodyTimeout,
+
+    /// The thread the `Request` was fetched on stopped before it was completed
+    Worker(MailboxError),
+
+    /// The host of the `Request` resolves to a private address, see
+    /// `DownloaderSettings::allow_private_addresses`
+    PrivateAddress(IpAddr),
+
+    /// The `Request` was redirected more than 10 times, while private addresses are refused
+    TooManyRedirects,
+}
+
+/// What a failed `Request` failed on, counted in `RequestErrors`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCategory {
+    /// The connection could not be made, e.g. the host doesn't resolve or refuses it
+    Connect,
+
+    /// The `Request` or the body of its `Response` took too long
+    Timeout,
+
+    /// The TLS handshake failed, e.g. the certificate isn't valid for the host
+    Tls,
+
+    /// The `Response` could not be read, e.g. its body isn't valid UTF-8 or gzip
+    Decode,
+
+    /// The server answered with a status the `Request` fails on, e.g. too many redirects
+    HttpStatus,
+
+    /// Any other error
+    Other,
+}
+
+impl FetchError {
+    /// The category the error is counted in. A `PrivateAddress` isn't counted, nothing was
+    /// sent.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            FetchError::Request(e) => FetchError::request_category(e),
+            FetchError::Body(_) => ErrorCategory::Decode,
+            FetchError::Timeout | FetchError::BodyTimeout => ErrorCategory::Timeout,
+            FetchError::TooManyRedirects => ErrorCategory::HttpStatus,
+            FetchError::Worker(_) | FetchError::PrivateAddress(_) => ErrorCategory::Other,
+        }
+    }
+
+    fn request_category(e: &reqwest::Error) -> ErrorCategory {
+        if e.is_timeout() {
+            return ErrorCategory::Timeout;
+        }
+        if e.status().is_some() || e.is_redirect() {
+            return ErrorCategory::HttpStatus;
+        }
+
+        // The TLS errors are wrapped in the `io::Error` of the connection
+        let mut source = e.get_ref().map(|e| e as &(dyn Error + 'static));
+        while let Some(error) = source {
+            let io = error.downcast_ref::<io::Error>().and_then(io::Error::get_ref);
+            if error.is::<native_tls::Error>() || io.is_some_and(|e| e.is::<native_tls::Error>()) {
+                return ErrorCategory::Tls;
+            }
+            source = error.source();
+        }
+
+        match e.get_ref().and_then(|e| e.downcast_ref::<hyper::Error>()) {
+            Some(e) if e.is_connect() => ErrorCategory::Connect,
+            Some(e) if e.is_parse() => ErrorCategory::Decode,
+            _ => ErrorCategory::Other,
+        }
+    }
+}
+
+/// Trait that defines how the `Downloader` retrieves the `Response` for a `Request`.
+///
+/// The production implementation is `HttpFetch`. `MockFetch` serves responses from
+/// memory, which allows testing spiders without a network connection.
+///
+/// The fetcher is shared by the threads `Requests` are fetched on (see
+/// `DownloaderSettings::workers`), so it must be `Send + Sync`.
+pub trait Fetch: Send + Sync {
+    /// Retrieves the resource for a `Request`. The `Downloader` middleware is passed
+    /// along so that `process_client` and `process_request` can be applied.
+    fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture;
+}
+
+/// Fetcher that makes HTTP requests with `reqwest`. The default one fetches private
+/// addresses, as `allow_private_addresses` does.
+#[derive(Default)]
+pub struct HttpFetch {
+    hosts: Hosts,
+    /// See `DownloaderSettings::body_read_timeout_ms`
+    body_read_timeout_ms: u64,
+}
+
+impl HttpFetch {
+    /// Applies `DownloaderSettings::resolve`, `allow_private_addresses` and
+    /// `body_read_timeout_ms`. Invalid addresses are skipped, settings are checked by
+    /// `Settings::validate`.
+    pub fn from_settings(settings: &DownloaderSettings) -> Self {
+        let fixed = settings.resolve.iter()
+            .filter_map(|(host, addr)| addr.parse().ok().map(|addr| (host.clone(), addr)))
+            .collect();
+        let resolver = if settings.allow_private_addresses {
+            None
+        } else {
+            Some(GaiResolver::new(DNS_THREADS))
+        };
+        Self {
+            hosts: Hosts { fixed: Arc::new(fixed), resolver },
+            body_read_timeout_ms: settings.body_read_timeout_ms,
+        }
+    }
+
+    /// Sends `request` to the address `hosts` route it to, following its redirects when
+    /// private addresses are refused: they are routed the same way, and a redirect to a
+    /// private address is returned as is. Otherwise `reqwest` follows them.
+    fn send(client: Client, hosts: Hosts, request: AsyncRequest)
+        -> Box<dyn Future<Item=AsyncResponse, Error=FetchError>>
+    {
+        let follow = hosts.resolver.is_some();
+        let redirects = future::loop_fn((request, 0, None), move |(request, hops, redirect)| {
+            let url = request.url().clone();
+            let (method, mut headers) = (request.method().clone(), request.headers().clone());
+            let client = client.clone();
+            hosts.route(url.clone())
+                .and_then(move |(target, host)| {
+                    let mut request = request;
+                    *request.url_mut() = target;
+                    if let Some(host) = host.and_then(|host| HeaderValue::from_str(&host).ok()) {
+                        request.headers_mut().insert(HOST, host);
+                    }
+                    client.execute(request).map_err(FetchError::Request)
+                })
+                .then(move |sent| {
+                    let res = match (sent, redirect) {
+                        (Err(FetchError::PrivateAddress(ip)), Some(redirect)) => {
+                            warn!("Not following the redirect to {}, it resolves to the \
+                                   private address {}", url, ip);
+                            return Ok(Loop::Break(redirect));
+                        }
+                        (sent, _) => sent?,
+                    };
+                    let location = res.headers().get(LOCATION)
+                        .and_then(|location| location.to_str().ok())
+                        .and_then(|location| url.join(location).ok());
+                    let next = match location {
+                        Some(next) if follow && HttpFetch::is_redirect(res.status()) => next,
+                        _ => return Ok(Loop::Break(res)),
+                    };
+                    if hops == MAX_REDIRECTS {
+                        return Err(FetchError::TooManyRedirects);
+                    }
+
+                    // As `reqwest` does, credentials aren't sent to another host
+                    if next.host_str() != url.host_str()
+                        || next.port_or_known_default() != url.port_or_known_default()
+                    {
+                        for name in &[AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION] {
+                            headers.remove(name);
+                        }
+                    }
+                    let mut request = AsyncRequest::new(method, next);
+                    *request.headers_mut() = headers;
+                    Ok(Loop::Continue((request, hops + 1, Some(res))))
+                })
+        });
+        Box::new(redirects)
+    }
+
+    /// Whether a `Response` with `status` is a redirect `reqwest` would follow
+    fn is_redirect(status: StatusCode) -> bool {
+        matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER
+                         | StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT)
+    }
+
+    /// Reads `body` into `buf`. A body sent with `Content-Encoding: gzip` is decompressed
+    /// by `reqwest`, a gzipped file (e.g. `sitemap.xml.gz`) is decompressed here.
+    fn read_body(body: &[u8], buf: &mut String) -> io::Result<usize> {
+        if body.starts_with(&[0x1f, 0x8b]) {
+            GzDecoder::new(body).read_to_string(buf)
+        } else {
+            Cursor::new(body).read_to_string(buf)
+        }
+    }
+}
+
+impl Fetch for HttpFetch {
+    fn fetch(&self, req: Request, middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+        // Loop through middleware and configure the ClientBuilder with any custom logic
+        // defined in any activated middleware
+        let mut cln_builder = ClientBuilder::new();
+        if self.hosts.resolver.is_some() {
+            // Redirects are followed by `send`, to check their addresses
+            cln_builder = cln_builder.redirect(RedirectPolicy::none());
+        }
+        for m in middleware {
+            cln_builder = m.process_client(cln_builder, &req);
+        }
+
+        let client = match cln_builder.build() {
+            Ok(client) => client,
+            Err(e) => return Box::new(future::err(FetchError::Request(e))),
+        };
+
+        // Loop through middleware and configure the RequestBuilder with any custom logic
+        // defined in any activated middleware
+        let mut req_builder = client.get(req.url.clone());
+        for m in middleware {
+            req_builder = m.process_request(req_builder, &req);
+        }
+        let request = match req_builder.build() {
+            Ok(request) => request,
+            Err(e) => return Box::new(future::err(FetchError::Request(e))),
+        };
+
+        let mut response = Response::new(req);
+        let body_read_timeout_ms = self.body_read_timeout_ms;
+
+        Box::new(HttpFetch::send(client, self.hosts.clone(), request)
+            .and_then(move |res| {
+                response.status = res.status();
+                response.headers = res.headers().clone();
+                let body = res.into_body().concat2().map_err(FetchError::Request);
+                let body = if body_read_timeout_ms == 0 {
+                    Either::A(body)
+                } else {
+                    let timeout = Duration::from_millis(body_read_timeout_ms);
+                    Either::B(Timeout::new(body, timeout)
+                        .map_err(|e| e.into_inner().unwrap_or(FetchError::BodyTimeout)))
+                };
+                body.map(move |body| (response, body))
+            })
+            .and_then(|(mut response, body)| {
+                match HttpFetch::read_body(&body, &mut response.body) {
+                    Ok(_) => Ok(response),
+                    Err(e) => Err(FetchError::Body(e)),
+                }
+            }))
+    }
+}
+
+/// Where `HttpFetch` connects to the hosts of `Requests`
+#[derive(Clone, Default)]
+struct Hosts {
+    /// Fixed addresses of hosts, see `DownloaderSettings::resolve`
+    fixed: Arc<HashMap<String, SocketAddr>>,
+    /// Looks up the other hosts to refuse those that resolve to private addresses, see
+    /// `DownloaderSettings::allow_private_addresses`. None when they are allowed.
+    resolver: Option<GaiResolver>,
+}
+
+impl Hosts {
+    /// The URL to connect to for `url`, and the host to send in the `Host` header if it
+    /// isn't that of the URL. Fails if the host resolves to a private address.
+    ///
+    /// The hosts of `fixed` are connected to at the address they are set to. Otherwise an
+    /// HTTP host is connected to at the address it was checked at, so that it can't resolve
+    /// to another one when the connection is made. An HTTPS host is connected to by name, the
+    /// certificate of an address it resolved to in the meantime wouldn't be valid for it.
+    /// A host that can't be resolved isn't refused, fetching it fails anyway.
+    fn route(&self, mut url: Url)
+        -> Box<dyn Future<Item=(Url, Option<String>), Error=FetchError>>
+    {
+        if let Some(addr) = url.host_str().and_then(|host| self.fixed.get(host)) {
+            let host = Hosts::pin(&mut url, addr.ip(), Some(addr.port()));
+            return Box::new(future::ok((url, host)));
+        }
+        let resolver = match &self.resolver {
+            Some(resolver) => resolver,
+            None => return Box::new(future::ok((url, None))),
+        };
+        Box::new(address::lookup(resolver, &url).then(move |addrs| {
+            let addrs = addrs.unwrap_or_else(|e| {
+                debug!("Failed to resolve {}: {}", url, e);
+                Vec::new()
+            });
+            if let Some(ip) = address::private_address(&addrs) {
+                return Err(FetchError::PrivateAddress(ip));
+            }
+            let host = match addrs.first() {
+                Some(ip) if url.scheme() == "http" => Hosts::pin(&mut url, *ip, None),
+                _ => None,
+            };
+            Ok((url, host))
+        }))
+    }
+
+    /// Points `url` at `ip`, and at `port` if set. Returns the original host, to be sent in
+    /// the `Host` header.
+    ///
+    /// As the connection is made to the IP address, HTTPS certificates are checked
+    /// against the address rather than the host.
+    fn pin(url: &mut Url, ip: IpAddr, port: Option<u16>) -> Option<String> {
+        let host = url.host_str()?.to_string();
+        let original = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host,
+        };
+        url.set_ip_host(ip).ok()?;
+        if let Some(port) = port {
+            url.set_port(Some(port)).ok()?;
+        }
+        Some(original)
+    }
+}
+
+/// Fetcher that serves `Response`s from memory. URLs that are not known to the
+/// `MockFetch` get a `404 Not Found` response with an empty body.
+#[derive(Default)]
+pub struct MockFetch {
+    pages: HashMap<Url, (u16, HeaderMap, String)>,
+}
+
+impl MockFetch {
+    /// Add a page with the status code and body to serve for the url
+    pub fn page(self, url: &str, status: u16, body: &str) -> Self {
+        self.page_with_headers(url, status, &[], body)
+    }
+
+    /// Same as `page`, with the `(name, value)` headers of the response.
+    ///
+    /// Panics if a header name or value is invalid.
+    pub fn page_with_headers(mut self, url: &str, status: u16, headers: &[(&str, &str)],
+                             body: &str) -> Self
+    {
+        let headers = headers.iter()
+            .map(|(name, value)| {
+                (HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                 HeaderValue::from_str(value).unwrap())
+            })
+            .collect();
+        self.pages.insert(Url::parse(url).unwrap(), (status, headers, body.to_string()));
+        self
+    }
+
+    /// Add a page to serve for the url, with the body read from a file, e.g. a page saved
+    /// from a browser. Panics if the file can't be read.
+    pub fn page_file<P: AsRef<Path>>(self, url: &str, path: P) -> Self {
+        let body = fs::read_to_string(path).unwrap();
+        self.page(url, 200, &body)
+    }
+}
+
+impl Fetch for MockFetch {
+    fn fetch(&self, req: Request, _middleware: &[Box<dyn DownloaderMiddleware>]) -> FetchFuture {
+        let mut response = Response::new(req);
+        match self.pages.get(&response.request.url) {
+            Some((status, headers, body)) => {
+                response.status = StatusCode::from_u16(*status)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                response.headers = headers.clone();
+                response.body = body.clone();
+            }
+            None => response.status = StatusCode::NOT_FOUND,
+        }
+        Box::new(future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    use actix::System;
+
+    use crate::settings::Settings;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        // A server that answers a single request and hands the request back to the test
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_lowercase()
+        });
+
+        let mut settings = Settings::default().downloader;
+        settings.resolve = vec![("site.test".to_string(), addr.to_string())];
+        let fetcher = HttpFetch::from_settings(&settings);
+
+        let url = Url::parse("http://site.test/page").unwrap();
+        let fetch = fetcher.fetch(Request::new(url.clone(), 0, 1), &[]);
+        let response = System::new("test").block_on(fetch).unwrap();
+        assert_eq!(response.body, "hello");
+        assert_eq!(response.request.url, url);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("get /page "), "{}", request);
+        assert!(request.contains("host: site.test\r\n"), "{}", request);
+    }
+
+    #[test]
+    fn test_private_redirects() {
+        // A server whose `/a` redirects to `/b`, which redirects to its address
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let location = match request.split(' ').nth(1).unwrap_or_default() {
+                    "/a" => "/b".to_string(),
+                    "/b" => format!("http://{}/c", addr),
+                    "/loop" => "/loop".to_string(),
+                    _ => {
+                        let _ = stream.write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nConnection: close\r\n\r\nc");
+                        continue;
+                    }
+                };
+                let _ = write!(stream, "HTTP/1.1 302 Found\r\nLocation: {}\r\n\
+                                        Content-Length: 0\r\nConnection: close\r\n\r\n",
+                               location);
+            }
+        });
+
+        let fetch = |path: &str, allow_private_addresses| {
+            let mut settings = Settings::default().downloader;
+            settings.allow_private_addresses = allow_private_addresses;
+            settings.resolve = vec![("site.test".to_string(), addr.to_string())];
+            let url = Url::parse(&format!("http://site.test{}", path)).unwrap();
+            let fetch = HttpFetch::from_settings(&settings).fetch(Request::new(url, 0, 1), &[]);
+            System::new("test").block_on(fetch)
+        };
+
+        // The redirect to site.test is followed, the one to the private address isn't
+        let response = fetch("/a", false).unwrap();
+        assert_eq!(response.status, StatusCode::FOUND);
+        assert_eq!(response.headers[LOCATION], format!("http://{}/c", addr).as_str());
+        assert!(matches!(fetch("/loop", false), Err(FetchError::TooManyRedirects)));
+        assert_eq!(fetch("/a", true).unwrap().body, "c");
+    }
+
+    #[test]
+    fn test_body_read_timeout() {
+        // A server that sends the headers right away, and the body a few bytes at a time
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\n");
+                for chunk in [&b"sl"[..], b"ow", b"ly"] {
+                    let _ = stream.write_all(chunk);
+                    thread::sleep(Duration::from_millis(200));
+                }
+            }
+        });
+
+        let fetch = |body_read_timeout_ms| {
+            let mut settings = Settings::default().downloader;
+            settings.allow_private_addresses = true;
+            settings.body_read_timeout_ms = body_read_timeout_ms;
+            let url = Url::parse(&format!("http://{}/", addr)).unwrap();
+            let fetch = HttpFetch::from_settings(&settings).fetch(Request::new(url, 0, 1), &[]);
+            System::new("test").block_on(fetch)
+        };
+
+        let started = Instant::now();
+        assert!(matches!(fetch(100), Err(FetchError::BodyTimeout)));
+        assert!(started.elapsed() < Duration::from_millis(400));
+        assert_eq!(fetch(2000).unwrap().body, "slowly");
+        assert_eq!(fetch(0).unwrap().body, "slowly");
+    }
+
+    #[test]
+    fn test_error_category() {
+        // A server that answers every connection with `response`
+        let serve = |response: &'static [u8]| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut buf = [0; 4096];
+                    let _ = stream.read(&mut buf);
+                    let _ = stream.write_all(response);
+                }
+            });
+            addr
+        };
+        let category = |url: String| {
+            let fetch = HttpFetch::default().fetch(Request::new(Url::parse(&url).unwrap(), 0, 1),
+                                                   &[]);
+            System::new("test").block_on(fetch).unwrap_err().category()
+        };
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        assert_eq!(category(format!("http://{}/", closed)), ErrorCategory::Connect);
+        assert_eq!(category("http://unresolvable.invalid/".to_string()), ErrorCategory::Connect);
+
+        let http = serve(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n\
+                           ok");
+        assert_eq!(category(format!("https://{}/", http)), ErrorCategory::Tls);
+
+        let garbled = serve(b"garbled\r\n\r\n");
+        assert_eq!(category(format!("http://{}/", garbled)), ErrorCategory::Decode);
+        let binary = serve(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n\
+                             \xff\xfe");
+        assert_eq!(category(format!("http://{}/", binary)), ErrorCategory::Decode);
+
+        let redirect = serve(b"HTTP/1.1 302 Found\r\nLocation: /\r\nContent-Length: 0\r\n\
+                               Connection: close\r\n\r\n");
+        assert_eq!(category(format!("http://{}/", redirect)), ErrorCategory::HttpStatus);
+
+        assert_eq!(FetchError::Timeout.category(), ErrorCategory::Timeout);
+        assert_eq!(FetchError::BodyTimeout.category(), ErrorCategory::Timeout);
+        assert_eq!(FetchError::Worker(MailboxError::Closed).category(), ErrorCategory::Other);
+    }
+}
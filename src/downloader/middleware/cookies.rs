@@ -0,0 +1,107 @@
+//! Cookies Middleware
+use reqwest::header::{HeaderValue, COOKIE};
+use reqwest::r#async::RequestBuilder;
+
+use crate::crawler::Request;
+use crate::downloader::middleware::DownloaderMiddleware;
+use crate::settings::CookiesSettings;
+
+/// Middleware that sends a fixed set of cookies with every `Request`, e.g. an auth token or
+/// a consent flag. They are set upfront, the cookies set by the sites aren't kept.
+///
+/// With `hosts`, the cookies are only sent to these hosts and their subdomains, so that a
+/// token isn't sent to every site that is linked to.
+pub struct Cookies {
+    header: Option<HeaderValue>,
+    hosts: Vec<String>,
+}
+
+impl Cookies {
+    /// Sends `cookies`, as `(name, value)` pairs, to all hosts
+    pub fn new(cookies: &[(String, String)]) -> Self {
+        Self { header: Cookies::header(cookies), hosts: vec![] }
+    }
+
+    pub fn from_settings(settings: CookiesSettings) -> Self {
+        let hosts = settings.hosts.iter().map(|host| host.to_lowercase()).collect();
+        Self { header: Cookies::header(&settings.cookies), hosts }
+    }
+
+    /// The `Cookie` header of `cookies`, e.g. `a=1; b=2`. `None` if there are no cookies or
+    /// one of them can't be sent in a header.
+    pub fn header(cookies: &[(String, String)]) -> Option<HeaderValue> {
+        if cookies.is_empty() {
+            return None;
+        }
+        let header = cookies.iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&header).ok()
+    }
+
+    /// Whether the cookies are sent with `req`
+    fn sent_to(&self, req: &Request) -> bool {
+        if self.hosts.is_empty() {
+            return true;
+        }
+        let host = match req.url.host_str() {
+            Some(host) => host,
+            None => return false,
+        };
+        self.hosts.iter().any(|allowed| {
+            host == allowed
+                || host.strip_suffix(allowed.as_str()).is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+impl DownloaderMiddleware for Cookies {
+    fn process_request(&self, builder: RequestBuilder, req: &Request) -> RequestBuilder {
+        match &self.header {
+            Some(header) if self.sent_to(req) => builder.header(COOKIE, header.clone()),
+            _ => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::r#async::Client;
+    use reqwest::Url;
+
+    use crate::settings::Settings;
+
+    use super::*;
+
+    fn pairs(cookies: &[(&str, &str)]) -> Vec<(String, String)> {
+        cookies.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn sent_cookie(middleware: &Cookies, url: &str) -> Option<String> {
+        let req = Request::new(Url::parse(url).unwrap(), 0, 0);
+        let builder = middleware.process_request(Client::new().get(req.url.clone()), &req);
+        let sent = builder.build().unwrap();
+        sent.headers().get(COOKIE).map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_cookies() {
+        let middleware = Cookies::new(&pairs(&[("session", "a1b2"), ("consent", "yes")]));
+        assert_eq!(sent_cookie(&middleware, "https://www.example.com/a"),
+                   Some("session=a1b2; consent=yes".to_string()));
+        assert_eq!(sent_cookie(&Cookies::new(&[]), "https://www.example.com/a"), None);
+        assert!(Cookies::header(&pairs(&[("token", "a\nb")])).is_none());
+
+        let mut settings = Settings::default().downloader.middleware.cookies;
+        settings.cookies = pairs(&[("token", "xyz"), ("lang", "en")]);
+        settings.hosts = vec!["Example.com".to_string()];
+        let middleware = Cookies::from_settings(settings);
+        assert_eq!(sent_cookie(&middleware, "https://example.com/"),
+                   Some("token=xyz; lang=en".to_string()));
+        assert_eq!(sent_cookie(&middleware, "https://api.example.com/v1"),
+                   Some("token=xyz; lang=en".to_string()));
+        assert_eq!(sent_cookie(&middleware, "https://notexample.com/"), None);
+        assert_eq!(sent_cookie(&middleware, "https://other.test/"), None);
+    }
+}
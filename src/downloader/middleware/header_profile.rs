@@ -0,0 +1,144 @@
+//! Header Profile Middleware
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::r#async::RequestBuilder;
+
+use crate::crawler::Request;
+use crate::downloader::middleware::DownloaderMiddleware;
+use crate::settings::HeaderProfileSettings;
+
+/// Headers of Chrome 120 on Windows, in the order Chrome sends them
+pub const CHROME: &[(&str, &str)] = &[
+    ("sec-ch-ua", r#""Not_A Brand";v="8", "Chromium";v="120", "Google Chrome";v="120""#),
+    ("sec-ch-ua-mobile", "?0"),
+    ("sec-ch-ua-platform", r#""Windows""#),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+                    (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"),
+    ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,\
+                image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"),
+    ("sec-fetch-site", "none"),
+    ("sec-fetch-mode", "navigate"),
+    ("sec-fetch-user", "?1"),
+    ("sec-fetch-dest", "document"),
+    ("accept-language", "en-US,en;q=0.9"),
+];
+
+/// Headers of Firefox 121 on Windows, in the order Firefox sends them
+pub const FIREFOX: &[(&str, &str)] = &[
+    ("user-agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 \
+                    Firefox/121.0"),
+    ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,\
+                image/webp,*/*;q=0.8"),
+    ("accept-language", "en-US,en;q=0.5"),
+    ("upgrade-insecure-requests", "1"),
+    ("sec-fetch-dest", "document"),
+    ("sec-fetch-mode", "navigate"),
+    ("sec-fetch-site", "none"),
+    ("sec-fetch-user", "?1"),
+];
+
+/// Middleware that sends every `Request` with the headers of a browser, picked at random
+/// from a list of profiles. A profile holds the full set of headers of one browser (including
+/// the User-Agent) in the order the browser sends them, so the headers of a `Request` always
+/// match each other.
+#[derive(Default)]
+pub struct HeaderProfile {
+    profiles: Vec<HeaderMap>,
+}
+
+impl HeaderProfile {
+    /// The built-in profile called `name` ("chrome" or "firefox")
+    pub fn builtin(name: &str) -> Option<&'static [(&'static str, &'static str)]> {
+        match name {
+            "chrome" => Some(CHROME),
+            "firefox" => Some(FIREFOX),
+            _ => None,
+        }
+    }
+
+    /// Profiles that are unknown or hold invalid headers are skipped, they are reported by
+    /// `Settings::validate`
+    pub fn from_settings(settings: HeaderProfileSettings) -> Self {
+        let mut middleware = Self::default();
+        for name in &settings.profiles {
+            if let Some(headers) = Self::builtin(name) {
+                middleware = middleware.add_profile(headers);
+            }
+        }
+        for headers in &settings.custom {
+            let headers: Vec<(&str, &str)> = headers.iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            if Self::to_header_map(&headers).is_some() {
+                middleware = middleware.add_profile(&headers);
+            }
+        }
+        middleware
+    }
+
+    /// Adds a profile, given as `(name, value)` pairs in the order they are sent.
+    ///
+    /// Panics if a header name or value is invalid.
+    pub fn add_profile(mut self, headers: &[(&str, &str)]) -> Self {
+        self.profiles.push(Self::to_header_map(headers).expect("invalid header profile"));
+        self
+    }
+
+    /// Returns `None` if a header name or value is invalid
+    pub fn to_header_map(headers: &[(&str, &str)]) -> Option<HeaderMap> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+            map.insert(name, HeaderValue::from_str(value).ok()?);
+        }
+        Some(map)
+    }
+
+    fn pick(&self) -> Option<&HeaderMap> {
+        if self.profiles.is_empty() {
+            return None;
+        }
+        let i = rand::thread_rng().gen_range(0, self.profiles.len());
+        Some(&self.profiles[i])
+    }
+}
+
+impl DownloaderMiddleware for HeaderProfile {
+    fn process_request(&self, builder: RequestBuilder, _req: &Request) -> RequestBuilder {
+        match self.pick() {
+            Some(headers) => builder.headers(headers.clone()),
+            None => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::Settings;
+
+    use super::*;
+
+    #[test]
+    fn test_header_profile() {
+        let mut settings = Settings::default().downloader.middleware.header_profile;
+        settings.profiles = vec!["firefox".to_string(), "safari".to_string()];
+        let middleware = HeaderProfile::from_settings(settings);
+        assert_eq!(middleware.profiles.len(), 1);
+
+        let headers = middleware.pick().unwrap();
+        let names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, vec![
+            "user-agent",
+            "accept",
+            "accept-language",
+            "upgrade-insecure-requests",
+            "sec-fetch-dest",
+            "sec-fetch-mode",
+            "sec-fetch-site",
+            "sec-fetch-user",
+        ]);
+        assert_eq!(headers["accept-language"], "en-US,en;q=0.5");
+        assert!(headers["user-agent"].to_str().unwrap().contains("Firefox/121.0"));
+    }
+}
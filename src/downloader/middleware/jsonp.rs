@@ -0,0 +1,62 @@
+//! JSONP Middleware
+use reqwest::header::{CONTENT_TYPE, HeaderValue};
+use serde_json::Value;
+
+use crate::crawler::Response;
+use crate::downloader::middleware::DownloaderMiddleware;
+
+/// Middleware that unwraps JSONP bodies, e.g. `callback({"id": 1});`, into the JSON they
+/// hold, `{"id": 1}`, for APIs that only answer in JSONP. The `Content-Type` of unwrapped
+/// `Responses` is set to `application/json`. Other bodies are left as they are.
+#[derive(Default)]
+pub struct Jsonp;
+
+impl Jsonp {
+    pub fn new() -> Self {
+        Jsonp
+    }
+
+    /// The JSON of a JSONP `body`: the argument of a call to a callback, e.g. `cb(...)` or
+    /// `jQuery123.cb(...)`, that may be followed by a `;` and preceded by a `/**/` comment.
+    /// `None` if the argument isn't valid JSON
+    pub fn unwrap(body: &str) -> Option<&str> {
+        let body = body.trim();
+        let body = body.strip_prefix("/**/").unwrap_or(body).trim_start();
+        let body = body.strip_suffix(';').unwrap_or(body).trim_end();
+        let open = body.find('(')?;
+        let callback = body[..open].trim();
+        let valid = !callback.is_empty() && callback.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.');
+        if !valid || !body.ends_with(')') {
+            return None;
+        }
+        let json = body[open + 1..body.len() - 1].trim();
+        serde_json::from_str::<Value>(json).ok().map(|_| json)
+    }
+}
+
+impl DownloaderMiddleware for Jsonp {
+    fn process_response(&self, mut res: Response) -> Response {
+        if let Some(json) = Jsonp::unwrap(&res.body) {
+            res.body = json.to_string();
+            res.headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unwrap() {
+        assert_eq!(Jsonp::unwrap(r#"callback({"id": 1});"#), Some(r#"{"id": 1}"#));
+        assert_eq!(Jsonp::unwrap("/**/ jQuery1910.cb_2 ( [1, 2] )\n"), Some("[1, 2]"));
+        assert_eq!(Jsonp::unwrap(r#"cb("a (b)")"#), Some(r#""a (b)""#));
+        assert_eq!(Jsonp::unwrap(r#"{"id": 1}"#), None);
+        assert_eq!(Jsonp::unwrap("<p>(1)</p>"), None);
+        assert_eq!(Jsonp::unwrap("alert(1); cb(2)"), None);
+        assert_eq!(Jsonp::unwrap("(1)"), None);
+    }
+}
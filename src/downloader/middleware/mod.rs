@@ -4,14 +4,24 @@
 use reqwest::r#async::{ClientBuilder, RequestBuilder};
 
 use crate::crawler::{Request, Response};
-pub use crate::downloader::middleware::{proxy::Proxy, user_agent::UserAgent};
+pub use crate::downloader::middleware::{
+    cookies::Cookies, header_profile::HeaderProfile, jsonp::Jsonp, origin::Origin, proxy::Proxy,
+    user_agent::UserAgent,
+};
 
+mod cookies;
+pub mod header_profile;
+mod jsonp;
+mod origin;
 mod proxy;
 mod user_agent;
 
 /// Trait that defines a middleware that can be used to add additional
 /// functionality to the Downloader.
-pub trait DownloaderMiddleware {
+///
+/// `Requests` may be fetched on several threads (see `DownloaderSettings::workers`), so
+/// middleware must be `Send + Sync`.
+pub trait DownloaderMiddleware: Send + Sync {
     /// Exposes a way to adjusts various parameters of the `ClientBuilder`.
     /// Accepts a `ClientBuilder`, applies custom logic to it and returns a new `ClientBuilder`.
     fn process_client(&self, cln: ClientBuilder, _req: &Request) -> ClientBuilder {
@@ -19,13 +29,24 @@ pub trait DownloaderMiddleware {
     }
 
     /// Exposes a way to adjust various parameters of the `RequestBuilder`
-    /// Accepts a `RequestBuilder`, applies custom logic to it and returns a new `RequestBuilder`.
-    fn process_request(&self, req: RequestBuilder) -> RequestBuilder {
-        req
+    /// Accepts a `RequestBuilder` and the `Request` it is built from, applies custom logic to it
+    /// and returns a new `RequestBuilder`.
+    fn process_request(&self, builder: RequestBuilder, _req: &Request) -> RequestBuilder {
+        builder
     }
 
-    /// Exposes a way to edit a response before sending it to the `Parser`.
+    /// Exposes a way to edit a response before sending it to the `Parser`, e.g. to transform
+    /// its body. The middleware are chained in the order they were enabled, each one given the
+    /// `Response` returned by the previous one, see `process_response`. `Responses` that are
+    /// retried (see `DownloaderSettings::retry_after_retries`) and robots.txt files don't go
+    /// through it.
     fn process_response(&self, res: Response) -> Response {
         res
     }
 }
+
+/// Hands `res` to the `process_response` of each of the `middleware` in turn, and returns
+/// the `Response` of the last one. This is what the `Parser` receives.
+pub fn process_response(middleware: &[Box<dyn DownloaderMiddleware>], res: Response) -> Response {
+    middleware.iter().fold(res, |res, m| m.process_response(res))
+}
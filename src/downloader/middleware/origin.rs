@@ -0,0 +1,89 @@
+//! Origin Middleware
+use reqwest::header::{HeaderValue, ORIGIN};
+use reqwest::r#async::RequestBuilder;
+
+use crate::crawler::Request;
+use crate::downloader::middleware::DownloaderMiddleware;
+use crate::settings::OriginSettings;
+
+/// Middleware that sends an `Origin` header, for APIs that reject `Requests` without one.
+///
+/// The header holds the scheme and host (and port, if it isn't the default one) of the URL
+/// of the `Request`, unless a fixed value is set.
+pub struct Origin {
+    value: Option<String>,
+    start_only: bool,
+}
+
+impl Origin {
+    /// Sends the origin of the URL of every `Request`
+    pub fn new() -> Self {
+        Self { value: None, start_only: false }
+    }
+
+    pub fn from_settings(settings: OriginSettings) -> Self {
+        let value = if settings.value.is_empty() { None } else { Some(settings.value) };
+        Self { value, start_only: settings.start_only }
+    }
+
+    /// The `Origin` header sent with `req`, if any
+    pub fn origin(&self, req: &Request) -> Option<String> {
+        if self.start_only && req.depth > 0 {
+            return None;
+        }
+        if let Some(value) = &self.value {
+            return Some(value.clone());
+        }
+        let origin = req.url.origin();
+        if origin.is_tuple() { Some(origin.ascii_serialization()) } else { None }
+    }
+}
+
+impl Default for Origin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DownloaderMiddleware for Origin {
+    fn process_request(&self, builder: RequestBuilder, req: &Request) -> RequestBuilder {
+        match self.origin(req).and_then(|origin| HeaderValue::from_str(&origin).ok()) {
+            Some(value) => builder.header(ORIGIN, value),
+            None => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::r#async::Client;
+    use reqwest::Url;
+
+    use crate::settings::Settings;
+
+    use super::*;
+
+    fn sent_origin(middleware: &Origin, url: &str, depth: u32) -> Option<String> {
+        let req = Request::new(Url::parse(url).unwrap(), depth, 0);
+        let builder = middleware.process_request(Client::new().get(req.url.clone()), &req);
+        let sent = builder.build().unwrap();
+        sent.headers().get(ORIGIN).map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_origin() {
+        let middleware = Origin::new();
+        assert_eq!(sent_origin(&middleware, "https://api.example.com/v1/items?page=2", 0),
+                   Some("https://api.example.com".to_string()));
+        assert_eq!(sent_origin(&middleware, "http://example.com:8080/a", 3),
+                   Some("http://example.com:8080".to_string()));
+
+        let mut settings = Settings::default().downloader.middleware.origin;
+        settings.value = "https://www.example.com".to_string();
+        settings.start_only = true;
+        let middleware = Origin::from_settings(settings);
+        assert_eq!(sent_origin(&middleware, "https://api.example.com/v1/items", 0),
+                   Some("https://www.example.com".to_string()));
+        assert_eq!(sent_origin(&middleware, "https://api.example.com/v1/items/1", 1), None);
+    }
+}
@@ -2,6 +2,7 @@
 use reqwest::header::{HeaderValue, USER_AGENT};
 use reqwest::r#async::RequestBuilder;
 
+use crate::crawler::Request;
 use crate::downloader::middleware::DownloaderMiddleware;
 use crate::settings::UserAgentSettings;
 
@@ -21,7 +22,7 @@ impl UserAgent {
 }
 
 impl DownloaderMiddleware for UserAgent {
-    fn process_request(&self, req: RequestBuilder) -> RequestBuilder {
-        req.header(USER_AGENT, HeaderValue::from_str(self.value.as_str()).unwrap())
+    fn process_request(&self, builder: RequestBuilder, _req: &Request) -> RequestBuilder {
+        builder.header(USER_AGENT, HeaderValue::from_str(self.value.as_str()).unwrap())
     }
 }
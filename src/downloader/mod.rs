@@ -12,19 +12,43 @@
 //! Other Features not yet included:
 //! - Assessment of site response (side down, non-200 responses)
 //! - Auto-throttle
-use std::cell::RefCell;
-use std::io::{Cursor, Read};
+//!
+//! The `Requests` themselves are fetched by `DownloadWorker`s, in turn. With
+//! `DownloaderSettings::workers` greater than one, each of them runs on its own `Arbiter`
+//! (thread), so that the network I/O of a crawl is spread over several event loops. The
+//! `Downloader` keeps its state on its own thread: the results are handed back to it.
+use std::collections::HashSet;
 use std::rc::Rc;
-
-use actix::{Actor, Arbiter, ArbiterService, Context, Handler, Message, Recipient};
-use futures::{Future, Stream};
-use reqwest::r#async::ClientBuilder;
-
-use crate::crawler::{Listener, Request, Response};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix::{Actor, ActorContext, ActorFuture, Addr, Arbiter, AsyncContext, Context,
+            fut::wrap_future, Handler, Message, Recipient, ResponseFuture, Supervised, Supervisor};
+use actix::msgs::StopArbiter;
+use chrono::Utc;
+use futures::Future;
+use reqwest::Url;
+use tokio_timer::Timeout;
+
+use crate::crawler::{CloseSpider, Listener, Request, Response};
+use crate::downloader::middleware::DownloaderMiddleware;
+pub use crate::downloader::fetch::{ErrorCategory, Fetch, FetchError, FetchFuture, HttpFetch,
+                                   MockFetch};
+pub use crate::downloader::robots::Robots;
+use crate::downloader::sitemap::Seeding;
+pub use crate::downloader::sitemap::{Sitemap, SitemapEntry};
+use crate::events::{Event, EventLog};
 use crate::parser::Parser;
+use crate::scheduler::{Retry, Scheduler, Seeded, SetHostDelay};
+use crate::settings::{DownloaderMiddlewareType, Update};
 use crate::spider::Spider;
 
+mod address;
+mod fetch;
 pub mod middleware;
+mod retry_after;
+mod robots;
+mod sitemap;
 
 /// The `Downloader` State
 ///
@@ -34,12 +58,106 @@ pub struct State {
     pub request_total: usize,
     pub request_success: usize,
     pub request_error: usize,
+
+    /// `request_error` by the category of the error
+    pub request_errors: RequestErrors,
+
+    /// `Requests` handed back to the `Scheduler` to be retried after their `Retry-After`
+    pub request_retried: usize,
+
+    /// `Requests` not sent as their host resolves to a private address, see
+    /// `DownloaderSettings::allow_private_addresses`
+    pub request_refused: usize,
+
+    /// Total size (in bytes) of the `Response` bodies received
+    pub bytes_downloaded: u64,
+}
+
+/// Number of failed `Requests` by `ErrorCategory`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct RequestErrors {
+    pub connect: usize,
+    pub timeout: usize,
+    pub tls: usize,
+    pub decode: usize,
+    pub http_status: usize,
+    pub other: usize,
+}
+
+impl RequestErrors {
+    /// Counts a `Request` that failed with an error of `category`
+    pub fn add(&mut self, category: ErrorCategory) {
+        let count = match category {
+            ErrorCategory::Connect => &mut self.connect,
+            ErrorCategory::Timeout => &mut self.timeout,
+            ErrorCategory::Tls => &mut self.tls,
+            ErrorCategory::Decode => &mut self.decode,
+            ErrorCategory::HttpStatus => &mut self.http_status,
+            ErrorCategory::Other => &mut self.other,
+        };
+        *count += 1;
+    }
+}
+
+/// The outcome of a single processed `Request`
+#[derive(Clone, Debug, Message)]
+pub struct Outcome {
+    /// The URL of the `Request`
+    pub url: Url,
+
+    /// The status code of the `Response`. `None` if no `Response` was received.
+    pub status: Option<u16>,
+
+    /// The time it took to retrieve the network resource
+    pub latency: Duration,
+
+    /// Size (in bytes) of the `Response` body. 0 if no `Response` was received.
+    pub bytes: u64,
+}
+
+/// Asks a `DownloadWorker` to fetch a `Request`, aborting it after `timeout` milliseconds
+/// (0 means no timeout)
+struct Download {
+    req: Request,
+    timeout: u64,
+}
+
+impl Message for Download {
+    type Result = Result<Response, FetchError>;
+}
+
+/// Fetches `Requests` for the `Downloader`, on the `Arbiter` it was started on
+struct DownloadWorker {
+    fetcher: Arc<dyn Fetch>,
+    middleware: Arc<Vec<Box<dyn DownloaderMiddleware>>>,
+}
+
+impl Actor for DownloadWorker {
+    type Context = Context<Self>;
+}
+
+/// Started with a `Supervisor` on its own `Arbiter`
+impl Supervised for DownloadWorker {}
+
+/// Define handler for `Download` message
+impl Handler<Download> for DownloadWorker {
+    type Result = ResponseFuture<Response, FetchError>;
+
+    fn handle(&mut self, msg: Download, _ctx: &mut Context<Self>) -> Self::Result {
+        let fetch = self.fetcher.fetch(msg.req, &self.middleware);
+        if msg.timeout == 0 {
+            return fetch;
+        }
+        Box::new(Timeout::new(fetch, Duration::from_millis(msg.timeout))
+            .map_err(|e| e.into_inner().unwrap_or(FetchError::Timeout)))
+    }
 }
 
 #[derive(Default)]
 struct DownloaderInner {
     state: State,
     state_listeners: Vec<Recipient<State>>,
+    outcome_listeners: Vec<Recipient<Outcome>>,
 }
 
 impl DownloaderInner {
@@ -47,6 +165,16 @@ impl DownloaderInner {
         self.state_listeners.push(recipient);
     }
 
+    fn add_outcome_listener(&mut self, recipient: Recipient<Outcome>) {
+        self.outcome_listeners.push(recipient);
+    }
+
+    fn dispatch_outcome(&self, outcome: Outcome) {
+        self.outcome_listeners.iter().for_each(|r| {
+            let _ = r.do_send(outcome.clone());
+        });
+    }
+
     fn dispatch_state(&self) {
         self.state_listeners.iter().for_each(|r| {
             let _ = r.do_send(self.state.clone());
@@ -58,93 +186,289 @@ impl DownloaderInner {
         self.dispatch_state();
     }
 
-    fn increase_request_success(&mut self) {
+    fn increase_request_success(&mut self, bytes: usize) {
         self.state.request_success += 1;
+        self.state.bytes_downloaded += bytes as u64;
         self.dispatch_state();
     }
 
-    fn increase_request_error(&mut self) {
+    fn increase_request_error(&mut self, category: ErrorCategory) {
         self.state.request_error += 1;
+        self.state.request_errors.add(category);
+        self.dispatch_state();
+    }
+
+    fn increase_request_retried(&mut self) {
+        self.state.request_retried += 1;
+        self.dispatch_state();
+    }
+
+    fn increase_request_refused(&mut self) {
+        self.state.request_refused += 1;
         self.dispatch_state();
     }
 }
 
-#[derive(Default)]
 pub struct Downloader {
     spider: Rc<Spider>,
-    inner: Rc<RefCell<DownloaderInner>>,
+    workers: Vec<Addr<DownloadWorker>>,
+    /// The `Arbiters` of the `workers`, if they don't run on the thread of the `Downloader`
+    arbiters: Vec<Addr<Arbiter>>,
+    /// Index of the worker that fetches the next `Request`
+    next_worker: usize,
+    inner: Arc<Mutex<DownloaderInner>>,
+    scheduler: Addr<Scheduler>,
+    parser: Addr<Parser>,
+
+    /// Live copy of `DownloaderSettings::timeout`, see `Update`
+    timeout: u64,
+
+    /// Hosts for which robots.txt has been requested
+    robots_hosts: HashSet<String>,
+
+    /// Set while the crawl is seeded from sitemaps, see `SpiderBuilder::start_from_sitemaps`
+    seeding: Option<Seeding>,
+
+    events: Option<Arc<EventLog>>,
 }
 
 impl Downloader {
-    pub fn new(spider: Rc<Spider>) -> Self {
+    /// `Responses` are sent to `parser`. The `Crawl-delay`s found in robots.txt files are
+    /// sent to `scheduler`.
+    pub fn new(
+        spider: Rc<Spider>,
+        fetcher: Arc<dyn Fetch>,
+        scheduler: Addr<Scheduler>,
+        parser: Addr<Parser>,
+        events: Option<Arc<EventLog>>,
+    ) -> Self {
+        let settings = &spider.settings().downloader;
+        let timeout = settings.timeout;
+
+        let middleware = spider.shared_downloader_middleware();
+        let mut workers = Vec::new();
+        let mut arbiters = Vec::new();
+        if settings.workers == 1 {
+            workers.push(DownloadWorker::create(move |_| DownloadWorker { fetcher, middleware }));
+        } else {
+            for i in 0..settings.workers {
+                let arbiter = Arbiter::new(format!("{}-downloader-{}", spider.name(), i));
+                let (fetcher, middleware) = (Arc::clone(&fetcher), Arc::clone(&middleware));
+                workers.push(Supervisor::start_in_arbiter(&arbiter, move |_| {
+                    DownloadWorker { fetcher, middleware }
+                }));
+                arbiters.push(arbiter);
+            }
+        }
+
         Self {
             spider,
-            ..Default::default()
+            workers,
+            arbiters,
+            next_worker: 0,
+            inner: Arc::default(),
+            scheduler,
+            parser,
+            timeout,
+            robots_hosts: HashSet::new(),
+            seeding: None,
+            events,
         }
     }
 
-    fn process(&self, req: Request) -> impl Future<Item=(), Error=()> {
-        let middleware = self.spider.downloader_middleware();
+    /// Hands `req` over to the next `DownloadWorker`. The fetch is aborted if it isn't
+    /// completed within the timeout.
+    fn fetch(&mut self, req: Request) -> impl Future<Item=Response, Error=FetchError> {
+        let worker = &self.workers[self.next_worker];
+        self.next_worker = (self.next_worker + 1) % self.workers.len();
+        worker.send(Download { req, timeout: self.timeout })
+            .then(|res| res.unwrap_or_else(|e| Err(FetchError::Worker(e))))
+    }
 
-        // Loop through middleware and configure the ClientBuilder with any custom logic
-        // defined in any activated middleware
-        let mut cln_builder = ClientBuilder::new();
-        for m in middleware {
-            cln_builder = m.process_client(cln_builder, &req);
-        }
+    /// Fetches the robots.txt of the host of `url` and hands its `Crawl-delay` over to the
+    /// `Scheduler`. The robots.txt request isn't counted in the `State`.
+    fn fetch_robots(&mut self, url: &Url) -> Option<impl Future<Item=(), Error=()>> {
+        let host = url.host_str()?.to_string();
+        let robots_url = Robots::url(url)?;
+
+        // The User-Agent that is sent, if any, determines the applicable robots.txt record
+        let settings = &self.spider.settings().downloader;
+        let user_agent_enabled = settings.middleware_list
+            .contains(&DownloaderMiddlewareType::UserAgent);
+        let user_agent = if user_agent_enabled {
+            settings.middleware.user_agent.value.clone()
+        } else {
+            "*".to_string()
+        };
+
+        let scheduler = self.scheduler.clone();
+        let req = Request::new(robots_url, 0, 0);
+        Some(self.fetch(req)
+            .map(move |res| {
+                if !res.status.is_success() {
+                    return;
+                }
+                if let Some(delay) = Robots::parse(&res.body).crawl_delay(&user_agent) {
+                    let delay_ms = delay.as_secs() * 1000 + u64::from(delay.subsec_millis());
+                    scheduler.do_send(SetHostDelay { host, delay_ms });
+                }
+            })
+            .map_err(|e| debug!("Failed to fetch robots.txt: {:?}", e)))
+    }
 
-        let client = cln_builder.build().unwrap();
+    /// Fetches the files of the `Seeding` one at a time. Once they are all fetched, the
+    /// `Requests` of the pages found are sent to the `Scheduler`. Files that can't be
+    /// fetched are skipped.
+    fn seed_next(&mut self, ctx: &mut Context<Self>) {
+        let file = match self.seeding.as_mut().map(|seeding| seeding.next()) {
+            Some(Some(file)) => file,
+            Some(None) => {
+                let seeding = self.seeding.take().unwrap();
+                let priority = self.spider.start_priority();
+                let requests = seeding.into_requests(self.spider.crawl_rules(), priority);
+                self.scheduler.do_send(Seeded(requests));
+                return;
+            }
+            None => return,
+        };
+
+        let req = Request::new(file.url().clone(), 0, 0);
+        let fetched = wrap_future::<_, Self>(self.fetch(req))
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(res) if res.status.is_success() => {
+                        let middleware = act.spider.downloader_middleware();
+                        let res = middleware::process_response(middleware, res);
+                        if let Some(seeding) = act.seeding.as_mut() {
+                            seeding.add(&file, &res.body);
+                        }
+                    }
+                    Ok(res) => warn!("Failed to fetch {}: {}", file.url(), res.status),
+                    Err(e) => warn!("Failed to fetch {}: {:?}", file.url(), e),
+                }
+                act.seed_next(ctx);
+                actix::fut::ok(())
+            });
+        ctx.spawn(fetched);
+    }
 
-        // Loop through middleware and configure the RequestBuilder with any custom logic
-        // defined in any activated middleware
-        let mut req_builder = client.get(req.url.clone());
-        for m in middleware {
-            req_builder = m.process_request(req_builder);
+    /// The time to wait before retrying the `Request` of `response`, if it is to be retried:
+    /// it has a 429 or 503 status code and a `Retry-After` within `retry_after_max_secs`, or
+    /// it is successful with a body shorter than `min_body_bytes`, and the `Request` wasn't
+    /// retried `retry_after_retries` times yet
+    fn retry_delay(spider: &Spider, response: &Response) -> Option<Duration> {
+        let settings = &spider.settings().downloader;
+        if response.request.retries >= settings.retry_after_retries {
+            return None;
+        }
+        // A short body is taken for a transient failure of the server
+        if response.status.is_success() && response.body.len() < settings.min_body_bytes {
+            debug!("Body of {} is {} bytes, under min_body_bytes",
+                   response.request.url, response.body.len());
+            return Some(Duration::from_secs(0));
+        }
+        if !retry_after::is_retryable(response.status) {
+            return None;
         }
+        let delay = retry_after::delay(&response.headers, Utc::now())?;
+        if delay > Duration::from_secs(settings.retry_after_max_secs) {
+            warn!("Not retrying {}, Retry-After of {:?} is over retry_after_max_secs",
+                  response.request.url, delay);
+            return None;
+        }
+        Some(delay)
+    }
 
-        let response = Rc::new(RefCell::new(Response::new(req)));
-        let response_clone = Rc::clone(&response);
+    fn process(&mut self, req: Request) -> impl Future<Item=(), Error=()> {
         let spider_clone = Rc::clone(&self.spider);
-        let inner_clone1 = Rc::clone(&self.inner);
-        let inner_clone2 = Rc::clone(&self.inner);
-
-        &self.inner.borrow_mut().increase_request_total();
-
-        req_builder
-            .send()
-            .and_then(move |res| {
-                response.borrow_mut().headers = res.headers().clone();
-                res.into_body().concat2()
-            })
-            .map(move |body| {
-                let mut res = String::new();
-                match Cursor::new(body).read_to_string(&mut res) {
-                    Ok(_) => {
-                        response_clone.borrow_mut().body = res;
-
-                        let middleware = spider_clone.downloader_middleware();
-
-                        // Loop through middleware and filter/edit the Response based on any custom
-                        // logic defined in any activated middleware
-                        let mut response = response_clone.borrow().clone();
-                        for m in middleware {
-                            response = m.process_response(response);
-                        }
-
-                        // Send response to parser
-                        send!(Parser, response.clone());
+        let url_policy = self.spider.url_policy().clone();
+        let inner_clone1 = Arc::clone(&self.inner);
+        let inner_clone2 = Arc::clone(&self.inner);
+        let parser = self.parser.clone();
+        let scheduler = self.scheduler.clone();
+        let scheduler_clone = self.scheduler.clone();
+        let (events_clone1, events_clone2) = (self.events.clone(), self.events.clone());
+        let request = req.clone();
+        let started = Instant::now();
+
+        self.inner.lock().unwrap().increase_request_total();
+
+        self.fetch(req)
+            .map(move |response| {
+                let bytes = response.body.len();
+                let latency = started.elapsed();
+                inner_clone1.lock().unwrap().dispatch_outcome(Outcome {
+                    url: response.request.url.clone(),
+                    status: Some(response.status.as_u16()),
+                    latency,
+                    bytes: bytes as u64,
+                });
+                if let Some(events) = &events_clone1 {
+                    let ms = latency.as_secs() * 1000 + u64::from(latency.subsec_millis());
+                    let status = Some(response.status.as_u16());
+                    events.emit(&response.request, Event::Downloaded { status, ms });
+                }
 
-                        inner_clone1.borrow_mut().increase_request_success();
-                    }
-                    Err(e) => {
-                        error!("Read body error: {:?}", e);
-                        inner_clone1.borrow_mut().increase_request_error();
+                spider_clone.url_policy().https_fetched(&response.request.url);
+
+                // Hand the `Request` back to the `Scheduler` if the server asked to retry later
+                if let Some(delay) = Downloader::retry_delay(&spider_clone, &response) {
+                    let mut req = response.request;
+                    req.retries += 1;
+                    info!("Retrying {} in {:?} (status {}, retry {})",
+                          req.url, delay, response.status, req.retries);
+                    let delay_ms = delay.as_secs() * 1000 + u64::from(delay.subsec_millis());
+                    if let Some(events) = &events_clone1 {
+                        events.emit(&req, Event::Retried { delay_ms });
                     }
+                    scheduler.do_send(Retry { req, delay_ms });
+                    inner_clone1.lock().unwrap().increase_request_retried();
+                    return;
                 }
+
+                // Filter/edit the Response based on any custom logic defined in any activated
+                // middleware, and send the result to the parser
+                let middleware = spider_clone.downloader_middleware();
+                parser.do_send(middleware::process_response(middleware, response));
+
+                inner_clone1.lock().unwrap().increase_request_success(bytes);
             })
             .map_err(move |e| {
-                error!("Request error: {:?}", e);
-                inner_clone2.borrow_mut().increase_request_error();
+                let category = e.category();
+                match e {
+                    FetchError::Request(e) => error!("Request error ({:?}): {:?}", category, e),
+                    FetchError::Body(e) => error!("Read body error: {:?}", e),
+                    FetchError::Timeout => error!("Request timed out: {}", request.url),
+                    FetchError::BodyTimeout => error!("Body read timed out: {}", request.url),
+                    FetchError::Worker(e) => error!("Download worker error: {:?}", e),
+                    FetchError::TooManyRedirects => error!("Too many redirects: {}", request.url),
+                    // Nothing was sent, the `Request` isn't counted as failed
+                    FetchError::PrivateAddress(ip) => {
+                        warn!("Refused {}, it resolves to the private address {}",
+                              request.url, ip);
+                        inner_clone2.lock().unwrap().increase_request_refused();
+                        return;
+                    }
+                }
+                let latency = started.elapsed();
+                if let Some(events) = &events_clone2 {
+                    let ms = latency.as_secs() * 1000 + u64::from(latency.subsec_millis());
+                    events.emit(&request, Event::Downloaded { status: None, ms });
+                }
+                inner_clone2.lock().unwrap().dispatch_outcome(Outcome {
+                    url: request.url.clone(),
+                    status: None,
+                    latency,
+                    bytes: 0,
+                });
+                inner_clone2.lock().unwrap().increase_request_error(category);
+
+                // The host doesn't have https, the link is fetched over http
+                if let Some(url) = url_policy.fetch_failed(&request.url) {
+                    info!("Fetching {} over http, its host failed over https", url);
+                    let req = Request { url, dedup_url: None, ..request };
+                    scheduler_clone.do_send(Retry { req, delay_ms: 0 });
+                }
             })
     }
 }
@@ -153,19 +477,31 @@ impl Downloader {
 impl Actor for Downloader {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
         info!("Downloader is started");
+        let hosts = self.spider.sitemap_hosts();
+        if !hosts.is_empty() {
+            self.seeding = Some(Seeding::new(hosts));
+            self.seed_next(ctx);
+        }
     }
 
     fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        for arbiter in &self.arbiters {
+            arbiter.do_send(StopArbiter(0));
+        }
         info!("Downloader is stopped");
     }
 }
 
-impl actix::Supervised for Downloader {}
+/// Define handler for `CloseSpider` message, sent by the `Scheduler` once the spider is
+/// closed. The `Downloader` is stopped, and its worker arbiters with it.
+impl Handler<CloseSpider> for Downloader {
+    type Result = ();
 
-impl ArbiterService for Downloader {
-    fn service_started(&mut self, _ctx: &mut Context<Self>) {}
+    fn handle(&mut self, _msg: CloseSpider, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
 }
 
 /// Define handler for `Listener<State>` message
@@ -173,7 +509,28 @@ impl Handler<Listener<State>> for Downloader {
     type Result = ();
 
     fn handle(&mut self, msg: Listener<State>, _ctx: &mut Context<Self>) {
-        self.inner.borrow_mut().add_state_listener(msg.r);
+        self.inner.lock().unwrap().add_state_listener(msg.r);
+    }
+}
+
+/// Define handler for `Listener<Outcome>` message
+impl Handler<Listener<Outcome>> for Downloader {
+    type Result = ();
+
+    fn handle(&mut self, msg: Listener<Outcome>, _ctx: &mut Context<Self>) {
+        self.inner.lock().unwrap().add_outcome_listener(msg.r);
+    }
+}
+
+/// Define handler for `Update` message. Applies the hot-reloadable `timeout` setting.
+impl Handler<Update> for Downloader {
+    type Result = ();
+
+    fn handle(&mut self, msg: Update, _ctx: &mut Context<Self>) {
+        if let Some(v) = msg.settings.downloader.and_then(|s| s.timeout) {
+            info!("Downloader timeout changed to {}", v);
+            self.timeout = v;
+        }
     }
 }
 
@@ -183,6 +540,14 @@ impl Handler<Request> for Downloader {
 
     fn handle(&mut self, msg: Request, _ctx: &mut Context<Self>) {
         trace!("Request: {}", msg.url);
+        if self.spider.settings().downloader.respect_crawl_delay {
+            let host = msg.url.host_str().unwrap_or_default().to_string();
+            if self.robots_hosts.insert(host) {
+                if let Some(robots) = self.fetch_robots(&msg.url) {
+                    Arbiter::spawn(robots);
+                }
+            }
+        }
         Arbiter::spawn(self.process(msg));
     }
 }
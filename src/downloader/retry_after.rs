@@ -0,0 +1,56 @@
+//! Retry-After
+//!
+//! Servers send a `Retry-After` header with `429 Too Many Requests` and
+//! `503 Service Unavailable` responses to tell how long to wait before trying again. See
+//! [RFC 7231](https://tools.ietf.org/html/rfc7231#section-7.1.3) for the format.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+/// Whether a `Response` with `status` is retried after its `Retry-After`
+pub fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// The time to wait given by the `Retry-After` header of `headers`, if any. A date in the
+/// past means no wait.
+pub fn delay(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    parse(headers.get(RETRY_AFTER)?.to_str().ok()?, now)
+}
+
+/// Parses a `Retry-After` value, either a number of seconds (`120`) or an HTTP date
+/// (`Wed, 21 Oct 2015 07:28:00 GMT`)
+pub fn parse(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    Some((date - now).to_std().unwrap_or_else(|_| Duration::from_secs(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:27:00Z").unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse("5", now), Some(Duration::from_secs(5)));
+        assert_eq!(parse(" 120 ", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse("Wed, 21 Oct 2015 07:28:00 GMT", now), Some(Duration::from_secs(60)));
+        assert_eq!(parse("Wed, 21 Oct 2015 07:00:00 GMT", now), Some(Duration::from_secs(0)));
+        assert_eq!(parse("-1", now), None);
+        assert_eq!(parse("soon", now), None);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(delay(&headers, now), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        assert_eq!(delay(&headers, now), Some(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,141 @@
+//! robots.txt
+//!
+//! Only the directives that Vortex acts upon are parsed. See
+//! [robotstxt.org](http://www.robotstxt.org/robotstxt.html) for the format.
+use std::time::Duration;
+
+use reqwest::Url;
+
+/// The records of a robots.txt file
+#[derive(Debug, Default)]
+pub struct Robots {
+    groups: Vec<Group>,
+    sitemaps: Vec<String>,
+}
+
+/// A record: the directives that apply to a set of user agents
+#[derive(Debug, Default)]
+struct Group {
+    user_agents: Vec<String>,
+    crawl_delay: Option<f64>,
+}
+
+impl Robots {
+    pub fn parse(content: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut sitemaps = Vec::new();
+        let mut in_user_agents = false;
+
+        for line in content.lines() {
+            // Strip comments
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap_or_default().trim().to_lowercase();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    // Consecutive user-agent lines share a record
+                    if !in_user_agents {
+                        groups.push(Group::default());
+                    }
+                    in_user_agents = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.user_agents.push(value.to_lowercase());
+                    }
+                }
+                "crawl-delay" => {
+                    in_user_agents = false;
+                    if let (Some(group), Ok(delay)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if delay >= 0.0 {
+                            group.crawl_delay = Some(delay);
+                        }
+                    }
+                }
+                // Not part of a record, it applies to every user agent
+                "sitemap" => {
+                    in_user_agents = false;
+                    sitemaps.push(value.to_string());
+                }
+                _ => in_user_agents = false,
+            }
+        }
+
+        Self { groups, sitemaps }
+    }
+
+    /// The `Crawl-delay` for the user agent. A record that names the user agent takes
+    /// precedence over the `*` record.
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.group(user_agent)
+            .and_then(|group| group.crawl_delay)
+            .map(|delay| Duration::from_millis((delay * 1000.0) as u64))
+    }
+
+    /// The URLs of the `Sitemap` lines, as they are written
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+
+    /// The URL of the robots.txt file of the host of `url`
+    pub fn url(url: &Url) -> Option<Url> {
+        url.join("/robots.txt").ok()
+    }
+
+    fn group(&self, user_agent: &str) -> Option<&Group> {
+        // Match on the product token, e.g. "vortex" for "Vortex/0.1 (+http://...)"
+        let token = user_agent.split('/').next().unwrap_or_default().trim().to_lowercase();
+        let named = self.groups.iter().find(|group| {
+            group.user_agents.iter().any(|agent| agent != "*" && token.contains(agent.as_str()))
+        });
+        named.or_else(|| {
+            self.groups.iter().find(|group| group.user_agents.iter().any(|agent| agent == "*"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crawl_delay() {
+        let robots = Robots::parse("
+            # Slow down
+            User-agent: *
+            Disallow: /private
+            Crawl-delay: 2.5
+
+            User-agent: Googlebot
+            User-agent: Vortex
+            Crawl-delay: 1 # seconds
+
+            User-agent: Other
+            Disallow: /
+        ");
+        assert_eq!(robots.crawl_delay("Vortex/0.1"), Some(Duration::from_secs(1)));
+        assert_eq!(robots.crawl_delay("Mozilla/5.0"), Some(Duration::from_millis(2500)));
+        assert_eq!(robots.crawl_delay("Other"), None);
+        assert_eq!(Robots::parse("").crawl_delay("Vortex"), None);
+
+        let url = Url::parse("https://en.wikipedia.org:8080/wiki/Rust?a=b").unwrap();
+        assert_eq!(Robots::url(&url).unwrap().as_str(), "https://en.wikipedia.org:8080/robots.txt");
+    }
+
+    #[test]
+    fn test_sitemaps() {
+        let robots = Robots::parse("
+            Sitemap: https://site.test/sitemap.xml
+            User-agent: *
+            SITEMAP:https://site.test/news.xml.gz # gzipped
+            Crawl-delay: 2
+        ");
+        assert_eq!(robots.sitemaps(),
+                   ["https://site.test/sitemap.xml", "https://site.test/news.xml.gz"]);
+        assert_eq!(robots.crawl_delay("Vortex"), Some(Duration::from_secs(2)));
+        assert!(Robots::parse("User-agent: *").sitemaps().is_empty());
+    }
+}
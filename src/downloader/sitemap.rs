@@ -0,0 +1,236 @@
+//! Sitemaps
+//!
+//! Sitemaps list the pages of a site (`<urlset>`), or other sitemaps (`<sitemapindex>`).
+//! Only `<loc>` and `<lastmod>` are parsed. See
+//! [sitemaps.org](https://www.sitemaps.org/protocol.html) for the format.
+//!
+//! With `SpiderBuilder::start_from_sitemaps`, the `Downloader` seeds the crawl from the
+//! sitemaps listed in the robots.txt of the hosts, see `Seeding`.
+use std::collections::{HashSet, VecDeque};
+
+use reqwest::{header::{CONTENT_TYPE, HeaderMap}, Url};
+use serde_json::Value;
+
+use crate::crawler::{Request, RequestVec};
+use crate::downloader::Robots;
+use crate::parser::xml;
+use crate::spider::{CrawlRule, ParseRule};
+
+/// The most sitemaps fetched while seeding, so that index files that list each other (or
+/// generate sitemaps endlessly) don't keep the crawl from starting
+const MAX_SITEMAPS: usize = 1000;
+
+/// A `<url>` or `<sitemap>` entry of a sitemap
+#[derive(Clone, Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: Url,
+    pub lastmod: Option<String>,
+}
+
+/// A parsed sitemap file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sitemap {
+    /// A `<urlset>`, the entries are pages
+    UrlSet(Vec<SitemapEntry>),
+
+    /// A `<sitemapindex>`, the entries are other sitemaps
+    Index(Vec<SitemapEntry>),
+}
+
+impl Sitemap {
+    /// Whether a `Response` with `headers` and `content` is a sitemap: it is sent as XML
+    /// (or gzipped, e.g. `sitemap.xml.gz`) and its root element is a `<urlset>` or a
+    /// `<sitemapindex>`
+    pub fn detect(headers: &HeaderMap, content: &str) -> bool {
+        let content_type = headers.get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        if !content_type.contains("xml") && !content_type.contains("gzip") {
+            return false;
+        }
+        xml::root(content).is_some_and(|root| root == "urlset" || root == "sitemapindex")
+    }
+
+    /// The entries of the sitemap, pages or other sitemaps
+    pub fn entries(&self) -> &[SitemapEntry] {
+        match self {
+            Sitemap::UrlSet(entries) | Sitemap::Index(entries) => entries,
+        }
+    }
+
+    /// Parses the sitemap found at `url`. Relative locations are resolved against `url`,
+    /// entries without a valid location are skipped.
+    pub fn parse(url: &Url, content: &str) -> Self {
+        let index = content.contains("<sitemapindex");
+        let entries = xml::elements(content, if index { "sitemap" } else { "url" }).iter()
+            .filter_map(|entry| {
+                Some(SitemapEntry {
+                    loc: url.join(&xml::element_text(entry, "loc")?).ok()?,
+                    lastmod: xml::element_text(entry, "lastmod").filter(|m| !m.is_empty()),
+                })
+            })
+            .collect();
+
+        if index { Sitemap::Index(entries) } else { Sitemap::UrlSet(entries) }
+    }
+}
+
+/// A file fetched while seeding
+#[derive(Clone, Debug)]
+pub(crate) enum SeedFile {
+    Robots(Url),
+    Sitemap(Url),
+}
+
+impl SeedFile {
+    pub fn url(&self) -> &Url {
+        match self {
+            SeedFile::Robots(url) | SeedFile::Sitemap(url) => url,
+        }
+    }
+}
+
+/// Seeds a crawl from sitemaps: the robots.txt of each host is fetched, the sitemaps of
+/// its `Sitemap` lines, and the sitemaps of the index files among them, in that order.
+pub(crate) struct Seeding {
+    pending: VecDeque<SeedFile>,
+    /// The sitemaps fetched so far
+    fetched: HashSet<Url>,
+    pages: Vec<SitemapEntry>,
+}
+
+impl Seeding {
+    pub fn new(hosts: &[Url]) -> Self {
+        Self {
+            pending: hosts.iter().filter_map(Robots::url).map(SeedFile::Robots).collect(),
+            fetched: HashSet::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    /// The next file to fetch, `None` once seeding is done. Sitemaps listed more than once
+    /// are fetched once.
+    pub fn next(&mut self) -> Option<SeedFile> {
+        while let Some(file) = self.pending.pop_front() {
+            if let SeedFile::Sitemap(url) = &file {
+                if self.fetched.len() >= MAX_SITEMAPS {
+                    warn!("Fetched {} sitemaps, skipping the other {}",
+                          MAX_SITEMAPS, self.pending.len() + 1);
+                    self.pending.clear();
+                    return None;
+                }
+                if !self.fetched.insert(url.clone()) {
+                    continue;
+                }
+            }
+            return Some(file);
+        }
+        None
+    }
+
+    /// Adds what is found in the `body` of `file`
+    pub fn add(&mut self, file: &SeedFile, body: &str) {
+        match file {
+            SeedFile::Robots(url) => {
+                let robots = Robots::parse(body);
+                let sitemaps = robots.sitemaps().iter()
+                    .filter_map(|sitemap| url.join(sitemap).ok())
+                    .map(SeedFile::Sitemap);
+                self.pending.extend(sitemaps);
+            }
+            SeedFile::Sitemap(url) => match Sitemap::parse(url, body) {
+                Sitemap::UrlSet(entries) => self.pages.extend(entries),
+                Sitemap::Index(entries) => {
+                    self.pending.extend(entries.into_iter().map(|e| SeedFile::Sitemap(e.loc)))
+                }
+            },
+        }
+    }
+
+    /// The depth 0 `Requests` of the pages found, that the `ParseRule::FilterUrls` crawl
+    /// rules allow, with the `priority` of the start URLs. The `<lastmod>` of a page is
+    /// added to the `meta` of its `Request`.
+    pub fn into_requests(self, crawl_rules: &[CrawlRule], priority: u32) -> RequestVec {
+        let requests = self.pages.into_iter()
+            .filter(|page| {
+                crawl_rules.iter().all(|rule| match rule.parse_rule {
+                    ParseRule::FilterUrls => rule.condition.allows_url(&page.loc),
+                    _ => true,
+                })
+            })
+            .map(|page| {
+                let mut req = Request::new(page.loc, 0, priority);
+                if let Some(lastmod) = page.lastmod {
+                    req.meta.insert("lastmod".to_string(), Value::String(lastmod));
+                }
+                req
+            })
+            .collect();
+        RequestVec::new(requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let url = Url::parse("https://site.test/sitemap.xml").unwrap();
+        let sitemap = Sitemap::parse(&url, r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url>
+                <loc>https://site.test/a?x=1&amp;y=2</loc>
+                <lastmod>2019-03-01</lastmod>
+                <changefreq>daily</changefreq>
+              </url>
+              <url><loc> <![CDATA[https://site.test/b]]> </loc></url>
+              <url><loc>/c</loc><lastmod></lastmod></url>
+              <url><lastmod>2019-03-01</lastmod></url>
+            </urlset>"#);
+        let entry = |loc: &str, lastmod: Option<&str>| SitemapEntry {
+            loc: Url::parse(loc).unwrap(),
+            lastmod: lastmod.map(|l| l.to_string()),
+        };
+        assert_eq!(sitemap, Sitemap::UrlSet(vec![
+            entry("https://site.test/a?x=1&y=2", Some("2019-03-01")),
+            entry("https://site.test/b", None),
+            entry("https://site.test/c", None),
+        ]));
+
+        let sitemap = Sitemap::parse(&url, r#"
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap>
+                <loc>https://site.test/pages.xml.gz</loc>
+                <lastmod>2019-03-01T10:00:00+00:00</lastmod>
+              </sitemap>
+              <sitemap><loc>https://site.test/news.xml</loc></sitemap>
+            </sitemapindex>"#);
+        assert_eq!(sitemap, Sitemap::Index(vec![
+            entry("https://site.test/pages.xml.gz", Some("2019-03-01T10:00:00+00:00")),
+            entry("https://site.test/news.xml", None),
+        ]));
+
+        assert_eq!(Sitemap::parse(&url, "Not Found"), Sitemap::UrlSet(vec![]));
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = |content_type: Option<&str>, content: &str| {
+            let mut headers = HeaderMap::new();
+            if let Some(content_type) = content_type {
+                headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+            }
+            Sitemap::detect(&headers, content)
+        };
+        let urlset = r#"<?xml version="1.0"?><!-- <html> --><urlset xmlns="...">"#;
+        assert!(detect(Some("application/xml"), urlset));
+        assert!(detect(Some("Text/XML; charset=utf-8"), "<sitemapindex>"));
+        assert!(detect(Some("application/x-gzip"), urlset));
+        assert!(!detect(Some("text/html"), urlset));
+        assert!(!detect(None, urlset));
+        assert!(!detect(Some("application/xml"), "<rss><urlset>"));
+        assert!(!detect(Some("application/xml"), "<!-- <urlset> unclosed"));
+    }
+}
@@ -0,0 +1,342 @@
+//! Dry runs: what a spider would crawl, without running the crawl
+//!
+//! A dry run fetches a limited number of pages, one at a time, and records for each page
+//! which links were found, what each crawl rule made of them and which data was extracted.
+//! `Item`s are not sent to the `Pipeline`, so nothing is exported. The `DryRunReport`
+//! serializes to JSON, so the effect of a change to the crawl rules can be diffed.
+//!
+//! `Spider::check` applies the crawl rules to a saved sample page instead, and reports the
+//! fields that weren't extracted, so that tests can tell when a site change breaks a rule.
+use std::collections::{HashSet, VecDeque};
+
+use actix::System;
+use serde_json::Value;
+
+use reqwest::Url;
+
+use crate::crawler::{Request, Response};
+use crate::downloader::{Fetch, middleware};
+use crate::parser::{Page, Parser};
+use crate::spider::Spider;
+
+/// The outcome of a dry run, see `Crawler::dry_run_pages`
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DryRunReport {
+    /// The pages that were fetched, in the order they were fetched
+    pub pages: Vec<PageReport>,
+}
+
+impl DryRunReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// What the spider made of a single page
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PageReport {
+    pub url: String,
+    pub depth: u32,
+
+    /// The status code of the `Response`. `None` if no `Response` was received.
+    pub status: Option<u16>,
+
+    /// Why no `Response` was received
+    pub error: Option<String>,
+
+    /// The links found on the page
+    pub links: Vec<String>,
+
+    /// The outcome of each crawl rule, in the order of the rules
+    pub rules: Vec<RuleReport>,
+
+    /// The links that would be followed
+    pub followed: Vec<String>,
+
+    /// The data that would be sent to the `Pipeline`
+    pub items: Vec<Value>,
+}
+
+/// The outcome of a crawl rule on a page. `rule` is the index of the rule, `name` its
+/// `CrawlRule::name`, left out of the JSON if the rule has none.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RuleReport {
+    /// `ParseRule::FilterUrls`: the links the condition allowed and denied
+    FilterUrls {
+        rule: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        allowed: Vec<String>,
+        denied: Vec<String>,
+    },
+
+    /// `ParseRule::Page`: the number of values the callback returned
+    Page {
+        rule: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        values: usize,
+    },
+
+    /// `ParseRule::Pattern`: whether the condition allowed the URL of the page, and the
+    /// matches of the pattern
+    Pattern {
+        rule: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        field: String,
+        applied: bool,
+        matches: Vec<String>,
+    },
+
+    /// `ParseRule::Feed`: whether the condition allowed the URL of the feed, and the number
+    /// of entries turned into `Items`
+    Feed {
+        rule: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        applied: bool,
+        entries: usize,
+    },
+
+    /// `ParseRule::List`: whether the condition allowed the URL of the page, the number of
+    /// elements that matched the container and of `Items` made of them
+    List {
+        rule: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        applied: bool,
+        containers: usize,
+        items: usize,
+    },
+}
+
+/// The outcome of `Spider::check` on a sample page
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ContractResult {
+    pub url: String,
+
+    /// The outcome of each crawl rule, in the order of the rules
+    pub rules: Vec<RuleReport>,
+
+    /// The links that would be followed
+    pub followed: Vec<String>,
+
+    /// The data that would be sent to the `Pipeline`
+    pub items: Vec<Value>,
+
+    /// The fields of the `ParseRule::Pattern` rules that applied to the page, but are in
+    /// none of the `items`
+    pub missing: Vec<String>,
+}
+
+impl ContractResult {
+    /// Whether every `ParseRule::Pattern` rule that applied to the page extracted its field
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Whether an `Item` has a value other than `null` for `field`
+    pub fn has_field(&self, field: &str) -> bool {
+        self.items.iter().any(|item| item.get(field).is_some_and(|value| !value.is_null()))
+    }
+
+    /// Panics, listing the fields that are missing, if the rules didn't extract all of
+    /// `fields` and the fields of the `ParseRule::Pattern` rules that applied to the page
+    pub fn assert_fields(&self, fields: &[&str]) {
+        let mut missing = self.missing.clone();
+        for field in fields {
+            if !self.has_field(field) && !missing.iter().any(|m| m == field) {
+                missing.push(field.to_string());
+            }
+        }
+        assert!(missing.is_empty(), "{}: no value extracted for {:?}", self.url, missing);
+    }
+}
+
+/// Applies the crawl rules of `spider` to `html`, a sample of the page at `url`
+pub(crate) fn check(spider: &Spider, url: Url, html: &str) -> ContractResult {
+    let mut res = Response::new(Request::new(url, 0, 1));
+    res.body = html.to_string();
+
+    let mut result = ContractResult { url: res.request.url.to_string(), ..Default::default() };
+    let (urls, items) = Parser::parse_traced(spider, &res, Some(&mut result.rules));
+    result.followed = urls.iter().map(|url| url.to_string()).collect();
+    result.items = items;
+    for report in &result.rules {
+        if let RuleReport::Pattern { field, applied: true, .. } = report {
+            if !result.has_field(field) && !result.missing.contains(field) {
+                result.missing.push(field.clone());
+            }
+        }
+    }
+    result
+}
+
+/// Crawls up to `max_pages` pages breadth first, starting from the spider's start urls
+pub(crate) fn run(spider: &Spider, fetcher: &dyn Fetch, max_pages: usize) -> DryRunReport {
+    let mut sys = System::new("dry-run");
+
+    let max_depth = spider.settings().scheduler.max_depth;
+    let mut queue: VecDeque<Request> = spider.start_requests().requests.iter().cloned().collect();
+    let mut seen: HashSet<_> = queue.iter().map(|req| req.url.clone()).collect();
+    let mut report = DryRunReport::default();
+
+    while report.pages.len() < max_pages {
+        let req = match queue.pop_front() {
+            Some(req) => req,
+            None => break,
+        };
+        let mut page = PageReport {
+            url: req.url.to_string(),
+            depth: req.depth,
+            ..Default::default()
+        };
+
+        match sys.block_on(fetcher.fetch(req.clone(), spider.downloader_middleware())) {
+            Ok(res) => {
+                let res = middleware::process_response(spider.downloader_middleware(), res);
+                page.status = Some(res.status.as_u16());
+                page.links = Page::with_normalizer(&res, spider.url_normalizer()).urls().iter()
+                    .map(|u| u.to_string())
+                    .collect();
+
+                let (urls, data) = Parser::parse_traced(spider, &res, Some(&mut page.rules));
+                page.items = data;
+
+                // Links found at `max_depth` are not followed
+                if max_depth == 0 || req.depth < max_depth {
+                    for url in urls {
+                        page.followed.push(url.to_string());
+                        if seen.insert(url.clone()) {
+                            queue.push_back(Request::new(url, req.depth + 1, 1));
+                        }
+                    }
+                }
+            }
+            Err(e) => page.error = Some(format!("{:?}", e)),
+        }
+
+        info!("Dry run: {} ({} links followed, {} items)",
+              page.url, page.followed.len(), page.items.len());
+        report.pages.push(page);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::Crawler;
+    use crate::downloader::MockFetch;
+    use crate::spider::{Condition, ParseRule, Pattern, SpiderBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_dry_run_pages() {
+        let fetcher = MockFetch::default()
+            .page("http://site.test/", 200,
+                  r#"<h1>Home</h1><a href="/a">A</a><a href="/login">Login</a>"#)
+            .page("http://site.test/a", 200, r#"<h1>A</h1><a href="/b">B</a>"#);
+
+        let cnd = Condition::new(vec!["site.test"], vec!["login"]);
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/"])
+            .crawl_rule(cnd.clone(), ParseRule::FilterUrls)
+            .crawl_rule(
+                cnd.clone(),
+                ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .build();
+
+        let report = Crawler::dry_run_pages_with_fetcher(&spider, &fetcher, 2);
+        let urls: Vec<&str> = report.pages.iter().map(|p| p.url.as_str()).collect();
+        assert_eq!(urls, vec!["http://site.test/", "http://site.test/a"]);
+
+        let home = &report.pages[0];
+        assert_eq!(home.status, Some(200));
+        assert_eq!(home.followed, vec!["http://site.test/a"]);
+        assert_eq!(home.items, vec![json!({ "title": "Home" })]);
+
+        let json: Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["pages"][0]["rules"], json!([
+            {
+                "kind": "filter_urls",
+                "rule": 0,
+                "allowed": ["http://site.test/a"],
+                "denied": ["http://site.test/login"]
+            },
+            {
+                "kind": "pattern",
+                "rule": 1,
+                "field": "title",
+                "applied": true,
+                "matches": ["Home"]
+            }
+        ]));
+    }
+
+    #[test]
+    fn test_check() {
+        let spider = SpiderBuilder::default()
+            .crawl_rule(Condition::new(vec!["site.test"], vec![]), ParseRule::FilterUrls)
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::pattern("price", Pattern::CssSelector(".price"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .crawl_rule(
+                Condition::new(vec!["site.test/shop"], vec![]),
+                ParseRule::pattern("sku", Pattern::CssSelector(".sku"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .build();
+
+        let result = spider.check("http://site.test/a", r#"<h1>A</h1><a href="/b">B</a>"#);
+        assert!(!result.is_ok());
+        assert_eq!(result.missing, vec!["price"]);
+        assert!(result.has_field("title"));
+        assert_eq!(result.followed, vec!["http://site.test/b"]);
+
+        let result = spider.check("http://site.test/a", r#"<h1>A</h1><p class="price">3</p>"#);
+        assert!(result.is_ok());
+        result.assert_fields(&["title", "price"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no value extracted for [\"price\", \"sku\"]")]
+    fn test_assert_fields() {
+        let spider = SpiderBuilder::default()
+            .crawl_rule(
+                Condition::new(vec!["site.test"], vec![]),
+                ParseRule::pattern("price", Pattern::CssSelector(".price"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .build();
+        spider.check("http://site.test/a", "<h1>A</h1>").assert_fields(&["price", "sku"]);
+    }
+
+    /// The title rule of the wikipedia example still works on the saved article
+    #[test]
+    fn test_check_wikipedia() {
+        let spider = SpiderBuilder::default()
+            .crawl_rule(
+                Condition::new(vec![r"en.wikipedia.org/wiki"], vec![r":[A-Za-z]|\?|#"]),
+                ParseRule::pattern("title", Pattern::CssSelector(".firstHeading"), |s| {
+                    Some(Value::String(s.first().unwrap().clone()))
+                }))
+            .build();
+        let html = include_str!("../examples/fixtures/wikipedia_rust.html");
+        let result = spider.check("http://en.wikipedia.org/wiki/Rust_(programming_language)", html);
+        result.assert_fields(&["title"]);
+        assert_eq!(result.items[0]["title"], json!("Rust (programming language)"));
+    }
+}
@@ -0,0 +1,94 @@
+//! Per-request lifecycle events
+//!
+//! With `StatsSettings::event_log_path` set, every step a `Request` goes through is written
+//! to that file as a line of JSON (NDJSON), e.g. (on a single line)
+//!
+//! ```text
+//! {"at":"2019-03-01T10:00:00.120Z","event":"downloaded","id":12,"ms":85,"status":200,
+//!  "url":"http://site.test/a"}
+//! ```
+//!
+//! Each line carries the URL and the id of the `Request`, so the history of a URL can be
+//! found with `grep`. The ids are given by the `Scheduler` as the `Requests` are queued.
+//!
+//! The events are written as they happen, by the actor that handles the `Request`, so the
+//! file is complete once the crawl has returned.
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::Value;
+
+use crate::crawler::Request;
+use crate::settings::StatsSettings;
+
+/// A step of a `Request` through the crawl
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Added to the queue of the `Scheduler`
+    Enqueued { depth: u32 },
+
+    /// Sent to the `Downloader`
+    Dispatched,
+
+    /// Fetched in `ms` milliseconds. `status` is `None` if no `Response` was received.
+    Downloaded { status: Option<u16>, ms: u64 },
+
+    /// Handed back to the `Scheduler`, to be dispatched again in `delay_ms` milliseconds
+    Retried { delay_ms: u64 },
+
+    /// The crawl rules were applied to the `Response`: the rules that extracted data, the
+    /// number of links to follow and of `Items`
+    Parsed { rules_matched: Vec<usize>, links_found: usize, items: usize },
+
+    /// An `Item` of the `Request` went through the pipeline `elements`. `dropped_by` names
+    /// the element that dropped it, if any.
+    Piped { elements: Vec<String>, dropped_by: Option<String> },
+}
+
+/// Writes the `Events` of a crawl to a file, see the module documentation
+pub struct EventLog {
+    path: String,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl EventLog {
+    /// Creates (or truncates) the file at `path`
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self { path: path.to_string(), file: Mutex::new(LineWriter::new(file)) })
+    }
+
+    /// The `EventLog` of `event_log_path`, if it is set. A file that can't be created is
+    /// logged, and the crawl runs without an `EventLog`.
+    pub fn from_settings(settings: &StatsSettings) -> Option<Arc<Self>> {
+        let path = &settings.event_log_path;
+        if path.is_empty() {
+            return None;
+        }
+        match EventLog::open(path) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                error!("Failed to create the event log {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Writes `event` of `req`
+    pub fn emit(&self, req: &Request, event: Event) {
+        let mut line = serde_json::to_value(&event).unwrap();
+        if let Value::Object(fields) = &mut line {
+            let at = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            fields.insert("at".to_string(), Value::String(at));
+            fields.insert("id".to_string(), json!(req.id));
+            fields.insert("url".to_string(), Value::String(req.url.to_string()));
+        }
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            error!("Failed to write to the event log {}: {}", self.path, e);
+        }
+    }
+}
@@ -19,13 +19,15 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 
-#[macro_use]
 pub mod crawler;
 pub mod downloader;
+pub mod dry_run;
+mod events;
 pub mod parser;
 pub mod pipeline;
-mod scheduler;
+pub mod scheduler;
 pub mod settings;
+mod shutdown;
 pub mod spider;
 mod stats;
 pub mod print;
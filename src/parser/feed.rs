@@ -0,0 +1,182 @@
+//! RSS 2.0 and Atom feeds
+//!
+//! Only the parts of an entry that end up in an `Item` are parsed, see `ParseRule::Feed`.
+//! See the [RSS 2.0](https://www.rssboard.org/rss-specification) and
+//! [Atom](https://tools.ietf.org/html/rfc4287) specifications for the formats.
+use reqwest::{header::{CONTENT_TYPE, HeaderMap}, Url};
+
+use crate::parser::xml;
+
+/// An `<item>` of an RSS feed, or an `<entry>` of an Atom feed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeedEntry {
+    pub title: Option<String>,
+
+    /// The link to the entry, resolved against the URL of the feed
+    pub link: Option<Url>,
+
+    /// As written in the feed: `<pubDate>` (RFC 2822) for RSS, `<published>` or else
+    /// `<updated>` (RFC 3339) for Atom
+    pub published: Option<String>,
+
+    /// `<description>` for RSS, `<summary>` or else `<content>` for Atom. HTML markup is
+    /// kept.
+    pub summary: Option<String>,
+}
+
+/// A parsed feed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Feed {
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// Whether a `Response` with `headers` and `content` is a feed: it is sent as XML (e.g.
+    /// `application/rss+xml`) and its root element is an `<rss>` or a `<feed>`
+    pub fn detect(headers: &HeaderMap, content: &str) -> bool {
+        let content_type = headers.get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        content_type.contains("xml")
+            && xml::root(content).is_some_and(|root| root == "rss" || root == "feed")
+    }
+
+    /// Parses the feed found at `url`
+    pub fn parse(url: &Url, content: &str) -> Self {
+        let entries = if xml::root(content) == Some("feed") {
+            xml::elements(content, "entry").iter()
+                .map(|entry| FeedEntry {
+                    title: Utils::text(entry, "title"),
+                    link: Utils::atom_link(url, entry),
+                    published: Utils::text(entry, "published")
+                        .or_else(|| Utils::text(entry, "updated")),
+                    summary: Utils::text(entry, "summary")
+                        .or_else(|| Utils::text(entry, "content")),
+                })
+                .collect()
+        } else {
+            xml::elements(content, "item").iter()
+                .map(|item| FeedEntry {
+                    title: Utils::text(item, "title"),
+                    link: Utils::text(item, "link").and_then(|link| url.join(&link).ok()),
+                    published: Utils::text(item, "pubDate"),
+                    summary: Utils::text(item, "description"),
+                })
+                .collect()
+        };
+        Self { entries }
+    }
+
+    /// The links of the entries
+    pub fn links(&self) -> Vec<Url> {
+        self.entries.iter().filter_map(|entry| entry.link.clone()).collect()
+    }
+}
+
+struct Utils;
+
+impl Utils {
+    /// The text of the first `name` element, `None` if it is empty
+    fn text(content: &str, name: &str) -> Option<String> {
+        xml::element_text(content, name).filter(|text| !text.is_empty())
+    }
+
+    /// The `href` of the `alternate` link of an Atom entry, a link without `rel` being an
+    /// `alternate` one
+    fn atom_link(url: &Url, entry: &str) -> Option<Url> {
+        let links = xml::tags(entry, "link");
+        let link = links.iter()
+            .find(|link| link.get("rel").is_none_or(|rel| rel == "alternate"))?;
+        url.join(link.get("href")?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, link: &str, published: &str, summary: &str) -> FeedEntry {
+        let some = |s: &str| if s.is_empty() { None } else { Some(s.to_string()) };
+        FeedEntry {
+            title: some(title),
+            link: some(link).map(|link| Url::parse(&link).unwrap()),
+            published: some(published),
+            summary: some(summary),
+        }
+    }
+
+    #[test]
+    fn test_rss() {
+        let url = Url::parse("http://news.test/feeds/rss.xml").unwrap();
+        let feed = Feed::parse(&url, r#"<?xml version="1.0" encoding="UTF-8"?>
+            <rss version="2.0">
+              <channel>
+                <title>News</title>
+                <link>http://news.test/</link>
+                <item>
+                  <title>Rust 1.0 &amp; beyond</title>
+                  <link>http://news.test/2015/rust</link>
+                  <pubDate>Fri, 15 May 2015 10:00:00 GMT</pubDate>
+                  <description><![CDATA[<p>Stable at last</p>]]></description>
+                </item>
+                <item>
+                  <title>Relative</title>
+                  <link>../2019/relative</link>
+                </item>
+                <item><description>No link</description></item>
+              </channel>
+            </rss>"#);
+        assert_eq!(feed.entries, vec![
+            entry("Rust 1.0 & beyond", "http://news.test/2015/rust",
+                  "Fri, 15 May 2015 10:00:00 GMT", "<p>Stable at last</p>"),
+            entry("Relative", "http://news.test/2019/relative", "", ""),
+            entry("", "", "", "No link"),
+        ]);
+        assert_eq!(feed.links().len(), 2);
+    }
+
+    #[test]
+    fn test_atom() {
+        let url = Url::parse("http://news.test/feeds/atom.xml").unwrap();
+        let feed = Feed::parse(&url, r#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+              <title>News</title>
+              <link href="http://news.test/"/>
+              <entry>
+                <title type="html">Rust 1.0</title>
+                <link rel="enclosure" href="http://news.test/rust.mp3"/>
+                <link rel="alternate" type="text/html" href="http://news.test/2015/rust"/>
+                <published>2015-05-15T10:00:00Z</published>
+                <updated>2015-05-16T10:00:00Z</updated>
+                <summary>Stable at last</summary>
+              </entry>
+              <entry>
+                <title>Relative</title>
+                <link href="/2019/relative"/>
+                <updated>2019-03-01T10:00:00Z</updated>
+                <content type="html">&lt;p&gt;Full text&lt;/p&gt;</content>
+              </entry>
+            </feed>"#);
+        assert_eq!(feed.entries, vec![
+            entry("Rust 1.0", "http://news.test/2015/rust", "2015-05-15T10:00:00Z",
+                  "Stable at last"),
+            entry("Relative", "http://news.test/2019/relative", "2019-03-01T10:00:00Z",
+                  "<p>Full text</p>"),
+        ]);
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = |content_type: &str, content: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+            Feed::detect(&headers, content)
+        };
+        assert!(detect("application/rss+xml", r#"<?xml version="1.0"?><rss version="2.0">"#));
+        assert!(detect("application/atom+xml; charset=utf-8", "<feed xmlns='...'>"));
+        assert!(detect("text/xml", "<rss>"));
+        assert!(!detect("text/html", "<rss>"));
+        assert!(!detect("application/xml", "<urlset>"));
+    }
+}
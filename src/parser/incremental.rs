@@ -0,0 +1,115 @@
+//! Incremental crawls
+//!
+//! With `ParserSettings::incremental`, a hash of the content of every page parsed is kept in
+//! `incremental_path`, a JSON object of URLs and hashes, e.g.
+//!
+//! ```text
+//! { "http://site.test/a": "a3f29c1e0b6d4e87" }
+//! ```
+//!
+//! When a recrawl finds the same content at a URL, no `Items` are made of it. Its links are
+//! still followed, so that new pages are found. The hashes are written once the crawl is
+//! closed, those of the pages that weren't crawled again are kept.
+use std::collections::BTreeMap;
+use std::{fs, io};
+
+use reqwest::Url;
+
+use crate::settings::ParserSettings;
+
+/// The content hashes of the pages of the previous crawls, see the module documentation
+pub(crate) struct Incremental {
+    path: String,
+    hashes: BTreeMap<String, String>,
+}
+
+impl Incremental {
+    /// Reads the hashes of the previous crawls from `path`. A missing file is the first
+    /// crawl, a file that can't be read is logged and ignored.
+    pub fn open(path: &str) -> Self {
+        let hashes = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring the incremental crawl state {}: {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!("Ignoring the incremental crawl state {}: {}", path, e);
+                BTreeMap::new()
+            }
+        };
+        Self { path: path.to_string(), hashes }
+    }
+
+    /// The `Incremental` of `incremental_path`, if `incremental` is set
+    pub fn from_settings(settings: &ParserSettings) -> Option<Self> {
+        if settings.incremental {
+            Some(Incremental::open(&settings.incremental_path))
+        } else {
+            None
+        }
+    }
+
+    /// The hash of `content`. It is the same from one build to the next, unlike that of
+    /// `std::hash`, as it is compared with that of previous crawls.
+    pub fn hash(content: &str) -> u64 {
+        // 64-bit FNV-1a
+        content.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Records `hash` as the content of `url`. Returns whether it is the same as in the
+    /// previous crawls.
+    pub fn unchanged(&mut self, url: &Url, hash: u64) -> bool {
+        let hash = format!("{:016x}", hash);
+        self.hashes.insert(url.to_string(), hash.clone()).is_some_and(|old| old == hash)
+    }
+
+    /// Writes the hashes to `incremental_path`
+    pub fn save(&self) -> io::Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(&self.hashes).unwrap())?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn test_incremental() {
+        let path = std::env::temp_dir().join(format!("vortex-incremental-{}.json", process::id()));
+        let path = path.to_str().unwrap();
+        let url = |path: &str| Url::parse(&format!("http://site.test/{}", path)).unwrap();
+        assert_eq!(Incremental::hash("<h1>A</h1>"), Incremental::hash("<h1>A</h1>"));
+        assert_ne!(Incremental::hash("<h1>A</h1>"), Incremental::hash("<h1>B</h1>"));
+        assert_eq!(Incremental::hash(""), 0xcbf2_9ce4_8422_2325);
+
+        let mut incremental = Incremental::open(path);
+        assert!(!incremental.unchanged(&url("a"), 1));
+        assert!(!incremental.unchanged(&url("b"), 2));
+        assert!(incremental.unchanged(&url("a"), 1));
+        incremental.save().unwrap();
+
+        // The next crawl compares with the saved hashes, and keeps those it doesn't see
+        let mut incremental = Incremental::open(path);
+        assert!(incremental.unchanged(&url("a"), 1));
+        assert!(!incremental.unchanged(&url("c"), 3));
+        incremental.save().unwrap();
+        let mut incremental = Incremental::open(path);
+        assert!(incremental.unchanged(&url("b"), 2));
+        assert!(!incremental.unchanged(&url("c"), 4));
+
+        fs::write(path, "not json").unwrap();
+        assert!(!Incremental::open(path).unchanged(&url("a"), 1));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Link graph export
+//!
+//! With `ParserSettings::link_graph` set, the `Parser` writes an edge for every link it
+//! follows: the URL of the page, the URL linked to, the anchor text of the link and whether
+//! it is marked `nofollow`. Links of sitemaps and feeds have no anchor text. In NDJSON, e.g.
+//!
+//! ```text
+//! {"source":"http://site.test/","target":"http://site.test/a","text":"A","nofollow":false}
+//! ```
+//!
+//! The edges are buffered, and flushed once the spider is closed.
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use reqwest::Url;
+
+use crate::parser::page::Link;
+use crate::settings::{LinkGraphFormat, LinkGraphSettings};
+
+/// An edge of the link graph, a line of the NDJSON format
+#[derive(Serialize)]
+struct Edge<'a> {
+    source: &'a str,
+    target: &'a str,
+    text: &'a str,
+    nofollow: bool,
+}
+
+/// Writes the edges of the link graph to `LinkGraphSettings::path`, see the module
+/// documentation
+pub struct LinkGraph {
+    settings: LinkGraphSettings,
+    file: BufWriter<File>,
+    /// Bytes written to the current file
+    written: u64,
+    /// Number of files rotated so far
+    rotated: usize,
+}
+
+impl LinkGraph {
+    /// Creates (or truncates) the file of `settings`
+    pub fn open(settings: LinkGraphSettings) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(&settings.path)?);
+        let mut graph = Self { settings, file, written: 0, rotated: 0 };
+        graph.write_header()?;
+        Ok(graph)
+    }
+
+    /// The `LinkGraph` of `settings`, if its path is set. A file that can't be created is
+    /// logged, and the crawl runs without exporting the graph.
+    pub fn from_settings(settings: &LinkGraphSettings) -> Option<Self> {
+        if settings.path.is_empty() {
+            return None;
+        }
+        match LinkGraph::open(settings.clone()) {
+            Ok(graph) => Some(graph),
+            Err(e) => {
+                error!("Failed to create the link graph {}: {}", settings.path, e);
+                None
+            }
+        }
+    }
+
+    /// Writes an edge from `source` for each of `links`
+    pub fn record(&mut self, source: &Url, links: &[Link]) {
+        for link in links {
+            if let Err(e) = self.write_edge(source, link) {
+                error!("Failed to write to the link graph {}: {}", self.settings.path, e);
+                return;
+            }
+        }
+    }
+
+    /// Writes the buffered edges to the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.settings.path
+    }
+
+    fn write_edge(&mut self, source: &Url, link: &Link) -> io::Result<()> {
+        let edge = Edge {
+            source: source.as_str(),
+            target: link.url.as_str(),
+            text: &link.text,
+            nofollow: link.nofollow,
+        };
+        let line = match self.settings.format {
+            LinkGraphFormat::Ndjson => serde_json::to_string(&edge).unwrap(),
+            LinkGraphFormat::Csv => format!("{},{},{},{}", Utils::csv_field(edge.source),
+                                            Utils::csv_field(edge.target),
+                                            Utils::csv_field(edge.text), edge.nofollow),
+        };
+        let max_file_bytes = self.settings.max_file_bytes;
+        if max_file_bytes > 0 && self.written > 0
+            && self.written + line.len() as u64 + 1 > max_file_bytes
+        {
+            self.rotate()?;
+        }
+        self.write_line(&line)
+    }
+
+    /// Renames the current file to `<path>.<n>` and starts a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.rotated += 1;
+        let path = &self.settings.path;
+        fs::rename(path, format!("{}.{}", path, self.rotated))?;
+        self.file = BufWriter::new(File::create(path)?);
+        self.written = 0;
+        self.write_header()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.settings.format {
+            LinkGraphFormat::Ndjson => Ok(()),
+            LinkGraphFormat::Csv => self.write_line("source,target,text,nofollow"),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+struct Utils;
+
+impl Utils {
+    /// `field` quoted if it holds a comma, a quote or a line break, its quotes doubled
+    fn csv_field(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    fn link(url: &str, text: &str, nofollow: bool) -> Link {
+        Link { url: Url::parse(url).unwrap(), text: text.to_string(), nofollow }
+    }
+
+    fn settings(name: &str, format: LinkGraphFormat, max_file_bytes: u64) -> LinkGraphSettings {
+        let path = std::env::temp_dir().join(format!("vortex-{}-{}", name, process::id()));
+        LinkGraphSettings { path: path.to_str().unwrap().to_string(), format, max_file_bytes }
+    }
+
+    #[test]
+    fn test_csv() {
+        let settings = settings("graph.csv", LinkGraphFormat::Csv, 0);
+        let mut graph = LinkGraph::open(settings.clone()).unwrap();
+        let source = Url::parse("http://site.test/").unwrap();
+        graph.record(&source, &[
+            link("http://site.test/a", "A", false),
+            link("http://site.test/b?x=1,2", "Say \"hi\", then\nleave", true),
+        ]);
+        graph.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&settings.path).unwrap(), concat!(
+            "source,target,text,nofollow\n",
+            "http://site.test/,http://site.test/a,A,false\n",
+            "http://site.test/,\"http://site.test/b?x=1,2\",\"Say \"\"hi\"\", then\nleave\",true\n",
+        ));
+        fs::remove_file(&settings.path).unwrap();
+    }
+
+    #[test]
+    fn test_rotate() {
+        // Room for two edges per file
+        let settings = settings("graph.ndjson", LinkGraphFormat::Ndjson, 200);
+        let mut graph = LinkGraph::open(settings.clone()).unwrap();
+        let source = Url::parse("http://site.test/").unwrap();
+        let links: Vec<Link> = (0..5)
+            .map(|i| link(&format!("http://site.test/{}", i), "", false))
+            .collect();
+        graph.record(&source, &links);
+        graph.flush().unwrap();
+
+        let targets = |path: &str| -> Vec<String> {
+            fs::read_to_string(path).unwrap().lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .map(|edge| edge["target"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let path = &settings.path;
+        assert_eq!(targets(&format!("{}.1", path)),
+                   vec!["http://site.test/0", "http://site.test/1"]);
+        assert_eq!(targets(&format!("{}.2", path)),
+                   vec!["http://site.test/2", "http://site.test/3"]);
+        assert_eq!(targets(path), vec!["http://site.test/4"]);
+        for path in [format!("{}.1", path), format!("{}.2", path), path.clone()] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
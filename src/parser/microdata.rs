@@ -0,0 +1,150 @@
+//! Reads the microdata of HTML documents (`itemscope`, `itemprop`), see `Page::microdata`.
+//!
+//! The items are found as in the microdata algorithm of the HTML standard, and written in
+//! its JSON form:
+//!
+//! ```text
+//! {"items": [{"type": ["https://schema.org/Product"], "properties": {"name": ["Mug"]}}]}
+//! ```
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use kuchiki::{Node, NodeRef};
+use reqwest::Url;
+use serde_json::{Map, Value};
+
+/// The microdata of a document
+pub(crate) struct Microdata<'a> {
+    /// The URL of the document, the URL values are resolved against
+    url: &'a Url,
+    /// The elements of the document by `id`, the first one of each, for `itemref`
+    ids: HashMap<String, NodeRef>,
+    /// The position of the elements in the document, the properties are in that order
+    order: HashMap<*const Node, usize>,
+}
+
+impl<'a> Microdata<'a> {
+    pub fn new(doc: &NodeRef, url: &'a Url) -> Self {
+        let mut ids = HashMap::new();
+        let mut order = HashMap::new();
+        for (i, node) in doc.descendants().enumerate() {
+            if let Some(id) = Utils::attribute(&node, "id") {
+                ids.entry(id).or_insert_with(|| node.clone());
+            }
+            order.insert(Rc::as_ptr(&node.0), i);
+        }
+        Self { url, ids, order }
+    }
+
+    /// The top-level items of `doc`, those that aren't the property of another item
+    pub fn items(&self, doc: &NodeRef) -> Value {
+        let items: Vec<Value> = doc.descendants()
+            .filter(|node| Utils::has_attribute(node, "itemscope")
+                && !Utils::has_attribute(node, "itemprop"))
+            .map(|node| self.item(&node, &mut Vec::new()))
+            .collect();
+        json!({ "items": items })
+    }
+
+    /// The item of the `itemscope` element `node`. `scopes` are the items it is nested in,
+    /// an item that is a property of itself is written `"ERROR"`, as in the standard
+    fn item(&self, node: &NodeRef, scopes: &mut Vec<*const Node>) -> Value {
+        let mut item = Map::new();
+        let types = Utils::attribute(node, "itemtype").unwrap_or_default();
+        let types: Vec<&str> = types.split_whitespace().collect();
+        if !types.is_empty() {
+            item.insert("type".to_string(), json!(types));
+        }
+        if let Some(id) = Utils::attribute(node, "itemid") {
+            item.insert("id".to_string(), json!(self.resolve(&id)));
+        }
+
+        scopes.push(Rc::as_ptr(&node.0));
+        let mut properties = Map::new();
+        for property in self.properties(node) {
+            let value = if !Utils::has_attribute(&property, "itemscope") {
+                self.value(&property)
+            } else if scopes.contains(&Rc::as_ptr(&property.0)) {
+                json!("ERROR")
+            } else {
+                self.item(&property, scopes)
+            };
+            let names = Utils::attribute(&property, "itemprop").unwrap_or_default();
+            for name in names.split_whitespace() {
+                let values = properties.entry(name.to_string()).or_insert_with(|| json!([]));
+                values.as_array_mut().unwrap().push(value.clone());
+            }
+        }
+        scopes.pop();
+
+        item.insert("properties".to_string(), Value::Object(properties));
+        Value::Object(item)
+    }
+
+    /// The `itemprop` elements of the item `root`, among its descendants and the elements
+    /// of its `itemref`, in document order. The descendants of nested items are theirs.
+    fn properties(&self, root: &NodeRef) -> Vec<NodeRef> {
+        let mut pending: Vec<NodeRef> = root.children().filter(Utils::is_element).collect();
+        let refs = Utils::attribute(root, "itemref").unwrap_or_default();
+        pending.extend(refs.split_whitespace().filter_map(|id| self.ids.get(id).cloned()));
+
+        // Elements met twice (e.g. referenced from within the item) are skipped
+        let mut seen: HashSet<*const Node> = HashSet::new();
+        seen.insert(Rc::as_ptr(&root.0));
+        let mut properties = Vec::new();
+        while let Some(node) = pending.pop() {
+            if !seen.insert(Rc::as_ptr(&node.0)) {
+                continue;
+            }
+            if !Utils::has_attribute(&node, "itemscope") {
+                pending.extend(node.children().filter(Utils::is_element));
+            }
+            if Utils::attribute(&node, "itemprop").is_some_and(|names| !names.trim().is_empty()) {
+                properties.push(node);
+            }
+        }
+        properties.sort_by_key(|node| self.order.get(&Rc::as_ptr(&node.0)).copied());
+        properties
+    }
+
+    /// The value of the property `node`, read from the attribute of its element (e.g. the
+    /// `href` of links, resolved) or else its text, with its whitespace collapsed
+    fn value(&self, node: &NodeRef) -> Value {
+        let name = node.as_element().map(|element| element.name.local.to_string());
+        let attribute = |name: &str| Utils::attribute(node, name).unwrap_or_default();
+        let value = match name.as_deref() {
+            Some("meta") => attribute("content"),
+            Some("audio") | Some("embed") | Some("iframe") | Some("img") | Some("source")
+            | Some("track") | Some("video") => self.resolve(&attribute("src")),
+            Some("a") | Some("area") | Some("link") => self.resolve(&attribute("href")),
+            Some("object") => self.resolve(&attribute("data")),
+            Some("data") | Some("meter") => attribute("value"),
+            Some("time") if Utils::has_attribute(node, "datetime") => attribute("datetime"),
+            _ => node.text_contents().split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        Value::String(value)
+    }
+
+    /// `url` made absolute, or as it is if it isn't a valid URL
+    fn resolve(&self, url: &str) -> String {
+        self.url.join(url.trim()).map(|url| url.to_string()).unwrap_or_else(|_| url.to_string())
+    }
+}
+
+struct Utils;
+
+impl Utils {
+    fn is_element(node: &NodeRef) -> bool {
+        node.as_element().is_some()
+    }
+
+    fn has_attribute(node: &NodeRef, name: &str) -> bool {
+        node.as_element().is_some_and(|element| element.attributes.borrow().contains(name))
+    }
+
+    fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+        let element = node.as_element()?;
+        let attributes = element.attributes.borrow();
+        attributes.get(name).map(String::from)
+    }
+}
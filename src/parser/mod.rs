@@ -3,92 +3,779 @@
 //! Receives `Responses` from the `Downloader` and subsequently executes
 //! the parsing logic defined in the spider's closure. The parsed data is
 //! outputted as a JSON and sent to the Pipeline for further processing.
+//!
+//! HTML parsing and the crawl rules run on a pool of `ParserSettings::workers` threads
+//! (`ParseWorker`s), so that large pages don't hold up the actors of the crawl. The `Parser`
+//! hands each `Response` to the next idle worker and passes the results on as they come
+//! back. With a single worker, that is the order in which the `Responses` were received.
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
+use std::sync::Arc;
 
-use actix::{Actor, Arbiter, ArbiterService, Context, Handler};
-use futures::Future;
+use actix::{Actor, ActorContext, ActorFuture, Addr, AsyncContext, Context, fut::wrap_future,
+            Handler, Message, MessageResult, Recipient, SyncArbiter, SyncContext};
+use kuchiki::{NodeRef, traits::*};
+use rand::{Rng, seq::SliceRandom};
 use reqwest::Url;
-use serde_json::Value;
+use serde_json::{Map, Value};
 
-use crate::crawler::{Item, RequestVec, Response};
-pub use crate::parser::page::Page;
+use crate::crawler::{CloseReason, CloseSpider, Item, Listener, Request, RequestVec, Response,
+                     StopSpider};
+use crate::downloader::Sitemap;
+use crate::dry_run::RuleReport;
+use crate::events::{Event, EventLog};
+pub use crate::parser::feed::{Feed, FeedEntry};
+use crate::parser::incremental::Incremental;
+pub use crate::parser::link_graph::LinkGraph;
+pub(crate) use crate::parser::normalize::Canonicalized;
+pub use crate::parser::normalize::{
+    DefaultNormalizer, ParamValueRule, StandardNormalizer, UrlCanonicalizer, UrlNormalizer,
+    TRACKING_PARAMS,
+};
+pub use crate::parser::page::{Link, Page, RelLinks};
+pub use crate::parser::url_policy::UrlPolicy;
 use crate::pipeline::Pipeline;
-use crate::scheduler::Scheduler;
+use crate::scheduler::{MarkVisitedAlias, Scheduler};
 use crate::settings::{CrawlStrategy, ParserSettings};
-use crate::spider::{Condition, ParseRule, Pattern, Spider};
+use crate::spider::{Condition, CrawlRule, ParseList, ParseRule, Pattern, Spider};
 
+mod feed;
+mod incremental;
+mod link_graph;
+mod microdata;
+mod normalize;
 mod page;
+mod url_policy;
+pub(crate) mod xml;
+
+/// The `Parser` State
+///
+/// Contains metrics of parsed `Responses`
+#[derive(Clone, Debug, Default, Message)]
+pub struct State {
+    /// Number of links that were not followed because `max_depth` was reached
+    pub depth_limited: usize,
+
+    /// Number of `Responses` received that haven't been parsed yet
+    pub parsing: usize,
+
+    /// Number of `Items` sent to the `Pipeline` that it hasn't processed yet
+    pub items_in_flight: usize,
+
+    /// Number of pages no data was extracted from because of `<meta name="robots"
+    /// content="noindex">`, see `ParserSettings::respect_meta_robots`
+    pub noindex_pages: usize,
+
+    /// Number of links that were not followed because of `rel="nofollow"` or of
+    /// `<meta name="robots" content="nofollow">`
+    pub nofollow_links: usize,
+
+    /// Number of pages no `Items` were made of because their content is the same as in the
+    /// previous crawls, see `ParserSettings::incremental`
+    pub unchanged_pages: usize,
+
+    /// Number of links that were not followed because of their extension, see
+    /// `ParserSettings::skip_extensions`
+    pub skipped_links: usize,
+
+    /// Number of links to AMP pages that were not followed, see `ParserSettings::skip_amp`
+    pub amp_links: usize,
+
+    /// Number of links that were not followed because they look like crawler traps, see
+    /// `ParserSettings::max_url_length`, `max_query_params` and `max_repeated_segments`
+    pub trap_links: usize,
+
+    /// Number of links that were not followed because of their scheme or port, see
+    /// `ParserSettings::url_policy`
+    pub policy_links: usize,
+
+    /// Number of links that were not followed because their href isn't a valid URL
+    pub invalid_links: usize,
+}
+
+/// Asks the `ParseWorker` to apply the crawl rules to a `Response`. Returns the URLs that
+/// should be followed, the data extracted from the `Response` body and, with `trace`, the
+/// outcome of each crawl rule.
+struct Parse {
+    res: Response,
+    trace: bool,
+}
+
+impl Message for Parse {
+    type Result = (Parsed, Vec<RuleReport>);
+}
+
+/// The outcome of the crawl rules on a `Response`, see `Parser::apply_rules`
+pub(crate) struct Parsed {
+    /// The URLs that should be followed
+    pub urls: Vec<Url>,
+
+    /// The data extracted from the `Response` body
+    pub data: Vec<Value>,
+
+    /// The name of the crawl rule that extracted each of `data`, if it has one
+    pub rules: Vec<Option<String>>,
+
+    /// Whether no data was extracted because the page is `noindex`
+    pub noindex: bool,
+
+    /// Number of links that were not followed because of `nofollow`
+    pub nofollow_links: usize,
+
+    /// Number of links that were not followed because of their extension
+    pub skipped_links: usize,
+
+    /// Number of links to AMP pages that were not followed
+    pub amp_links: usize,
+
+    /// Number of links that were not followed because they look like crawler traps
+    pub trap_links: usize,
+
+    /// Number of links that were not followed because of their scheme or port
+    pub policy_links: usize,
+
+    /// Number of links that were not followed because their href isn't a valid URL
+    pub invalid_links: usize,
+
+    /// The AMP version of the page, with `skip_amp`
+    pub amp: Option<Url>,
+
+    /// The canonical URL of the page, with `prefer_canonical`
+    pub canonical: Option<Url>,
+
+    /// The hash of the `Response` body, with `incremental`
+    pub content_hash: Option<u64>,
+
+    /// The pagination links of the page
+    pub rel_links: RelLinks,
+
+    /// The `rel="next"` link to follow, with `follow_rel_next`
+    pub rel_next: Option<Url>,
+
+    /// The links followed (`urls` and `rel_next`) with their anchor text, with `link_graph`
+    pub links: Vec<Link>,
+}
+
+/// Parses `Responses` on a thread of its own, see `Parser::parse`. The `Parser` starts
+/// `ParserSettings::workers` of them.
+pub struct ParseWorker {
+    crawl_rules: Vec<CrawlRule>,
+    settings: ParserSettings,
+    normalizer: Arc<dyn UrlNormalizer>,
+    policy: UrlPolicy,
+}
+
+impl Actor for ParseWorker {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<Parse> for ParseWorker {
+    type Result = MessageResult<Parse>;
+
+    fn handle(&mut self, msg: Parse, _ctx: &mut SyncContext<Self>) -> Self::Result {
+        let mut reports = Vec::new();
+        let trace = if msg.trace { Some(&mut reports) } else { None };
+        let parsed = Parser::apply_rules(
+            &self.crawl_rules, &self.settings, &self.normalizer, &self.policy, &msg.res, trace);
+        MessageResult((parsed, reports))
+    }
+}
 
-#[derive(Default)]
 pub struct Parser {
     spider: Rc<Spider>,
+    scheduler: Addr<Scheduler>,
+    pipeline: Addr<Pipeline>,
+    workers: Addr<ParseWorker>,
+    /// `Responses` waiting for a `ParseWorker`, or for the `Pipeline` to catch up
+    waiting: VecDeque<Response>,
+    /// Number of `Responses` handed to the `ParseWorker`s
+    active: usize,
+    /// Set once `CloseSpider` is received while `Responses` are still being parsed
+    closing: Option<CloseSpider>,
+    state: State,
+    state_listeners: Vec<Recipient<State>>,
+    events: Option<Arc<EventLog>>,
+    /// The content hashes of the previous crawls, with `ParserSettings::incremental`
+    incremental: Option<Incremental>,
+    /// The export of the links followed, with `ParserSettings::link_graph`
+    link_graph: Option<LinkGraph>,
 }
 
 impl Parser {
-    pub fn new(spider: Rc<Spider>) -> Self {
-        Self { spider }
+    /// The links found are sent to `scheduler`, the `Item`s to `pipeline`.
+    ///
+    /// Must be called from within a running actix `System`, it starts the `ParseWorker`s.
+    pub fn new(spider: Rc<Spider>, scheduler: Addr<Scheduler>, pipeline: Addr<Pipeline>,
+               events: Option<Arc<EventLog>>) -> Self {
+        let crawl_rules = spider.crawl_rules().clone();
+        let settings = spider.settings().parser.clone();
+        let normalizer = spider.url_normalizer();
+        let policy = spider.url_policy().clone();
+        let incremental = Incremental::from_settings(&settings);
+        let link_graph = LinkGraph::from_settings(&settings.link_graph);
+        let workers = SyncArbiter::start(settings.workers, move || ParseWorker {
+            crawl_rules: crawl_rules.clone(),
+            settings: settings.clone(),
+            normalizer: Arc::clone(&normalizer),
+            policy: policy.clone(),
+        });
+
+        Self {
+            spider,
+            scheduler,
+            pipeline,
+            workers,
+            waiting: VecDeque::new(),
+            active: 0,
+            closing: None,
+            state: State::default(),
+            state_listeners: Vec::new(),
+            events,
+            incremental,
+            link_graph,
+        }
     }
 
-    fn process(&self, res: Response) {
-        // Construct Page Object from response
-        let page = Page::from_response(&res);
+    fn dispatch_state(&self) {
+        self.state_listeners.iter().for_each(|r| {
+            let _ = r.do_send(self.state.clone());
+        });
+    }
 
-        // Urls
-        let mut urls = page.urls().clone();
+    /// Hands the waiting `Responses` over to the idle `ParseWorker`s and passes the results
+    /// on once they're parsed. While the `Pipeline` has `max_inflight` `Items` to process,
+    /// the `Responses` are held back. As they count as in flight for the `Scheduler`, it
+    /// slows down the crawl until the `Pipeline` has caught up.
+    fn parse_waiting(&mut self, ctx: &mut Context<Self>) {
+        let workers = self.spider.settings().parser.workers;
+        let max_inflight = self.spider.settings().pipeline.max_inflight;
+        while self.active < workers
+            && (max_inflight == 0 || self.state.items_in_flight < max_inflight)
+        {
+            let res = match self.waiting.pop_front() {
+                Some(res) => res,
+                None => break,
+            };
+            self.active += 1;
 
-        //
+            let req = res.request.clone();
+            let parse = Parse { res, trace: self.events.is_some() };
+            let parsed = wrap_future::<_, Self>(self.workers.send(parse))
+                .map(move |(parsed, reports), act, ctx| {
+                    if let Some(events) = &act.events {
+                        events.emit(&req, Event::Parsed {
+                            rules_matched: Utils::rules_matched(&reports),
+                            links_found: parsed.urls.len(),
+                            items: parsed.data.len(),
+                        });
+                    }
+                    if parsed.noindex {
+                        act.state.noindex_pages += 1;
+                    }
+                    act.state.nofollow_links += parsed.nofollow_links;
+                    act.state.skipped_links += parsed.skipped_links;
+                    act.state.amp_links += parsed.amp_links;
+                    act.state.trap_links += parsed.trap_links;
+                    act.state.policy_links += parsed.policy_links;
+                    act.state.invalid_links += parsed.invalid_links;
+                    if let Some(link_graph) = act.link_graph.as_mut() {
+                        link_graph.record(&req.url, &parsed.links);
+                    }
+                    let mut parsed = parsed;
+                    if let (Some(incremental), Some(hash)) =
+                        (act.incremental.as_mut(), parsed.content_hash)
+                    {
+                        if incremental.unchanged(&req.url, hash) {
+                            debug!("Unchanged since the previous crawl: {}", req.url);
+                            act.state.unchanged_pages += 1;
+                            parsed.data.clear();
+                            parsed.rules.clear();
+                        }
+                    }
+                    for alias in parsed.canonical.iter().chain(&parsed.amp) {
+                        if *alias != req.url {
+                            let alias = MarkVisitedAlias(req.url.clone(), alias.clone());
+                            act.scheduler.do_send(alias);
+                        }
+                    }
+                    act.process(req, parsed, ctx)
+                })
+                .map_err(|e, act, _ctx| {
+                    // The worker is gone, the `Responses` left can't all be parsed
+                    error!("Parse worker error: {:?}", e);
+                    act.scheduler.do_send(StopSpider { reason: CloseReason::Error });
+                })
+                .then(|_, act, ctx| {
+                    act.active -= 1;
+                    act.state.parsing -= 1;
+                    act.dispatch_state();
+                    act.parse_waiting(ctx);
+                    if act.state.parsing == 0 {
+                        if let Some(msg) = act.closing.take() {
+                            act.close(msg, ctx);
+                        }
+                    }
+                    actix::fut::ok(())
+                });
+            ctx.spawn(parsed);
+        }
+    }
+
+    /// Passes `msg` on to the `Pipeline`, once all `Responses` have been parsed, and stops
+    /// the `Parser`. The content hashes of an incremental crawl are saved then.
+    fn close(&mut self, msg: CloseSpider, ctx: &mut Context<Self>) {
+        if let Some(incremental) = &self.incremental {
+            match incremental.save() {
+                Ok(()) => info!("Saved the incremental crawl state to {}", incremental.path()),
+                Err(e) => error!("Failed to save the incremental crawl state to {}: {}",
+                                 incremental.path(), e),
+            }
+        }
+        if let Some(link_graph) = self.link_graph.as_mut() {
+            if let Err(e) = link_graph.flush() {
+                error!("Failed to write the link graph {}: {}", link_graph.path(), e);
+            }
+        }
+        self.pipeline.do_send(msg);
+        ctx.stop();
+    }
+
+    /// Sends `item` to the `Pipeline`, keeping count of the `Items` it hasn't processed yet
+    fn send_item(&mut self, item: Item, ctx: &mut Context<Self>) {
+        self.state.items_in_flight += 1;
+        let sent = wrap_future::<_, Self>(self.pipeline.send(item))
+            .then(|_, act, ctx| {
+                act.state.items_in_flight -= 1;
+                act.parse_waiting(ctx);
+                actix::fut::ok(())
+            });
+        ctx.spawn(sent);
+    }
+
+    fn process(&mut self, req: Request, parsed: Parsed, ctx: &mut Context<Self>) {
+        let Parsed { mut urls, data, rules, rel_links, rel_next, .. } = parsed;
+
+        // The next page is at the depth of this one, and crawled before the other links of
+        // that depth
+        if let Some(url) = rel_next {
+            trace!("Following the next page {}", url);
+            let priority = req.priority.saturating_add(1);
+            self.scheduler.do_send(RequestVec::from_urls(vec![url], req.depth, priority));
+        }
+
+        // Links found at `max_depth` are not followed
+        let max_depth = self.spider.settings().scheduler.max_depth;
+        if max_depth > 0 && req.depth >= max_depth && !urls.is_empty() {
+            self.state.depth_limited += urls.len();
+            self.dispatch_state();
+            urls.clear();
+        }
+
+        // Send links to scheduler
+        let requests = Utils::request_vec(&self.spider, &req, urls, &mut rand::thread_rng());
+        self.scheduler.do_send(requests);
+
+        // Send item (json) to pipeline
+        for (d, rule) in data.into_iter().zip(rules) {
+            let mut item = Item::new(req.clone(), d);
+            item.rule = rule;
+            item.rel_links = rel_links.clone();
+            self.send_item(item, ctx);
+        }
+    }
+
+    /// Applies the spider's crawl rules to a `Response`. Returns the URLs that should be
+    /// followed and the data extracted from the `Response` body. For a sitemap (see
+    /// `Sitemap::detect`), the URLs it lists are filtered, and no data is extracted.
+    pub(crate) fn parse(spider: &Spider, res: &Response) -> (Vec<Url>, Vec<Value>) {
+        Parser::parse_traced(spider, res, None)
+    }
+
+    /// Same as `parse`. If `trace` is given, a `RuleReport` describing the outcome of each
+    /// crawl rule is added to it.
+    pub(crate) fn parse_traced(
+        spider: &Spider,
+        res: &Response,
+        trace: Option<&mut Vec<RuleReport>>,
+    ) -> (Vec<Url>, Vec<Value>) {
+        let normalizer = spider.url_normalizer();
+        let settings = &spider.settings().parser;
+        let policy = spider.url_policy();
+        let parsed =
+            Parser::apply_rules(spider.crawl_rules(), settings, &normalizer, policy, res, trace);
+        (parsed.urls, parsed.data)
+    }
+
+    fn apply_rules(
+        crawl_rules: &[CrawlRule],
+        settings: &ParserSettings,
+        normalizer: &Arc<dyn UrlNormalizer>,
+        policy: &UrlPolicy,
+        res: &Response,
+        mut trace: Option<&mut Vec<RuleReport>>,
+    ) -> Parsed {
+        // A sitemap only yields the URLs it lists, no data is extracted from it. A feed
+        // yields the links of its entries, and data for the `ParseRule::Feed` rules only.
+        let mut feed = None;
+        let mut nofollow_links = 0;
+        let mut invalid_links = 0;
+        let (page, mut urls) = if Sitemap::detect(&res.headers, &res.body) {
+            let urls = Sitemap::parse(&res.request.url, &res.body).entries().iter()
+                .filter_map(|entry| {
+                    normalizer.normalize(&res.request.url, entry.loc.as_str()).ok()
+                })
+                .collect();
+            (None, urls)
+        } else if Feed::detect(&res.headers, &res.body) {
+            let parsed = Feed::parse(&res.request.url, &res.body);
+            let urls = parsed.links().iter()
+                .filter_map(|link| normalizer.normalize(&res.request.url, link.as_str()).ok())
+                .collect();
+            feed = Some(parsed);
+            (None, urls)
+        } else {
+            // Construct Page Object from response
+            let page = Page::with_normalizer(res, Arc::clone(normalizer));
+
+            // Urls. A `nofollow` page has none to follow
+            let nofollow = settings.respect_meta_robots && page.nofollow();
+            let mut urls = if nofollow {
+                Vec::new()
+            } else if settings.respect_nofollow {
+                page.follow_urls().clone()
+            } else {
+                page.urls().clone()
+            };
+            nofollow_links = page.urls().len() - urls.len();
+            invalid_links = page.invalid_links();
+            if settings.follow_feed_links && !nofollow {
+                for link in page.feed_links() {
+                    if !urls.contains(&link) {
+                        urls.push(link);
+                    }
+                }
+            }
+            (Some(page), urls)
+        };
+
+        // Links to images, archives, etc. are not followed
+        let found = urls.len();
+        urls.retain(|url| !Utils::skip_extension(&settings.skip_extensions, url));
+        let skipped_links = found - urls.len();
+        if skipped_links > 0 {
+            debug!("Skipping {} links of {} by extension", skipped_links, res.request.url);
+        }
+
+        // Nor are the links that keep growing, e.g. of calendars and faceted search
+        let found = urls.len();
+        urls.retain(|url| match Utils::trap(settings, url) {
+            Some(reason) => {
+                debug!("Not following {:.100} of {}: {}", url.as_str(), res.request.url, reason);
+                false
+            }
+            None => true,
+        });
+        let trap_links = found - urls.len();
+
+        // Nor are those of other schemes or ports than `url_policy` allows, http links may be
+        // upgraded to https. The links upgraded are kept track of for the link graph
+        let link_graph = !settings.link_graph.path.is_empty();
+        let mut upgraded = HashMap::new();
+        let found = urls.len();
+        urls = urls.into_iter()
+            .filter_map(|url| {
+                let found = if link_graph { Some(url.clone()) } else { None };
+                let url = policy.apply(url)?;
+                if let Some(found) = found.filter(|found| *found != url) {
+                    upgraded.insert(url.clone(), found);
+                }
+                Some(url)
+            })
+            .collect();
+        let policy_links = found - urls.len();
+
+        // Nor are the AMP versions of pages, that of this page is marked as visited
+        let amp = if settings.skip_amp { page.as_ref().and_then(Page::amphtml) } else { None };
+        let found = urls.len();
+        urls.retain(|url| {
+            Some(url) != amp.as_ref() && !(settings.skip_amp_urls && Utils::amp_url(url))
+        });
+        let amp_links = found - urls.len();
+
+        // The links of a `noindex` page are still followed, no data is extracted from it
+        let noindex = settings.respect_meta_robots && page.as_ref().is_some_and(Page::noindex);
+
+        // The fields of the `Pattern` rules make one `Item`, whatever the values of the
+        // `Page` rules. It comes first, then the values of the `Page` and `Feed` rules in the
+        // order of the rules, then the `Items` of the `List` rules
+        let mut fields = Map::new();
         let mut data: Vec<Value> = Vec::new();
+        let mut list_data: Vec<Value> = Vec::new();
+        let mut rules: Vec<Option<String>> = Vec::new();
+        let mut list_rules: Vec<Option<String>> = Vec::new();
 
-        let crawl_rules = self.spider.crawl_rules();
-        for rule in crawl_rules {
+        // The rules apply to the page up to their `max_depth`, and `FilterUrls` to its links,
+        // which are one level deeper. The `Item` of the `Pattern` rules belongs to the first
+        // named one that extracted a field
+        let url = &res.request.url;
+        let depth = res.request.depth;
+        let mut fields_rule = None;
+        let mut follow = true;
+
+        for (i, rule) in crawl_rules.iter().enumerate() {
+            let name = rule.name.as_deref();
+            let tag = name.filter(|_| settings.tag_rule);
+            if follow && !rule.follow && rule.applies_at(depth) && rule.condition.allows_url(url) {
+                trace!("Rule {} doesn't follow the links of {}", rule.label(i), url);
+                follow = false;
+            }
             match rule.parse_rule {
                 ParseRule::FilterUrls => {
-                    urls = Utils::filter_urls(&rule.condition, urls);
+                    let before = if trace.is_some() { urls.clone() } else { Vec::new() };
+                    let found = urls.len();
+                    urls = if rule.applies_at(depth + 1) {
+                        Utils::filter_urls(&rule.condition, urls)
+                    } else {
+                        Vec::new()
+                    };
+                    if urls.len() < found {
+                        trace!("Rule {} denied {} links of {}", rule.label(i), found - urls.len(),
+                               url);
+                    }
+                    if let Some(trace) = trace.as_mut() {
+                        trace.push(RuleReport::FilterUrls {
+                            rule: i,
+                            name: rule.name.clone(),
+                            allowed: urls.iter().map(|u| u.to_string()).collect(),
+                            denied: before.iter()
+                                .filter(|u| !urls.contains(u))
+                                .map(|u| u.to_string())
+                                .collect(),
+                        });
+                    }
                 }
                 ParseRule::Page(ref parse_rule) => {
-                    if let Some(values) = (parse_rule.callback)(&page) {
-                        data.extend(values);
+                    let page = match &page {
+                        Some(page) if !noindex && rule.applies_at(depth) => page,
+                        _ => continue,
+                    };
+                    let mut values = (parse_rule.callback)(page).unwrap_or_default();
+                    if let Some(trace) = trace.as_mut() {
+                        trace.push(RuleReport::Page {
+                            rule: i,
+                            name: rule.name.clone(),
+                            values: values.len(),
+                        });
+                    }
+                    if !values.is_empty() {
+                        trace!("Rule {} extracted {} values from {}", rule.label(i), values.len(),
+                               url);
+                    }
+                    if let Some(name) = tag {
+                        Utils::tag_rule(&mut values, name);
                     }
+                    rules.extend(values.iter().map(|_| rule.name.clone()));
+                    data.extend(values);
                 }
                 ParseRule::Pattern(ref parse_rule) => {
-                    let urls = Utils::filter_urls(&rule.condition, vec![res.request.url.clone()]);
-                    if !urls.is_empty() {
-                        let matches = match parse_rule.pattern {
-                            Pattern::CssSelector(sel) => page.matches_selectors(sel),
-                            Pattern::Regex(exp) => page.matches_regex(exp),
+                    let page = match &page {
+                        Some(page) if !noindex => page,
+                        _ => continue,
+                    };
+                    let urls = if rule.applies_at(depth) {
+                        Utils::filter_urls(&rule.condition, vec![url.clone()])
+                    } else {
+                        Vec::new()
+                    };
+                    let matches = if urls.is_empty() {
+                        Vec::new()
+                    } else {
+                        match parse_rule.pattern {
+                            // Invalid selectors are rejected by `SpiderBuilder::try_build`
+                            Pattern::CssSelector(_) => parse_rule.selectors.as_ref()
+                                .map(|selectors| page.matches_selectors(selectors))
+                                .unwrap_or_default(),
+                            // Invalid expressions are rejected by `SpiderBuilder::try_build`
+                            Pattern::Regex(_) => parse_rule.regex.as_ref()
+                                .map(|regex| page.matches_regex(regex))
+                                .unwrap_or_default(),
                             Pattern::Xpath(_) => unimplemented!(),
-                        };
-
-                        if !matches.is_empty() {
-                            if let Some(value) = (parse_rule.callback)(matches) {
-                                if data.is_empty() {
-                                    data.push(json!({}));
-                                }
-                                if let Some(data) = data[0].as_object_mut() {
-                                    data.insert(parse_rule.field.to_owned(), value);
-                                }
-                            }
+                            Pattern::Header(name) => res.headers.get_all(name).iter()
+                                .filter_map(|value| value.to_str().ok())
+                                .map(String::from)
+                                .collect(),
+                        }
+                    };
+                    if let Some(trace) = trace.as_mut() {
+                        trace.push(RuleReport::Pattern {
+                            rule: i,
+                            name: rule.name.clone(),
+                            field: parse_rule.field.to_string(),
+                            applied: !urls.is_empty(),
+                            matches: matches.clone(),
+                        });
+                    }
+
+                    if !matches.is_empty() {
+                        if let Some(value) = (parse_rule.callback)(matches) {
+                            trace!("Rule {} extracted {} from {}", rule.label(i), parse_rule.field,
+                                   url);
+                            fields.insert(parse_rule.field.to_owned(), value);
+                            fields_rule = fields_rule.or(name);
                         }
                     }
                 }
+                ParseRule::List(ref parse_rule) => {
+                    let page = match &page {
+                        Some(page) if !noindex => page,
+                        _ => continue,
+                    };
+                    let applied = rule.applies_at(depth) && rule.condition.allows_url(url);
+                    let containers = match (&parse_rule.selectors, applied) {
+                        (Some(selectors), true) => page.select_elements(selectors),
+                        _ => Vec::new(),
+                    };
+                    let mut items: Vec<Value> = containers.iter()
+                        .map(|container| Utils::list_item(parse_rule, container, res))
+                        .filter(|item| item.as_object().is_some_and(|item| !item.is_empty()))
+                        .collect();
+                    if let Some(trace) = trace.as_mut() {
+                        trace.push(RuleReport::List {
+                            rule: i,
+                            name: rule.name.clone(),
+                            applied,
+                            containers: containers.len(),
+                            items: items.len(),
+                        });
+                    }
+                    if !items.is_empty() {
+                        trace!("Rule {} extracted {} items from {}", rule.label(i), items.len(),
+                               url);
+                    }
+                    if let Some(name) = tag {
+                        Utils::tag_rule(&mut items, name);
+                    }
+                    list_rules.extend(items.iter().map(|_| rule.name.clone()));
+                    list_data.extend(items);
+                }
+                ParseRule::Feed(ref parse_rule) => {
+                    let feed = match &feed {
+                        Some(feed) => feed,
+                        None => continue,
+                    };
+                    let applied = rule.applies_at(depth) && rule.condition.allows_url(url);
+                    if applied {
+                        let mut items: Vec<Value> =
+                            feed.entries.iter().map(|entry| parse_rule.item(entry)).collect();
+                        trace!("Rule {} extracted {} entries from {}", rule.label(i), items.len(),
+                               url);
+                        if let Some(name) = tag {
+                            Utils::tag_rule(&mut items, name);
+                        }
+                        rules.extend(items.iter().map(|_| rule.name.clone()));
+                        data.extend(items);
+                    }
+                    if let Some(trace) = trace.as_mut() {
+                        let entries = if applied { feed.entries.len() } else { 0 };
+                        trace.push(RuleReport::Feed {
+                            rule: i,
+                            name: rule.name.clone(),
+                            applied,
+                            entries,
+                        });
+                    }
+                }
             }
         }
 
-        // Set depth of new batch of links
-        let depth = res.request.depth + 1;
+        if !fields.is_empty() {
+            if let Some(name) = fields_rule.filter(|_| settings.tag_rule) {
+                fields.insert("_rule".to_string(), json!(name));
+            }
+            data.insert(0, Value::Object(fields));
+            rules.insert(0, fields_rule.map(String::from));
+        }
+        data.extend(list_data);
+        rules.extend(list_rules);
 
-        // Set priority of new batch of links
-        let settings = &self.spider.settings().parser;
-        let priority = Utils::calc_priority(settings, &res);
+        // The links of the pages of a rule that doesn't follow them are dropped
+        if !follow {
+            urls.clear();
+        }
 
-        trace!("Depth: {}   Priority: {}", depth, priority);
+        let canonical = if settings.prefer_canonical {
+            page.as_ref().and_then(Page::canonical)
+        } else {
+            None
+        };
+        if let Some(canonical) = &canonical {
+            for data in data.iter_mut().filter_map(Value::as_object_mut) {
+                data.insert("_canonical".to_string(), json!(canonical.as_str()));
+            }
+        }
 
-        // Send links to scheduler
-        send!(Scheduler, RequestVec::from_urls(urls, depth, priority));
+        let max_links = settings.max_links_per_page;
+        if max_links > 0 && urls.len() > max_links {
+            debug!("Following {} of the {} links of {}", max_links, urls.len(), res.request.url);
+            urls.truncate(max_links);
+        }
 
-        // Send item (json) to pipeline
-        for d in data {
-            send!(Pipeline, Item::new(res.request.clone(), d));
+        let content_hash = if settings.incremental {
+            Some(Incremental::hash(&res.body))
+        } else {
+            None
+        };
+
+        // The next page is followed from the pages the crawl rules extracted data from, it
+        // is sent apart from the other links
+        let rel_links = page.as_ref().map(Page::rel_links).unwrap_or_default();
+        let nofollow = settings.respect_meta_robots && page.as_ref().is_some_and(Page::nofollow);
+        let rel_next = rel_links.next.clone()
+            .filter(|next| settings.follow_rel_next && follow && !nofollow && !data.is_empty()
+                && *next != res.request.url);
+        if let Some(next) = &rel_next {
+            urls.retain(|url| url != next);
+        }
+
+        // The links followed with their anchor text, once per URL with that of the first link
+        // to it. The links of sitemaps and feeds have none
+        let links = if link_graph {
+            let page_links: HashMap<&Url, &Link> = page.iter()
+                .flat_map(|page| page.links().iter().rev())
+                .map(|link| (&link.url, link))
+                .collect();
+            let mut seen = HashSet::new();
+            urls.iter().chain(&rel_next)
+                .filter(|url| seen.insert(*url))
+                .map(|url| {
+                    let found = upgraded.get(url).unwrap_or(url);
+                    let (text, nofollow) = page_links.get(found)
+                        .map(|link| (link.text.clone(), link.nofollow))
+                        .unwrap_or_default();
+                    Link { url: url.clone(), text, nofollow }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Parsed {
+            urls,
+            data,
+            rules,
+            noindex,
+            nofollow_links,
+            skipped_links,
+            amp_links,
+            trap_links,
+            policy_links,
+            invalid_links,
+            amp,
+            canonical,
+            content_hash,
+            rel_links,
+            rel_next,
+            links,
         }
     }
 }
@@ -106,32 +793,188 @@ impl Actor for Parser {
     }
 }
 
-impl actix::Supervised for Parser {}
+/// Define handler for `Listener<State>` message
+impl Handler<Listener<State>> for Parser {
+    type Result = ();
 
-impl ArbiterService for Parser {
-    fn service_started(&mut self, _ctx: &mut Context<Self>) {}
+    fn handle(&mut self, msg: Listener<State>, _ctx: &mut Context<Self>) {
+        self.state_listeners.push(msg.r);
+    }
 }
 
 impl Handler<Response> for Parser {
     type Result = ();
 
-    fn handle(&mut self, msg: Response, _ctx: &mut Context<Self>) {
+    fn handle(&mut self, msg: Response, ctx: &mut Context<Self>) {
         trace!("Response: {}", msg.request.url);
-        self.process(msg);
+        self.waiting.push_back(msg);
+        self.state.parsing += 1;
+        self.dispatch_state();
+        self.parse_waiting(ctx);
     }
 }
 
-struct Utils;
+/// Define handler for `CloseSpider` message. It is passed on to the `Pipeline` once all
+/// `Response`s received before the message have been parsed.
+impl Handler<CloseSpider> for Parser {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSpider, ctx: &mut Context<Self>) {
+        if self.state.parsing == 0 {
+            self.close(msg, ctx);
+        } else {
+            self.closing = Some(msg);
+        }
+    }
+}
+
+pub(crate) struct Utils;
 
 impl Utils {
+    /// The `Item` data `parse_rule` makes of `container`, an element of the page of `res`
+    fn list_item(parse_rule: &ParseList, container: &NodeRef, res: &Response) -> Value {
+        let mut data = Map::new();
+        for pattern in &parse_rule.patterns {
+            let matches: Vec<String> = match pattern.pattern {
+                Pattern::CssSelector(_) => pattern.selectors.as_ref()
+                    .map(|selectors| {
+                        selectors.filter(container.inclusive_descendants().elements())
+                            .map(|n| n.text_contents())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                Pattern::Regex(_) => pattern.regex.as_ref()
+                    .map(|regex| {
+                        regex.find_iter(&container.to_string())
+                            .map(|m| m.as_str().to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                // Rejected by `SpiderBuilder::try_build`
+                Pattern::Xpath(exp) => {
+                    warn!("Xpath patterns aren't supported, {:?} matches nothing", exp);
+                    Vec::new()
+                }
+                Pattern::Header(name) => res.headers.get_all(name).iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .map(String::from)
+                    .collect(),
+            };
+            if matches.is_empty() {
+                continue;
+            }
+            if let Some(value) = (pattern.callback)(matches) {
+                data.insert(pattern.field.to_string(), value);
+            }
+        }
+        Value::Object(data)
+    }
+
+    /// Adds the name of the crawl rule that extracted `values` to them, as `_rule`, unless
+    /// the rule set one of its own
+    fn tag_rule(values: &mut [Value], name: &str) {
+        for value in values.iter_mut().filter_map(Value::as_object_mut) {
+            value.entry("_rule").or_insert_with(|| json!(name));
+        }
+    }
+
+    /// The crawl rules that extracted data
+    fn rules_matched(reports: &[RuleReport]) -> Vec<usize> {
+        reports.iter()
+            .filter_map(|report| match report {
+                RuleReport::Page { rule, values, .. } if *values > 0 => Some(*rule),
+                RuleReport::Pattern { rule, applied: true, matches, .. } if !matches.is_empty() => {
+                    Some(*rule)
+                }
+                RuleReport::Feed { rule, entries, .. } if *entries > 0 => Some(*rule),
+                RuleReport::List { rule, items, .. } if *items > 0 => Some(*rule),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the path of `url` ends with one of the `extensions`, the query string and
+    /// fragment aside
+    fn skip_extension(extensions: &[String], url: &Url) -> bool {
+        let name = url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default()
+            .to_lowercase();
+        extensions.iter().any(|ext| {
+            let ext = ext.trim_start_matches('.').to_lowercase();
+            !ext.is_empty() && name.strip_suffix(&ext)
+                .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+        })
+    }
+
+    /// Why `url` looks like a crawler trap, if it does: it is longer than `max_url_length`,
+    /// or has more than `max_query_params` query parameters, or a path segment repeated more
+    /// than `max_repeated_segments` times in a row
+    fn trap(settings: &ParserSettings, url: &Url) -> Option<String> {
+        let length = url.as_str().len();
+        if settings.max_url_length > 0 && length > settings.max_url_length {
+            return Some(format!("{} characters (max_url_length)", length));
+        }
+        let params = url.query_pairs().count();
+        if settings.max_query_params > 0 && params > settings.max_query_params {
+            return Some(format!("{} query parameters (max_query_params)", params));
+        }
+        if settings.max_repeated_segments > 0 {
+            let mut run = (None, 0);
+            for segment in url.path_segments().into_iter().flatten().filter(|s| !s.is_empty()) {
+                run = if run.0 == Some(segment) { (run.0, run.1 + 1) } else { (Some(segment), 1) };
+                if run.1 > settings.max_repeated_segments {
+                    return Some(format!("{:?} repeated (max_repeated_segments)", segment));
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether `url` looks like that of an AMP page: its path ends with `/amp`, or its query
+    /// string has `amp=1`
+    fn amp_url(url: &Url) -> bool {
+        url.path().trim_end_matches('/').to_lowercase().ends_with("/amp")
+            || url.query_pairs().any(|(name, value)| name == "amp" && value == "1")
+    }
+
     fn filter_urls(cnd: &Condition, urls: Vec<Url>) -> Vec<Url> {
         urls.into_iter()
-            .filter(|url| cnd.allow.is_match(url.as_str()) && !cnd.deny.is_match(url.as_str()))
+            .filter(|url| cnd.allows_url(url))
             .collect()
     }
 
-    fn calc_priority(settings: &ParserSettings, res: &Response) -> u32 {
-        let depth = res.request.depth as f32;
+    /// The `Requests` of the links found at `req`, each link once, in the order found or
+    /// shuffled with `rng` if `randomize_order` is set. They are given the revisit interval
+    /// of the crawl rule that governs them.
+    fn request_vec<R: Rng>(spider: &Spider, req: &Request, mut urls: Vec<Url>, rng: &mut R)
+        -> RequestVec
+    {
+        let settings = &spider.settings().parser;
+        // A page repeats links, e.g. those of its navigation
+        let mut seen = HashSet::new();
+        urls.retain(|url| seen.insert(url.clone()));
+        if settings.randomize_order {
+            urls.shuffle(rng);
+        }
+
+        // Set depth and priority of new batch of links
+        let depth = req.depth + 1;
+        let priority = Utils::calc_priority(settings, depth);
+
+        trace!("Depth: {}   Priority: {}", depth, priority);
+
+        let mut requests = RequestVec::from_urls(urls, depth, priority);
+        for req in &mut requests.requests {
+            req.revisit_after = CrawlRule::revisit_interval(spider.crawl_rules(), req);
+        }
+        requests
+    }
+
+    /// The priority of a `Request` at `depth` under the crawl strategy. BFO pops the start
+    /// URLs first and DFO last, with the deeper links in between.
+    pub(crate) fn calc_priority(settings: &ParserSettings, depth: u32) -> u32 {
+        let depth = depth as f32;
         let priority = match settings.crawl_strategy {
             CrawlStrategy::BFO => 1.0 - depth / (depth + 1.0),
             CrawlStrategy::DFO => depth / (depth + 1.0),
@@ -141,3 +984,657 @@ impl Utils {
         (priority * 1_000_000_000.0) as u32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use reqwest::header::{CONTENT_TYPE, HeaderValue};
+
+    use crate::crawler::Request;
+    use crate::settings::Settings;
+    use crate::spider::{Condition, ParseFeed, ParsePattern, ParseRule, SpiderBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_respect_nofollow() {
+        let url = Url::parse("http://en.wikipedia.org/wiki/Rust").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<a href="/wiki/Ferris">Ferris</a>
+                      <a href="/wiki/Ads" rel="nofollow">Ads</a>"#.to_string();
+
+        let mut settings = Settings::default();
+        let spider = SpiderBuilder::default().settings(settings.clone()).build();
+        let (urls, _) = Parser::parse(&spider, &res);
+        assert_eq!(urls.len(), 2);
+
+        settings.parser.respect_nofollow = true;
+        let spider = SpiderBuilder::default().settings(settings).build();
+        let (urls, _) = Parser::parse(&spider, &res);
+        assert_eq!(urls, vec![Url::parse("http://en.wikipedia.org/wiki/Ferris").unwrap()]);
+    }
+
+    #[test]
+    fn test_request_vec() {
+        let url = Url::parse("http://site.test/").unwrap();
+        let mut res = Response::new(Request::new(url, 2, 1));
+        res.body = r#"<a href="/a">A</a> <a href="/b">B</a>
+                      <a href="/a">A again</a> <a href="http://site.test/b">B again</a>"#
+            .to_string();
+
+        let spider = SpiderBuilder::default().build();
+        let (urls, _) = Parser::parse(&spider, &res);
+        let reqs = Utils::request_vec(&spider, &res.request, urls, &mut rand::thread_rng());
+        let urls: Vec<_> = reqs.requests.iter().map(|req| req.url.as_str()).collect();
+        assert_eq!(urls, vec!["http://site.test/a", "http://site.test/b"]);
+        assert!(reqs.requests.iter().all(|req| req.depth == 3));
+    }
+
+    #[test]
+    fn test_randomize_order() {
+        let req = Request::new(Url::parse("http://site.test/").unwrap(), 0, 1);
+        let found: Vec<Url> = (0..20)
+            .map(|i| Url::parse(&format!("http://site.test/{}", i)).unwrap())
+            .collect();
+        let order = |spider: &Spider, seed: u64| -> Vec<Url> {
+            let mut rng = StdRng::seed_from_u64(seed);
+            Utils::request_vec(spider, &req, found.clone(), &mut rng).requests.into_iter()
+                .map(|req| req.url)
+                .collect()
+        };
+
+        let mut settings = Settings::default();
+        let spider = SpiderBuilder::default().settings(settings.clone()).build();
+        assert_eq!(order(&spider, 1), found);
+
+        // Each run has an order of its own, with all the links
+        settings.parser.randomize_order = true;
+        let spider = SpiderBuilder::default().settings(settings).build();
+        let (first, second) = (order(&spider, 1), order(&spider, 2));
+        assert_ne!(first, found);
+        assert_ne!(second, found);
+        assert_ne!(first, second);
+        assert_eq!(first, order(&spider, 1));
+        let mut sorted = first.clone();
+        sorted.sort_by_key(|url| url.path()[1..].parse::<u32>().unwrap());
+        assert_eq!(sorted, found);
+    }
+
+    #[test]
+    fn test_meta_robots() {
+        let parse = |settings: &Settings, robots: &str| {
+            let url = Url::parse("http://en.wikipedia.org/wiki/Rust").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.body = format!(r#"<html><head><meta name="robots" content="{}"></head><body>
+                <h1>Rust</h1>
+                <a href="/wiki/Ferris">Ferris</a>
+                <a href="/wiki/Ads" rel="nofollow">Ads</a></body></html>"#, robots);
+            let spider = SpiderBuilder::default()
+                .settings(settings.clone())
+                .crawl_rule(
+                    Condition::any(),
+                    ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| Some(json!(s))))
+                .build();
+            let parsed = Parser::apply_rules(spider.crawl_rules(), &settings.parser,
+                                             &spider.url_normalizer(), spider.url_policy(),
+                                             &res, None);
+            (parsed.urls.len(), parsed.data.len(), parsed.noindex, parsed.nofollow_links)
+        };
+
+        // Ignored by default
+        let mut settings = Settings::default();
+        assert_eq!(parse(&settings, "noindex, nofollow"), (2, 1, false, 0));
+
+        settings.parser.respect_meta_robots = true;
+        assert_eq!(parse(&settings, "index, follow"), (2, 1, false, 0));
+        assert_eq!(parse(&settings, "noindex"), (2, 0, true, 0));
+        assert_eq!(parse(&settings, "nofollow"), (0, 1, false, 2));
+        assert_eq!(parse(&settings, "noindex, nofollow"), (0, 0, true, 2));
+        assert_eq!(parse(&settings, "none"), (0, 0, true, 2));
+
+        // The links marked with `rel="nofollow"` are counted too
+        settings.parser.respect_nofollow = true;
+        assert_eq!(parse(&settings, "index, follow"), (1, 1, false, 1));
+        assert_eq!(parse(&settings, "noindex"), (1, 0, true, 1));
+        assert_eq!(parse(&settings, "nofollow"), (0, 1, false, 2));
+        settings.parser.respect_meta_robots = false;
+        assert_eq!(parse(&settings, "noindex, nofollow"), (1, 1, false, 1));
+    }
+
+    #[test]
+    fn test_prefer_canonical() {
+        let parse = |settings: &Settings, head: &str| {
+            let url = Url::parse("http://shop.test/items/42?ref=home").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.body = format!("<html><head>{}</head><body><h1>Item</h1></body></html>", head);
+            let spider = SpiderBuilder::default()
+                .settings(settings.clone())
+                .crawl_rule(
+                    Condition::any(),
+                    ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| Some(json!(s))))
+                .build();
+            let parsed = Parser::apply_rules(spider.crawl_rules(), &settings.parser,
+                                             &spider.url_normalizer(), spider.url_policy(),
+                                             &res, None);
+            (parsed.canonical.map(|url| url.to_string()), parsed.data)
+        };
+        let head = r#"<link rel="canonical" href="/items/42">"#;
+
+        let mut settings = Settings::default();
+        assert_eq!(parse(&settings, head), (None, vec![json!({ "title": ["Item"] })]));
+
+        settings.parser.prefer_canonical = true;
+        let canonical = "http://shop.test/items/42";
+        assert_eq!(parse(&settings, head), (
+            Some(canonical.to_string()),
+            vec![json!({ "title": ["Item"], "_canonical": canonical })],
+        ));
+        assert_eq!(parse(&settings, ""), (None, vec![json!({ "title": ["Item"] })]));
+
+        // Canonical URLs on other hosts are recorded the same, they are never fetched
+        let head = r#"<link rel="canonical" href="http://cdn.test/42">"#;
+        let (canonical, _) = parse(&settings, head);
+        assert_eq!(canonical, Some("http://cdn.test/42".to_string()));
+    }
+
+    #[test]
+    fn test_follow_rel_next() {
+        let url = Url::parse("http://shop.test/items?page=2").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<html><head><link rel="next" href="?page=3"></head>
+                      <body><h1>Items</h1><a href="?page=3">3</a><a href="/cart">Cart</a></body>
+                      </html>"#.to_string();
+        let parse = |settings: &Settings, body: &str| {
+            let mut res = res.clone();
+            res.body = body.to_string();
+            let spider = SpiderBuilder::default()
+                .settings(settings.clone())
+                .crawl_rule(
+                    Condition::any(),
+                    ParseRule::pattern("title", Pattern::CssSelector("h1"), |s| {
+                        Some(Value::String(s[0].clone()))
+                    }))
+                .build();
+            let parsed = Parser::apply_rules(spider.crawl_rules(), &settings.parser,
+                                             &spider.url_normalizer(), spider.url_policy(),
+                                             &res, None);
+            let urls: Vec<_> = parsed.urls.iter().map(Url::to_string).collect();
+            (parsed.rel_next.map(|url| url.to_string()), urls)
+        };
+        let next = "http://shop.test/items?page=3".to_string();
+        let cart = "http://shop.test/cart".to_string();
+
+        let mut settings = Settings::default();
+        assert_eq!(parse(&settings, &res.body), (None, vec![next.clone(), cart.clone()]));
+
+        // The next page is sent apart from the other links
+        settings.parser.follow_rel_next = true;
+        assert_eq!(parse(&settings, &res.body), (Some(next.clone()), vec![cart.clone()]));
+
+        // Only from the pages the crawl rules extracted data from
+        let body = res.body.replace("<h1>Items</h1>", "");
+        assert_eq!(parse(&settings, &body), (None, vec![next, cart]));
+    }
+
+    #[test]
+    fn test_skip_amp() {
+        let amp_url = |url: &str| Utils::amp_url(&Url::parse(url).unwrap());
+        assert!(amp_url("http://news.test/2019/03/rust/amp"));
+        assert!(amp_url("http://news.test/2019/03/rust/AMP/"));
+        assert!(amp_url("http://news.test/2019/03/rust?amp=1"));
+        assert!(amp_url("http://news.test/2019/03/rust?page=2&amp=1"));
+        assert!(!amp_url("http://news.test/2019/03/rust"));
+        assert!(!amp_url("http://news.test/2019/03/ramp"));
+        assert!(!amp_url("http://news.test/amp/2019/03/rust"));
+        assert!(!amp_url("http://news.test/2019/03/rust?amp=0"));
+
+        let url = Url::parse("http://news.test/2019/03/rust").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<html><head>
+                        <title>Rust 2018</title>
+                        <link rel="amphtml" href="http://amp.news.test/2019/03/rust">
+                      </head><body>
+                        <article><h1>Rust 2018</h1><p>The edition is out.</p></article>
+                        <a href="http://amp.news.test/2019/03/rust">AMP</a>
+                        <a href="/2019/03/cargo">Cargo</a>
+                        <a href="/2019/03/cargo/amp">Cargo (AMP)</a>
+                        <a href="/2019/03/clippy?amp=1">Clippy (AMP)</a>
+                      </body></html>"#.to_string();
+        let parse = |settings: &Settings| {
+            let spider = SpiderBuilder::default().settings(settings.clone()).build();
+            let parsed = Parser::apply_rules(spider.crawl_rules(), &settings.parser,
+                                             &spider.url_normalizer(), spider.url_policy(),
+                                             &res, None);
+            let urls: Vec<_> = parsed.urls.iter().map(Url::to_string).collect();
+            (urls, parsed.amp.map(|url| url.to_string()), parsed.amp_links)
+        };
+
+        let mut settings = Settings::default();
+        let amp = Some("http://amp.news.test/2019/03/rust".to_string());
+        assert_eq!(parse(&settings), (vec!["http://news.test/2019/03/cargo".to_string()], amp, 3));
+
+        settings.parser.skip_amp_urls = false;
+        let (urls, _, amp_links) = parse(&settings);
+        assert_eq!((urls.len(), amp_links), (3, 1));
+
+        settings.parser.skip_amp = false;
+        let (urls, amp, amp_links) = parse(&settings);
+        assert_eq!((urls.len(), amp, amp_links), (4, None, 0));
+    }
+
+    #[test]
+    fn test_max_links_per_page() {
+        let url = Url::parse("http://en.wikipedia.org/wiki/Hub").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = (0..100).map(|i| format!(r#"<a href="/wiki/{}">{}</a>"#, i, i)).collect();
+
+        let mut settings = Settings::default();
+        let spider = SpiderBuilder::default().settings(settings.clone()).build();
+        let (urls, _) = Parser::parse(&spider, &res);
+        assert_eq!(urls.len(), 100);
+
+        // The cap applies after the crawl rules, in the order of the page
+        settings.parser.max_links_per_page = 10;
+        let spider = SpiderBuilder::default()
+            .settings(settings)
+            .crawl_rule(Condition::any_except(vec![r"/wiki/\d$"]), ParseRule::FilterUrls)
+            .build();
+        let (urls, _) = Parser::parse(&spider, &res);
+        let expected: Vec<Url> = (10..20)
+            .map(|i| Url::parse(&format!("http://en.wikipedia.org/wiki/{}", i)).unwrap())
+            .collect();
+        assert_eq!(urls, expected);
+    }
+
+    #[test]
+    fn test_skip_extensions() {
+        let skipped = |url: &str| {
+            let extensions = Settings::default().parser.skip_extensions;
+            Utils::skip_extension(&extensions, &Url::parse(url).unwrap())
+        };
+        assert!(skipped("http://site.test/a.pdf"));
+        assert!(skipped("http://site.test/files/Report.PDF"));
+        assert!(skipped("http://site.test/a.pdf?download=1#page=2"));
+        assert!(skipped("http://site.test/backup.tar.gz"));
+        assert!(!skipped("http://site.test/download?file=a.pdf"));
+        assert!(!skipped("http://site.test/a.pdf/"));
+        assert!(!skipped("http://site.test/.zip"));
+        assert!(!skipped("http://site.test/mp4"));
+        assert!(!skipped("http://site.test/page.html"));
+        assert!(!skipped("http://images.jpg/"));
+
+        let url = Url::parse("http://site.test/").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<a href="/a">A</a> <img src="/logo.png"> <a href="/logo.png">Logo</a>
+                      <a href="/paper.pdf">Paper</a> <a href="/get?f=paper.pdf">Paper</a>"#
+            .to_string();
+        let mut settings = Settings::default();
+        let spider = SpiderBuilder::default().settings(settings.clone()).build();
+        let parsed = Parser::apply_rules(spider.crawl_rules(), &settings.parser,
+                                         &spider.url_normalizer(), spider.url_policy(), &res,
+                                         None);
+        let urls: Vec<_> = parsed.urls.iter().map(Url::path).collect();
+        assert_eq!(urls, vec!["/a", "/get"]);
+        assert_eq!(parsed.skipped_links, 2);
+
+        settings.parser.skip_extensions.clear();
+        let spider = SpiderBuilder::default().settings(settings).build();
+        let (urls, _) = Parser::parse(&spider, &res);
+        assert_eq!(urls.len(), 4);
+    }
+
+    #[test]
+    fn test_invalid_links() {
+        let url = Url::parse("http://site.test/").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = "<a href=\" /a\n\">A</a> <a href=\"http://exa mple.com/\">Broken</a>
+                    <a href=\"https://münchen.example/straße\">IDN</a>
+                    <a href=\"http://[::1/\">Broken</a>"
+            .to_string();
+        let settings = Settings::default();
+        let spider = SpiderBuilder::default().settings(settings.clone()).build();
+        let parsed = Parser::apply_rules(spider.crawl_rules(), &settings.parser,
+                                         &spider.url_normalizer(), spider.url_policy(), &res,
+                                         None);
+        let urls: Vec<_> = parsed.urls.iter().map(Url::as_str).collect();
+        assert_eq!(urls, vec!["http://site.test/a", "https://xn--mnchen-3ya.example/stra%C3%9Fe"]);
+        assert_eq!(parsed.invalid_links, 2);
+    }
+
+    #[test]
+    fn test_trap() {
+        let mut settings = Settings::default().parser;
+        let trap = |settings: &ParserSettings, url: &str| {
+            Utils::trap(settings, &Url::parse(url).unwrap())
+        };
+        let long = format!("http://site.test/search?q={}", "a".repeat(2083));
+        let facets = (0..51).map(|i| format!("f{}=1", i)).collect::<Vec<_>>().join("&");
+        let facets = format!("http://site.test/search?{}", facets);
+
+        assert!(trap(&settings, &long).is_some_and(|reason| reason.contains("max_url_length")));
+        assert!(trap(&settings, &facets).is_some_and(|reason| reason.contains("max_query")));
+        assert_eq!(trap(&settings, "http://site.test/a/a/a/a/b").as_deref(),
+                   Some(r#""a" repeated (max_repeated_segments)"#));
+        assert_eq!(trap(&settings, "http://site.test/a/a/a/b/a/a/a"), None);
+        assert_eq!(trap(&settings, "http://site.test/a//a/a/"), None);
+        assert_eq!(trap(&settings, "http://site.test/2019/01/01/a"), None);
+
+        settings.max_url_length = 0;
+        settings.max_query_params = 0;
+        settings.max_repeated_segments = 0;
+        assert_eq!(trap(&settings, &long), None);
+        assert_eq!(trap(&settings, &facets), None);
+        assert_eq!(trap(&settings, "http://site.test/a/a/a/a/b"), None);
+    }
+
+    #[test]
+    fn test_page_and_pattern_rules() {
+        let url = Url::parse("http://site.test/a").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = "<h1>A</h1><p class='lead'>Lead</p><li>1</li><li>2</li>".to_string();
+        let cnd = Condition::any();
+        let text = |s: Vec<String>| Some(json!(s[0]));
+        let spider = SpiderBuilder::default()
+            .crawl_rule(cnd.clone(), ParseRule::callback(|page| {
+                Some(page.doc().select("li").unwrap().map(|li| json!(li.text_contents())).collect())
+            }))
+            .crawl_rule(cnd.clone(), ParseRule::pattern("title", Pattern::CssSelector("h1"), text))
+            .crawl_rule(cnd.clone(), ParseRule::callback(|_| Some(vec![json!({ "page": true })])))
+            .crawl_rule(cnd, ParseRule::pattern("lead", Pattern::CssSelector(".lead"), text))
+            .build();
+
+        // The `Pattern` fields aren't lost to the values of the `Page` rule before them, nor
+        // merged into those of the `Page` rule in between
+        let (_, data) = Parser::parse(&spider, &res);
+        assert_eq!(data, vec![
+            json!({ "title": "A", "lead": "Lead" }),
+            json!("1"),
+            json!("2"),
+            json!({ "page": true }),
+        ]);
+
+        // Without matches, only the `Page` values are left
+        res.body = "<li>1</li>".to_string();
+        assert_eq!(Parser::parse(&spider, &res).1, vec![json!("1"), json!({ "page": true })]);
+    }
+
+    #[test]
+    fn test_rule_options() {
+        let response = |depth: u32| {
+            let url = Url::parse("http://site.test/articles/1").unwrap();
+            let mut res = Response::new(Request::new(url, depth, 1));
+            res.body = "<h1>A</h1><a href='/articles/2'>2</a><a href='/about'>About</a>"
+                .to_string();
+            res
+        };
+        let text = |s: Vec<String>| Some(json!(s[0]));
+        let builder = || SpiderBuilder::default()
+            .rule(CrawlRule::builder()
+                .name("articles")
+                .condition(Condition::path_prefixes(vec!["/articles/"]))
+                .max_depth(1)
+                .build())
+            .rule(CrawlRule::builder()
+                .name("title")
+                .parse(ParseRule::pattern("title", Pattern::CssSelector("h1"), text))
+                .build())
+            .rule(CrawlRule::builder()
+                .name("page")
+                .parse(ParseRule::callback(|_| Some(vec![json!({}), json!({ "_rule": "own" })])))
+                .max_depth(0)
+                .build());
+        let mut settings = Settings::default();
+        settings.parser.tag_rule = true;
+        let spider = builder().settings(settings.clone()).build();
+
+        // The links of a page at depth 1 would be 2 deep, past the `max_depth` of the rule
+        // that allows them
+        let (urls, data) = Parser::parse(&spider, &response(0));
+        assert_eq!(urls.iter().map(Url::path).collect::<Vec<_>>(), vec!["/articles/2"]);
+        assert_eq!(data, vec![
+            json!({ "title": "A", "_rule": "title" }),
+            json!({ "_rule": "page" }),
+            json!({ "_rule": "own" }),
+        ]);
+        let (urls, data) = Parser::parse(&spider, &response(1));
+        assert!(urls.is_empty());
+        assert_eq!(data, vec![json!({ "title": "A", "_rule": "title" })]);
+
+        // The names are in the reports, but only added to the `Items` with `tag_rule`
+        let mut reports = Vec::new();
+        let spider = builder().rule(CrawlRule::new(Condition::any(),
+                                                   ParseRule::FilterUrls)).build();
+        let (_, data) = Parser::parse_traced(&spider, &response(0), Some(&mut reports));
+        assert_eq!(data[0], json!({ "title": "A" }));
+        let reports = serde_json::to_value(&reports).unwrap();
+        assert_eq!(reports[0]["name"], json!("articles"));
+        assert_eq!(reports[3].get("name"), None);
+
+        // Nor are the links followed from the pages of a rule that doesn't follow them
+        let spider = builder()
+            .rule(CrawlRule::builder()
+                .condition(Condition::path_prefixes(vec!["/articles/1"]))
+                .follow(false)
+                .build())
+            .settings(settings)
+            .build();
+        let (urls, data) = Parser::parse(&spider, &response(0));
+        assert!(urls.is_empty());
+        assert_eq!(data.len(), 3);
+    }
+
+    #[test]
+    fn test_css_selector_pattern() {
+        let url = Url::parse("http://en.wikipedia.org/wiki/Rust").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = "<h1>Rust</h1><p class='lead'>A language</p>".to_string();
+
+        let rule = |sel| ParseRule::pattern("text", Pattern::CssSelector(sel), |s| Some(json!(s)));
+        let spider = SpiderBuilder::default()
+            .crawl_rule(Condition::any(), rule("h1, p.lead"))
+            .crawl_rule(Condition::any(), rule("p[unclosed"))
+            .build();
+
+        // The invalid selector matches nothing instead of panicking
+        let (_, data) = Parser::parse(&spider, &res);
+        assert_eq!(data, vec![json!({ "text": ["Rust", "A language"] })]);
+    }
+
+    #[test]
+    fn test_header_pattern() {
+        let url = Url::parse("http://api.test/items?page=1").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.headers.insert("x-total-count", HeaderValue::from_static("1234"));
+        res.headers.append("link", HeaderValue::from_static("</items?page=2>; rel=\"next\""));
+        res.headers.append("link", HeaderValue::from_static("</items?page=9>; rel=\"last\""));
+        res.body = "<h1>Items</h1>".to_string();
+
+        let rule = |field, name| {
+            ParseRule::pattern(field, Pattern::Header(name), |values| Some(json!(values)))
+        };
+        let spider = SpiderBuilder::default()
+            .crawl_rule(Condition::any(), rule("total", "X-Total-Count"))
+            .crawl_rule(Condition::any(), rule("links", "Link"))
+            .crawl_rule(Condition::any(), rule("missing", "Last-Modified"))
+            .build();
+
+        // A missing header leaves the field out, like a pattern that matches nothing
+        let (_, data) = Parser::parse(&spider, &res);
+        assert_eq!(data, vec![json!({
+            "total": ["1234"],
+            "links": ["</items?page=2>; rel=\"next\"", "</items?page=9>; rel=\"last\""],
+        })]);
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let response = |content_type: &str, body: &str| {
+            let url = Url::parse("http://site.test/sitemap.xml").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+            res.body = body.to_string();
+            res
+        };
+        let spider = SpiderBuilder::default()
+            .crawl_rule(Condition::any_except(vec!["private"]), ParseRule::FilterUrls)
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::callback(|_| Some(vec![json!({ "page": true })])))
+            .build();
+        let urls = |res: &Response| -> Vec<String> {
+            Parser::parse(&spider, res).0.iter().map(|u| u.to_string()).collect()
+        };
+
+        // The URLs of a urlset are filtered by the crawl rules, no item is extracted
+        let res = response("application/xml", r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!-- generated -->
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>http://site.test/a</loc><lastmod>2019-03-01</lastmod></url>
+              <url><loc>http://site.test/private/b</loc></url>
+              <url><loc>/c</loc></url>
+            </urlset>"#);
+        assert_eq!(urls(&res), vec!["http://site.test/a", "http://site.test/c"]);
+        assert!(Parser::parse(&spider, &res).1.is_empty());
+
+        // The sitemaps of an index are followed
+        let res = response("text/xml; charset=UTF-8", r#"
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap><loc>http://site.test/pages.xml.gz</loc></sitemap>
+              <sitemap><loc>http://site.test/news.xml</loc></sitemap>
+            </sitemapindex>"#);
+        assert_eq!(urls(&res), vec!["http://site.test/pages.xml.gz", "http://site.test/news.xml"]);
+
+        // Malformed XML yields the entries that are complete
+        let res = response("application/xml", "<urlset><url><loc>http://site.test/d</loc></url>\
+                                               <url><loc>http://site.test/e</url>\
+                                               <url><loc>http://site.test/f");
+        assert_eq!(urls(&res), vec!["http://site.test/d"]);
+
+        // Other documents are parsed as HTML, whatever their root or content type
+        let res = response("application/xml", "<catalog><a href='/g'>g</a></catalog>");
+        assert_eq!(Parser::parse(&spider, &res), (vec![Url::parse("http://site.test/g").unwrap()],
+                                                   vec![json!({ "page": true })]));
+        let res = response("text/html", "<urlset><url><loc>/h</loc></url></urlset>");
+        assert_eq!(Parser::parse(&spider, &res), (vec![], vec![json!({ "page": true })]));
+    }
+
+    #[test]
+    fn test_list() {
+        let url = Url::parse("http://shop.test/catalog").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<h1>Catalog</h1>
+            <div class="product"><h2>Kettle</h2><span class="price">$25</span>
+              <a href="/kettle">More</a></div>
+            <div class="product"><h2>Toaster</h2><span class="price">$40</span>
+              <a href="/toaster">More</a></div>
+            <div class="product"><h2>Mug</h2><span class="sku">sku-771</span></div>
+            <div class="product"><p>Sold out</p></div>"#.to_string();
+        let text = |s: Vec<String>| Some(json!(s[0].trim()));
+        let spider = SpiderBuilder::default()
+            .crawl_rule(
+                Condition::new(vec!["shop.test"], vec![]),
+                ParseRule::list(".product", vec![
+                    ParsePattern::new("name", Pattern::CssSelector("h2"), text),
+                    ParsePattern::new("price", Pattern::CssSelector(".price"), text),
+                    ParsePattern::new("sku", Pattern::Regex(r"sku-\d+"), text),
+                ]))
+            .crawl_rule(
+                Condition::new(vec!["shop.test"], vec![]),
+                ParseRule::pattern("title", Pattern::CssSelector("h1"), text))
+            .build();
+
+        // The fields of the page go to an `Item` of their own, before the list's, and the
+        // element without any field is left out
+        let (urls, data) = Parser::parse(&spider, &res);
+        assert_eq!(urls.len(), 2);
+        assert_eq!(data, vec![
+            json!({ "title": "Catalog" }),
+            json!({ "name": "Kettle", "price": "$25" }),
+            json!({ "name": "Toaster", "price": "$40" }),
+            json!({ "name": "Mug", "sku": "sku-771" }),
+        ]);
+
+        let mut reports = Vec::new();
+        Parser::parse_traced(&spider, &res, Some(&mut reports));
+        match &reports[0] {
+            RuleReport::List { applied, containers, items, .. } => {
+                assert_eq!((*applied, *containers, *items), (true, 4, 3));
+            }
+            report => panic!("unexpected report {:?}", report),
+        }
+
+        // An Xpath pattern, rejected by `try_build`, matches nothing instead of panicking
+        let xpath = vec![ParsePattern::new("price", Pattern::Xpath("//span"), text)];
+        if let ParseRule::List(list) = ParseRule::list(".product", xpath) {
+            let page = kuchiki::parse_html().one(res.body.as_str());
+            let container = page.select_first(".product").unwrap();
+            assert_eq!(Utils::list_item(&list, container.as_node(), &res), json!({}));
+        }
+    }
+
+    #[test]
+    fn test_feed() {
+        let response = |url: &str, content_type: &str, body: &str| {
+            let mut res = Response::new(Request::new(Url::parse(url).unwrap(), 0, 1));
+            res.headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+            res.body = body.to_string();
+            res
+        };
+        let feed = ParseFeed { summary: "", published: "date", ..Default::default() };
+        let mut settings = Settings::default();
+        let spider = |settings: Settings| SpiderBuilder::default()
+            .settings(settings)
+            .crawl_rule(Condition::any_except(vec!["/ads/"]), ParseRule::FilterUrls)
+            .crawl_rule(Condition::new(vec!["/feeds/"], vec![]), ParseRule::Feed(feed.clone()))
+            .crawl_rule(
+                Condition::any(),
+                ParseRule::callback(|_| Some(vec![json!({ "page": true })])))
+            .build();
+
+        // The entry links are followed, an `Item` is made of each entry
+        let res = response("http://news.test/feeds/rss.xml", "application/rss+xml", r#"
+            <rss version="2.0"><channel>
+              <item>
+                <title>First</title>
+                <link>/2019/first</link>
+                <pubDate>Fri, 01 Mar 2019 10:00:00 GMT</pubDate>
+                <description>Summary</description>
+              </item>
+              <item><title>Sponsored</title><link>http://news.test/ads/1</link></item>
+              <item><title>No link</title></item>
+            </channel></rss>"#);
+        let (urls, data) = Parser::parse(&spider(settings.clone()), &res);
+        assert_eq!(urls, vec![Url::parse("http://news.test/2019/first").unwrap()]);
+        assert_eq!(data, vec![
+            json!({ "title": "First", "link": "http://news.test/2019/first",
+                    "date": "Fri, 01 Mar 2019 10:00:00 GMT" }),
+            json!({ "title": "Sponsored", "link": "http://news.test/ads/1", "date": null }),
+            json!({ "title": "No link", "link": null, "date": null }),
+        ]);
+
+        // Atom feeds too, only where the condition of the rule allows
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <entry><title>Atom</title><link href="entries/1"/></entry>
+            </feed>"#;
+        let res = response("http://news.test/feeds/atom.xml", "application/atom+xml", atom);
+        let (urls, data) = Parser::parse(&spider(settings.clone()), &res);
+        assert_eq!(urls, vec![Url::parse("http://news.test/feeds/entries/1").unwrap()]);
+        assert_eq!(data.len(), 1);
+        let res = response("http://news.test/atom.xml", "application/atom+xml", atom);
+        assert!(Parser::parse(&spider(settings.clone()), &res).1.is_empty());
+
+        // The feeds pages link to are followed with `follow_feed_links`
+        let res = response("http://news.test/", "text/html", r#"
+            <link rel="alternate" type="application/rss+xml" href="/feeds/rss.xml">
+            <a href="/about">About</a>"#);
+        let (urls, data) = Parser::parse(&spider(settings.clone()), &res);
+        assert_eq!(urls, vec![Url::parse("http://news.test/about").unwrap()]);
+        assert_eq!(data, vec![json!({ "page": true })]);
+        settings.parser.follow_feed_links = true;
+        let (urls, _) = Parser::parse(&spider(settings), &res);
+        assert_eq!(urls, vec![
+            Url::parse("http://news.test/about").unwrap(),
+            Url::parse("http://news.test/feeds/rss.xml").unwrap(),
+        ]);
+    }
+}
@@ -0,0 +1,577 @@
+//! URL normalization
+//!
+//! The links found on a page are turned into absolute URLs by a `UrlNormalizer` before they
+//! are filtered by the crawl rules and queued. Different sites treat different URLs as the
+//! same page, so the normalizer can be replaced with `SpiderBuilder::url_normalizer`.
+//!
+//! The URLs it returns are then canonicalized by a `UrlCanonicalizer`, per
+//! `ParserSettings::canonicalize`.
+use std::sync::Arc;
+
+use regex::Regex;
+use reqwest::{Url, UrlError};
+
+use crate::settings::CanonicalizeSettings;
+
+/// The query parameters of click and campaign tracking, stripped with
+/// `CanonicalizeSettings::strip_tracking_params`
+pub const TRACKING_PARAMS: &[&str] = &[
+    "utm_source", "utm_medium", "utm_campaign", "utm_term", "utm_content", "utm_id",
+    "utm_name", "fbclid", "gclid", "gclsrc", "dclid", "gbraid", "wbraid", "msclkid", "yclid",
+    "twclid", "ttclid", "igshid", "li_fat_id", "mc_cid", "mc_eid", "_ga", "_gl", "_hsenc",
+    "_hsmi", "mkt_tok",
+];
+
+/// Turns the href of a link into the URL that is crawled
+pub trait UrlNormalizer: Send + Sync {
+    /// Resolves `href`, found on the page at `base`, to an absolute URL
+    fn normalize(&self, base: &Url, href: &str) -> Result<Url, UrlError>;
+}
+
+/// Joins relative hrefs with the URL of the page, and leaves the URL as it is otherwise.
+/// Used unless the spider installs another `UrlNormalizer`.
+///
+/// Hrefs are parsed the way browsers do: the whitespace around them is trimmed and the tabs
+/// and newlines in them are removed, internationalized hosts are converted to punycode, and
+/// the characters of the path, query and fragment that can't appear in a URL are
+/// percent-encoded as UTF-8. Escapes that are already there are kept as they are, so an
+/// encoded href and its unencoded form give the same URL.
+#[derive(Clone, Debug, Default)]
+pub struct DefaultNormalizer;
+
+impl UrlNormalizer for DefaultNormalizer {
+    fn normalize(&self, base: &Url, href: &str) -> Result<Url, UrlError> {
+        Utils::join(base, href)
+    }
+}
+
+/// Same as `DefaultNormalizer`, and then canonicalizes the URL according to its toggles,
+/// which are all off by default. The URL parser already lowercases the host, and leaves out
+/// the default port of http and https.
+///
+/// ```
+/// use vortex::parser::StandardNormalizer;
+///
+/// let normalizer = StandardNormalizer { strip_fragment: true, ..Default::default() };
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StandardNormalizer {
+    /// Lowercase the path, for servers that ignore its case
+    pub lowercase: bool,
+
+    /// Remove the fragment (`#section`), which never reaches the server
+    pub strip_fragment: bool,
+
+    /// Remove the port if it is the default port of the scheme
+    pub strip_default_port: bool,
+
+    /// Sort the query parameters by name, keeping the order of repeated names. The query is
+    /// re-encoded in the process, e.g. `%20` becomes `+`.
+    pub sort_query: bool,
+}
+
+impl UrlNormalizer for StandardNormalizer {
+    fn normalize(&self, base: &Url, href: &str) -> Result<Url, UrlError> {
+        let mut url = Utils::join(base, href)?;
+
+        if self.lowercase {
+            let path = url.path().to_lowercase();
+            url.set_path(&path);
+        }
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+        if self.strip_default_port && url.port().is_some() {
+            let mut portless = url.clone();
+            let _ = portless.set_port(None);
+            if url.port() == portless.port_or_known_default() {
+                url = portless;
+            }
+        }
+        if self.sort_query && url.query().is_some() {
+            let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+            pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+
+        Ok(url)
+    }
+}
+
+/// Rewrites the URLs that point to the same page in one form, so that they are crawled once:
+/// the `Scheduler` canonicalizes the URLs it queues, and the links found on pages are
+/// canonicalized before the crawl rules are applied to them. The URL parser already
+/// lowercases the scheme and host, and leaves out the default port of http and https.
+#[derive(Clone, Debug, Default)]
+pub struct UrlCanonicalizer {
+    /// Remove the fragment (`#section`)
+    pub strip_fragment: bool,
+
+    /// Replace runs of slashes in the path with one, e.g. `/a//b` becomes `/a/b`
+    pub collapse_slashes: bool,
+
+    /// Remove the slash at the end of the path, e.g. `/a/` becomes `/a`. The root path is kept
+    pub strip_trailing_slash: bool,
+
+    /// Decode the percent-encoded unreserved characters (letters, digits, `-`, `.`, `_` and
+    /// `~`) of the path and query, e.g. `%7Euser` becomes `~user`, and uppercase the hex
+    /// digits of the other escapes
+    pub decode_unreserved: bool,
+
+    /// Remove the query parameters with these names. Names are case-sensitive, and the
+    /// order of the other parameters is kept
+    pub strip_params: Vec<String>,
+
+    /// Remove the query parameters whose names aren't in the list, unless it is empty
+    pub keep_only_params: Vec<String>,
+
+    /// Sort the query parameters by name, keeping the order of repeated names. Unlike
+    /// `StandardNormalizer::sort_query`, the parameters aren't re-encoded
+    pub sort_query: bool,
+
+    /// Rewrite the values of query parameters in the URLs the `Scheduler` deduplicates
+    /// `Requests` by, see `dedup_url`. The URLs fetched are left as they are
+    pub param_value_rules: Vec<ParamValueRule>,
+}
+
+/// Replaces the matches of `pattern` in the values of the query parameters named `param`
+/// with `replacement`, e.g. the session ids of `sid=3f2a9c` with nothing
+#[derive(Clone, Debug)]
+pub struct ParamValueRule {
+    pub param: String,
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+impl UrlCanonicalizer {
+    pub fn from_settings(settings: CanonicalizeSettings) -> Self {
+        let mut strip_params = settings.strip_params;
+        if settings.strip_tracking_params {
+            strip_params.extend(TRACKING_PARAMS.iter().map(|name| name.to_string()));
+        }
+        Self {
+            strip_fragment: settings.strip_fragment,
+            collapse_slashes: settings.collapse_slashes,
+            strip_trailing_slash: settings.strip_trailing_slash,
+            decode_unreserved: settings.decode_unreserved,
+            strip_params,
+            keep_only_params: settings.keep_only_params,
+            sort_query: settings.sort_query,
+            // Invalid patterns are rejected by `Settings::validate`
+            param_value_rules: settings.param_value_rules.iter()
+                .filter_map(|(param, pattern, replacement)| {
+                    Some(ParamValueRule {
+                        param: param.clone(),
+                        pattern: Regex::new(pattern).ok()?,
+                        replacement: replacement.clone(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// The canonical form of `url`
+    pub fn canonicalize(&self, mut url: Url) -> Url {
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+        if url.cannot_be_a_base() {
+            return url;
+        }
+
+        let mut path = url.path().to_string();
+        if self.collapse_slashes {
+            while path.contains("//") {
+                path = path.replace("//", "/");
+            }
+        }
+        if self.strip_trailing_slash && path.len() > 1 {
+            path = path.trim_end_matches('/').to_string();
+            if path.is_empty() {
+                path.push('/');
+            }
+        }
+        if self.decode_unreserved {
+            path = Utils::decode_unreserved(&path);
+            if let Some(query) = url.query().map(Utils::decode_unreserved) {
+                url.set_query(Some(&query));
+            }
+        }
+        if path != url.path() {
+            url.set_path(&path);
+        }
+        if let Some(query) = url.query().map(|query| self.filter_query(query)) {
+            if Some(query.as_str()) != url.query() {
+                url.set_query(if query.is_empty() { None } else { Some(&query) });
+            }
+        }
+        url
+    }
+
+    /// The URL `url`, a canonical one, is deduplicated by, if `param_value_rules` rewrite
+    /// its query. Two URLs with the same `dedup_url` are fetched once.
+    pub fn dedup_url(&self, url: &Url) -> Option<Url> {
+        if self.param_value_rules.is_empty() {
+            return None;
+        }
+        let query = url.query()?;
+        let rewritten = query.split('&')
+            .map(|param| {
+                let (name, value) = match param.find('=') {
+                    Some(i) => (&param[..i], &param[i + 1..]),
+                    None => return param.to_string(),
+                };
+                let value = self.param_value_rules.iter()
+                    .filter(|rule| rule.param == Utils::decode_unreserved(name))
+                    .fold(value.to_string(), |value, rule| {
+                        rule.pattern.replace_all(&value, rule.replacement.as_str()).into_owned()
+                    });
+                format!("{}={}", name, value)
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        if rewritten == query {
+            return None;
+        }
+        let mut dedup_url = url.clone();
+        dedup_url.set_query(Some(&rewritten));
+        Some(dedup_url)
+    }
+
+    /// `query` without the parameters removed by `strip_params` and `keep_only_params`,
+    /// sorted with `sort_query`. The parameters are compared by name, with their unreserved
+    /// characters decoded, so `utm%5Fsource` is `utm_source`.
+    fn filter_query(&self, query: &str) -> String {
+        if self.strip_params.is_empty() && self.keep_only_params.is_empty() && !self.sort_query {
+            return query.to_string();
+        }
+        let mut params: Vec<(String, &str)> = query.split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let name = param.split('=').next().unwrap_or_default();
+                (Utils::decode_unreserved(name), param)
+            })
+            .filter(|(name, _)| {
+                !self.strip_params.contains(name)
+                    && (self.keep_only_params.is_empty() || self.keep_only_params.contains(name))
+            })
+            .collect();
+        if self.sort_query {
+            params.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        params.iter().map(|(_, param)| *param).collect::<Vec<_>>().join("&")
+    }
+}
+
+/// Canonicalizes the URLs of a `UrlNormalizer`, see `Spider::url_normalizer`
+pub(crate) struct Canonicalized {
+    pub normalizer: Arc<dyn UrlNormalizer>,
+    pub canonicalizer: UrlCanonicalizer,
+}
+
+impl UrlNormalizer for Canonicalized {
+    fn normalize(&self, base: &Url, href: &str) -> Result<Url, UrlError> {
+        let url = self.normalizer.normalize(base, href)?;
+        Ok(self.canonicalizer.canonicalize(url))
+    }
+}
+
+/// The characters of a fragment that `Utils::join` percent-encodes
+const FRAGMENT_ENCODED: &[char] = &[' ', '"', '<', '>', '`'];
+
+struct Utils;
+
+impl Utils {
+    /// Resolves `href` against `base`. The URL parser leaves the spaces, quotes, `<`, `>` and
+    /// backticks of the fragment as they are, browsers encode them.
+    fn join(base: &Url, href: &str) -> Result<Url, UrlError> {
+        let mut url = base.join(href)?;
+        let encoded = url.fragment()
+            .filter(|fragment| fragment.contains(FRAGMENT_ENCODED))
+            .map(|fragment| {
+                fragment.chars()
+                    .map(|c| if FRAGMENT_ENCODED.contains(&c) {
+                        format!("%{:02X}", c as u32)
+                    } else {
+                        c.to_string()
+                    })
+                    .collect::<String>()
+            });
+        if let Some(fragment) = encoded {
+            url.set_fragment(Some(&fragment));
+        }
+        Ok(url)
+    }
+
+    /// Decodes the percent-encoded unreserved characters of `s`, and uppercases the hex
+    /// digits of the other escapes
+    fn decode_unreserved(s: &str) -> String {
+        let mut decoded = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(i) = rest.find('%') {
+            decoded.push_str(&rest[..i]);
+            let escape = rest.get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok().map(|byte| (hex, byte)));
+            match escape {
+                Some((_, byte)) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                    decoded.push(byte as char);
+                    rest = &rest[i + 3..];
+                }
+                Some((hex, _)) => {
+                    decoded.push('%');
+                    decoded.push_str(&hex.to_uppercase());
+                    rest = &rest[i + 3..];
+                }
+                None => {
+                    decoded.push('%');
+                    rest = &rest[i + 1..];
+                }
+            }
+        }
+        decoded.push_str(rest);
+        decoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::Settings;
+
+    use super::*;
+
+    fn normalize(normalizer: &dyn UrlNormalizer, href: &str) -> String {
+        let base = Url::parse("http://site.test/src/").unwrap();
+        normalizer.normalize(&base, href).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_default_normalizer() {
+        let base = Url::parse("http://en.wikipedia.org/src/").unwrap();
+        let normalize = |href| DefaultNormalizer.normalize(&base, href).unwrap().to_string();
+
+        assert_eq!(normalize("picture.jpg"), "http://en.wikipedia.org/src/picture.jpg");
+        assert_eq!(normalize("../picture.jpg"), "http://en.wikipedia.org/picture.jpg");
+        assert_eq!(normalize("images/picture.jpg"),
+                   "http://en.wikipedia.org/src/images/picture.jpg");
+        assert_eq!(normalize("/images/picture.jpg"), "http://en.wikipedia.org/images/picture.jpg");
+        assert_eq!(normalize("http://ru.wikipedia.org"), "http://ru.wikipedia.org/");
+        assert_eq!(normalize("http://ru.wikipedia.org/index.html"),
+                   "http://ru.wikipedia.org/index.html");
+        assert_eq!(normalize("A.html?b=1&a=2#top"),
+                   "http://en.wikipedia.org/src/A.html?b=1&a=2#top");
+    }
+
+    #[test]
+    fn test_lowercase() {
+        let normalizer = StandardNormalizer { lowercase: true, ..Default::default() };
+        assert_eq!(normalize(&normalizer, "HTTP://Site.TEST/Wiki/Rust?Q=A"),
+                   "http://site.test/wiki/rust?Q=A");
+        assert_eq!(normalize(&StandardNormalizer::default(), "/Wiki/Rust"),
+                   "http://site.test/Wiki/Rust");
+    }
+
+    #[test]
+    fn test_strip_fragment() {
+        let normalizer = StandardNormalizer { strip_fragment: true, ..Default::default() };
+        assert_eq!(normalize(&normalizer, "page#top"), "http://site.test/src/page");
+        assert_eq!(normalize(&normalizer, "#top"), "http://site.test/src/");
+        assert_eq!(normalize(&StandardNormalizer::default(), "page#top"),
+                   "http://site.test/src/page#top");
+    }
+
+    #[test]
+    fn test_strip_default_port() {
+        let normalizer = StandardNormalizer { strip_default_port: true, ..Default::default() };
+        assert_eq!(normalize(&normalizer, "http://site.test:80/a"), "http://site.test/a");
+        assert_eq!(normalize(&normalizer, "https://site.test:443/a"), "https://site.test/a");
+        assert_eq!(normalize(&normalizer, "http://site.test:8080/a"), "http://site.test:8080/a");
+        assert_eq!(normalize(&normalizer, "https://site.test:80/a"), "https://site.test:80/a");
+    }
+
+    #[test]
+    fn test_sort_query() {
+        let normalizer = StandardNormalizer { sort_query: true, ..Default::default() };
+        assert_eq!(normalize(&normalizer, "?b=2&a=1&b=1&c"),
+                   "http://site.test/src/?a=1&b=2&b=1&c=");
+        assert_eq!(normalize(&normalizer, "page"), "http://site.test/src/page");
+        assert_eq!(normalize(&StandardNormalizer::default(), "?b=2&a=1"),
+                   "http://site.test/src/?b=2&a=1");
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let all = UrlCanonicalizer {
+            strip_fragment: true,
+            collapse_slashes: true,
+            strip_trailing_slash: true,
+            decode_unreserved: true,
+            ..Default::default()
+        };
+        let default = UrlCanonicalizer::from_settings(Settings::default().parser.canonicalize);
+        let none = UrlCanonicalizer::default();
+        let canonicalize = |canonicalizer: &UrlCanonicalizer, url: &str| {
+            canonicalizer.canonicalize(Url::parse(url).unwrap()).to_string()
+        };
+
+        // (input, all options, default options)
+        let table = [
+            ("http://example.com/a", "http://example.com/a", "http://example.com/a"),
+            ("http://example.com/a/", "http://example.com/a", "http://example.com/a/"),
+            ("http://EXAMPLE.com/a", "http://example.com/a", "http://example.com/a"),
+            ("HTTP://Example.COM/A", "http://example.com/A", "http://example.com/A"),
+            ("http://example.com/a#section", "http://example.com/a", "http://example.com/a"),
+            ("http://example.com:80/a", "http://example.com/a", "http://example.com/a"),
+            ("https://example.com:443/a", "https://example.com/a", "https://example.com/a"),
+            ("http://example.com:8080/a/", "http://example.com:8080/a",
+             "http://example.com:8080/a/"),
+            ("http://example.com", "http://example.com/", "http://example.com/"),
+            ("http://example.com/", "http://example.com/", "http://example.com/"),
+            ("http://example.com//", "http://example.com/", "http://example.com/"),
+            ("http://example.com//a///b//", "http://example.com/a/b", "http://example.com/a/b/"),
+            ("http://example.com/%7Euser/%61%2d%5F", "http://example.com/~user/a-_",
+             "http://example.com/~user/a-_"),
+            ("http://example.com/a%2fb%20c", "http://example.com/a%2Fb%20c",
+             "http://example.com/a%2Fb%20c"),
+            ("http://example.com/a?q=%41%2b#x", "http://example.com/a?q=A%2B",
+             "http://example.com/a?q=A%2B"),
+            ("http://example.com/a/?b=1&a=2", "http://example.com/a?b=1&a=2",
+             "http://example.com/a/?b=1&a=2"),
+            ("http://example.com/100%", "http://example.com/100%", "http://example.com/100%"),
+            ("mailto:someone@example.com", "mailto:someone@example.com",
+             "mailto:someone@example.com"),
+        ];
+        for (url, expected_all, expected_default) in table.iter() {
+            assert_eq!(canonicalize(&all, url), *expected_all, "{}", url);
+            assert_eq!(canonicalize(&default, url), *expected_default, "{}", url);
+        }
+        assert_eq!(canonicalize(&none, "http://example.com//a/#top"), "http://example.com//a/#top");
+    }
+
+    #[test]
+    fn test_strip_params() {
+        let canonicalize = |canonicalizer: &UrlCanonicalizer, url: &str| {
+            canonicalizer.canonicalize(Url::parse(url).unwrap()).to_string()
+        };
+        let mut settings = Settings::default().parser.canonicalize;
+        settings.strip_params = vec!["sessionid".to_string()];
+        let strip = UrlCanonicalizer::from_settings(settings.clone());
+
+        // The order of the other parameters is kept, and an empty query is removed
+        assert_eq!(canonicalize(&strip, "http://example.com/a?b=2&utm_source=x&a=1&fbclid=y"),
+                   "http://example.com/a?b=2&a=1");
+        assert_eq!(canonicalize(&strip, "http://example.com/a?utm_source=x&gclid=y#top"),
+                   "http://example.com/a");
+        assert_eq!(canonicalize(&strip, "http://example.com/a?sessionid=1"),
+                   "http://example.com/a");
+        assert_eq!(canonicalize(&strip, "http://example.com/a?"), "http://example.com/a?");
+        assert_eq!(canonicalize(&strip, "http://example.com/a?q=a%20b&&utm_medium"),
+                   "http://example.com/a?q=a%20b");
+        assert_eq!(canonicalize(&strip, "http://example.com/a?utm%5Fsource=x&id=1"),
+                   "http://example.com/a?id=1");
+        // Names are case-sensitive
+        assert_eq!(canonicalize(&strip, "http://example.com/a?UTM_SOURCE=x&SessionId=1"),
+                   "http://example.com/a?UTM_SOURCE=x&SessionId=1");
+
+        settings.keep_only_params = vec!["id".to_string(), "page".to_string()];
+        let keep = UrlCanonicalizer::from_settings(settings.clone());
+        assert_eq!(canonicalize(&keep, "http://example.com/a?sort=asc&page=2&id=7&ID=8"),
+                   "http://example.com/a?page=2&id=7");
+        assert_eq!(canonicalize(&keep, "http://example.com/a?sort=asc"), "http://example.com/a");
+
+        settings.keep_only_params = vec![];
+        settings.sort_query = true;
+        let sort = UrlCanonicalizer::from_settings(settings.clone());
+        assert_eq!(canonicalize(&sort, "http://example.com/a?b=2&utm_term=x&a=1&b=1&c"),
+                   "http://example.com/a?a=1&b=2&b=1&c");
+
+        settings.strip_tracking_params = false;
+        settings.sort_query = false;
+        settings.param_value_rules = vec![];
+        let tracked = UrlCanonicalizer::from_settings(settings);
+        assert_eq!(canonicalize(&tracked, "http://example.com/a?utm_source=x&sessionid=1"),
+                   "http://example.com/a?utm_source=x");
+    }
+
+    #[test]
+    fn test_dedup_url() {
+        let mut settings = Settings::default().parser.canonicalize;
+        let dedup_url = |canonicalizer: &UrlCanonicalizer, url: &str| {
+            canonicalizer.dedup_url(&Url::parse(url).unwrap()).map(|url| url.to_string())
+        };
+        let none = UrlCanonicalizer::from_settings(settings.clone());
+        assert_eq!(dedup_url(&none, "http://example.com/a?sid=3f2a&page=2"), None);
+
+        settings.param_value_rules = vec![
+            ("sid".to_string(), "[0-9a-f]+".to_string(), String::new()),
+            ("ts".to_string(), r"^\d+$".to_string(), "0".to_string()),
+        ];
+        let rules = UrlCanonicalizer::from_settings(settings);
+        assert_eq!(dedup_url(&rules, "http://example.com/a?page=2&sid=3f2a9c"),
+                   Some("http://example.com/a?page=2&sid=".to_string()));
+        assert_eq!(dedup_url(&rules, "http://example.com/a?ts=1554&ts=x&SID=3f"),
+                   Some("http://example.com/a?ts=0&ts=x&SID=3f".to_string()));
+        assert_eq!(dedup_url(&rules, "http://example.com/a?sid=&page"), None);
+        assert_eq!(dedup_url(&rules, "http://example.com/a"), None);
+    }
+
+    /// Hrefs as found on real pages, and the URLs they resolve to on
+    /// `http://site.test/src/`
+    mod hrefs {
+        use super::*;
+
+        #[test]
+        fn test_hrefs() {
+            let table = [
+                // Whitespace around the href is trimmed, tabs and newlines in it are removed
+                ("  page.html  ", "http://site.test/src/page.html"),
+                ("\n\t/a/\nb.html\r\n", "http://site.test/a/b.html"),
+                ("http://other.test/a\n?q=1", "http://other.test/a?q=1"),
+                ("/a b c", "http://site.test/a%20b%20c"),
+                // Non-breaking and other unicode spaces aren't trimmed, and are encoded
+                ("\u{a0}/a", "http://site.test/src/%C2%A0/a"),
+                // Internationalized hosts are converted to punycode, and lowercased first
+                ("https://münchen.example/straße", "https://xn--mnchen-3ya.example/stra%C3%9Fe"),
+                ("https://MÜNCHEN.example/", "https://xn--mnchen-3ya.example/"),
+                ("//xn--mnchen-3ya.example/", "http://xn--mnchen-3ya.example/"),
+                ("http://☃.net/", "http://xn--n3h.net/"),
+                ("http://пример.испытание/", "http://xn--e1afmkfd.xn--80akhbyknj4f/"),
+                // Unicode is encoded as UTF-8, escapes are kept, so both forms are the same URL
+                ("/café", "http://site.test/caf%C3%A9"),
+                ("/caf%C3%A9", "http://site.test/caf%C3%A9"),
+                ("/a%20b c", "http://site.test/a%20b%20c"),
+                ("/100%25", "http://site.test/100%25"),
+                ("/100%", "http://site.test/100%"),
+                ("/a%zz", "http://site.test/a%zz"),
+                ("?q=a b&city=Zürich", "http://site.test/src/?q=a%20b&city=Z%C3%BCrich"),
+                ("?q=%E2%9C%93&r=✓", "http://site.test/src/?q=%E2%9C%93&r=%E2%9C%93"),
+                ("/☃?☃=☃#☃", "http://site.test/%E2%98%83?%E2%98%83=%E2%98%83#%E2%98%83"),
+                ("/a\u{200b}b", "http://site.test/a%E2%80%8Bb"),
+                ("/a{b}`c`", "http://site.test/a%7Bb%7D%60c%60"),
+                ("/a\"b\"<c>", "http://site.test/a%22b%22%3Cc%3E"),
+                // As in the path and query, the spaces and quotes of the fragment are encoded
+                ("page#a b", "http://site.test/src/page#a%20b"),
+                ("page#a%20b", "http://site.test/src/page#a%20b"),
+                ("page#\"q\"<x>`y`", "http://site.test/src/page#%22q%22%3Cx%3E%60y%60"),
+                // Backslashes are slashes in http URLs, and dot segments are resolved
+                ("http://other.test\\a\\b", "http://other.test/a/b"),
+                ("/a/./b/../c", "http://site.test/a/c"),
+                ("../../../a", "http://site.test/a"),
+                ("HTTP://Other.TEST:80/A", "http://other.test/A"),
+            ];
+            for (href, expected) in table.iter() {
+                assert_eq!(normalize(&DefaultNormalizer, href), *expected, "{:?}", href);
+                assert_eq!(normalize(&StandardNormalizer::default(), href), *expected,
+                           "{:?}", href);
+            }
+        }
+
+        #[test]
+        fn test_invalid_hrefs() {
+            let base = Url::parse("http://site.test/src/").unwrap();
+            for href in ["http://exa mple.com/", "http://%zz.test/", "http://[::1/",
+                         "http://site.test:99999/", "http://", "https://a..b\u{0}.test/"] {
+                assert!(DefaultNormalizer.normalize(&base, href).is_err(), "{:?}", href);
+            }
+        }
+    }
+}
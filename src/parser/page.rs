@@ -1,98 +1,660 @@
 //!
-use kuchiki::{NodeRef, traits::*};
+use std::cell::{Ref, RefCell};
+use std::sync::Arc;
+
+use kuchiki::{NodeRef, Selectors, traits::*};
 use regex::Regex;
-use reqwest::{Url, UrlError};
+use reqwest::Url;
+use serde_json::Value;
 
 use crate::crawler::Response;
+use crate::parser::microdata::Microdata;
+use crate::parser::normalize::{DefaultNormalizer, UrlNormalizer};
+
+/// The pagination links of a page, declared with `rel="next"`, `"prev"`, `"first"` and
+/// `"last"`, see `Page::rel_links`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RelLinks {
+    pub next: Option<Url>,
+    pub prev: Option<Url>,
+    pub first: Option<Url>,
+    pub last: Option<Url>,
+}
+
+/// A link of a page, see `Page::links`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    /// The normalized href
+    pub url: Url,
+
+    /// The anchor text, with its whitespace collapsed
+    pub text: String,
+
+    /// Whether the link is marked with `rel="nofollow"` (or `"ugc"`, or `"sponsored"`)
+    pub nofollow: bool,
+}
 
 ///??
 pub struct Page {
     doc: NodeRef,
+    /// The serialized `doc`, see `html`
+    html: RefCell<Option<String>>,
+    url: Url,
+    links: Vec<Link>,
     urls: Vec<Url>,
+    follow_urls: Vec<Url>,
+    /// Number of links whose href isn't a valid URL, see `invalid_links`
+    invalid_links: usize,
+    normalizer: Arc<dyn UrlNormalizer>,
 }
 
 impl Page {
     pub fn from_response(res: &Response) -> Self {
+        Page::with_normalizer(res, Arc::new(DefaultNormalizer))
+    }
+
+    /// Same as `from_response`, the links are resolved with `normalizer`
+    pub fn with_normalizer(res: &Response, normalizer: Arc<dyn UrlNormalizer>) -> Self {
         //??
         let doc = kuchiki::parse_html().one(res.body.as_str());
 
-        //??
-        let urls = Utils::get_urls(&doc).iter()
-            .filter_map(|url| {
-                Utils::normalize_url(&res.request.url, url.as_str()).ok()
+        // Links to the page itself (e.g. `href="#top"`) are left out, as are the hrefs that
+        // aren't valid URLs
+        let mut invalid_links = 0;
+        let links: Vec<Link> = Utils::get_links(&doc).into_iter()
+            .filter_map(|(href, text, nofollow)| {
+                match normalizer.normalize(&res.request.url, href.as_str()) {
+                    Ok(url) => Some(Link { url, text, nofollow }),
+                    Err(e) => {
+                        debug!("Invalid link {:.100?} on {}: {}", href, res.request.url, e);
+                        invalid_links += 1;
+                        None
+                    }
+                }
             })
+            .filter(|link| !Utils::same_document(&link.url, &res.request.url))
             .collect();
 
-        Self { doc, urls }
+        let urls = links.iter().map(|link| link.url.clone()).collect();
+        let follow_urls = links.iter()
+            .filter(|link| !link.nofollow)
+            .map(|link| link.url.clone())
+            .collect();
+
+        Self {
+            doc,
+            html: RefCell::new(None),
+            url: res.request.url.clone(),
+            links,
+            urls,
+            follow_urls,
+            invalid_links,
+            normalizer,
+        }
     }
 
     pub fn doc(&self) -> &NodeRef {
         &self.doc
     }
 
+    /// The links of the page with their anchor text, in the order of the page. Same as
+    /// `urls`, the links to the page itself and the invalid ones are left out
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
     pub fn urls(&self) -> &Vec<Url> {
         &self.urls
     }
 
-    pub fn matches_selectors(&self, sel: &str) -> Vec<String> {
-        self.doc.select(sel).unwrap()
+    /// Same as `urls`, without the links marked with `rel="nofollow"` (or `"ugc"`, or
+    /// `"sponsored"`)
+    pub fn follow_urls(&self) -> &Vec<Url> {
+        &self.follow_urls
+    }
+
+    /// Number of links left out of `urls` because their href couldn't be parsed as a URL,
+    /// e.g. `http://exa mple.com/`
+    pub fn invalid_links(&self) -> usize {
+        self.invalid_links
+    }
+
+    /// Whether the page asks not to be indexed, with `<meta name="robots" content="noindex">`
+    /// (or `"none"`)
+    pub fn noindex(&self) -> bool {
+        self.robots_directives().iter().any(|d| d == "noindex" || d == "none")
+    }
+
+    /// Whether the page asks for its links not to be followed, with
+    /// `<meta name="robots" content="nofollow">` (or `"none"`)
+    pub fn nofollow(&self) -> bool {
+        self.robots_directives().iter().any(|d| d == "nofollow" || d == "none")
+    }
+
+    /// Returns the normalized URLs of the RSS and Atom feeds the page links to, i.e. of its
+    /// `<link rel="alternate">` elements with a feed type
+    pub fn feed_links(&self) -> Vec<Url> {
+        self.doc.select("link").unwrap()
+            .filter_map(|node| {
+                let attributes = node.attributes.borrow();
+                let alternate = attributes.get("rel")?.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("alternate"));
+                let feed_type = attributes.get("type")?.trim().to_lowercase();
+                if !alternate || (feed_type != "application/rss+xml"
+                    && feed_type != "application/atom+xml")
+                {
+                    return None;
+                }
+                self.normalizer.normalize(&self.url, attributes.get("href")?).ok()
+            })
+            .collect()
+    }
+
+    /// Returns the normalized URL of the `<link rel="canonical">` of the page, if it has one.
+    /// The first one is used if there are several.
+    pub fn canonical(&self) -> Option<Url> {
+        self.link("canonical")
+    }
+
+    /// Returns the normalized URL of the AMP version of the page, declared with
+    /// `<link rel="amphtml">`, if it has one
+    pub fn amphtml(&self) -> Option<Url> {
+        self.link("amphtml")
+    }
+
+    /// Returns the normalized pagination links of the page, read from the `rel` of its
+    /// `<link>` elements and then of its `<a>` elements (`"previous"` standing for
+    /// `"prev"`). The first link of each kind is used, so those of the `<head>` win over
+    /// the anchors of the body.
+    pub fn rel_links(&self) -> RelLinks {
+        let mut links = RelLinks::default();
+        for selector in &["link", "a"] {
+            for node in self.doc.select(selector).unwrap() {
+                let attributes = node.attributes.borrow();
+                let (rel, href) = match (attributes.get("rel"), attributes.get("href")) {
+                    (Some(rel), Some(href)) => (rel.to_lowercase(), href.trim()),
+                    _ => continue,
+                };
+                for rel in rel.split_whitespace() {
+                    let link = match rel {
+                        "next" => &mut links.next,
+                        "prev" | "previous" => &mut links.prev,
+                        "first" => &mut links.first,
+                        "last" => &mut links.last,
+                        _ => continue,
+                    };
+                    if link.is_none() {
+                        *link = self.normalizer.normalize(&self.url, href).ok();
+                    }
+                }
+            }
+        }
+        links
+    }
+
+    /// Returns the normalized href of every link together with its anchor text, see `links`.
+    /// Whitespace in the text is collapsed, e.g. `"  Rust\n language "` becomes
+    /// `"Rust language"`.
+    pub fn links_with_text(&self) -> Vec<(Url, String)> {
+        self.links.iter().map(|link| (link.url.clone(), link.text.clone())).collect()
+    }
+
+    /// Returns the structured data of the `<script type="application/ld+json">` elements of
+    /// the page, e.g. of products or articles, in the order of the page. The elements of a
+    /// script holding an array are values of their own. Scripts that aren't valid JSON are
+    /// logged and skipped.
+    pub fn json_ld(&self) -> Vec<Value> {
+        self.doc.select("script").unwrap()
+            .filter(|node| {
+                node.attributes.borrow().get("type")
+                    .and_then(|t| t.split(';').next())
+                    .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/ld+json"))
+            })
+            .filter_map(|node| {
+                serde_json::from_str::<Value>(&node.text_contents())
+                    .map_err(|e| debug!("Invalid JSON-LD on {}: {}", self.url, e))
+                    .ok()
+            })
+            .flat_map(|value| match value {
+                Value::Array(values) => values,
+                value => vec![value],
+            })
+            .collect()
+    }
+
+    /// Returns the microdata of the page, the `itemscope` elements and their `itemprop`
+    /// properties, nested items and `itemref` included. It is written in the JSON form of
+    /// the HTML standard, `{"items": [{"type": [..], "id": .., "properties": {..}}]}`, where
+    /// each property has the list of its values. URL values (e.g. the `href` of links) are
+    /// made absolute, and the whitespace of text values is collapsed.
+    pub fn microdata(&self) -> Value {
+        Microdata::new(&self.doc, &self.url).items(&self.doc)
+    }
+
+    pub fn matches_selectors(&self, selectors: &Selectors) -> Vec<String> {
+        selectors.filter(self.doc.inclusive_descendants().elements())
             .map(|n| { n.text_contents() })
             .collect()
     }
 
-    pub fn matches_regex(&self, exp: &str) -> Vec<String> {
-        Regex::new(exp).unwrap()
-            .find_iter(self.doc.to_string().as_str())
+    /// The elements that match `selectors`, in the order of the page
+    pub fn select_elements(&self, selectors: &Selectors) -> Vec<NodeRef> {
+        selectors.filter(self.doc.inclusive_descendants().elements())
+            .map(|n| n.as_node().clone())
+            .collect()
+    }
+
+    pub fn matches_regex(&self, regex: &Regex) -> Vec<String> {
+        regex.find_iter(&self.html())
             .map(|m| { m.as_str().to_string() })
             .collect()
     }
+
+    /// The normalized href of the first `<link>` element with `rel`
+    fn link(&self, rel: &str) -> Option<Url> {
+        self.doc.select("link").unwrap()
+            .find_map(|node| {
+                let attributes = node.attributes.borrow();
+                let found = attributes.get("rel")?.split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case(rel));
+                if !found {
+                    return None;
+                }
+                self.normalizer.normalize(&self.url, attributes.get("href")?.trim()).ok()
+            })
+    }
+
+    /// The lowercased directives of the `<meta name="robots">` elements of the page
+    fn robots_directives(&self) -> Vec<String> {
+        self.doc.select("meta").unwrap()
+            .filter_map(|node| {
+                let attributes = node.attributes.borrow();
+                if !attributes.get("name")?.trim().eq_ignore_ascii_case("robots") {
+                    return None;
+                }
+                Some(attributes.get("content")?.to_lowercase())
+            })
+            .flat_map(|content| {
+                content.split(',').map(|d| d.trim().to_string()).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The document serialized to HTML. It is serialized on the first call only, so the
+    /// regex rules of a page share one copy.
+    fn html(&self) -> Ref<'_, str> {
+        if self.html.borrow().is_none() {
+            *self.html.borrow_mut() = Some(self.doc.to_string());
+        }
+        Ref::map(self.html.borrow(), |html| html.as_ref().unwrap().as_str())
+    }
 }
 
 struct Utils;
 
 impl Utils {
-    fn get_urls(doc: &NodeRef) -> Vec<String> {
+    /// The href, collapsed anchor text and nofollow flag of the `<a>` elements of `doc`
+    fn get_links(doc: &NodeRef) -> Vec<(String, String, bool)> {
         doc.select("a").unwrap()
             .filter_map(|node| {
                 node.as_node().as_element()
                     .and_then(|element| {
-                        element.attributes.borrow().get("href")
-                            .map(|url| url.to_string())
+                        let attributes = element.attributes.borrow();
+                        let nofollow = attributes.get("rel")
+                            .map(|rel| {
+                                rel.split_whitespace().any(|r| {
+                                    ["nofollow", "ugc", "sponsored"].iter()
+                                        .any(|n| r.eq_ignore_ascii_case(n))
+                                })
+                            })
+                            .unwrap_or(false);
+                        let text = node.as_node().text_contents();
+                        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                        attributes.get("href")
+                            .map(|url| (url.to_string(), text, nofollow))
                     })
             })
             .collect()
     }
 
-    fn normalize_url(src: &Url, url: &str) -> Result<Url, UrlError> {
-        // Join with Response source url if relative to create an absolute url
-        src.join(url)
+    /// Whether `url` and `other` point to the same document, i.e. differ by the fragment
+    /// at most
+    fn same_document(url: &Url, other: &Url) -> bool {
+        let mut url = url.clone();
+        let mut other = other.clone();
+        url.set_fragment(None);
+        other.set_fragment(None);
+        url == other
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::crawler::Request;
+
     use super::*;
 
     #[test]
-    fn test_normalize_url() {
-        let base = Url::parse("http://en.wikipedia.org/src/").unwrap();
+    fn test_links_with_text() {
+        let url = Url::parse("http://en.wikipedia.org/wiki/Rust").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"
+            <a href="/wiki/Ferris">Ferris</a>
+            <a href="Cargo">  The <b>Cargo</b>
+                package manager </a>
+            <a name="anchor">No href</a>
+            <a href="Rust#History">History</a>
+            <a href="http://exa mple.com/">Invalid</a>
+            <a href="https://www.rust-lang.org/"></a>"#.to_string();
+
+        let links = Page::from_response(&res).links_with_text();
+        let urls: Vec<&str> = links.iter().map(|(url, _)| url.as_str()).collect();
+        let texts: Vec<&str> = links.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(urls, vec![
+            "http://en.wikipedia.org/wiki/Ferris",
+            "http://en.wikipedia.org/wiki/Cargo",
+            "https://www.rust-lang.org/",
+        ]);
+        assert_eq!(texts, vec!["Ferris", "The Cargo package manager", ""]);
+    }
 
-        let p = Utils::normalize_url(&base, "picture.jpg").unwrap();
-        assert_eq!(p.as_str(), "http://en.wikipedia.org/src/picture.jpg");
+    #[test]
+    fn test_same_document_links() {
+        let url = Url::parse("http://en.wikipedia.org/wiki/Rust#History").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r##"
+            <a href="#top">Top</a>
+            <a href="Rust#History">History</a>
+            <a href="">Reload</a>
+            <a href="/wiki/Rust#Syntax">Syntax</a>
+            <a href="/wiki/Rust?action=edit">Edit</a>
+            <a href="/wiki/Ferris#Name">Ferris</a>"##.to_string();
 
-        let p = Utils::normalize_url(&base, "../picture.jpg").unwrap();
-        assert_eq!(p.as_str(), "http://en.wikipedia.org/picture.jpg");
+        let page = Page::from_response(&res);
+        let urls: Vec<&str> = page.urls().iter().map(|u| u.as_str()).collect();
+        assert_eq!(urls, vec![
+            "http://en.wikipedia.org/wiki/Rust?action=edit",
+            "http://en.wikipedia.org/wiki/Ferris#Name",
+        ]);
+        assert_eq!(page.follow_urls(), page.urls());
+    }
+
+    #[test]
+    fn test_feed_links() {
+        let url = Url::parse("http://news.test/blog/").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<html><head>
+            <link rel="alternate" type="application/rss+xml" href="rss.xml">
+            <link rel="alternate" type="application/atom+xml" href="/atom.xml">
+            <link rel="Alternate" type="Application/RSS+XML" href="http://feeds.test/news">
+            <link rel="alternate" hreflang="de" href="/de/blog/">
+            <link rel="stylesheet" type="text/css" href="/style.css">
+            </head><body><a href="/about">About</a></body></html>"#.to_string();
+
+        let page = Page::from_response(&res);
+        let feeds = page.feed_links();
+        let urls: Vec<&str> = feeds.iter().map(|u| u.as_str()).collect();
+        assert_eq!(urls, vec![
+            "http://news.test/blog/rss.xml",
+            "http://news.test/atom.xml",
+            "http://feeds.test/news",
+        ]);
+        assert_eq!(page.urls().len(), 1);
+    }
+
+    #[test]
+    fn test_canonical() {
+        let canonical = |head: &str| {
+            let url = Url::parse("http://shop.test/items/42?ref=home").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.body = format!("<html><head>{}</head><body></body></html>", head);
+            Page::from_response(&res).canonical().map(|url| url.to_string())
+        };
+        assert_eq!(canonical(""), None);
+        assert_eq!(canonical(r#"<link rel="canonical" href="/items/42">"#),
+                   Some("http://shop.test/items/42".to_string()));
+        assert_eq!(canonical(r#"<link rel="Canonical" href=" http://www.shop.test/42 ">"#),
+                   Some("http://www.shop.test/42".to_string()));
+        assert_eq!(canonical(r#"<link rel="stylesheet" href="/style.css">
+                               <link rel="canonical">
+                               <link rel="canonical" href="42">
+                               <link rel="canonical" href="43">"#),
+                   Some("http://shop.test/items/42".to_string()));
+    }
+
+    #[test]
+    fn test_amphtml() {
+        let amphtml = |head: &str| {
+            let url = Url::parse("http://news.test/2019/03/rust").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.body = format!("<html><head>{}</head><body><h1>Rust</h1></body></html>", head);
+            Page::from_response(&res).amphtml().map(|url| url.to_string())
+        };
+        assert_eq!(amphtml(r#"<link rel="canonical" href="/2019/03/rust">"#), None);
+        assert_eq!(amphtml(r#"<link rel="canonical" href="/2019/03/rust">
+                              <link rel="AmpHtml" href="rust/amp">"#),
+                   Some("http://news.test/2019/03/rust/amp".to_string()));
+        assert_eq!(amphtml(r#"<link rel="amphtml" href="http://amp.news.test/2019/03/rust">"#),
+                   Some("http://amp.news.test/2019/03/rust".to_string()));
+    }
+
+    #[test]
+    fn test_rel_links() {
+        let rel_links = |html: &str| {
+            let url = Url::parse("http://shop.test/items?page=2").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.body = html.to_string();
+            let links = Page::from_response(&res).rel_links();
+            let url = |url: Option<Url>| url.map(|url| url.to_string()).unwrap_or_default();
+            (url(links.next), url(links.prev), url(links.first), url(links.last))
+        };
+        let page = |page: &str| format!("http://shop.test/items?page={}", page);
+        assert_eq!(rel_links("<a href='?page=3'>3</a>"), Default::default());
 
-        let p = Utils::normalize_url(&base, "images/picture.jpg").unwrap();
-        assert_eq!(p.as_str(), "http://en.wikipedia.org/src/images/picture.jpg");
+        // Declared in the head
+        assert_eq!(rel_links(r#"<html><head>
+                                  <link rel="prev" href="?page=1">
+                                  <link rel="Next" href="/items?page=3">
+                                  <link rel="first" href="?page=1">
+                                  <link rel="last" href="?page=9">
+                                </head><body></body></html>"#),
+                   (page("3"), page("1"), page("1"), page("9")));
 
-        let p = Utils::normalize_url(&base, "/images/picture.jpg").unwrap();
-        assert_eq!(p.as_str(), "http://en.wikipedia.org/images/picture.jpg");
+        // Anchors of the body
+        assert_eq!(rel_links(r#"<a href="?page=1" rel="previous">Previous</a>
+                                <a href="?page=3" rel="next nofollow">Next</a>
+                                <a href="?page=4" rel="next">Next</a>"#),
+                   (page("3"), page("1"), String::new(), String::new()));
+
+        // The head wins over the anchors
+        assert_eq!(rel_links(r#"<html><head><link rel="next" href="?page=3"></head>
+                                <body><a href="?page=4" rel="next">Next</a>
+                                      <a href="?page=1" rel="prev">Prev</a></body></html>"#),
+                   (page("3"), page("1"), String::new(), String::new()));
+    }
+
+    #[test]
+    fn test_follow_urls() {
+        let url = Url::parse("http://en.wikipedia.org/wiki/Rust").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"
+            <a href="/wiki/Ferris">Ferris</a>
+            <a href="/wiki/Ads" rel="nofollow">Ads</a>
+            <a href="/wiki/Login" rel="noopener NoFollow">Login</a>
+            <a href="/wiki/Cargo" rel="noopener">Cargo</a>
+            <a href="/wiki/Talk" rel="ugc">Talk</a>
+            <a href="/wiki/Sponsor" rel="Sponsored">Sponsor</a>"#.to_string();
+
+        let page = Page::from_response(&res);
+        assert_eq!(page.urls().len(), 6);
+        let urls: Vec<&str> = page.follow_urls().iter().map(|u| u.as_str()).collect();
+        assert_eq!(urls, vec![
+            "http://en.wikipedia.org/wiki/Ferris",
+            "http://en.wikipedia.org/wiki/Cargo",
+        ]);
+        let links: Vec<(&str, &str, bool)> = page.links().iter()
+            .map(|link| (link.url.path(), link.text.as_str(), link.nofollow))
+            .collect();
+        assert_eq!(links, vec![
+            ("/wiki/Ferris", "Ferris", false),
+            ("/wiki/Ads", "Ads", true),
+            ("/wiki/Login", "Login", true),
+            ("/wiki/Cargo", "Cargo", false),
+            ("/wiki/Talk", "Talk", true),
+            ("/wiki/Sponsor", "Sponsor", true),
+        ]);
+    }
+
+    #[test]
+    fn test_meta_robots() {
+        let page = |head: &str| {
+            let url = Url::parse("http://site.test/").unwrap();
+            let mut res = Response::new(Request::new(url, 0, 1));
+            res.body = format!("<html><head>{}</head><body></body></html>", head);
+            let page = Page::from_response(&res);
+            (page.noindex(), page.nofollow())
+        };
+        assert_eq!(page(""), (false, false));
+        assert_eq!(page(r#"<meta name="robots" content="index, follow">"#), (false, false));
+        assert_eq!(page(r#"<meta name="robots" content="noindex">"#), (true, false));
+        assert_eq!(page(r#"<meta name="robots" content="nofollow">"#), (false, true));
+        assert_eq!(page(r#"<meta name="ROBOTS" content="NoIndex,NoFollow">"#), (true, true));
+        assert_eq!(page(r#"<meta name="robots" content="none">"#), (true, true));
+        assert_eq!(page(r#"<meta name="robots" content="noindex">
+                          <meta name="robots" content="nofollow">"#), (true, true));
+        assert_eq!(page(r#"<meta name="description" content="noindex, nofollow">"#),
+                   (false, false));
+    }
+
+    #[test]
+    fn test_json_ld() {
+        let url = Url::parse("http://shop.test/items/42").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<html><head>
+            <script type="application/ld+json">
+            {
+              "@context": "https://schema.org/",
+              "@type": "Product",
+              "name": "Rust mug",
+              "sku": "42",
+              "offers": { "@type": "Offer", "price": "9.99", "priceCurrency": "EUR" }
+            }
+            </script>
+            <script type="application/ld+json">{ "@type": "Product", "name": </script>
+            <script type="text/javascript">var data = { "name": "not structured" };</script>
+            <script type="Application/LD+JSON; charset=utf-8">
+              [{ "@type": "BreadcrumbList" }, { "@type": "Organization", "name": "Shop" }]
+            </script>
+            </head><body><h1>Rust mug</h1></body></html>"#.to_string();
+
+        let json_ld = Page::from_response(&res).json_ld();
+        assert_eq!(json_ld, vec![
+            json!({
+                "@context": "https://schema.org/",
+                "@type": "Product",
+                "name": "Rust mug",
+                "sku": "42",
+                "offers": { "@type": "Offer", "price": "9.99", "priceCurrency": "EUR" },
+            }),
+            json!({ "@type": "BreadcrumbList" }),
+            json!({ "@type": "Organization", "name": "Shop" }),
+        ]);
+
+        res.body = "<h1>No structured data</h1>".to_string();
+        assert!(Page::from_response(&res).json_ld().is_empty());
+    }
+
+    #[test]
+    fn test_microdata() {
+        let url = Url::parse("http://shop.test/items/42").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = r#"<html><body>
+            <div itemscope itemtype="https://schema.org/Product" itemid="/items/42"
+                 itemref="reviews">
+              <h1 itemprop="name">Rust
+                mug</h1>
+              <img itemprop="image" src="/img/mug.png" alt="">
+              <meta itemprop="sku" content="42">
+              <div itemprop="offers" itemscope itemtype="https://schema.org/Offer">
+                <data itemprop="price" value="9.99">9,99 €</data>
+                <link itemprop="availability" href="https://schema.org/InStock">
+                <span itemprop="name">Not a name of the product</span>
+              </div>
+              <p>Made by <span itemprop="brand manufacturer">Ferris</span></p>
+            </div>
+            <section id="reviews">
+              <div itemprop="review" itemscope itemtype="https://schema.org/Review">
+                <time itemprop="datePublished" datetime="2019-03-01">March 1st</time>
+                <span itemprop="author">Jane</span>
+              </div>
+            </section>
+            <p itemscope><a itemprop="url" href="../about">About</a></p>
+            </body></html>"#.to_string();
+
+        assert_eq!(Page::from_response(&res).microdata(), json!({
+            "items": [
+                {
+                    "type": ["https://schema.org/Product"],
+                    "id": "http://shop.test/items/42",
+                    "properties": {
+                        "name": ["Rust mug"],
+                        "image": ["http://shop.test/img/mug.png"],
+                        "sku": ["42"],
+                        "offers": [{
+                            "type": ["https://schema.org/Offer"],
+                            "properties": {
+                                "price": ["9.99"],
+                                "availability": ["https://schema.org/InStock"],
+                                "name": ["Not a name of the product"],
+                            },
+                        }],
+                        "brand": ["Ferris"],
+                        "manufacturer": ["Ferris"],
+                        "review": [{
+                            "type": ["https://schema.org/Review"],
+                            "properties": {
+                                "datePublished": ["2019-03-01"],
+                                "author": ["Jane"],
+                            },
+                        }],
+                    },
+                },
+                { "properties": { "url": ["http://shop.test/about"] } },
+            ]
+        }));
+
+        // Items referencing each other through `itemref` aren't expanded forever
+        res.body = r#"<div itemscope itemref="b"></div>
+            <div id="b" itemprop="x" itemscope itemref="c"></div>
+            <div id="c" itemprop="y" itemscope itemref="b"></div>"#.to_string();
+        assert_eq!(Page::from_response(&res).microdata(), json!({
+            "items": [{ "properties": {
+                "x": [{ "properties": { "y": [{ "properties": { "x": ["ERROR"] } }] } }]
+            } }]
+        }));
+
+        res.body = "<h1>No microdata</h1>".to_string();
+        assert_eq!(Page::from_response(&res).microdata(), json!({ "items": [] }));
+    }
+
+    #[test]
+    fn test_matches_regex() {
+        let url = Url::parse("http://site.test/").unwrap();
+        let mut res = Response::new(Request::new(url, 0, 1));
+        res.body = (0..10_000)
+            .map(|i| format!("<p>item-{} <span>sku{:05}</span></p>", i, i))
+            .collect();
+        let page = Page::from_response(&res);
 
-        let p = Utils::normalize_url(&base, "http://ru.wikipedia.org").unwrap();
-        assert_eq!(p.as_str(), "http://ru.wikipedia.org/");
+        let regex = |exp: &str| Regex::new(exp).unwrap();
+        assert_eq!(page.matches_regex(&regex(r"item-\d+")).len(), 10_000);
+        let html = page.html().as_ptr();
 
-        let p = Utils::normalize_url(&base, "http://ru.wikipedia.org/index.html").unwrap();
-        assert_eq!(p.as_str(), "http://ru.wikipedia.org/index.html");
+        // Further regex rules match against the same serialized document
+        assert_eq!(page.matches_regex(&regex(r"sku0999\d")), (9990..10_000)
+            .map(|i| format!("sku{:05}", i))
+            .collect::<Vec<_>>());
+        assert_eq!(page.matches_regex(&regex(r"item-42\b")), vec!["item-42"]);
+        assert_eq!(page.html().as_ptr(), html);
     }
 }
@@ -0,0 +1,161 @@
+//! Scheme and port policy of the links followed
+//!
+//! With `UrlPolicySettings::upgrade_http`, the http links found are followed over https. The
+//! https `Requests` to a host are its probes: if one can't be fetched before one was, the
+//! host is taken for one without https, the URL is fetched again over http and the links to
+//! the host are no longer upgraded.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Url;
+
+use crate::settings::UrlPolicySettings;
+
+/// Applies `ParserSettings::url_policy` to the links found. Clones share the state of the
+/// https probes.
+#[derive(Clone, Debug, Default)]
+pub struct UrlPolicy {
+    https_only: bool,
+    upgrade_http: bool,
+    allowed_ports: Vec<u16>,
+    /// Whether https works, by host, once it was probed
+    https_hosts: Arc<Mutex<HashMap<String, bool>>>,
+}
+
+impl UrlPolicy {
+    pub fn from_settings(settings: UrlPolicySettings) -> Self {
+        Self {
+            https_only: settings.https_only,
+            upgrade_http: settings.upgrade_http,
+            allowed_ports: settings.allowed_ports,
+            https_hosts: Arc::default(),
+        }
+    }
+
+    /// `url` as it is followed, upgraded to https if need be. `None` if it isn't followed:
+    /// it isn't https with `https_only`, or its port isn't one of the `allowed_ports`.
+    pub fn apply(&self, mut url: Url) -> Option<Url> {
+        if self.upgrade_http && url.scheme() == "http" && !self.https_failed(&url) {
+            url = UrlPolicy::https_url(&url)?;
+        }
+        if self.https_only && url.scheme() != "https" {
+            return None;
+        }
+        let port = url.port_or_known_default();
+        let allowed = port.is_some_and(|port| self.allowed_ports.contains(&port));
+        if !self.allowed_ports.is_empty() && !allowed {
+            return None;
+        }
+        Some(url)
+    }
+
+    /// The https URL a `Request` to the http `url` is deduplicated by, with `upgrade_http`,
+    /// so that the links to both forms of a page are fetched once
+    pub fn dedup_url(&self, url: &Url) -> Option<Url> {
+        if self.upgrade_http && url.scheme() == "http" {
+            UrlPolicy::https_url(url)
+        } else {
+            None
+        }
+    }
+
+    /// Records that `url` was fetched over https
+    pub fn https_fetched(&self, url: &Url) {
+        if self.upgrade_http && url.scheme() == "https" {
+            if let Some(host) = url.host_str() {
+                self.https_hosts.lock().unwrap().entry(host.to_string()).or_insert(true);
+            }
+        }
+    }
+
+    /// Records that `url` couldn't be fetched. Unless https worked for its host before, the
+    /// host is no longer upgraded and the http URL to fetch instead is returned, as the
+    /// links upgraded before the probe failed are.
+    pub fn fetch_failed(&self, url: &Url) -> Option<Url> {
+        if !self.upgrade_http || self.https_only || url.scheme() != "https" {
+            return None;
+        }
+        let host = url.host_str()?.to_string();
+        let mut https_hosts = self.https_hosts.lock().unwrap();
+        if https_hosts.get(&host) == Some(&true) {
+            return None;
+        }
+        https_hosts.insert(host, false);
+        let mut http_url = url.clone();
+        http_url.set_scheme("http").ok()?;
+        Some(http_url)
+    }
+
+    /// Whether the https probe of the host of `url` failed
+    fn https_failed(&self, url: &Url) -> bool {
+        url.host_str()
+            .is_some_and(|host| self.https_hosts.lock().unwrap().get(host) == Some(&false))
+    }
+
+    /// The https form of the http `url`. Port 80 is dropped, other ports are kept.
+    fn https_url(url: &Url) -> Option<Url> {
+        let mut https_url = url.clone();
+        if url.port() == Some(80) {
+            https_url.set_port(None).ok()?;
+        }
+        https_url.set_scheme("https").ok()?;
+        Some(https_url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::Settings;
+
+    use super::*;
+
+    fn apply(policy: &UrlPolicy, url: &str) -> Option<String> {
+        policy.apply(Url::parse(url).unwrap()).map(|url| url.to_string())
+    }
+
+    #[test]
+    fn test_url_policy() {
+        let mut settings = Settings::default().parser.url_policy;
+        let policy = UrlPolicy::from_settings(settings.clone());
+        assert_eq!(apply(&policy, "http://site.test:8080/a"),
+                   Some("http://site.test:8080/a".into()));
+
+        settings.https_only = true;
+        let policy = UrlPolicy::from_settings(settings.clone());
+        assert_eq!(apply(&policy, "https://site.test/a"), Some("https://site.test/a".into()));
+        assert_eq!(apply(&policy, "http://site.test/a"), None);
+        assert_eq!(apply(&policy, "ftp://site.test/a"), None);
+
+        settings.upgrade_http = true;
+        settings.allowed_ports = vec![443, 8443];
+        let policy = UrlPolicy::from_settings(settings);
+        assert_eq!(apply(&policy, "http://site.test/a"), Some("https://site.test/a".into()));
+        assert_eq!(apply(&policy, "http://site.test:80/a"), Some("https://site.test/a".into()));
+        assert_eq!(apply(&policy, "https://site.test:8443/a"),
+                   Some("https://site.test:8443/a".into()));
+        assert_eq!(apply(&policy, "http://site.test:8080/a"), None);
+        assert_eq!(apply(&policy, "https://site.test:9000/a"), None);
+    }
+
+    #[test]
+    fn test_https_probe() {
+        let mut settings = Settings::default().parser.url_policy;
+        settings.upgrade_http = true;
+        let policy = UrlPolicy::from_settings(settings);
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert_eq!(policy.dedup_url(&url("http://a.test/x")), Some(url("https://a.test/x")));
+        assert_eq!(policy.dedup_url(&url("https://a.test/x")), None);
+
+        // a.test has https, b.test fails its probe and is no longer upgraded
+        policy.https_fetched(&url("https://a.test/"));
+        assert_eq!(policy.fetch_failed(&url("https://a.test/x")), None);
+        assert_eq!(policy.clone().fetch_failed(&url("https://b.test:8080/x")),
+                   Some(url("http://b.test:8080/x")));
+        assert_eq!(policy.fetch_failed(&url("https://b.test/y")),
+                   Some(url("http://b.test/y")));
+        assert_eq!(apply(&policy, "http://a.test/z"), Some("https://a.test/z".into()));
+        assert_eq!(apply(&policy, "http://b.test/z"), Some("http://b.test/z".into()));
+        assert_eq!(apply(&policy, "http://c.test/z"), Some("https://c.test/z".into()));
+    }
+}
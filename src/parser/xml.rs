@@ -0,0 +1,99 @@
+//! Reads the few parts of XML documents that sitemaps and feeds need, with regular
+//! expressions rather than a full XML parser. Namespaces are ignored, and an element can't
+//! contain elements of the same name.
+use std::collections::HashMap;
+
+use regex::Regex;
+
+/// The name of the root element of an XML document, skipping the XML declaration,
+/// comments and the doctype
+pub(crate) fn root(content: &str) -> Option<&str> {
+    let mut rest = content;
+    loop {
+        rest = &rest[rest.find('<')? + 1..];
+        if rest.starts_with("!--") {
+            rest = &rest[rest.find("-->")?..];
+        } else if !rest.starts_with('?') && !rest.starts_with('!') {
+            let end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+            return Some(&rest[..end]);
+        }
+    }
+}
+
+/// The contents of the `name` elements of `content`. Elements that aren't closed are
+/// skipped.
+pub(crate) fn elements<'a>(content: &'a str, name: &str) -> Vec<&'a str> {
+    let regex = Regex::new(&format!(r"(?s)<{0}\b[^>]*>(.*?)</{0}>", regex::escape(name)))
+        .unwrap();
+    regex.captures_iter(content)
+        .filter_map(|c| c.get(1))
+        .map(|m| m.as_str())
+        .collect()
+}
+
+/// The text of the first `name` element of `content`, see `text`
+pub(crate) fn element_text(content: &str, name: &str) -> Option<String> {
+    elements(content, name).first().map(|element| text(element))
+}
+
+/// The attributes of the `name` start tags (and empty-element tags) of `content`
+pub(crate) fn tags(content: &str, name: &str) -> Vec<HashMap<String, String>> {
+    let tag = Regex::new(&format!(r"<{}\b([^>]*)>", regex::escape(name))).unwrap();
+    let attribute = Regex::new(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    tag.captures_iter(content)
+        .map(|tag| {
+            attribute.captures_iter(tag.get(1).map_or("", |m| m.as_str()))
+                .filter_map(|a| {
+                    let value = a.get(2).or_else(|| a.get(3))?.as_str();
+                    Some((a[1].to_lowercase(), text(value)))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The text of an element: trimmed, without a CDATA section, with the XML entities
+/// decoded
+pub(crate) fn text(value: &str) -> String {
+    let value = value.trim();
+    if value.starts_with("<![CDATA[") && value.ends_with("]]>") {
+        return value[9..value.len() - 3].trim().to_string();
+    }
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml() {
+        let content = r#"<?xml version="1.0"?>
+            <!DOCTYPE feed>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+              <title type="text"> Tom &amp; Jerry </title>
+              <link rel="alternate" href='/a?x=1&amp;y=2'/>
+              <link href="/b">
+              <subtitle><![CDATA[<b>bold</b>]]></subtitle>
+              <title>unclosed
+            </feed>"#;
+        assert_eq!(root(content), Some("feed"));
+        assert_eq!(root("<!-- <rss> --><urlset>"), Some("urlset"));
+        assert_eq!(root("no xml"), None);
+
+        assert_eq!(elements(content, "title"), vec![" Tom &amp; Jerry "]);
+        assert_eq!(element_text(content, "title"), Some("Tom & Jerry".to_string()));
+        assert_eq!(element_text(content, "subtitle"), Some("<b>bold</b>".to_string()));
+        assert_eq!(element_text(content, "missing"), None);
+
+        let links = tags(content, "link");
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0]["rel"], "alternate");
+        assert_eq!(links[0]["href"], "/a?x=1&y=2");
+        assert_eq!(links[1]["href"], "/b");
+    }
+}
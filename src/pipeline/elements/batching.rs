@@ -0,0 +1,103 @@
+//! Batching Pipeline Element
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use crate::crawler::Item;
+use crate::pipeline::elements::PipelineElement;
+
+type BatchWriter = Box<dyn Fn(Vec<Item>)>;
+
+/// Pipeline Element that accumulates `Item`s and hands them to a writer in batches,
+/// reducing the number of writes to a downstream sink (database, API, etc).
+///
+/// A batch is written once `max_items` have been buffered, or on the first `Pipeline`
+/// flush after `max_delay` has passed since the previous write. Whatever is left in the
+/// buffer is written when the `Pipeline` is stopped.
+///
+/// `Item`s are passed on to the next element unchanged.
+pub struct Batching {
+    max_items: usize,
+    max_delay: Duration,
+    writer: BatchWriter,
+    buffer: RefCell<Vec<Item>>,
+    last_write: RefCell<Instant>,
+}
+
+impl Batching {
+    /// `max_delay` is in milliseconds. A `max_delay` of `0` disables time based writes.
+    pub fn new<F>(max_items: usize, max_delay: u64, writer: F) -> Self
+        where F: Fn(Vec<Item>) + 'static
+    {
+        Self {
+            max_items,
+            max_delay: Duration::from_millis(max_delay),
+            writer: Box::new(writer),
+            buffer: RefCell::new(Vec::new()),
+            last_write: RefCell::new(Instant::now()),
+        }
+    }
+
+    fn write(&self) {
+        let batch: Vec<Item> = self.buffer.borrow_mut().drain(..).collect();
+        *self.last_write.borrow_mut() = Instant::now();
+        if !batch.is_empty() {
+            (self.writer)(batch);
+        }
+    }
+}
+
+impl PipelineElement for Batching {
+    fn process_item(&self, item: Item) -> Option<Item> {
+        self.buffer.borrow_mut().push(item.clone());
+        if self.buffer.borrow().len() >= self.max_items {
+            self.write();
+        }
+        Some(item)
+    }
+
+    fn flush(&self) {
+        if self.max_delay > Duration::from_millis(0)
+            && self.last_write.borrow().elapsed() >= self.max_delay
+        {
+            self.write();
+        }
+    }
+
+    fn close(&self) {
+        self.write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use reqwest::Url;
+
+    use crate::crawler::Request;
+
+    use super::*;
+
+    fn item() -> Item {
+        Item::new(Request::new(Url::parse("http://en.wikipedia.org").unwrap(), 0, 1), json!({}))
+    }
+
+    #[test]
+    fn test_buffered_until_batch_size() {
+        let batches = Rc::new(RefCell::new(Vec::new()));
+        let b = Rc::clone(&batches);
+        let batching = Batching::new(3, 0, move |items| b.borrow_mut().push(items.len()));
+
+        batching.process_item(item());
+        batching.process_item(item());
+        batching.flush();
+        assert!(batches.borrow().is_empty());
+
+        batching.process_item(item());
+        assert_eq!(*batches.borrow(), vec![3]);
+
+        batching.process_item(item());
+        batching.close();
+        assert_eq!(*batches.borrow(), vec![3, 1]);
+    }
+}
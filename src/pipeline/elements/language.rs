@@ -0,0 +1,141 @@
+//! Language Detection Pipeline Element
+use serde_json::Value;
+use whatlang::Lang;
+
+use crate::crawler::Item;
+use crate::pipeline::elements::PipelineElement;
+use crate::settings::LanguageSettings;
+
+/// Pipeline Element that detects the language of the text of `Item`s, adds it to `field`
+/// as an ISO 639-3 code (e.g. `"eng"`, `"deu"`) and, with `keep`, drops the `Item`s in
+/// other languages. The text is that of the `fields` of the `Item`, or of all of its string
+/// fields if none are given.
+pub struct Language {
+    fields: Vec<String>,
+    field: String,
+    keep: Vec<Lang>,
+}
+
+impl Language {
+    /// Returns an error if a code of `keep` isn't an ISO 639-3 code known to the detector
+    pub fn try_from_settings(settings: LanguageSettings) -> Result<Self, String> {
+        let keep = settings.keep.iter()
+            .map(|code| {
+                Lang::from_code(code.to_lowercase())
+                    .ok_or_else(|| format!("unknown language code {:?}", code))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { fields: settings.fields, field: settings.field, keep })
+    }
+
+    /// Panics if a language code is unknown. Settings are checked by `Settings::validate`.
+    pub fn from_settings(settings: LanguageSettings) -> Self {
+        Language::try_from_settings(settings).unwrap()
+    }
+
+    /// The language of `data`, `None` if there is no text or it can't be told
+    fn detect(&self, data: &Value) -> Option<Lang> {
+        let mut text = String::new();
+        if self.fields.is_empty() {
+            if let Some(data) = data.as_object() {
+                data.values().for_each(|value| Utils::push_text(&mut text, value));
+            }
+        } else {
+            for field in &self.fields {
+                if let Some(value) = data.get(field) {
+                    Utils::push_text(&mut text, value);
+                }
+            }
+        }
+        whatlang::detect(&text).map(|info| info.lang())
+    }
+}
+
+impl PipelineElement for Language {
+    fn process_item(&self, mut item: Item) -> Option<Item> {
+        let lang = self.detect(&item.data);
+
+        if !self.keep.is_empty() && !lang.is_some_and(|lang| self.keep.contains(&lang)) {
+            debug!("Dropping an item of {} in language {:?}",
+                   item.request.url, lang.map(|lang| lang.code()));
+            return None;
+        }
+
+        if let Some(data) = item.data.as_object_mut() {
+            let lang = lang.map_or(Value::Null, |lang| json!(lang.code()));
+            data.insert(self.field.clone(), lang);
+        }
+        Some(item)
+    }
+}
+
+struct Utils;
+
+impl Utils {
+    /// Appends the strings of `value`, and of the arrays of strings, to `text`
+    fn push_text(text: &mut String, value: &Value) {
+        match value {
+            Value::String(s) => {
+                text.push_str(s);
+                text.push('\n');
+            }
+            Value::Array(values) => values.iter().for_each(|value| Utils::push_text(text, value)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use crate::crawler::Request;
+
+    use super::*;
+
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog. It is a sentence that \
+                           contains every letter of the English alphabet.";
+    const GERMAN: &str = "Der schnelle braune Fuchs springt über den faulen Hund. Das ist ein \
+                          Satz, der jeden Buchstaben des deutschen Alphabets enthält.";
+
+    fn item(data: Value) -> Item {
+        Item::new(Request::new(Url::parse("http://site.test").unwrap(), 0, 1), data)
+    }
+
+    fn language(fields: Vec<&str>, keep: Vec<&str>) -> Language {
+        Language::from_settings(LanguageSettings {
+            fields: fields.into_iter().map(String::from).collect(),
+            field: "lang".to_string(),
+            keep: keep.into_iter().map(String::from).collect(),
+        })
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = language(vec![], vec![]);
+        let lang = |data| detect.process_item(item(data)).unwrap().data["lang"].clone();
+        assert_eq!(lang(json!({ "text": ENGLISH, "id": 1 })), json!("eng"));
+        assert_eq!(lang(json!({ "text": [GERMAN, GERMAN] })), json!("deu"));
+        assert_eq!(lang(json!({ "id": 1 })), Value::Null);
+
+        // Only the `fields` are read
+        let detect = language(vec!["body"], vec![]);
+        let data = json!({ "title": ENGLISH, "body": GERMAN });
+        assert_eq!(detect.process_item(item(data)).unwrap().data["lang"], json!("deu"));
+    }
+
+    #[test]
+    fn test_keep() {
+        let keep = language(vec![], vec!["DEU"]);
+        assert!(keep.process_item(item(json!({ "text": ENGLISH }))).is_none());
+        assert!(keep.process_item(item(json!({ "id": 1 }))).is_none());
+        let kept = keep.process_item(item(json!({ "text": GERMAN }))).unwrap();
+        assert_eq!(kept.data["lang"], json!("deu"));
+
+        assert!(Language::try_from_settings(LanguageSettings {
+            fields: vec![],
+            field: "lang".to_string(),
+            keep: vec!["en".to_string()],
+        }).is_err());
+    }
+}
@@ -2,12 +2,38 @@
 //!
 //! Define custom processing for `Parser` output.
 use crate::crawler::Item;
+pub use crate::pipeline::elements::batching::Batching;
+pub use crate::pipeline::elements::language::Language;
+pub use crate::pipeline::elements::pagination::Pagination;
+pub use crate::pipeline::elements::schema_validate::{OnInvalid, SchemaValidate};
 pub use crate::pipeline::elements::timestamping::{TimeOffset, Timestamping};
 
+mod batching;
+mod language;
+mod pagination;
+mod schema_validate;
 mod timestamping;
 
 pub trait PipelineElement {
     /// Exposes a way to implement custom logic for processing `Parser` output.
-    /// Accepts an `Item` and returns a new `Item`.
-    fn process_item(&self, item: Item) -> Item;
+    /// Accepts an `Item` and returns a new `Item`, or `None` to drop the `Item`, in which
+    /// case it isn't passed on to the following elements.
+    fn process_item(&self, item: Item) -> Option<Item>;
+
+    /// Name of the element in the event log (see `StatsSettings::event_log_path`). Defaults
+    /// to the name of its type.
+    fn name(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        let path = name.split('<').next().unwrap_or(name);
+        path.rsplit("::").next().unwrap_or(path).to_string()
+    }
+
+    /// Called by the `Pipeline` on a timer (see `PipelineSettings::flush_interval`).
+    /// Elements that buffer output can use it to write out pending data.
+    fn flush(&self) {}
+
+    /// Called once when the `Pipeline` is stopped. Defaults to a final `flush`.
+    fn close(&self) {
+        self.flush();
+    }
 }
@@ -0,0 +1,60 @@
+//! Pagination Pipeline Element
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::crawler::Item;
+use crate::pipeline::elements::PipelineElement;
+use crate::settings::PaginationSettings;
+
+/// Pipeline Element that adds the `rel="next"` and `rel="prev"` links of the page an `Item`
+/// was extracted from (see `Page::rel_links`) to its `next_field` and `prev_field`. A field
+/// is null if the page has no such link.
+pub struct Pagination {
+    next_field: String,
+    prev_field: String,
+}
+
+impl Pagination {
+    pub fn from_settings(settings: PaginationSettings) -> Self {
+        Self { next_field: settings.next_field, prev_field: settings.prev_field }
+    }
+}
+
+impl PipelineElement for Pagination {
+    fn process_item(&self, mut item: Item) -> Option<Item> {
+        let links = &item.rel_links;
+        let url = |url: &Option<Url>| url.as_ref().map_or(Value::Null, |url| json!(url.as_str()));
+        let (next, prev) = (url(&links.next), url(&links.prev));
+        if let Some(data) = item.data.as_object_mut() {
+            data.insert(self.next_field.clone(), next);
+            data.insert(self.prev_field.clone(), prev);
+        }
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        let pagination = Pagination::from_settings(PaginationSettings {
+            next_field: "_page_next".to_string(),
+            prev_field: "_page_prev".to_string(),
+        });
+        let url = |url: &str| Url::parse(url).unwrap();
+        let mut item = Item::new(Request::new(url("http://site.test/?page=2"), 0, 1),
+                                 json!({ "title": "A" }));
+        item.rel_links.next = Some(url("http://site.test/?page=3"));
+
+        let item = pagination.process_item(item).unwrap();
+        assert_eq!(item.data, json!({
+            "title": "A",
+            "_page_next": "http://site.test/?page=3",
+            "_page_prev": null,
+        }));
+    }
+}
@@ -0,0 +1,133 @@
+//! Schema Validation Pipeline Element
+use std::fs;
+use std::path::Path;
+
+use reqwest::Url;
+use serde_json::Value;
+use valico::json_schema::Scope;
+
+use crate::crawler::Item;
+use crate::pipeline::elements::PipelineElement;
+use crate::settings::SchemaValidateSettings;
+
+/// What `SchemaValidate` does with `Item`s that don't match the schema
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OnInvalid {
+    /// Drop the `Item`
+    Drop,
+
+    /// Log the validation errors and pass the `Item` on
+    Keep,
+
+    /// Pass the `Item` on with the validation errors added to the `schema_errors` field
+    Error,
+}
+
+/// Pipeline Element that validates the data of `Item`s against a JSON schema, to catch
+/// malformed extraction early.
+pub struct SchemaValidate {
+    scope: Scope,
+    schema: Url,
+    on_invalid: OnInvalid,
+}
+
+impl SchemaValidate {
+    /// Returns an error if `schema` isn't a valid JSON schema
+    pub fn new(schema: Value, on_invalid: OnInvalid) -> Result<Self, String> {
+        let mut scope = Scope::new();
+        let schema = scope.compile(schema, false).map_err(|e| format!("{:?}", e))?;
+        Ok(Self { scope, schema, on_invalid })
+    }
+
+    /// Reads the JSON schema from a file
+    pub fn from_file<P: AsRef<Path>>(path: P, on_invalid: OnInvalid) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let schema = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        SchemaValidate::new(schema, on_invalid)
+    }
+
+    /// `SchemaValidateSettings::schema` is either the schema as inline JSON or the path
+    /// of a file containing it. Returns an error if the schema can't be loaded.
+    pub fn try_from_settings(settings: SchemaValidateSettings) -> Result<Self, String> {
+        if settings.schema.trim_start().starts_with('{') {
+            let schema = serde_json::from_str(&settings.schema).map_err(|e| e.to_string())?;
+            SchemaValidate::new(schema, settings.on_invalid)
+        } else {
+            SchemaValidate::from_file(&settings.schema, settings.on_invalid)
+        }
+    }
+
+    /// Panics if the schema can't be loaded. Settings are checked by `Settings::validate`.
+    pub fn from_settings(settings: SchemaValidateSettings) -> Self {
+        SchemaValidate::try_from_settings(settings).unwrap()
+    }
+
+    /// Returns the validation errors of `data`, empty if `data` matches the schema
+    fn validate(&self, data: &Value) -> Vec<String> {
+        let schema = self.scope.resolve(&self.schema).unwrap();
+        schema.validate(data).errors.iter()
+            .map(|e| format!("{}: {}", e.get_path(), e.get_title()))
+            .collect()
+    }
+}
+
+impl PipelineElement for SchemaValidate {
+    fn process_item(&self, mut item: Item) -> Option<Item> {
+        let errors = self.validate(&item.data);
+        if errors.is_empty() {
+            return Some(item);
+        }
+
+        match self.on_invalid {
+            OnInvalid::Drop => {
+                warn!("Dropping invalid item from {}: {:?}", item.request.url, errors);
+                None
+            }
+            OnInvalid::Keep => {
+                warn!("Invalid item from {}: {:?}", item.request.url, errors);
+                Some(item)
+            }
+            OnInvalid::Error => {
+                if let Some(data) = item.data.as_object_mut() {
+                    data.insert("schema_errors".to_string(), json!(errors));
+                }
+                Some(item)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::Request;
+
+    use super::*;
+
+    fn item(data: Value) -> Item {
+        Item::new(Request::new(Url::parse("http://en.wikipedia.org").unwrap(), 0, 1), data)
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": { "title": { "type": "string" } },
+            "required": ["title"]
+        })
+    }
+
+    #[test]
+    fn test_schema_validate() {
+        let drop = SchemaValidate::new(schema(), OnInvalid::Drop).unwrap();
+        assert!(drop.process_item(item(json!({ "title": "Rust" }))).is_some());
+        assert!(drop.process_item(item(json!({ "name": "Rust" }))).is_none());
+        assert!(drop.process_item(item(json!({ "title": 42 }))).is_none());
+
+        let keep = SchemaValidate::new(schema(), OnInvalid::Keep).unwrap();
+        let kept = keep.process_item(item(json!({ "title": 42 }))).unwrap();
+        assert_eq!(kept.data, json!({ "title": 42 }));
+
+        let error = SchemaValidate::new(schema(), OnInvalid::Error).unwrap();
+        let flagged = error.process_item(item(json!({ "name": "Rust" }))).unwrap();
+        assert_eq!(flagged.data["schema_errors"].as_array().unwrap().len(), 1);
+    }
+}
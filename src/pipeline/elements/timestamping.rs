@@ -9,7 +9,7 @@ use crate::pipeline::elements::PipelineElement;
 use crate::settings::TimestampingSettings;
 
 /// The time offsets available for Timestamping
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TimeOffset {
     /// The system local time zone
     Local,
@@ -90,7 +90,7 @@ impl Timestamping {
 }
 
 impl PipelineElement for Timestamping {
-    fn process_item(&self, mut item: Item) -> Item {
+    fn process_item(&self, mut item: Item) -> Option<Item> {
         if let Some(data) = item.data.as_object_mut() {
             let v = match self.offset {
                 TimeOffset::Local => Utils::convert::<Local>(Local::now(), &self.format),
@@ -98,7 +98,7 @@ impl PipelineElement for Timestamping {
             };
             data.insert(self.field.to_string(), Value::String(v));
         }
-        item
+        Some(item)
     }
 }
 
@@ -17,31 +17,161 @@
 //!
 //! Eventually ML models would be trained and used in the item pipeline
 //! for aforementioned tasks for classification and analysis.
+use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
-use actix::{Actor, ArbiterService, Context, Handler};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message, Recipient};
+use reqwest::Url;
 
-use crate::crawler::Item;
+use crate::crawler::{CloseReason, CloseSpider, Item, Listener, StopSpider};
+use crate::events::{Event, EventLog};
+use crate::scheduler::Scheduler;
 use crate::spider::Spider;
+use crate::stats::Stats;
 
 pub mod elements;
 
-#[derive(Default)]
+/// The `Pipeline` State
+///
+/// Contains metrics of processed `Items`
+#[derive(Clone, Debug, Default, Message)]
+pub struct State {
+    pub item_total: usize,
+
+    /// Number of `Items` dropped by a pipeline element
+    pub item_dropped: usize,
+}
+
+/// The outcome of a single processed `Item`
+#[derive(Clone, Debug, Message)]
+pub struct Outcome {
+    /// The URL of the `Request` from which the `Item` has been constructed
+    pub url: Url,
+
+    /// The crawl rule of the `Item`, see `Item::rule`
+    pub rule: Option<String>,
+}
+
 pub struct Pipeline {
     spider: Rc<Spider>,
+    scheduler: Addr<Scheduler>,
+    stats: Addr<Stats>,
+    state: State,
+    /// Number of `Items` processed by crawl rule name, for `max_items_per_rule`
+    rule_items: HashMap<String, usize>,
+    state_listeners: Vec<Recipient<State>>,
+    outcome_listeners: Vec<Recipient<Outcome>>,
+    closed: bool,
+    events: Option<Arc<EventLog>>,
 }
 
 impl Pipeline {
-    pub fn new(spider: Rc<Spider>) -> Self {
-        Self { spider }
+    /// `scheduler` is stopped once `max_items` is reached. The `CloseSpider` message is
+    /// passed on to `stats`.
+    pub fn new(spider: Rc<Spider>, scheduler: Addr<Scheduler>, stats: Addr<Stats>,
+               events: Option<Arc<EventLog>>) -> Self {
+        Self {
+            spider,
+            scheduler,
+            stats,
+            state: State::default(),
+            rule_items: HashMap::new(),
+            state_listeners: Vec::new(),
+            outcome_listeners: Vec::new(),
+            closed: false,
+            events,
+        }
     }
 
-    fn process(&self, item: Item) {
+    fn process(&mut self, item: Item) {
         let p = self.spider.pipeline_elements();
 
-        let mut item = item.clone();
+        // The `Item`s of a rule that reached its `max_items_per_rule` are dropped
+        let rule = item.rule.clone();
+        let max = rule.as_ref()
+            .and_then(|rule| self.spider.settings().pipeline.max_items_per_rule.get(rule));
+        if let (Some(rule), Some(&max)) = (&rule, max) {
+            if max > 0 && self.rule_items.get(rule).copied().unwrap_or(0) >= max {
+                debug!("Dropping an item of {}, rule {:?} reached max_items_per_rule",
+                       item.request.url, rule);
+                if let Some(events) = &self.events {
+                    events.emit(&item.request, Event::Piped {
+                        elements: Vec::new(),
+                        dropped_by: Some("max_items_per_rule".to_string()),
+                    });
+                }
+                self.state.item_dropped += 1;
+                self.dispatch_state();
+                return;
+            }
+        }
+
+        let request = item.request.clone();
+        let outcome = Outcome { url: request.url.clone(), rule: rule.clone() };
+
+        // The names of the elements are only needed for the event log
+        let mut elements = Vec::new();
+        let mut item = Some(item);
         for m in p {
-            item = m.process_item(item);
+            item = match item {
+                Some(item) => {
+                    if self.events.is_some() {
+                        elements.push(m.name());
+                    }
+                    m.process_item(item)
+                }
+                None => break,
+            };
+        }
+        if let Some(events) = &self.events {
+            let dropped_by = if item.is_none() { elements.last().cloned() } else { None };
+            events.emit(&request, Event::Piped { elements, dropped_by });
+        }
+
+        if item.is_none() {
+            self.state.item_dropped += 1;
+            self.dispatch_state();
+            return;
+        }
+
+        self.state.item_total += 1;
+        if let Some(rule) = rule {
+            *self.rule_items.entry(rule).or_insert(0) += 1;
+        }
+        self.dispatch_state();
+        self.outcome_listeners.iter().for_each(|r| {
+            let _ = r.do_send(outcome.clone());
+        });
+
+        // Stop the crawl once the item budget is spent
+        let max_items = self.spider.settings().pipeline.max_items;
+        if max_items > 0 && self.state.item_total == max_items {
+            info!("Pipeline reached max_items, stopping spider");
+            self.scheduler.do_send(StopSpider { reason: CloseReason::ItemLimit });
+        }
+    }
+
+    fn dispatch_state(&self) {
+        self.state_listeners.iter().for_each(|r| {
+            let _ = r.do_send(self.state.clone());
+        });
+    }
+
+    fn flush(&self) {
+        for m in self.spider.pipeline_elements() {
+            m.flush();
+        }
+    }
+
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        for m in self.spider.pipeline_elements() {
+            m.close();
         }
     }
 }
@@ -50,19 +180,46 @@ impl Pipeline {
 impl Actor for Pipeline {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
         info!("Pipeline is started");
+        let interval = self.spider.settings().pipeline.flush_interval;
+        ctx.run_interval(Duration::from_millis(interval), |act, _| act.flush());
     }
 
     fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        self.close();
         info!("Pipeline is stopped");
     }
 }
 
-impl actix::Supervised for Pipeline {}
+/// Define handler for `Listener<State>` message
+impl Handler<Listener<State>> for Pipeline {
+    type Result = ();
 
-impl ArbiterService for Pipeline {
-    fn service_started(&mut self, _ctx: &mut Context<Self>) {}
+    fn handle(&mut self, msg: Listener<State>, _ctx: &mut Context<Self>) {
+        self.state_listeners.push(msg.r);
+    }
+}
+
+/// Define handler for `Listener<Outcome>` message
+impl Handler<Listener<Outcome>> for Pipeline {
+    type Result = ();
+
+    fn handle(&mut self, msg: Listener<Outcome>, _ctx: &mut Context<Self>) {
+        self.outcome_listeners.push(msg.r);
+    }
+}
+
+/// Define handler for `CloseSpider` message. All `Item`s received before the message
+/// have been processed at this point, so the elements are closed and the `Pipeline` is stopped.
+impl Handler<CloseSpider> for Pipeline {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSpider, ctx: &mut Context<Self>) {
+        self.close();
+        self.stats.do_send(msg);
+        ctx.stop();
+    }
 }
 
 impl Handler<Item> for Pipeline {
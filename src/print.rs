@@ -2,6 +2,7 @@
 //!
 //! `Print` uses both the Downloader Middleware and Pipeline Element functionality
 //! to add debug verbosity to standard logging module that `Vortex` uses.
+use rand::Rng;
 use reqwest::r#async::{ClientBuilder, RequestBuilder};
 use serde_json::Value;
 
@@ -15,31 +16,45 @@ use crate::settings::PrintSettings;
 /// functionality
 pub struct Print {
     max_len: usize,
+    sample: f64,
 }
 
 impl Print {
     #[allow(dead_code)]
-    pub fn new(max_len: usize) -> Self {
-        Self { max_len }
+    pub fn new(max_len: usize, sample: f64) -> Self {
+        Self { max_len, sample }
     }
 
     pub fn from_settings(settings: PrintSettings) -> Self {
-        Self { max_len: settings.max_len }
+        Self { max_len: settings.max_len, sample: settings.sample }
+    }
+
+    /// Decides whether the current request/response/item is logged, so that only
+    /// the `sample` fraction of them is printed
+    fn sampled(&self) -> bool {
+        self.sample >= 1.0 || rand::thread_rng().gen::<f64>() < self.sample
     }
 }
 
 impl DownloaderMiddleware for Print {
     fn process_client(&self, cln: ClientBuilder, _req: &Request) -> ClientBuilder {
-        info!("{:?}", cln);
+        if self.sampled() {
+            info!("{:?}", cln);
+        }
         cln
     }
 
-    fn process_request(&self, req: RequestBuilder) -> RequestBuilder {
-        info!("{:?}", req);
-        req
+    fn process_request(&self, builder: RequestBuilder, _req: &Request) -> RequestBuilder {
+        if self.sampled() {
+            info!("{:?}", builder);
+        }
+        builder
     }
 
     fn process_response(&self, res: Response) -> Response {
+        if !self.sampled() {
+            return res;
+        }
         let mut res_clone = res.clone();
         if self.max_len > 0 {
             res_clone.body = Utils::crop_len(res_clone.body.as_str(), self.max_len);
@@ -50,7 +65,10 @@ impl DownloaderMiddleware for Print {
 }
 
 impl PipelineElement for Print {
-    fn process_item(&self, item: Item) -> Item {
+    fn process_item(&self, item: Item) -> Option<Item> {
+        if !self.sampled() {
+            return Some(item);
+        }
         let mut item_clone = item.clone();
         if self.max_len > 0 {
             if let Some(data) = item_clone.data.as_object_mut() {
@@ -63,7 +81,7 @@ impl PipelineElement for Print {
             }
         }
         info!("{:?}", item_clone);
-        item
+        Some(item)
     }
 }
 
@@ -78,3 +96,21 @@ impl Utils {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_sampled(print: &Print, n: usize) -> usize {
+        (0..n).filter(|_| print.sampled()).count()
+    }
+
+    #[test]
+    fn test_sampling() {
+        assert_eq!(count_sampled(&Print::new(0, 1.0), 1000), 1000);
+        assert_eq!(count_sampled(&Print::new(0, 0.0), 1000), 0);
+
+        let n = count_sampled(&Print::new(0, 0.1), 10_000);
+        assert!(n > 800 && n < 1200, "sampled {} out of 10000", n);
+    }
+}
@@ -6,50 +6,187 @@
 //! - Breadth First Order (BFO)
 //! - Depth First Order (DFO)
 //! - Downloader feedback
-use std::{cell::RefCell, rc::Rc, time::Duration};
+use std::{collections::{HashMap, VecDeque}, rc::Rc, sync::{Arc, Mutex}, time::Duration};
 
-use actix::{Actor, Arbiter, ArbiterService, Context, Handler, Message, Recipient};
+use actix::{Actor, ActorContext, Addr, Arbiter, AsyncContext, Context, Handler, Message,
+            MessageResult, Recipient, System};
 use chrono::Utc;
 use futures::{Future, stream::Stream};
+use reqwest::Url;
 use tokio_timer::Interval;
 
-use crate::crawler::{Listener, RequestVec};
+use crate::crawler::{AbortSpider, CloseReason, CloseSpider, CrawlStatus, GetStatus, HandleSignals,
+                     Listener, PauseSpider, Request, RequestVec, ResumeSpider, StopSpider};
 use crate::downloader::{self, Downloader};
-use crate::scheduler::queue::{Queue, QueueBuilder};
-use crate::settings::{CrawlStrategy, ParserSettings};
-use crate::spider::Spider;
+use crate::events::{Event, EventLog};
+use crate::parser::{self, Parser, UrlCanonicalizer, UrlPolicy};
+pub(crate) use crate::scheduler::queue::{Queue, QueueBuilder};
+use crate::settings::{CrawlStrategy, ParserSettings, SchedulerSettings, Settings, Update};
+use crate::shutdown::Shutdown;
+use crate::spider::{CrawlRule, Spider, StartUrls};
+
+#[cfg(feature = "redis")]
+pub use crate::scheduler::seen::RedisSeenStore;
+pub use crate::scheduler::seen::{MemorySeenStore, SeenStore};
 
 mod queue;
+mod seen;
 
 ///??   - ala `Downloader` State
-#[derive(Clone, Debug, Message)]
+#[derive(Clone, Debug, Default, Message)]
 pub struct State {
     pub queue_len: usize,
+
+    /// Number of `Requests` sent to the `Downloader`
+    pub dispatched: usize,
+
+    /// Number of `Requests` sent to the `Downloader` that haven't been processed yet
+    pub in_flight: usize,
+
+    /// Number of `Requests` that were not dispatched because `max_requests` was reached
+    pub budget_dropped: usize,
+
+    /// Highest `queue_len` so far
+    pub max_queue_len: usize,
+
+    /// Number of URLs taken from the queue (or marked as visited, see `MarkVisitedAlias`),
+    /// which are never queued again
+    pub visited: usize,
+
+    /// Estimate of the memory (in bytes) taken by the queue: `queue_len` times the average
+    /// length of the queued URLs
+    pub queue_bytes: u64,
+
+    /// Highest `queue_bytes` so far
+    pub max_queue_bytes: u64,
+}
+
+/// Sets the minimum delay (in milliseconds) between `Requests` to a host, e.g. from the
+/// `Crawl-delay` of its robots.txt. It only takes effect if it is larger than
+/// `download_delay`, and it is capped at `max_crawl_delay`.
+#[derive(Clone, Debug, Message)]
+pub struct SetHostDelay {
+    pub host: String,
+    pub delay_ms: u64,
+}
+
+/// Retries a `Request` that received a 429 or 503 `Response` with a `Retry-After`. No
+/// `Requests` are sent to its host for `delay_ms` milliseconds. Also sends the http
+/// `Request` of a failed https probe of `UrlPolicy`, with no delay.
+#[derive(Clone, Debug, Message)]
+pub struct Retry {
+    pub req: Request,
+    pub delay_ms: u64,
 }
 
+/// The `Requests` seeded from the sitemaps of the spider, see
+/// `SpiderBuilder::start_from_sitemaps`. The spider isn't closed for being idle before
+/// they have been received.
+#[derive(Clone, Debug, Message)]
+pub struct Seeded(pub RequestVec);
+
+/// Marks an alias (the second) of a fetched URL (the first) as visited, so that the links to
+/// either URL are deduplicated together. The alias is the canonical URL of the page (see
+/// `ParserSettings::prefer_canonical`) or its AMP version (see `ParserSettings::skip_amp`)
+#[derive(Clone, Debug, Message)]
+pub struct MarkVisitedAlias(pub Url, pub Url);
+
 struct SchedulerInner {
+    /// Live copy of the settings, see `Update`
+    settings: SchedulerSettings,
+    /// Incremented to stop the running queue handler when it is replaced
+    generation: usize,
     queue: Box<dyn Queue>,
-    unprocessed_requests: usize,
+    /// `Requests` taken from the queue that wait for the delay of their host
+    waiting: VecDeque<Request>,
+    host_delays: HashMap<String, u64>,
+    host_timestamps: HashMap<String, i64>,
+    /// Time before which no `Requests` are sent to a host, from the `Retry-After` of its
+    /// `Responses`
+    host_retry_at: HashMap<String, i64>,
+    dispatched: usize,
+    completed: usize,
+    /// Number of downloaded `Responses` that the `Parser` is parsing
+    parsing: usize,
+    budget_dropped: usize,
+    max_queue_len: usize,
+    max_queue_bytes: u64,
+    /// Total length of the URLs passed to the queue, and their number, to estimate the
+    /// memory taken by the queue
+    url_len_total: u64,
+    url_count: u64,
     timestamp: i64,
+    /// Time the crawl started, the concurrency ramps up from it, see `concurrency`
+    started_at: i64,
+    /// Time the last `Request` was sent to the `Downloader`
+    last_dispatch: i64,
+    idle_since: Option<i64>,
+    /// Set while the `Seeded` `Requests` are awaited
+    seeding: bool,
+    /// Start URLs read a chunk at a time while the queue runs low, see
+    /// `SpiderBuilder::start_urls_from_file`
+    start_urls: Option<StartUrls>,
+    budget_reached: bool,
+    paused: bool,
+    closing: Option<CloseReason>,
+    closed: bool,
     state_listeners: Vec<Recipient<State>>,
+    events: Option<Arc<EventLog>>,
+    /// Id of the last `Request` queued
+    last_id: u64,
+    /// Canonicalizes the URLs queued, so that they are deduplicated in their canonical form
+    canonicalizer: UrlCanonicalizer,
+    /// Deduplicates the http and https forms of URLs, with `UrlPolicySettings::upgrade_http`
+    url_policy: UrlPolicy,
 }
 
 impl Default for SchedulerInner {
     fn default() -> Self {
         Self {
+            settings: Settings::default().scheduler,
+            generation: 0,
             queue: QueueBuilder::build(CrawlStrategy::Basic),
-            unprocessed_requests: 0,
+            waiting: VecDeque::new(),
+            host_delays: HashMap::new(),
+            host_timestamps: HashMap::new(),
+            host_retry_at: HashMap::new(),
+            dispatched: 0,
+            completed: 0,
+            parsing: 0,
+            budget_dropped: 0,
+            max_queue_len: 0,
+            max_queue_bytes: 0,
+            url_len_total: 0,
+            url_count: 0,
             timestamp: Utc::now().timestamp_millis(),
+            started_at: Utc::now().timestamp_millis(),
+            last_dispatch: 0,
+            idle_since: None,
+            seeding: false,
+            start_urls: None,
+            budget_reached: false,
+            paused: false,
+            closing: None,
+            closed: false,
             state_listeners: Vec::new(),
+            events: None,
+            last_id: 0,
+            canonicalizer: UrlCanonicalizer::default(),
+            url_policy: UrlPolicy::default(),
         }
     }
 }
 
 impl SchedulerInner {
-    pub fn new(settings: ParserSettings) -> Self {
-        let queue = QueueBuilder::build(settings.crawl_strategy);
+    pub fn new(settings: SchedulerSettings, parser_settings: ParserSettings) -> Self {
+        let queue = QueueBuilder::build(parser_settings.crawl_strategy);
+        let canonicalizer = UrlCanonicalizer::from_settings(parser_settings.canonicalize);
+        let url_policy = UrlPolicy::from_settings(parser_settings.url_policy);
         Self {
+            settings,
             queue,
+            canonicalizer,
+            url_policy,
             ..Default::default()
         }
     }
@@ -58,9 +195,212 @@ impl SchedulerInner {
         self.state_listeners.push(recipient);
     }
 
+    /// Number of `Requests` looked at in the queue per tick when searching for a `Request`
+    /// whose host isn't delayed
+    const LOOKAHEAD: usize = 16;
+
+    /// Number of start URLs read at once, see `feed_start_urls`
+    const START_URLS_CHUNK: usize = 1000;
+
+    /// Number of `Requests` waiting to be dispatched
+    fn pending(&self) -> usize {
+        self.queue.len() + self.waiting.len()
+    }
+
+    fn host_ready(&self, req: &Request, timestamp: i64) -> bool {
+        let host = req.url.host_str().unwrap_or_default();
+        if let Some(retry_at) = self.host_retry_at.get(host) {
+            if timestamp < *retry_at {
+                return false;
+            }
+        }
+        match (self.host_delays.get(host), self.host_timestamps.get(host)) {
+            (Some(delay), Some(last)) => (timestamp - last) > *delay as i64,
+            _ => true,
+        }
+    }
+
+    /// Returns the next `Request` whose host isn't delayed. `Requests` of delayed hosts
+    /// are set aside until their host is ready.
+    fn next_request(&mut self, timestamp: i64) -> Option<Request> {
+        if let Some(i) = self.waiting.iter().position(|r| self.host_ready(r, timestamp)) {
+            return self.waiting.remove(i);
+        }
+        for _ in 0..SchedulerInner::LOOKAHEAD {
+            match self.queue.pop() {
+                Some(req) => {
+                    if self.host_ready(&req, timestamp) {
+                        return Some(req);
+                    }
+                    self.waiting.push_back(req);
+                }
+                None => break,
+            }
+        }
+        None
+    }
+
+    /// Delay (in milliseconds) since the last dispatch before `req` may be dispatched,
+    /// scaled with its depth by `depth_delay_factor`
+    fn request_delay(&self, req: &Request) -> u64 {
+        let scale = 1.0 + f64::from(req.depth) * self.settings.depth_delay_factor;
+        (self.settings.download_delay as f64 * scale.max(0.0)) as u64
+    }
+
+    /// Estimate of the memory (in bytes) taken by the `Requests` waiting to be dispatched
+    fn queue_bytes(&self) -> u64 {
+        if self.url_count == 0 {
+            return 0;
+        }
+        self.pending() as u64 * self.url_len_total / self.url_count
+    }
+
+    /// Number of `Requests` that may be in flight at `timestamp`. During the warm-up it
+    /// grows linearly from `warmup_start_concurrency` to `concurrent_requests`.
+    fn concurrency(&self, timestamp: i64) -> usize {
+        let settings = &self.settings;
+        let warmup = settings.warmup_secs as i64 * 1000;
+        let elapsed = timestamp - self.started_at;
+        if elapsed >= warmup {
+            return settings.concurrent_requests;
+        }
+        let start = settings.warmup_start_concurrency.min(settings.concurrent_requests);
+        let ramp = (settings.concurrent_requests - start) as i64 * elapsed.max(0) / warmup;
+        start + ramp as usize
+    }
+
+    /// Number of `Requests` sent to the `Downloader` that haven't been processed yet, i.e.
+    /// downloaded and parsed
+    fn in_flight(&self) -> usize {
+        self.dispatched - self.completed + self.parsing
+    }
+
+    /// Reads the next start URLs into the queue, once fewer than `START_URLS_CHUNK` `Requests`
+    /// are pending
+    fn feed_start_urls(&mut self, spider: &Spider) {
+        while self.pending() < Self::START_URLS_CHUNK && !self.budget_reached {
+            let start_urls = match &mut self.start_urls {
+                Some(start_urls) => start_urls,
+                None => return,
+            };
+            let mut requests = start_urls.next_requests(Self::START_URLS_CHUNK,
+                                                        spider.start_priority());
+            if requests.is_empty() {
+                info!("{}", start_urls.summary());
+                self.start_urls = None;
+                return;
+            }
+            for req in &mut requests {
+                req.revisit_after = CrawlRule::revisit_interval(spider.crawl_rules(), req);
+            }
+            self.enqueue(RequestVec::new(requests));
+        }
+    }
+
+    /// Adds `Requests` to the queue. Once the request budget is spent, they are only counted.
+    /// URLs longer than `max_url_length` are dropped.
+    fn enqueue(&mut self, requests: RequestVec) {
+        if self.budget_reached {
+            self.budget_dropped += requests.requests.len();
+        } else {
+            let max_url_length = self.settings.max_url_length;
+            for mut req in requests.requests {
+                req.url = self.canonicalizer.canonicalize(req.url);
+                req.dedup_url = self.dedup_url(&req.url);
+                if max_url_length > 0 && req.url.as_str().len() > max_url_length {
+                    warn!("Dropped URL of {} characters (max_url_length is {}): {:.100}...",
+                          req.url.as_str().len(), max_url_length, req.url.as_str());
+                    continue;
+                }
+                self.url_len_total += req.url.as_str().len() as u64;
+                self.url_count += 1;
+                self.last_id += 1;
+                req.id = self.last_id;
+                match &self.events {
+                    Some(events) => {
+                        let queued = req.clone();
+                        if self.queue.push(req) {
+                            events.emit(&queued, Event::Enqueued { depth: queued.depth });
+                        }
+                    }
+                    None => {
+                        self.queue.push(req);
+                    }
+                }
+            }
+        }
+        self.max_queue_len = self.max_queue_len.max(self.pending());
+        self.max_queue_bytes = self.max_queue_bytes.max(self.queue_bytes());
+    }
+
+    /// The URL a `Request` to `url` is deduplicated by, if it isn't `url`, see
+    /// `Request::dedup_url`
+    fn dedup_url(&self, url: &Url) -> Option<Url> {
+        let dedup_url = self.canonicalizer.dedup_url(url);
+        let key = dedup_url.as_ref().unwrap_or(url);
+        self.url_policy.dedup_url(key).or(dedup_url)
+    }
+
+    /// Sets `req` aside to be dispatched again once `delay_ms` have passed since
+    /// `timestamp`, holding back the other `Requests` to its host until then. It isn't
+    /// checked against the visited URLs, as it is one of them.
+    fn retry(&mut self, req: Request, delay_ms: u64, timestamp: i64) {
+        if self.budget_reached {
+            self.budget_dropped += 1;
+            return;
+        }
+        let host = req.url.host_str().unwrap_or_default().to_string();
+        let retry_at = self.host_retry_at.entry(host).or_insert(0);
+        *retry_at = (*retry_at).max(timestamp + delay_ms as i64);
+        self.waiting.push_back(req);
+        self.max_queue_len = self.max_queue_len.max(self.pending());
+    }
+
+    /// Sets the delay between `Requests` to `host`, between `download_delay` and
+    /// `max_crawl_delay`. Changes of the delay of a host are logged.
+    fn set_host_delay(&mut self, host: String, delay_ms: u64) {
+        let max = self.settings.max_crawl_delay;
+        let delay_ms = if max > 0 && delay_ms > max {
+            warn!("Capping the delay of {} at {}ms (max_crawl_delay), it asks for {}ms",
+                  host, max, delay_ms);
+            max
+        } else {
+            delay_ms
+        };
+
+        let delay = if delay_ms > self.settings.download_delay { Some(delay_ms) } else { None };
+        if self.host_delays.get(&host).copied() == delay {
+            return;
+        }
+        match delay {
+            Some(delay) => {
+                info!("Delaying requests to {} by {}ms", host, delay);
+                self.host_delays.insert(host, delay);
+            }
+            None => {
+                info!("Requests to {} are no longer delayed past download_delay", host);
+                self.host_delays.remove(&host);
+            }
+        }
+    }
+
+    /// Stops dispatching `Requests`. The first reason given is kept.
+    fn close(&mut self, reason: CloseReason) {
+        if self.closing.is_none() {
+            self.closing = Some(reason);
+        }
+    }
+
     fn dispatch_state(&self) {
         let state = State {
-            queue_len: self.queue.len(),
+            queue_len: self.pending(),
+            dispatched: self.dispatched,
+            in_flight: self.in_flight(),
+            budget_dropped: self.budget_dropped,
+            max_queue_len: self.max_queue_len,
+            visited: self.queue.visited_len(),
+            queue_bytes: self.queue_bytes(),
+            max_queue_bytes: self.max_queue_bytes,
         };
         self.state_listeners.iter().for_each(|r| {
             let _ = r.do_send(state.clone());
@@ -68,34 +408,121 @@ impl SchedulerInner {
     }
 }
 
-#[derive(Default)]
 pub struct Scheduler {
     spider: Rc<Spider>,
-    inner: Rc<RefCell<SchedulerInner>>,
+    inner: Arc<Mutex<SchedulerInner>>,
+    downloader: Addr<Downloader>,
+    parser: Addr<Parser>,
 }
 
 impl Scheduler {
-    pub fn new(spider: Rc<Spider>) -> Self {
-        let inner = Rc::new(RefCell::new(
-            SchedulerInner::new(spider.settings().parser.clone())));
-        Self { spider, inner }
+    /// `Requests` are dispatched to `downloader`. The `CloseSpider` message is sent to
+    /// `parser`.
+    pub fn new(spider: Rc<Spider>, downloader: Addr<Downloader>, parser: Addr<Parser>,
+               events: Option<Arc<EventLog>>) -> Self {
+        let settings = spider.settings();
+        let mut inner = SchedulerInner::new(settings.scheduler.clone(), settings.parser.clone());
+        inner.events = events;
+        inner.seeding = !spider.sitemap_hosts().is_empty();
+        if let Some(queue) = spider.take_queue() {
+            inner.queue = queue;
+        }
+        inner.start_urls = spider.take_lazy_start_urls();
+        Self { spider, inner: Arc::new(Mutex::new(inner)), downloader, parser }
     }
 
-    fn run_queue_handler(&self) {
-        let settings = self.spider.settings().scheduler.clone();
-        let inner_clone = Rc::clone(&self.inner);
+    /// Spawns the loop that dispatches `Requests` from the queue, ticking at the current
+    /// `download_delay`. A loop started earlier stops once the generation is incremented,
+    /// and the loop stops once the spider is closed.
+    fn run_queue_handler(&self, ctx: &mut Context<Self>) {
+        let (download_delay, generation) = {
+            let inner = self.inner.lock().unwrap();
+            (inner.settings.download_delay, inner.generation)
+        };
+        let inner_clone1 = Arc::clone(&self.inner);
+        let inner_clone2 = Arc::clone(&self.inner);
+        let spider = Rc::clone(&self.spider);
+        let downloader = self.downloader.clone();
+        let scheduler = ctx.address();
         Arbiter::spawn(
-            Interval::new_interval(Duration::from_millis(settings.download_delay))
+            Interval::new_interval(Duration::from_millis(download_delay))
+                .take_while(move |_| {
+                    let inner = inner_clone1.lock().unwrap();
+                    Ok(inner.generation == generation && !inner.closed)
+                })
                 .for_each(move |_| {
                     let timestamp = Utc::now().timestamp_millis();
-                    if inner_clone.borrow().unprocessed_requests < settings.concurrent_requests
-                        && (timestamp - inner_clone.borrow().timestamp) > settings.download_delay as i64
+                    let mut inner = inner_clone2.lock().unwrap();
+                    let settings = inner.settings.clone();
+                    inner.queue.refresh();
+
+                    if inner.closing.is_none() {
+                        inner.feed_start_urls(&spider);
+                    }
+
+                    if inner.closing.is_none()
+                        && !inner.paused
+                        && inner.in_flight() < inner.concurrency(timestamp)
+                        && (timestamp - inner.timestamp) > settings.download_delay as i64
                     {
-                        if let Some(req) = inner_clone.borrow_mut().queue.pop() {
-                            send!(Downloader, req);
+                        if let Some(req) = inner.next_request(timestamp) {
+                            if timestamp - inner.last_dispatch < inner.request_delay(&req) as i64 {
+                                // Not yet due at its depth, try it again on the next tick
+                                inner.waiting.push_front(req);
+                            } else {
+                                let host = req.url.host_str().unwrap_or_default().to_string();
+                                inner.host_timestamps.insert(host, timestamp);
+                                if let Some(events) = &inner.events {
+                                    events.emit(&req, Event::Dispatched);
+                                }
+                                downloader.do_send(req);
+                                inner.dispatched += 1;
+                                inner.last_dispatch = timestamp;
+                            }
+                        }
+                        inner.timestamp = timestamp;
+
+                        // Stop dispatching once the request budget is spent
+                        if settings.max_requests > 0 && inner.dispatched >= settings.max_requests {
+                            info!("Scheduler reached max_requests, closing spider");
+                            inner.budget_reached = true;
+                            inner.budget_dropped = inner.pending();
+                            inner.close(CloseReason::RequestLimit);
+                        }
+                        inner.dispatch_state();
+                    }
+
+                    // The `on_idle` hook may refill the queue once it runs empty
+                    if inner.pending() == 0 && inner.in_flight() == 0 && !inner.seeding
+                        && inner.idle_since.is_none() && inner.closing.is_none()
+                    {
+                        if let Some(on_idle) = &spider.hooks().on_idle {
+                            if let Some(requests) = on_idle() {
+                                info!("Scheduler is idle, on_idle added {} requests",
+                                      requests.requests.len());
+                                inner.enqueue(requests);
+                            }
+                        }
+                    }
+
+                    // Close the spider once the queue has been empty, with no requests
+                    // in flight, for longer than `idle_timeout`
+                    if inner.pending() == 0 && inner.in_flight() == 0 && !inner.seeding {
+                        let idle_since = *inner.idle_since.get_or_insert(timestamp);
+                        if (timestamp - idle_since) > settings.idle_timeout as i64 {
+                            info!("Scheduler is idle, closing spider");
+                            inner.close(CloseReason::Finished);
+                        }
+                    } else {
+                        inner.idle_since = None;
+                    }
+
+                    // Once closing, wait for the in flight requests to be processed
+                    if let Some(reason) = inner.closing {
+                        if inner.in_flight() == 0 {
+                            inner.closed = true;
+                            scheduler.do_send(CloseSpider { reason, clean: true });
                         }
-                        inner_clone.borrow_mut().timestamp = timestamp;
-                        inner_clone.borrow().dispatch_state();
                     }
                     Ok(())
                 })
@@ -107,9 +534,19 @@ impl Scheduler {
 impl Actor for Scheduler {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
         info!("Scheduler is started");
-        self.run_queue_handler();
+        self.inner.lock().unwrap().started_at = Utc::now().timestamp_millis();
+        self.run_queue_handler(ctx);
+
+        // Stop the crawl the same way as `StopSpider` once `max_duration_secs` has passed
+        let max_duration = self.spider.settings().spider.max_duration_secs;
+        if max_duration > 0 {
+            ctx.run_later(Duration::from_secs(max_duration), |_act, ctx| {
+                info!("Scheduler reached max_duration_secs, closing spider");
+                ctx.notify(StopSpider { reason: CloseReason::TimeLimit });
+            });
+        }
     }
 
     fn stopped(&mut self, _ctx: &mut Context<Self>) {
@@ -117,18 +554,161 @@ impl Actor for Scheduler {
     }
 }
 
-impl actix::Supervised for Scheduler {}
-
-impl ArbiterService for Scheduler {
-    fn service_started(&mut self, _ctx: &mut Context<Self>) {}
-}
-
 /// Define handler for `Listener<State>` message
 impl Handler<Listener<State>> for Scheduler {
     type Result = ();
 
     fn handle(&mut self, msg: Listener<State>, _ctx: &mut Context<Self>) {
-        self.inner.borrow_mut().add_state_listener(msg.r);
+        self.inner.lock().unwrap().add_state_listener(msg.r);
+    }
+}
+
+/// Define handler for `StopSpider` message. If the `Requests` in flight haven't been
+/// processed within `shutdown_timeout`, the spider is closed regardless.
+impl Handler<StopSpider> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: StopSpider, ctx: &mut Context<Self>) {
+        let timeout = {
+            let mut inner = self.inner.lock().unwrap();
+            info!("Scheduler stopped ({:?}), {} requests in flight",
+                  msg.reason, inner.in_flight());
+            inner.close(msg.reason);
+            inner.settings.shutdown_timeout
+        };
+
+        ctx.run_later(Duration::from_millis(timeout), |act, ctx| {
+            let mut inner = act.inner.lock().unwrap();
+            if let (false, Some(reason)) = (inner.closed, inner.closing) {
+                warn!("Closing spider with {} requests in flight", inner.in_flight());
+                inner.closed = true;
+                ctx.notify(CloseSpider { reason, clean: false });
+            }
+        });
+    }
+}
+
+/// Define handler for `CloseSpider` message, sent by the `Scheduler` to itself once it is
+/// closed. It is passed on to the `Parser`, and the `Downloader` and the `Scheduler` are
+/// stopped, so that nothing of the crawl is left running in the system.
+impl Handler<CloseSpider> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSpider, ctx: &mut Context<Self>) {
+        self.parser.do_send(msg.clone());
+        self.downloader.do_send(msg);
+        ctx.stop();
+    }
+}
+
+/// Define handler for `PauseSpider` message
+impl Handler<PauseSpider> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, _msg: PauseSpider, _ctx: &mut Context<Self>) {
+        info!("Scheduler paused");
+        self.inner.lock().unwrap().paused = true;
+    }
+}
+
+/// Define handler for `ResumeSpider` message
+impl Handler<ResumeSpider> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ResumeSpider, _ctx: &mut Context<Self>) {
+        info!("Scheduler resumed");
+        self.inner.lock().unwrap().paused = false;
+    }
+}
+
+/// Define handler for `HandleSignals` message
+impl Handler<HandleSignals> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, _msg: HandleSignals, ctx: &mut Context<Self>) {
+        Shutdown::new(vec![ctx.address()]).start();
+    }
+}
+
+/// Define handler for `AbortSpider` message
+impl Handler<AbortSpider> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, _msg: AbortSpider, _ctx: &mut Context<Self>) {
+        warn!("Aborting crawl with {} requests in flight", self.inner.lock().unwrap().in_flight());
+        System::current().stop();
+    }
+}
+
+/// Define handler for `GetStatus` message. The number of `Items` is left to `Stats`.
+impl Handler<GetStatus> for Scheduler {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _msg: GetStatus, _ctx: &mut Context<Self>) -> Self::Result {
+        let inner = self.inner.lock().unwrap();
+        MessageResult(CrawlStatus {
+            queue_len: inner.pending(),
+            in_flight: inner.in_flight(),
+            items: 0,
+            running: !inner.closed,
+        })
+    }
+}
+
+/// Define handler for `SetHostDelay` message
+impl Handler<SetHostDelay> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetHostDelay, _ctx: &mut Context<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.set_host_delay(msg.host, msg.delay_ms);
+    }
+}
+
+/// Define handler for `Retry` message
+impl Handler<Retry> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Retry, _ctx: &mut Context<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.retry(msg.req, msg.delay_ms, Utc::now().timestamp_millis());
+        inner.dispatch_state();
+    }
+}
+
+/// Define handler for `Update` message. Applies the hot-reloadable `download_delay`
+/// and `concurrent_requests` settings.
+impl Handler<Update> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Update, ctx: &mut Context<Self>) {
+        let settings = match msg.settings.scheduler {
+            Some(settings) => settings,
+            None => return,
+        };
+
+        let mut restart = false;
+        {
+            let mut inner = self.inner.lock().unwrap();
+            match settings.concurrent_requests {
+                Some(0) => warn!("Ignoring concurrent_requests update, must be greater than 0"),
+                Some(v) => inner.settings.concurrent_requests = v,
+                None => {}
+            }
+            match settings.download_delay {
+                Some(0) => warn!("Ignoring download_delay update, must be greater than 0"),
+                Some(v) if v != inner.settings.download_delay => {
+                    info!("Scheduler download_delay changed to {}", v);
+                    inner.settings.download_delay = v;
+                    inner.generation += 1;
+                    restart = true;
+                }
+                _ => {}
+            }
+        }
+        if restart {
+            self.run_queue_handler(ctx);
+        }
     }
 }
 
@@ -138,10 +718,37 @@ impl Handler<RequestVec> for Scheduler {
 
     fn handle(&mut self, msg: RequestVec, _ctx: &mut Context<Self>) {
         trace!("RequestVec (len): {}", msg.requests.len());
-        for req in msg.requests {
-            self.inner.borrow_mut().queue.push(req);
+        let mut inner = self.inner.lock().unwrap();
+        inner.enqueue(msg);
+        inner.dispatch_state();
+    }
+}
+
+/// Define handler for `Seeded` message
+impl Handler<Seeded> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Seeded, _ctx: &mut Context<Self>) {
+        info!("Seeded {} requests from sitemaps", msg.0.requests.len());
+        let mut inner = self.inner.lock().unwrap();
+        inner.seeding = false;
+        inner.enqueue(msg.0);
+        inner.dispatch_state();
+    }
+}
+
+/// Define handler for `MarkVisitedAlias` message
+impl Handler<MarkVisitedAlias> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: MarkVisitedAlias, _ctx: &mut Context<Self>) {
+        debug!("Marking {} visited, an alias of {}", msg.1, msg.0);
+        let mut inner = self.inner.lock().unwrap();
+        for url in [msg.0, msg.1] {
+            let url = inner.dedup_url(&url).unwrap_or(url);
+            inner.queue.mark_visited(url);
         }
-        self.inner.borrow().dispatch_state();
+        inner.dispatch_state();
     }
 }
 
@@ -150,7 +757,234 @@ impl Handler<downloader::State> for Scheduler {
     type Result = ();
 
     fn handle(&mut self, msg: downloader::State, _ctx: &mut Context<Self>) {
-        self.inner.borrow_mut().unprocessed_requests =
-            msg.request_total - msg.request_success - msg.request_error;
+        let mut inner = self.inner.lock().unwrap();
+        inner.completed = msg.request_success + msg.request_error + msg.request_retried
+            + msg.request_refused;
+        inner.dispatch_state();
+    }
+}
+
+/// Define handler for `parser::State` message
+impl Handler<parser::State> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, msg: parser::State, _ctx: &mut Context<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.parsing = msg.parsing;
+        inner.dispatch_state();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::parser::Utils;
+    use crate::spider::SpiderBuilder;
+
+    use super::*;
+
+    fn request(depth: u32) -> Request {
+        Request::new(Url::parse("http://site.test/").unwrap(), depth, 1)
+    }
+
+    #[test]
+    fn test_request_delay() {
+        let mut settings = Settings::default();
+        settings.scheduler.download_delay = 100;
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+
+        // A factor of 0 keeps the delay at every depth
+        assert_eq!(inner.request_delay(&request(0)), 100);
+        assert_eq!(inner.request_delay(&request(5)), 100);
+
+        inner.settings.depth_delay_factor = 0.5;
+        assert_eq!(inner.request_delay(&request(0)), 100);
+        assert_eq!(inner.request_delay(&request(1)), 150);
+        assert_eq!(inner.request_delay(&request(4)), 300);
+
+        // Deep pages are faster with a negative factor, down to no delay
+        inner.settings.depth_delay_factor = -0.25;
+        assert_eq!(inner.request_delay(&request(2)), 50);
+        assert_eq!(inner.request_delay(&request(8)), 0);
+    }
+
+    #[test]
+    fn test_warmup() {
+        let mut settings = Settings::default();
+        settings.scheduler.concurrent_requests = 9;
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+        inner.started_at = 0;
+        assert_eq!(inner.concurrency(0), 9);
+
+        // From 1 to 9 over 4 seconds
+        inner.settings.warmup_secs = 4;
+        let ramp: Vec<usize> = (0..=5).map(|secs| inner.concurrency(secs * 1000)).collect();
+        assert_eq!(ramp, vec![1, 3, 5, 7, 9, 9]);
+        assert_eq!(inner.concurrency(1499), 3);
+
+        // A start above `concurrent_requests` doesn't go over it
+        inner.settings.warmup_start_concurrency = 12;
+        assert_eq!(inner.concurrency(0), 9);
+
+        let mut settings = Settings::default();
+        settings.scheduler.warmup_secs = 10;
+        settings.scheduler.warmup_start_concurrency = 0;
+        let errors = settings.validate().unwrap_err();
+        assert_eq!(errors[0].path, "scheduler.warmup_start_concurrency");
+    }
+
+    #[test]
+    fn test_queue_watermarks() {
+        let settings = Settings::default();
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+
+        // URLs of 20 and 40 characters, 30 on average
+        let urls = vec!["http://site.test/abc", "http://site.test/abcdefghijklmnopqrstuvw"];
+        inner.enqueue(RequestVec::from_strs(urls, 0, 1));
+        assert_eq!((inner.pending(), inner.queue_bytes()), (2, 60));
+
+        inner.next_request(0);
+        inner.next_request(0);
+        inner.enqueue(RequestVec::from_strs(vec!["http://site.test/abc"], 1, 1));
+        assert_eq!((inner.pending(), inner.queue_bytes()), (0, 0));
+        inner.enqueue(RequestVec::from_strs(vec!["http://site.test/xyz"], 1, 1));
+        assert_eq!((inner.pending(), inner.queue_bytes()), (1, 25));
+
+        // The high-water marks are kept as the queue shrinks
+        assert_eq!((inner.max_queue_len, inner.max_queue_bytes), (2, 60));
+        assert_eq!(inner.queue.visited_len(), 2);
+    }
+
+    #[test]
+    fn test_max_url_length() {
+        let mut settings = Settings::default();
+        settings.scheduler.max_url_length = 30;
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+
+        let long = format!("http://site.test/?q={}", "a".repeat(1000));
+        let urls = vec!["http://site.test/a", long.as_str(), "http://site.test/?q=1234567890"];
+        inner.enqueue(RequestVec::from_strs(urls, 0, 1));
+        assert_eq!(inner.pending(), 2);
+        assert_eq!(inner.next_request(0).unwrap().url.as_str(), "http://site.test/a");
+        assert_eq!(inner.next_request(0).unwrap().url.as_str(), "http://site.test/?q=1234567890");
+        assert!(inner.next_request(0).is_none());
+    }
+
+    #[test]
+    fn test_start_priority() {
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/a", "http://site.test/b"])
+            .settings(Settings::default())
+            .build();
+        let settings = spider.settings().clone();
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser.clone());
+
+        // The links found on the first start URL are popped after the second one under BFO
+        inner.enqueue(spider.start_requests().clone());
+        inner.next_request(0).unwrap();
+        let priority = Utils::calc_priority(&settings.parser, 1);
+        let urls = vec!["http://site.test/a/1", "http://site.test/a/2"];
+        inner.enqueue(RequestVec::from_strs(urls, 1, priority));
+        assert_eq!(inner.next_request(0).unwrap().depth, 0);
+        assert_eq!(inner.next_request(0).unwrap().depth, 1);
+        assert_eq!(inner.next_request(0).unwrap().depth, 1);
+    }
+
+    #[test]
+    fn test_mark_visited_alias() {
+        let settings = Settings::default();
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+
+        let urls = vec!["http://site.test/a?utm=1", "http://site.test/b", "http://site.test/a"];
+        inner.enqueue(RequestVec::from_strs(urls, 0, 1));
+        let fetched = inner.next_request(0).unwrap().url;
+        inner.queue.mark_visited(fetched);
+        inner.queue.mark_visited(Url::parse("http://site.test/a").unwrap());
+
+        // The canonical URL is skipped although it was queued, and isn't queued again
+        inner.enqueue(RequestVec::from_strs(vec!["http://site.test/a?utm=1"], 1, 1));
+        inner.enqueue(RequestVec::from_strs(vec!["http://site.test/a"], 1, 1));
+        assert_eq!(inner.next_request(0).unwrap().url.as_str(), "http://site.test/b");
+        assert!(inner.next_request(0).is_none());
+        assert_eq!(inner.queue.visited_len(), 3);
+    }
+
+    #[test]
+    fn test_set_host_delay() {
+        let mut settings = Settings::default();
+        settings.scheduler.download_delay = 100;
+        settings.scheduler.max_crawl_delay = 5000;
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+        let delay = |inner: &SchedulerInner| inner.host_delays.get("site.test").copied();
+
+        // Delays up to `download_delay` have no effect
+        inner.set_host_delay("site.test".to_string(), 100);
+        assert_eq!(delay(&inner), None);
+        inner.set_host_delay("site.test".to_string(), 2000);
+        assert_eq!(delay(&inner), Some(2000));
+        inner.set_host_delay("site.test".to_string(), 3_600_000);
+        assert_eq!(delay(&inner), Some(5000));
+        inner.set_host_delay("site.test".to_string(), 50);
+        assert_eq!(delay(&inner), None);
+
+        inner.settings.max_crawl_delay = 0;
+        inner.set_host_delay("site.test".to_string(), 3_600_000);
+        assert_eq!(delay(&inner), Some(3_600_000));
+    }
+
+    #[test]
+    fn test_retry() {
+        let settings = Settings::default();
+        let mut inner = SchedulerInner::new(settings.scheduler, settings.parser);
+        let urls = vec!["http://site.test/a", "http://other.test/"];
+        inner.enqueue(RequestVec::from_strs(urls, 0, 1));
+        let retried = inner.next_request(0).unwrap();
+        assert_eq!(retried.url.as_str(), "http://site.test/a");
+
+        // A retried URL is dispatched again, although it was visited
+        inner.enqueue(RequestVec::from_strs(vec!["http://site.test/b"], 0, 1));
+        inner.retry(retried, 5000, 1000);
+        assert_eq!(inner.pending(), 3);
+
+        // Other hosts aren't held back
+        assert_eq!(inner.next_request(1000).unwrap().url.as_str(), "http://other.test/");
+        assert!(inner.next_request(5999).is_none());
+        assert_eq!(inner.next_request(6000).unwrap().url.as_str(), "http://site.test/a");
+        assert_eq!(inner.next_request(6000).unwrap().url.as_str(), "http://site.test/b");
+    }
+
+    #[test]
+    fn test_concurrent_state_updates() {
+        let settings = Settings::default();
+        let inner = Arc::new(Mutex::new(SchedulerInner::new(settings.scheduler, settings.parser)));
+
+        // 4 threads enqueue 100 URLs each, while another one dispatches them
+        let producers: Vec<_> = (0..4)
+            .map(|t| {
+                let inner = Arc::clone(&inner);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        let url = format!("http://site.test/{}/{}", t, i);
+                        inner.lock().unwrap().enqueue(RequestVec::from_strs(vec![&url], 1, 1));
+                    }
+                })
+            })
+            .collect();
+        let i = Arc::clone(&inner);
+        let consumer = thread::spawn(move || {
+            for _ in 0..1000 {
+                let mut inner = i.lock().unwrap();
+                if inner.next_request(0).is_some() {
+                    inner.dispatched += 1;
+                }
+            }
+        });
+        producers.into_iter().for_each(|t| t.join().unwrap());
+        consumer.join().unwrap();
+
+        let inner = inner.lock().unwrap();
+        assert_eq!(inner.pending() + inner.dispatched, 400);
+        assert_eq!(inner.queue.visited_len(), inner.dispatched);
     }
 }
@@ -4,7 +4,11 @@ use std::collections::{BinaryHeap, VecDeque};
 use reqwest::Url;
 
 use crate::crawler::Request;
-use crate::settings::CrawlStrategy;
+use crate::scheduler::seen::{MemorySeenStore, SeenStore};
+use crate::settings::{CrawlStrategy, SchedulerSettings};
+
+#[cfg(feature = "redis")]
+pub use self::redis_queue::RedisQueue;
 
 /// The `Queue` trait defines 3 basic functions that all queues should implement.
 /// Different `std:collections` types are used based on the most efficient
@@ -14,10 +18,25 @@ use crate::settings::CrawlStrategy;
 /// - push (adding a `Request` to the queue.
 /// - pop (retrieving a `Request` from the queue.
 /// - len (determining how many `Requests` are in the queue.
-pub trait Queue {
-    fn push(&mut self, item: Request);
+///
+/// `visited_len` reports the number of URLs that were already taken from the queue, or marked
+/// as visited with `mark_visited`. A visited URL is taken again once the `revisit_after` of
+/// its `Request` has passed.
+///
+/// The queue is part of the state the `Scheduler` shares across threads, so it must be `Send`.
+pub trait Queue: Send {
+    /// Adds `item`, unless its URL was visited and isn't due for a revisit. Returns whether
+    /// it was added.
+    fn push(&mut self, item: Request) -> bool;
     fn pop(&mut self) -> Option<Request>;
     fn len(&self) -> usize;
+    fn visited_len(&self) -> usize;
+    /// Marks `url` as visited now, so that it is skipped if queued, and isn't queued again
+    /// unless it is due for a revisit
+    fn mark_visited(&mut self, url: Url);
+    /// Updates `len` and `visited_len` from a queue kept outside the process, which other
+    /// processes change too. Called by the `Scheduler` once per tick.
+    fn refresh(&mut self) {}
 }
 
 /// The `QueueBuilder` creates a `Box` pointer that contains the appropriate queue that best fits
@@ -26,40 +45,106 @@ pub struct QueueBuilder;
 
 impl QueueBuilder {
     pub fn build(strategy: CrawlStrategy) -> Box<dyn Queue> {
+        Self::build_with_store(strategy, Box::new(MemorySeenStore::default()))
+    }
+
+    /// The queue of `settings`: a `RedisQueue` if `queue_url` is set, or else the queue of
+    /// `strategy` in memory. The visited URLs are kept in `store`, by default in Redis too
+    /// for a `RedisQueue`. Returns an error if the Redis server can't be reached.
+    pub fn from_settings(settings: &SchedulerSettings, strategy: CrawlStrategy,
+                         store: Option<Box<dyn SeenStore>>) -> Result<Box<dyn Queue>, String>
+    {
+        if !settings.queue_url.is_empty() {
+            return Self::open_redis(settings, strategy, store);
+        }
+        let store = store.unwrap_or_else(|| Box::new(MemorySeenStore::default()));
+        Ok(Self::build_with_store(strategy, store))
+    }
+
+    #[cfg(feature = "redis")]
+    fn open_redis(settings: &SchedulerSettings, strategy: CrawlStrategy,
+                  store: Option<Box<dyn SeenStore>>) -> Result<Box<dyn Queue>, String>
+    {
+        let queue = RedisQueue::open(&settings.queue_url, &settings.queue_key, strategy, store)
+            .map_err(|e| e.to_string())?;
+        Ok(Box::new(queue))
+    }
+
+    #[cfg(not(feature = "redis"))]
+    fn open_redis(_settings: &SchedulerSettings, _strategy: CrawlStrategy,
+                  _store: Option<Box<dyn SeenStore>>) -> Result<Box<dyn Queue>, String>
+    {
+        Err("vortex is built without the redis feature".to_string())
+    }
+
+    /// Same as `build`, the visited URLs are kept in `store`
+    pub fn build_with_store(strategy: CrawlStrategy, store: Box<dyn SeenStore>) -> Box<dyn Queue> {
+        let visited = Visited { store };
         match strategy {
-            CrawlStrategy::Basic => Box::new(BasicQueue::default()),
-            _ => Box::new(PriorityQueue::default()),
+            CrawlStrategy::Basic => Box::new(BasicQueue { queue: VecDeque::new(), visited }),
+            _ => Box::new(PriorityQueue { queue: BinaryHeap::new(), visited }),
         }
     }
 }
 
-/// The `BasicQueue` contains 2 vectors that are used to keep track of enqueued and already
-/// visited `Request`s.
+/// The URLs taken from a queue or marked as visited, with the time they last were
+struct Visited {
+    store: Box<dyn SeenStore>,
+}
+
+impl Default for Visited {
+    fn default() -> Self {
+        Self { store: Box::new(MemorySeenStore::default()) }
+    }
+}
+
+impl Visited {
+    /// Whether `item` can be taken from the queue: its URL wasn't visited, or its
+    /// `revisit_after` has passed since it last was
+    fn allows(&self, item: &Request) -> bool {
+        match self.store.elapsed(item.dedup_key()) {
+            Some(elapsed) => item.revisit_after.is_some_and(|after| elapsed >= after),
+            None => true,
+        }
+    }
+
+    fn insert(&mut self, url: Url) {
+        self.store.insert(url);
+    }
+
+    fn len(&self) -> usize {
+        self.store.len()
+    }
+}
+
+/// The `BasicQueue` keeps track of enqueued and already visited `Request`s.
 ///
 /// `queue` is a double-ended vector (`VecDeque`) that functions as a FIFO. New `Request`s are
 /// added at the back-end and processed sequentially from the front-end.
 ///
-/// `visited` is a simple vector that keeps tracks of urls that were already processed by the
-/// `downloader`
+/// `visited` keeps track of the urls that were already processed by the `downloader`, and
+/// when
 #[derive(Default)]
 struct BasicQueue {
     queue: VecDeque<Request>,
-    visited: Vec<Url>,
+    visited: Visited,
 }
 
 impl Queue for BasicQueue {
-    fn push(&mut self, item: Request) {
-        if !self.visited.contains(&item.url) {
-            self.queue.push_back(item);
+    fn push(&mut self, item: Request) -> bool {
+        if !self.visited.allows(&item) {
+            return false;
         }
+        self.queue.push_back(item);
+        true
     }
 
     fn pop(&mut self) -> Option<Request> {
         loop {
             match self.queue.pop_front() {
                 Some(item) => {
-                    if !self.visited.contains(&item.url) {
-                        self.visited.push(item.url.clone());
+                    if self.visited.allows(&item) {
+                        self.visited.insert(item.dedup_key().clone());
                         return Some(item);
                     }
                 }
@@ -71,35 +156,44 @@ impl Queue for BasicQueue {
     fn len(&self) -> usize {
         self.queue.len()
     }
+
+    fn visited_len(&self) -> usize {
+        self.visited.len()
+    }
+
+    fn mark_visited(&mut self, url: Url) {
+        self.visited.insert(url);
+    }
 }
 
-/// The `PriorityQueue` contains 2 vectors that are used to keep track of enqueued and already
-/// visited `Request`s.
+/// The `PriorityQueue` keeps track of enqueued and already visited `Request`s.
 ///
 /// `queue` is a `BinarHeap` that sorts the `Request`s based on the priority that the crawl strategy
 /// defined.
 ///
-/// `visited` is a simple vector that keeps tracks of urls that were already processed by the
-/// `downloader`
+/// `visited` keeps track of the urls that were already processed by the `downloader`, and
+/// when
 #[derive(Default)]
 struct PriorityQueue {
     queue: BinaryHeap<Request>,
-    visited: Vec<Url>,
+    visited: Visited,
 }
 
 impl Queue for PriorityQueue {
-    fn push(&mut self, item: Request) {
-        if !self.visited.contains(&item.url) {
-            self.queue.push(item);
+    fn push(&mut self, item: Request) -> bool {
+        if !self.visited.allows(&item) {
+            return false;
         }
+        self.queue.push(item);
+        true
     }
 
     fn pop(&mut self) -> Option<Request> {
         loop {
             match self.queue.pop() {
                 Some(item) => {
-                    if !self.visited.contains(&item.url) {
-                        self.visited.push(item.url.clone());
+                    if self.visited.allows(&item) {
+                        self.visited.insert(item.dedup_key().clone());
                         return Some(item);
                     }
                 }
@@ -111,10 +205,175 @@ impl Queue for PriorityQueue {
     fn len(&self) -> usize {
         self.queue.len()
     }
+
+    fn visited_len(&self) -> usize {
+        self.visited.len()
+    }
+
+    fn mark_visited(&mut self, url: Url) {
+        self.visited.insert(url);
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_queue {
+    use std::sync::Mutex;
+
+    use redis::{Commands, Connection, RedisResult};
+    use reqwest::Url;
+
+    use crate::crawler::Request;
+    use crate::scheduler::seen::{RedisSeenStore, SeenStore};
+    use crate::settings::CrawlStrategy;
+
+    use super::{Queue, Visited};
+
+    /// A queue kept on a Redis server, so that several crawler processes take their
+    /// `Requests` from it, see `SchedulerSettings::queue_url`. The `Requests` are serialized
+    /// to JSON, in the list `<key>:queue` with the `Basic` strategy (first in, first out), or
+    /// else in the sorted set `<key>:queue` scored by their priority. The visited URLs are
+    /// kept in a `SeenStore`, by default the `RedisSeenStore` `<key>:seen`, so that the
+    /// processes don't crawl a URL twice.
+    ///
+    /// All the processes must use the same crawl strategy. Each closes once it has found
+    /// the queue empty for `idle_timeout`, even if the others are still crawling. Redis
+    /// errors are logged, and the `Requests` involved are lost.
+    ///
+    /// `len` and `visited_len` don't make a round trip to the server: they are counted as
+    /// `Requests` are pushed and popped, and brought up to date by `refresh`.
+    pub struct RedisQueue {
+        connection: Mutex<Connection>,
+        /// `<key>:queue`
+        key: String,
+        /// Whether `key` is a list rather than a sorted set
+        fifo: bool,
+        visited: Visited,
+        /// Number of `Requests` in the queue, as of the last `refresh`
+        len: usize,
+        /// Number of visited URLs, as of the last `refresh`
+        visited_len: usize,
+    }
+
+    impl RedisQueue {
+        /// Connects to the server of `url`, e.g. `redis://127.0.0.1/`, to keep the queue in
+        /// the keys starting with `key`
+        pub fn open(url: &str, key: &str, strategy: CrawlStrategy,
+                    store: Option<Box<dyn SeenStore>>) -> RedisResult<Self>
+        {
+            let store = match store {
+                Some(store) => store,
+                None => Box::new(RedisSeenStore::open(url, &format!("{}:seen", key))?),
+            };
+            let connection = redis::Client::open(url)?.get_connection()?;
+            let mut queue = Self {
+                connection: Mutex::new(connection),
+                key: format!("{}:queue", key),
+                fifo: strategy == CrawlStrategy::Basic,
+                visited: Visited { store },
+                len: 0,
+                visited_len: 0,
+            };
+            queue.refresh();
+            Ok(queue)
+        }
+
+        /// Takes the next serialized `Request` off the queue
+        fn pop_json(&self) -> RedisResult<Option<String>> {
+            let mut connection = self.connection.lock().unwrap();
+            if self.fifo {
+                connection.lpop(&self.key, None)
+            } else {
+                let popped: Vec<(String, f64)> = connection.zpopmax(&self.key, 1)?;
+                Ok(popped.into_iter().next().map(|(json, _)| json))
+            }
+        }
+    }
+
+    impl Queue for RedisQueue {
+        fn push(&mut self, item: Request) -> bool {
+            if !self.visited.allows(&item) {
+                return false;
+            }
+            let json = serde_json::to_string(&item).unwrap();
+            let mut connection = self.connection.lock().unwrap();
+            let pushed: RedisResult<()> = if self.fifo {
+                connection.rpush(&self.key, json)
+            } else {
+                connection.zadd(&self.key, json, item.priority)
+            };
+            let pushed = pushed
+                .map_err(|e| error!("Failed to queue {} in Redis: {}", item.url, e))
+                .is_ok();
+            if pushed {
+                self.len += 1;
+            }
+            pushed
+        }
+
+        fn pop(&mut self) -> Option<Request> {
+            loop {
+                let json = match self.pop_json() {
+                    Ok(Some(json)) => json,
+                    Ok(None) => {
+                        self.len = 0;
+                        return None;
+                    }
+                    Err(e) => {
+                        error!("Failed to take a request from the Redis queue: {}", e);
+                        return None;
+                    }
+                };
+                self.len = self.len.saturating_sub(1);
+                match serde_json::from_str::<Request>(&json) {
+                    Ok(item) => {
+                        if self.visited.allows(&item) {
+                            self.visited.insert(item.dedup_key().clone());
+                            self.visited_len += 1;
+                            return Some(item);
+                        }
+                    }
+                    Err(e) => error!("Skipping a request of the Redis queue {:.100}: {}", json, e),
+                }
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.len
+        }
+
+        fn visited_len(&self) -> usize {
+            self.visited_len
+        }
+
+        fn mark_visited(&mut self, url: Url) {
+            self.visited.insert(url);
+            self.visited_len += 1;
+        }
+
+        fn refresh(&mut self) {
+            let len = {
+                let mut connection = self.connection.lock().unwrap();
+                if self.fifo {
+                    connection.llen(&self.key)
+                } else {
+                    connection.zcard(&self.key)
+                }
+            };
+            match len {
+                Ok(len) => self.len = len,
+                Err(e) => error!("Failed to count the Redis queue: {}", e),
+            }
+            self.visited_len = self.visited.len();
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
     use super::*;
 
     #[test]
@@ -151,4 +410,112 @@ mod tests {
         assert_eq!(item.unwrap().depth, 1);
         assert_eq!(queue.pop(), None);
     }
+
+    #[test]
+    fn test_revisit() {
+        let url = Url::parse("http://site.test/news").unwrap();
+        let mut revisited = Request::new(url.clone(), 1, 1);
+        revisited.revisit_after = Some(Duration::from_millis(30));
+
+        for mut queue in [QueueBuilder::build(CrawlStrategy::Basic),
+                          QueueBuilder::build(CrawlStrategy::BFO)] {
+            assert!(queue.push(revisited.clone()));
+            assert!(queue.pop().is_some());
+
+            // Until the interval has passed, it is visited like any other URL
+            assert!(!queue.push(revisited.clone()));
+            assert!(!queue.push(Request::new(url.clone(), 1, 1)));
+            thread::sleep(Duration::from_millis(40));
+            assert!(!queue.push(Request::new(url.clone(), 1, 1)));
+            assert!(queue.push(revisited.clone()));
+            assert!(queue.push(revisited.clone()));
+            assert!(queue.pop().is_some());
+            assert!(queue.pop().is_none());
+            assert_eq!(queue.visited_len(), 1);
+        }
+    }
+
+    /// A `SeenStore` shared by several queues, like a Redis store by several crawlers
+    #[derive(Clone, Default)]
+    struct SharedStore(Arc<Mutex<MemorySeenStore>>);
+
+    impl SeenStore for SharedStore {
+        fn elapsed(&self, url: &Url) -> Option<Duration> {
+            self.0.lock().unwrap().elapsed(url)
+        }
+
+        fn insert(&mut self, url: Url) {
+            self.0.lock().unwrap().insert(url)
+        }
+
+        fn len(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+    }
+
+    #[test]
+    fn test_shared_seen_store() {
+        let store = SharedStore::default();
+        let mut queue_1 = QueueBuilder::build_with_store(CrawlStrategy::Basic,
+                                                         Box::new(store.clone()));
+        let mut queue_2 = QueueBuilder::build_with_store(CrawlStrategy::BFO,
+                                                         Box::new(store.clone()));
+        let request = |url| Request::new(Url::parse(url).unwrap(), 0, 1);
+
+        // A URL taken from one queue is deduplicated by the other
+        assert!(queue_1.push(request("http://site.test/a")));
+        assert!(queue_2.push(request("http://site.test/a")));
+        assert!(queue_1.pop().is_some());
+        assert!(queue_2.pop().is_none());
+        assert!(!queue_2.push(request("http://site.test/a")));
+
+        queue_2.mark_visited(Url::parse("http://site.test/b").unwrap());
+        assert!(!queue_1.push(request("http://site.test/b")));
+        assert_eq!(queue_1.visited_len(), 2);
+        assert!(store.contains(&Url::parse("http://site.test/b").unwrap()));
+    }
+
+    /// Needs a Redis server, at `REDIS_URL` or on localhost:
+    /// `cargo test --features redis -- --ignored`
+    #[cfg(feature = "redis")]
+    #[test]
+    #[ignore]
+    fn test_redis_queue() {
+        use redis::Commands;
+
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".into());
+        let key = format!("vortex-test-{}", std::process::id());
+        let request = |path: &str, priority| {
+            Request::new(Url::parse("http://site.test/").unwrap().join(path).unwrap(), 1, priority)
+        };
+
+        for strategy in [CrawlStrategy::Basic, CrawlStrategy::BFO] {
+            let open = || RedisQueue::open(&url, &key, strategy.clone(), None).unwrap();
+            let (mut pusher, mut popper) = (open(), open());
+
+            // The `Requests` pushed by one crawler are popped by the other
+            assert!(pusher.push(request("a", 1)));
+            assert!(pusher.push(request("b", 2)));
+            assert_eq!((pusher.len(), popper.len()), (2, 0));
+            popper.refresh();
+            assert_eq!(popper.len(), 2);
+            let first = popper.pop().unwrap();
+            let expected = if strategy == CrawlStrategy::Basic { "/a" } else { "/b" };
+            assert_eq!(first.url.path(), expected);
+            assert_eq!(first.depth, 1);
+
+            // Neither queues a URL the other has taken
+            assert!(!pusher.push(first.clone()));
+            assert!(popper.pop().is_some());
+            assert!(pusher.pop().is_none());
+            assert_eq!(pusher.len(), 0);
+            pusher.refresh();
+            assert_eq!(pusher.visited_len(), 2);
+
+            let mut connection = redis::Client::open(url.as_str()).unwrap()
+                .get_connection().unwrap();
+            let _: () = connection.del(&[format!("{}:queue", key), format!("{}:seen", key)])
+                .unwrap();
+        }
+    }
 }
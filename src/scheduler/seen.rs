@@ -0,0 +1,143 @@
+//! Stores the URLs the queue has seen, to deduplicate them, see `SeenStore`.
+//!
+//! The default `MemorySeenStore` keeps them in the memory of the crawl. With the `redis`
+//! feature, `RedisSeenStore` keeps them in a Redis hash, shared by the crawler processes of a
+//! distributed crawl.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+
+/// The URLs taken from the queue or marked as visited, with the time they last were. The
+/// queue skips the URLs it contains, unless their `Request` is due for a revisit.
+///
+/// A store is given to the spider with `SpiderBuilder::seen_store`. It is part of the state
+/// the `Scheduler` shares across threads, so it must be `Send`.
+pub trait SeenStore: Send {
+    /// How long ago `url` was last inserted, if it was
+    fn elapsed(&self, url: &Url) -> Option<Duration>;
+
+    /// Records `url` as seen now
+    fn insert(&mut self, url: Url);
+
+    /// Number of URLs in the store
+    fn len(&self) -> usize;
+
+    /// Whether `url` was inserted
+    fn contains(&self, url: &Url) -> bool {
+        self.elapsed(url).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The default `SeenStore`, in memory
+#[derive(Default)]
+pub struct MemorySeenStore {
+    urls: HashMap<Url, Instant>,
+}
+
+impl SeenStore for MemorySeenStore {
+    fn elapsed(&self, url: &Url) -> Option<Duration> {
+        self.urls.get(url).map(Instant::elapsed)
+    }
+
+    fn insert(&mut self, url: Url) {
+        self.urls.insert(url, Instant::now());
+    }
+
+    fn len(&self) -> usize {
+        self.urls.len()
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisSeenStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use redis::{Commands, Connection, RedisResult};
+    use reqwest::Url;
+
+    use super::SeenStore;
+
+    /// A `SeenStore` in the Redis hash `key`, from URLs to the time they were seen, in
+    /// milliseconds since the epoch. Crawler processes given the same server and key share
+    /// their deduplication: a URL taken from the queue by one isn't crawled by the others.
+    ///
+    /// A URL is looked up and then inserted in two commands, so two processes taking the
+    /// same URL at the same instant may both crawl it. Redis errors are logged, and a URL
+    /// that can't be looked up is crawled.
+    pub struct RedisSeenStore {
+        connection: Mutex<Connection>,
+        key: String,
+    }
+
+    impl RedisSeenStore {
+        /// Connects to the server of `url`, e.g. `redis://127.0.0.1/`, to keep the URLs in
+        /// the hash `key`
+        pub fn open(url: &str, key: &str) -> RedisResult<Self> {
+            let connection = redis::Client::open(url)?.get_connection()?;
+            Ok(Self { connection: Mutex::new(connection), key: key.to_string() })
+        }
+
+        fn now() -> u64 {
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+        }
+    }
+
+    impl SeenStore for RedisSeenStore {
+        fn elapsed(&self, url: &Url) -> Option<Duration> {
+            let mut connection = self.connection.lock().unwrap();
+            let seen_at: RedisResult<Option<u64>> = connection.hget(&self.key, url.as_str());
+            match seen_at {
+                Ok(seen_at) => seen_at
+                    .map(|seen_at| Duration::from_millis(Self::now().saturating_sub(seen_at))),
+                Err(e) => {
+                    error!("Failed to look up {} in the Redis seen store: {}", url, e);
+                    None
+                }
+            }
+        }
+
+        fn insert(&mut self, url: Url) {
+            let mut connection = self.connection.lock().unwrap();
+            let inserted: RedisResult<()> = connection.hset(&self.key, url.as_str(), Self::now());
+            if let Err(e) = inserted {
+                error!("Failed to insert {} in the Redis seen store: {}", url, e);
+            }
+        }
+
+        fn len(&self) -> usize {
+            let mut connection = self.connection.lock().unwrap();
+            connection.hlen(&self.key).unwrap_or_else(|e| {
+                error!("Failed to count the Redis seen store: {}", e);
+                0
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_seen_store() {
+        let url = Url::parse("http://site.test/a").unwrap();
+        let mut store = MemorySeenStore::default();
+        assert!(!store.contains(&url));
+        assert!(store.is_empty());
+
+        store.insert(url.clone());
+        store.insert(url.clone());
+        assert!(store.contains(&url));
+        assert!(store.elapsed(&url).unwrap() < Duration::from_secs(1));
+        assert_eq!(store.len(), 1);
+    }
+}
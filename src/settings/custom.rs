@@ -1,11 +1,13 @@
 ///??
-use std::{fs::File, io, io::Read, path::Path};
+use std::{collections::{BTreeMap, HashMap}, fs::File, io, io::Read, path::Path};
 
 use toml;
 
 use crate::settings::{
-    CrawlStrategy, DownloaderMiddlewareType, PipelineElementType, PrintSettings,
-    ProxySettings, TimestampingSettings, UserAgentSettings,
+    CanonicalizeSettings, CookiesSettings, CrawlStrategy, DownloaderMiddlewareType,
+    HeaderProfileSettings, LanguageSettings, LinkGraphSettings, OriginSettings, PaginationSettings,
+    PipelineElementType, PrintSettings, ProxySettings, SchemaValidateSettings,
+    TimestampingSettings, UrlPolicySettings, UserAgentSettings,
 };
 
 ///?? Main `Settings` by module
@@ -25,6 +27,13 @@ pub struct Settings {
 
     /// `Pipeline` settings
     pub pipeline: Option<PipelineSettings>,
+
+    /// `Stats` settings
+    pub stats: Option<StatsSettings>,
+
+    /// Named overlays that are applied on top of the other values of the file,
+    /// e.g. `[profile.staging]`
+    pub profile: Option<HashMap<String, Settings>>,
 }
 
 impl Settings {
@@ -49,6 +58,18 @@ pub struct SpiderSettings {
 
     /// Spider version
     pub version: Option<String>,
+
+    /// URLs to initiate the crawl
+    pub start_urls: Option<Vec<String>>,
+
+    /// Regular expressions for URLs to follow
+    pub allow: Option<Vec<String>>,
+
+    /// Regular expressions for URLs not to follow
+    pub deny: Option<Vec<String>>,
+
+    /// Time (in seconds) after which the crawl is stopped. 0 means unlimited
+    pub max_duration_secs: Option<u64>,
 }
 
 /// `Scheduler` settings
@@ -57,8 +78,48 @@ pub struct SchedulerSettings {
     /// Delay between issuing `Requests` to the `Downloader`
     pub download_delay: Option<u64>,
 
+    /// Scales the delay before a `Request` with its depth
+    pub depth_delay_factor: Option<f64>,
+
     /// Quantity of `Requests` being sent in parallel to the `Downloader`
     pub concurrent_requests: Option<usize>,
+
+    /// Time (in seconds) over which the concurrency ramps up when the crawl starts
+    pub warmup_secs: Option<u64>,
+
+    /// Quantity of `Requests` sent in parallel when the warm-up starts
+    pub warmup_start_concurrency: Option<usize>,
+
+    /// Time (in milliseconds) the queue must stay empty before the crawl is closed
+    pub idle_timeout: Option<u64>,
+
+    /// Maximum depth of `Requests` to follow links from. 0 means unlimited
+    pub max_depth: Option<u32>,
+
+    /// Maximum number of `Requests` sent to the `Downloader`. 0 means unlimited
+    pub max_requests: Option<usize>,
+
+    /// Time (in milliseconds) to wait for the `Requests` in flight when the crawl is
+    /// stopped (e.g. with Ctrl+C)
+    pub shutdown_timeout: Option<u64>,
+
+    /// Number of `Requests` waiting to be dispatched past which a warning is logged
+    pub queue_warn_len: Option<usize>,
+
+    /// Estimated memory (in bytes) taken by the queue past which a warning is logged
+    pub queue_warn_bytes: Option<u64>,
+
+    /// Length past which URLs are dropped instead of queued
+    pub max_url_length: Option<usize>,
+
+    /// Longest delay between `Requests` to a host that its `Crawl-delay` can set
+    pub max_crawl_delay: Option<u64>,
+
+    /// URL of the Redis server holding the queue
+    pub queue_url: Option<String>,
+
+    /// Prefix of the Redis keys of the queue
+    pub queue_key: Option<String>,
 }
 
 /// `Downloader` settings
@@ -69,6 +130,36 @@ pub struct DownloaderSettings {
 
     /// `Downloader` Middleware settings
     pub middleware: Option<DownloaderMiddlewareSettings>,
+
+    /// Time (in milliseconds) after which a `Request` is aborted. 0 means no timeout
+    pub timeout: Option<u64>,
+
+    /// Time (in milliseconds) the body of a `Response` has to be received in. 0 means no
+    /// timeout
+    pub body_read_timeout_ms: Option<u64>,
+
+    /// Fetch the robots.txt of every host and wait at least its `Crawl-delay` between
+    /// `Requests` to the host
+    pub respect_crawl_delay: Option<bool>,
+
+    /// Hosts that are connected to at a fixed address, as `(host, "ip:port")` pairs
+    pub resolve: Option<Vec<(String, String)>>,
+
+    /// Fetch URLs whose host resolves to a loopback, link-local or private address
+    pub allow_private_addresses: Option<bool>,
+
+    /// Number of threads `Requests` are fetched on
+    pub workers: Option<usize>,
+
+    /// Number of times a `Request` is retried after the `Retry-After` of a 429 or 503
+    /// `Response`. 0 disables retries
+    pub retry_after_retries: Option<u32>,
+
+    /// Longest `Retry-After` (in seconds) that is waited for
+    pub retry_after_max_secs: Option<u64>,
+
+    /// Smallest body (in bytes) of a successful `Response`, shorter ones are retried
+    pub min_body_bytes: Option<usize>,
 }
 
 ///?? `Downloader` Middleware settings by module
@@ -82,6 +173,15 @@ pub struct DownloaderMiddlewareSettings {
 
     /// Print module settings
     pub print: Option<PrintSettings>,
+
+    /// Header Profile module settings
+    pub header_profile: Option<HeaderProfileSettings>,
+
+    /// Origin module settings
+    pub origin: Option<OriginSettings>,
+
+    /// Cookies module settings
+    pub cookies: Option<CookiesSettings>,
 }
 
 /// `Parser` settings
@@ -89,6 +189,67 @@ pub struct DownloaderMiddlewareSettings {
 pub struct ParserSettings {
     /// Crawl strategies
     pub crawl_strategy: Option<CrawlStrategy>,
+
+    /// Don't follow links with `rel="nofollow"`. Data can still be extracted from them.
+    pub respect_nofollow: Option<bool>,
+
+    /// Honor `<meta name="robots">` (`noindex`, `nofollow`)
+    pub respect_meta_robots: Option<bool>,
+
+    /// Read the canonical URL of pages
+    pub prefer_canonical: Option<bool>,
+
+    /// Add the name of the crawl rule to the `Items` it extracted
+    pub tag_rule: Option<bool>,
+
+    /// How URLs are canonicalized
+    pub canonicalize: Option<CanonicalizeSettings>,
+
+    /// Don't follow the AMP versions of the pages fetched
+    pub skip_amp: Option<bool>,
+
+    /// Don't follow the links that look like AMP pages
+    pub skip_amp_urls: Option<bool>,
+
+    /// Make no `Items` of the pages unchanged since the previous crawls
+    pub incremental: Option<bool>,
+
+    /// The file the content hashes of the pages are kept in between crawls
+    pub incremental_path: Option<String>,
+
+    /// Follow the RSS and Atom feeds that pages link to
+    pub follow_feed_links: Option<bool>,
+
+    /// Follow the `rel="next"` link of the pages data was extracted from
+    pub follow_rel_next: Option<bool>,
+
+    /// Extensions of the links that aren't followed, also read as `deny_extensions`
+    #[serde(alias = "deny_extensions")]
+    pub skip_extensions: Option<Vec<String>>,
+
+    /// Length past which links are not followed
+    pub max_url_length: Option<usize>,
+
+    /// Most query parameters of the links followed
+    pub max_query_params: Option<usize>,
+
+    /// Most times a path segment is repeated in a row in the links followed
+    pub max_repeated_segments: Option<usize>,
+
+    /// The schemes and ports of the links followed
+    pub url_policy: Option<UrlPolicySettings>,
+
+    /// Most links followed per page
+    pub max_links_per_page: Option<usize>,
+
+    /// Shuffle the links of a page before they are queued
+    pub randomize_order: Option<bool>,
+
+    /// Export of the link graph
+    pub link_graph: Option<LinkGraphSettings>,
+
+    /// Number of threads `Responses` are parsed on
+    pub workers: Option<usize>,
 }
 
 /// `Pipeline` settings
@@ -99,6 +260,19 @@ pub struct PipelineSettings {
 
     /// `Pipeline` Element settings
     pub element: Option<PipelineElementSettings>,
+
+    /// Interval (in milliseconds) at which pipeline elements are flushed
+    pub flush_interval: Option<u64>,
+
+    /// Number of `Items` waiting to be processed, after which the `Parser` stops parsing.
+    /// 0 means unlimited
+    pub max_inflight: Option<usize>,
+
+    /// Number of `Items` after which the crawl is stopped
+    pub max_items: Option<usize>,
+
+    /// Number of `Items` by crawl rule name, after which the `Items` of the rule are dropped
+    pub max_items_per_rule: Option<BTreeMap<String, usize>>,
 }
 
 /// `Pipeline` Element settings
@@ -109,4 +283,40 @@ pub struct PipelineElementSettings {
 
     /// Print module settings
     pub print: Option<PrintSettings>,
+
+    /// Schema validation module settings
+    pub schema_validate: Option<SchemaValidateSettings>,
+
+    /// Language detection module settings
+    pub language: Option<LanguageSettings>,
+
+    /// Pagination module settings
+    pub pagination: Option<PaginationSettings>,
+}
+
+/// `Stats` settings
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatsSettings {
+    /// Interval (in seconds) at which the progress of the crawl is logged. 0 disables it
+    pub log_interval_secs: Option<u64>,
+
+    /// Number of hosts that are tracked separately. 0 means unlimited
+    pub max_hosts: Option<usize>,
+
+    /// Number of hosts, by number of `Requests`, listed on the progress line
+    pub top_hosts: Option<usize>,
+
+    /// Share of 4xx/5xx `Responses` since the previous progress line above which a warning
+    /// is logged
+    pub error_status_threshold: Option<f64>,
+
+    /// Path of a JSON file the summary of the crawl is written to. Empty to not write one
+    pub summary_path: Option<String>,
+
+    /// Path of an NDJSON file the lifecycle events of the `Requests` are written to. Empty to
+    /// not write one
+    pub event_log_path: Option<String>,
+
+    /// Time (in milliseconds) past which a `Request` is logged as slow. 0 disables it
+    pub slow_request_threshold_ms: Option<u64>,
 }
@@ -0,0 +1,60 @@
+//! Graceful shutdown on process signals
+//!
+//! The first SIGINT/SIGTERM (Ctrl+C on Windows) stops the `Scheduler`s from dispatching
+//! new `Request`s. Each spider is closed once the `Request`s in flight and the `Pipeline`
+//! are drained, see `StopSpider`. A second signal exits the process immediately.
+use std::process;
+
+use actix::{Actor, Addr, AsyncContext, Context, Handler, System};
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+
+use crate::crawler::{CloseReason, StopSpider};
+use crate::scheduler::Scheduler;
+
+/// Exit status of a process that was killed by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+
+pub struct Shutdown {
+    schedulers: Vec<Addr<Scheduler>>,
+    signals: usize,
+}
+
+impl Shutdown {
+    /// `schedulers` are the `Scheduler`s of all spiders in the system
+    pub fn new(schedulers: Vec<Addr<Scheduler>>) -> Self {
+        Self { schedulers, signals: 0 }
+    }
+}
+
+/// Provide Actor implementation for `Shutdown`
+impl Actor for Shutdown {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+/// Define handler for `Signal` message
+impl Handler<Signal> for Shutdown {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, _ctx: &mut Context<Self>) {
+        match msg.0 {
+            SignalType::Int | SignalType::Term => {}
+            _ => return,
+        }
+
+        self.signals += 1;
+        if self.signals == 1 {
+            warn!("Received {:?}, shutting down. Repeat to exit immediately", msg.0);
+            for scheduler in &self.schedulers {
+                scheduler.do_send(StopSpider { reason: CloseReason::Interrupted });
+            }
+        } else {
+            warn!("Received {:?} again, exiting", msg.0);
+            process::exit(EXIT_INTERRUPTED);
+        }
+    }
+}
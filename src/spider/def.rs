@@ -0,0 +1,89 @@
+//! Spiders defined as types, see `SpiderDef`, and kept by name in a `SpiderRegistry`.
+use std::collections::HashMap;
+
+use crate::spider::{CrawlRule, SpiderBuilder};
+
+/// A reusable spider definition: its name, start URLs, crawl rules, and anything else set on
+/// the `SpiderBuilder` in `configure`. `SpiderBuilder::from_def` makes a builder of it.
+///
+/// ```
+/// use vortex::spider::{Condition, CrawlRule, ParseRule, SpiderDef, SpiderRegistry};
+///
+/// struct News;
+///
+/// impl SpiderDef for News {
+///     fn name(&self) -> &str {
+///         "news"
+///     }
+///
+///     fn start_urls(&self) -> Vec<&str> {
+///         vec!["https://news.example.com/"]
+///     }
+///
+///     fn rules(&self) -> Vec<CrawlRule> {
+///         let cnd = Condition::new(vec!["news.example.com"], vec![]);
+///         vec![CrawlRule::new(cnd, ParseRule::FilterUrls)]
+///     }
+/// }
+///
+/// let registry = SpiderRegistry::default().register(News);
+/// let spider = registry.builder("news").unwrap().build();
+/// assert_eq!(spider.settings().spider.name, "news");
+/// ```
+pub trait SpiderDef {
+    /// The name of the spider, under which it is registered
+    fn name(&self) -> &str;
+
+    /// The URLs to start the crawl from. None by default, e.g. when they are set in the
+    /// settings file.
+    fn start_urls(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    /// The crawl rules, added in that order before those of `configure`
+    fn rules(&self) -> Vec<CrawlRule> {
+        Vec::new()
+    }
+
+    /// Sets up the rest of the spider, e.g. its settings, middleware and pipeline elements
+    fn configure(&self, builder: SpiderBuilder) -> SpiderBuilder {
+        builder
+    }
+}
+
+/// Spider definitions by name, to select the spider to crawl with at runtime, see
+/// `Crawler::run_by_name`
+#[derive(Default)]
+pub struct SpiderRegistry {
+    defs: HashMap<String, Box<dyn SpiderDef>>,
+}
+
+impl SpiderRegistry {
+    /// Adds `def`, replacing the definition of the same name if there is one
+    pub fn register<T>(mut self, def: T) -> Self
+        where T: SpiderDef + 'static
+    {
+        let name = def.name().to_string();
+        if self.defs.insert(name.clone(), Box::new(def)).is_some() {
+            warn!("Replaced the spider definition {:?} in the registry", name);
+        }
+        self
+    }
+
+    /// The names of the definitions, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.defs.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The definition named `name`
+    pub fn get(&self, name: &str) -> Option<&dyn SpiderDef> {
+        self.defs.get(name).map(|def| def.as_ref())
+    }
+
+    /// A builder of the definition named `name`, see `SpiderBuilder::from_def`
+    pub fn builder(&self, name: &str) -> Option<SpiderBuilder> {
+        self.get(name).map(SpiderBuilder::from_def)
+    }
+}
@@ -5,20 +5,53 @@
 //! - `start_urls` supply a url or a list of urls to initiate the crawl
 //! - `crawl_rules` define which links need to be followed and which need to be parsed,
 //! by supplying the parsing logic in a closure
-use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use regex::RegexSet;
-use serde_json::Value;
+use kuchiki::Selectors;
+use regex::{Regex, RegexSet};
+use reqwest::Url;
+use serde_json::{Map, Value};
 
-use crate::crawler::RequestVec;
-use crate::downloader::middleware::{DownloaderMiddleware, Proxy, UserAgent};
-use crate::parser::Page;
-use crate::pipeline::elements::{PipelineElement, Timestamping};
+use crate::crawler::{CrawlReport, Request, RequestVec};
+use crate::dry_run::{self, ContractResult};
+use crate::downloader::middleware::{
+    Cookies, DownloaderMiddleware, HeaderProfile, Jsonp, Origin, Proxy, UserAgent,
+};
+use crate::parser::{
+    self, Canonicalized, DefaultNormalizer, FeedEntry, Page, UrlCanonicalizer, UrlNormalizer,
+    UrlPolicy,
+};
+use crate::pipeline::elements::{
+    Language, Pagination, PipelineElement, SchemaValidate, Timestamping,
+};
 use crate::print::Print;
-use crate::settings::{DownloaderMiddlewareType, PipelineElementType, Settings};
+use crate::scheduler::{Queue, QueueBuilder, SeenStore};
+use crate::settings::{DownloaderMiddlewareType, PipelineElementType, Settings, SettingsError};
 
-type PageCallback = Rc<Fn(&Page) -> Option<Vec<Value>>>;
-type PatternCallback = Rc<Fn(Vec<String>) -> Option<Value>>;
+pub use crate::spider::def::{SpiderDef, SpiderRegistry};
+pub(crate) use crate::spider::start_urls::StartUrls;
+
+mod def;
+mod start_urls;
+
+type PageCallback = Arc<dyn Fn(&Page) -> Option<Vec<Value>> + Send + Sync>;
+type PatternCallback = Arc<dyn Fn(Vec<String>) -> Option<Value> + Send + Sync>;
+type IdleHook = Box<dyn Fn() -> Option<RequestVec>>;
+type CloseHook = Box<dyn Fn(&CrawlReport)>;
+
+/// Callbacks invoked at points of the lifecycle of a crawl. See `SpiderBuilder::on_start`,
+/// `SpiderBuilder::on_idle` and `SpiderBuilder::on_close`.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub on_start: Option<Box<dyn Fn()>>,
+    pub on_idle: Option<IdleHook>,
+    pub on_close: Option<CloseHook>,
+}
 
 /// Defines the processing logic for URLs:
 /// - which ones to continue crawling
@@ -28,11 +61,139 @@ type PatternCallback = Rc<Fn(Vec<String>) -> Option<Value>>;
 /// - how to process the extracted data
 /// - what data to assign to a specified JSON key field
 ///
-/// Contains a `Condition` and a `ParseRule`.
+/// Contains a `Condition` and a `ParseRule`, and options built with `CrawlRule::builder`.
 #[derive(Clone)]
 pub struct CrawlRule {
+    /// Tells the rule apart in the logs, the reports of `Spider::check` and the `_rule` field
+    /// of `Items` (see `ParserSettings::tag_rule`)
+    pub name: Option<String>,
     pub condition: Condition,
     pub parse_rule: ParseRule,
+    /// Interval after which the URLs the `condition` allows are fetched again, if they are
+    /// found again. `None` fetches them once.
+    pub revisit_after: Option<Duration>,
+    /// Depth of the URLs past which the rule allows nothing: no data is extracted from the
+    /// pages, and no links are followed with `ParseRule::FilterUrls`. `None` for any depth.
+    pub max_depth: Option<u32>,
+    /// Whether the links of the pages the `condition` allows are followed, `true` by default
+    pub follow: bool,
+}
+
+impl CrawlRule {
+    /// A rule with no name nor options, see `SpiderBuilder::crawl_rule`
+    pub fn new(condition: Condition, parse_rule: ParseRule) -> Self {
+        Self {
+            name: None,
+            condition,
+            parse_rule,
+            revisit_after: None,
+            max_depth: None,
+            follow: true,
+        }
+    }
+
+    /// A `CrawlRuleBuilder`, for rules with a name or options
+    pub fn builder() -> CrawlRuleBuilder {
+        CrawlRuleBuilder::default()
+    }
+
+    /// The name of the rule, or its index `i` in the crawl rules, for the logs
+    pub(crate) fn label(&self, i: usize) -> String {
+        match &self.name {
+            Some(name) => format!("{:?}", name),
+            None => format!("#{}", i),
+        }
+    }
+
+    /// Whether the rule applies to a URL `depth` deep, see `max_depth`
+    pub(crate) fn applies_at(&self, depth: u32) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
+
+    /// The `revisit_after` of the first of `crawl_rules` with one whose `condition` allows
+    /// the URL of `req`
+    pub(crate) fn revisit_interval(crawl_rules: &[CrawlRule], req: &Request)
+        -> Option<Duration>
+    {
+        crawl_rules.iter()
+            .filter(|rule| rule.revisit_after.is_some() && rule.applies_at(req.depth))
+            .find(|rule| rule.condition.allows_url(&req.url))
+            .and_then(|rule| rule.revisit_after)
+    }
+}
+
+/// Used to construct a `CrawlRule`. The `condition` defaults to allowing every URL and the
+/// `parse` rule to `ParseRule::FilterUrls`, e.g.
+///
+/// ```
+/// use vortex::spider::{Condition, CrawlRule, ParseRule, Pattern};
+///
+/// let text = |s: Vec<String>| Some(s[0].as_str().into());
+/// let rule = CrawlRule::builder()
+///     .name("article")
+///     .condition(Condition::path_prefixes(vec!["/articles/"]))
+///     .parse(ParseRule::pattern("title", Pattern::CssSelector("h1"), text))
+///     .max_depth(3)
+///     .follow(false)
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct CrawlRuleBuilder {
+    name: Option<String>,
+    condition: Option<Condition>,
+    parse_rule: Option<ParseRule>,
+    revisit_after: Option<Duration>,
+    max_depth: Option<u32>,
+    follow: Option<bool>,
+}
+
+impl CrawlRuleBuilder {
+    /// Set `CrawlRule::name`
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Set `CrawlRule::condition`
+    pub fn condition(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Set `CrawlRule::parse_rule`
+    pub fn parse(mut self, parse_rule: ParseRule) -> Self {
+        self.parse_rule = Some(parse_rule);
+        self
+    }
+
+    /// Fetch the URLs the condition allows again once `revisit_after` has passed, see
+    /// `SpiderBuilder::crawl_rule_with_revisit`
+    pub fn revisit_after(mut self, revisit_after: Duration) -> Self {
+        self.revisit_after = Some(revisit_after);
+        self
+    }
+
+    /// Set `CrawlRule::max_depth`
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Set `CrawlRule::follow`
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = Some(follow);
+        self
+    }
+
+    pub fn build(self) -> CrawlRule {
+        let condition = self.condition.unwrap_or_else(Condition::any);
+        let mut rule = CrawlRule::new(condition, self.parse_rule.unwrap_or(ParseRule::FilterUrls));
+        rule.name = self.name;
+        rule.revisit_after = self.revisit_after;
+        rule.max_depth = self.max_depth;
+        rule.follow = self.follow.unwrap_or(true);
+        rule
+    }
 }
 
 /// Presents a condition used to filter URLs that is defined by two overlapping regular expressions
@@ -43,10 +204,25 @@ pub struct CrawlRule {
 ///
 /// The intersection of these 2 conditions yields the set of URLs that is processed in an instance
 /// of a `CrawlRule`.
+///
+/// An empty `allow` allows nothing. `Condition::any` allows every URL, and
+/// `Condition::any_except` every URL but those of `deny`. The `allow` of `SpiderSettings`
+/// allows every URL when it is empty.
+///
+/// `Condition::hosts` and `Condition::path_prefixes` allow URLs without regular expressions,
+/// by a hash lookup of the host or a binary search of the path. They are checked before
+/// `allow` and `deny`, which are cheap to skip when they are empty.
 #[derive(Clone)]
 pub struct Condition {
     pub allow: RegexSet,
     pub deny: RegexSet,
+    /// Whether every URL is allowed, so that `allow` isn't run
+    allow_all: bool,
+    /// Hosts of the URLs allowed, any if empty
+    hosts: HashSet<String>,
+    /// Prefixes of the paths of the URLs allowed, any if empty. Sorted, without the prefixes
+    /// that start with another one, see `Condition::has_path_prefix`.
+    path_prefixes: Vec<String>,
 }
 
 impl Condition {
@@ -54,7 +230,102 @@ impl Condition {
         Self {
             allow: RegexSet::new(allow).unwrap(),
             deny: RegexSet::new(deny).unwrap(),
+            allow_all: false,
+            hosts: HashSet::new(),
+            path_prefixes: Vec::new(),
+        }
+    }
+
+    /// Allows every URL
+    pub fn any() -> Self {
+        Condition::any_except(vec![])
+    }
+
+    /// Allows every URL but those matching one of `deny`
+    pub fn any_except(deny: Vec<&'static str>) -> Self {
+        let mut condition = Condition::new(vec![""], deny);
+        condition.allow_all = true;
+        condition
+    }
+
+    /// Allows the URLs of `hosts`, e.g. `"en.wikipedia.org"`, compared case insensitively.
+    /// Subdomains aren't allowed, they must be listed.
+    pub fn hosts(hosts: Vec<&str>) -> Self {
+        let mut condition = Condition::any();
+        condition.hosts = hosts.iter().map(|host| host.to_lowercase()).collect();
+        condition
+    }
+
+    /// Allows the URLs whose path starts with one of `prefixes`, e.g. `"/wiki/"`
+    pub fn path_prefixes(prefixes: Vec<&str>) -> Self {
+        let mut prefixes: Vec<String> = prefixes.iter().map(|prefix| prefix.to_string()).collect();
+        prefixes.sort();
+        // A prefix that starts with a shorter one, which sorts before it, allows nothing more
+        let mut kept: Vec<String> = Vec::new();
+        for prefix in prefixes {
+            if !kept.last().is_some_and(|last| prefix.starts_with(last.as_str())) {
+                kept.push(prefix);
+            }
+        }
+        let mut condition = Condition::any();
+        condition.path_prefixes = kept;
+        condition
+    }
+
+    /// Whether `url` is allowed and not denied. A URL that can't be parsed isn't allowed by
+    /// `hosts` and `path_prefixes`.
+    pub fn allows(&self, url: &str) -> bool {
+        if self.hosts.is_empty() && self.path_prefixes.is_empty() {
+            return self.regexes_allow(url);
+        }
+        Url::parse(url).is_ok_and(|url| self.allows_url(&url))
+    }
+
+    /// Same as `allows`, for a parsed `url`
+    pub fn allows_url(&self, url: &Url) -> bool {
+        if !self.hosts.is_empty() && !url.host_str().is_some_and(|host| self.hosts.contains(host))
+        {
+            return false;
         }
+        if !self.path_prefixes.is_empty() && !self.has_path_prefix(url.path()) {
+            return false;
+        }
+        self.regexes_allow(url.as_str())
+    }
+
+    fn regexes_allow(&self, url: &str) -> bool {
+        (self.allow_all || self.allow.is_match(url)) && !self.deny.is_match(url)
+    }
+
+    /// Whether `path` starts with one of the `path_prefixes`. The only one it can start with
+    /// is the last one that sorts before it, as no prefix starts with another.
+    fn has_path_prefix(&self, path: &str) -> bool {
+        let i = match self.path_prefixes.binary_search_by(|prefix| prefix.as_str().cmp(path)) {
+            Ok(_) => return true,
+            Err(0) => return false,
+            Err(i) => i - 1,
+        };
+        path.starts_with(self.path_prefixes[i].as_str())
+    }
+
+    /// Construct a `Condition` from strings that are only known at runtime (e.g. settings).
+    /// An empty `allow` allows every URL, as `Condition::any_except` does.
+    pub fn from_strings(allow: &[String], deny: &[String]) -> Result<Self, Vec<BuildError>> {
+        let mut errors = Utils::check_regexes(allow);
+        errors.extend(Utils::check_regexes(deny));
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let allow_all = allow.is_empty();
+        let allow = if allow_all { vec![String::new()] } else { allow.to_vec() };
+        Ok(Self {
+            allow: RegexSet::new(allow).unwrap(),
+            deny: RegexSet::new(deny).unwrap(),
+            allow_all,
+            hosts: HashSet::new(),
+            path_prefixes: Vec::new(),
+        })
     }
 }
 
@@ -66,32 +337,74 @@ pub enum ParseRule {
     FilterUrls,
 
     /// Use `ParsePage`. Create a custom closure that handles all the logic of parsing and
-    /// JSON construction
+    /// JSON construction. Each value it returns is an `Item` of its own.
     Page(ParsePage),
 
     /// Use `ParsePattern`. Use the provided struct to assign a single JSON field a value.
+    /// The fields of all the `Pattern` rules of a page make one `Item`, which comes before
+    /// those of the other rules.
     Pattern(ParsePattern),
+
+    /// Use `ParseFeed`. Turn each entry of an RSS or Atom feed into an `Item`. The rule only
+    /// applies to feeds, and feeds only to this rule and `FilterUrls`.
+    Feed(ParseFeed),
+
+    /// Use `ParseList`. Make an `Item` of each element of a list, e.g. of each product of a
+    /// catalog page.
+    List(ParseList),
 }
 
 impl ParseRule {
+    /// `callback` is called on a worker thread of the `Parser`, hence `Send` and `Sync`. The
+    /// same goes for the callback of `pattern`.
     pub fn callback<F: 'static>(callback: F) -> Self
         where
-            F: Fn(&Page) -> Option<Vec<Value>>,
+            F: Fn(&Page) -> Option<Vec<Value>> + Send + Sync,
     {
         ParseRule::Page(ParsePage {
-            callback: Rc::new(callback),
+            callback: Arc::new(callback),
         })
     }
 
+    /// A `Pattern::Regex` or `Pattern::CssSelector` is compiled here, once for all pages. An
+    /// invalid expression or selector is reported by `SpiderBuilder::try_build`.
     pub fn pattern<F: 'static>(field: &'static str, pattern: Pattern, callback: F) -> Self
         where
-            F: Fn(Vec<String>) -> Option<Value>,
+            F: Fn(Vec<String>) -> Option<Value> + Send + Sync,
     {
-        ParseRule::Pattern(ParsePattern {
+        ParseRule::Pattern(ParsePattern::new(field, pattern, callback))
+    }
+
+    /// Makes an `Item` of each element that matches the CSS selector `container`, with the
+    /// fields of `patterns` applied to the element instead of the page. An invalid selector
+    /// is reported by `SpiderBuilder::try_build`.
+    pub fn list(container: &'static str, patterns: Vec<ParsePattern>) -> Self {
+        let selectors = Selectors::compile(container).ok().map(Arc::new);
+        ParseRule::List(ParseList { container, selectors, patterns })
+    }
+}
+
+impl ParsePattern {
+    /// Same as `ParseRule::pattern`, for the patterns of `ParseRule::list`
+    pub fn new<F>(field: &'static str, pattern: Pattern, callback: F) -> Self
+        where
+            F: Fn(Vec<String>) -> Option<Value> + Send + Sync + 'static,
+    {
+        let regex = match pattern {
+            Pattern::Regex(exp) => Regex::new(exp).ok().map(Arc::new),
+            _ => None,
+        };
+        let selectors = match pattern {
+            Pattern::CssSelector(sel) => Selectors::compile(sel).ok().map(Arc::new),
+            _ => None,
+        };
+        Self {
             field,
             pattern,
-            callback: Rc::new(callback),
-        })
+            regex,
+            selectors,
+            callback: Arc::new(callback),
+        }
     }
 }
 
@@ -113,11 +426,74 @@ pub struct ParsePattern {
     /// `callback`
     pub pattern: Pattern,
 
+    /// The compiled `Pattern::Regex`. `None` for other patterns and invalid expressions.
+    pub(crate) regex: Option<Arc<Regex>>,
+
+    /// The compiled `Pattern::CssSelector`. `None` for other patterns and invalid selectors.
+    pub(crate) selectors: Option<Arc<Selectors>>,
+
     /// A closure that processes the result of applying the `pattern` to a `Response` body.
     pub callback: PatternCallback,
 }
 
-/// The available ways of extracting a section from the HTML-tree
+/// Makes an `Item` of each element matching `container`. Within an element, CSS selectors
+/// match its descendants and regular expressions its HTML. Elements none of the `patterns`
+/// extracted a value from are left out.
+#[derive(Clone)]
+pub struct ParseList {
+    /// The CSS selector of the elements, e.g. `".product"`
+    pub container: &'static str,
+
+    /// The compiled `container`. `None` for an invalid selector.
+    pub(crate) selectors: Option<Arc<Selectors>>,
+
+    /// The fields of each `Item`
+    pub patterns: Vec<ParsePattern>,
+}
+
+/// Names the JSON fields of the `Items` made from feed entries. An empty name leaves the
+/// field out. A value missing from the entry is `null`.
+#[derive(Clone, Debug)]
+pub struct ParseFeed {
+    /// The title of the entry, `"title"` by default
+    pub title: &'static str,
+
+    /// The link to the entry, `"link"` by default
+    pub link: &'static str,
+
+    /// The publication date, as written in the feed, `"published"` by default
+    pub published: &'static str,
+
+    /// The summary of the entry, HTML markup included, `"summary"` by default
+    pub summary: &'static str,
+}
+
+impl Default for ParseFeed {
+    fn default() -> Self {
+        Self { title: "title", link: "link", published: "published", summary: "summary" }
+    }
+}
+
+impl ParseFeed {
+    /// The `Item` data of `entry`
+    pub fn item(&self, entry: &FeedEntry) -> Value {
+        let mut data = Map::new();
+        let fields = [
+            (self.title, entry.title.clone()),
+            (self.link, entry.link.as_ref().map(|link| link.to_string())),
+            (self.published, entry.published.clone()),
+            (self.summary, entry.summary.clone()),
+        ];
+        for (field, value) in fields.iter().cloned() {
+            if !field.is_empty() {
+                data.insert(field.to_string(), value.map_or(Value::Null, Value::String));
+            }
+        }
+        Value::Object(data)
+    }
+}
+
+/// The available ways of extracting a section from the HTML-tree, or from the `Response`
 #[derive(Clone)]
 pub enum Pattern {
     /// Use a CSS Selector
@@ -128,6 +504,59 @@ pub enum Pattern {
 
     /// Use an xpath - NOT IMPLEMENTED!
     Xpath(&'static str),
+
+    /// Use the values of a `Response` header, e.g. `Pattern::Header("X-Total-Count")`. The
+    /// name is case-insensitive, a header sent several times has several values.
+    Header(&'static str),
+}
+
+/// An error found while building a `Spider`
+#[derive(Clone, Debug)]
+pub enum BuildError {
+    /// A start URL could not be parsed
+    InvalidUrl { url: String, error: String },
+
+    /// A `Condition` regular expression could not be compiled
+    InvalidRegex { pattern: String, error: String },
+
+    /// A CSS selector of a `ParseRule` could not be compiled
+    InvalidSelector { selector: String },
+
+    /// A `Pattern` that the `Parser` doesn't support, e.g. `Pattern::Xpath`
+    UnsupportedPattern { pattern: String },
+
+    /// The name of the spider is empty
+    EmptyName,
+
+    /// A file of start URLs could not be opened
+    StartUrlsFile { path: String, error: String },
+
+    /// The queue could not be opened, see `SchedulerSettings::queue_url`
+    Queue { url: String, error: String },
+
+    /// A settings value is invalid
+    Settings(SettingsError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::InvalidUrl { url, error } =>
+                write!(f, "invalid start url {:?}: {}", url, error),
+            BuildError::InvalidRegex { pattern, error } =>
+                write!(f, "invalid regex {:?}: {}", pattern, error),
+            BuildError::InvalidSelector { selector } =>
+                write!(f, "invalid CSS selector {:?}", selector),
+            BuildError::UnsupportedPattern { pattern } =>
+                write!(f, "unsupported pattern {}", pattern),
+            BuildError::EmptyName => write!(f, "the spider name is empty"),
+            BuildError::StartUrlsFile { path, error } =>
+                write!(f, "can't open start urls file {:?}: {}", path, error),
+            BuildError::Queue { url, error } =>
+                write!(f, "can't open the queue at {:?}: {}", url, error),
+            BuildError::Settings(e) => write!(f, "invalid setting {}", e),
+        }
+    }
 }
 
 /// Used to construct a `Spider`
@@ -136,6 +565,15 @@ pub struct SpiderBuilder {
     /// The URLs to initiate the crawl
     start_requests: RequestVec,
 
+    /// Hosts whose sitemaps seed the crawl
+    sitemap_hosts: Vec<Url>,
+
+    /// Start URLs read once the crawl has started, from files and iterators
+    lazy_start_urls: Option<StartUrls>,
+
+    /// Where the queue keeps the URLs it has seen, see `seen_store`
+    seen_store: Option<Box<dyn SeenStore>>,
+
     /// The settings used for the crawl
     settings: Settings,
 
@@ -147,9 +585,30 @@ pub struct SpiderBuilder {
 
     /// Enabled `pipeline` elements for post-processing
     elements: Vec<Box<dyn PipelineElement>>,
+
+    /// Resolves the links found on pages, `None` for the `DefaultNormalizer`
+    url_normalizer: Option<Arc<dyn UrlNormalizer>>,
+
+    /// Settings file that is watched for changes while the crawl is running
+    settings_watch: Option<PathBuf>,
+
+    /// Lifecycle callbacks
+    hooks: Hooks,
+
+    /// Errors found while setting up the builder, reported by `try_build`
+    errors: Vec<BuildError>,
 }
 
 impl SpiderBuilder {
+    /// A builder of the spider `def` defines: its start URLs and crawl rules are set, then
+    /// `SpiderDef::configure` is applied. Its name is set last, so it overrides the one of
+    /// settings set in `configure`.
+    pub fn from_def<D: SpiderDef + ?Sized>(def: &D) -> Self {
+        let builder = SpiderBuilder::default().start_urls(def.start_urls());
+        let builder = def.rules().into_iter().fold(builder, SpiderBuilder::rule);
+        def.configure(builder).name(def.name())
+    }
+
     /// Set `Spider` name
     pub fn name(mut self, name: &str) -> Self {
         self.settings.spider.name = name.to_string();
@@ -162,9 +621,82 @@ impl SpiderBuilder {
         self
     }
 
-    /// Construct a `RequestVec` from a `Vec` of URL strings
+    /// Stop the crawl once it has run for `duration`, see `SpiderSettings::max_duration_secs`.
+    /// The duration is rounded down to whole seconds.
+    pub fn max_duration(mut self, duration: Duration) -> Self {
+        self.settings.spider.max_duration_secs = duration.as_secs();
+        self
+    }
+
+    /// Construct a `RequestVec` from a `Vec` of URL strings. The URLs that can't be parsed
+    /// are reported by `try_build`.
     pub fn start_urls(mut self, urls: Vec<&str>) -> Self {
-        self.start_requests = RequestVec::from_strs(urls, 0, 1);
+        let mut requests = Vec::new();
+        for url in urls {
+            match Url::parse(url) {
+                Ok(u) => requests.push(Request::new(u, 0, 1)),
+                Err(e) => self.errors.push(BuildError::InvalidUrl {
+                    url: url.to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+        self.start_requests = RequestVec::new(requests);
+        self
+    }
+
+    /// Start the crawl from the URLs of the file at `path` too, one per line, e.g. a seed list
+    /// generated by another system. The file may be gzipped. Blank lines and lines starting
+    /// with `#` are ignored, and the lines that aren't valid URLs are skipped.
+    ///
+    /// The file is read once the crawl has started, a chunk of URLs at a time whenever the
+    /// queue runs low, so that large lists aren't held in memory. How many URLs were read and
+    /// skipped is logged once it is read. A file that can't be opened is reported by
+    /// `try_build`. Dry runs (`Crawler::dry_run_pages`) only start from the `start_urls`.
+    pub fn start_urls_from_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        match StartUrls::from_file(&path) {
+            Ok(start_urls) => self.add_lazy_start_urls(start_urls),
+            Err(e) => self.errors.push(BuildError::StartUrlsFile {
+                path: path.as_ref().display().to_string(),
+                error: e.to_string(),
+            }),
+        }
+        self
+    }
+
+    /// Same as `start_urls_from_file`, with the URLs of `urls`, e.g. read from a database.
+    /// The iterator is consumed on the thread of the crawl.
+    pub fn start_urls_from_iter<I>(mut self, urls: I) -> Self
+        where
+            I: IntoIterator<Item = String>,
+            I::IntoIter: Send + 'static,
+    {
+        self.add_lazy_start_urls(StartUrls::from_iter(urls));
+        self
+    }
+
+    fn add_lazy_start_urls(&mut self, start_urls: StartUrls) {
+        self.lazy_start_urls = Some(match self.lazy_start_urls.take() {
+            Some(previous) => previous.chain(start_urls),
+            None => start_urls,
+        });
+    }
+
+    /// Seed the crawl from the sitemaps of the hosts of `urls`, in addition to the
+    /// `start_urls`. At startup, the `Sitemap` lines of the robots.txt of each host are
+    /// followed, through sitemap index files and gzipped sitemaps, and the page URLs that
+    /// the `ParseRule::FilterUrls` crawl rules allow are queued at depth 0. Their
+    /// `<lastmod>` is added to the `meta` of the `Request` as `"lastmod"`.
+    pub fn start_from_sitemaps(mut self, urls: Vec<&str>) -> Self {
+        for url in urls {
+            match Url::parse(url) {
+                Ok(u) => self.sitemap_hosts.push(u),
+                Err(e) => self.errors.push(BuildError::InvalidUrl {
+                    url: url.to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
         self
     }
 
@@ -174,12 +706,46 @@ impl SpiderBuilder {
         self
     }
 
-    /// Add a crawl rule
-    pub fn crawl_rule(mut self, condition: Condition, parse_rule: ParseRule) -> Self {
-        self.crawl_rules.push(CrawlRule { condition, parse_rule });
+    /// Set the settings parameters from a TOML file, applying the values of the
+    /// `[profile.<profile>]` section on top. See `Settings::from_file_with_profile`.
+    pub fn settings_profile<P: AsRef<Path>>(mut self, path: P, profile: &str) -> Self {
+        match Settings::from_file_with_profile(path, profile) {
+            Ok(settings) => self.settings = settings,
+            Err(e) => self.errors.push(BuildError::Settings(e)),
+        }
+        self
+    }
+
+    /// Reload the hot-reloadable settings from a TOML file whenever it changes while the
+    /// crawl is running. See `Settings::watch`.
+    pub fn watch_settings<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.settings_watch = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a crawl rule, e.g. one made with `CrawlRule::builder`. The rules are applied in the
+    /// order they are added.
+    pub fn rule(mut self, rule: CrawlRule) -> Self {
+        self.crawl_rules.push(rule);
         self
     }
 
+    /// Add a crawl rule with no name nor options, shorthand for `rule(CrawlRule::new(..))`
+    pub fn crawl_rule(self, condition: Condition, parse_rule: ParseRule) -> Self {
+        self.rule(CrawlRule::new(condition, parse_rule))
+    }
+
+    /// Same as `crawl_rule`, and the URLs `condition` allows are fetched again once
+    /// `revisit_after` has passed, whenever they are found, e.g. to monitor the front page
+    /// of a news site. The first of these rules that allows a URL sets its interval.
+    pub fn crawl_rule_with_revisit(self, condition: Condition, parse_rule: ParseRule,
+                                   revisit_after: Duration) -> Self
+    {
+        let mut rule = CrawlRule::new(condition, parse_rule);
+        rule.revisit_after = Some(revisit_after);
+        self.rule(rule)
+    }
+
     /// Enable a `downloader` middleware
     pub fn downloader_middleware<T: 'static>(mut self, middleware: T) -> Self
         where T: DownloaderMiddleware
@@ -196,9 +762,155 @@ impl SpiderBuilder {
         self
     }
 
+    /// Deduplicate the URLs of the queue with `store` instead of in memory (or in Redis with
+    /// `SchedulerSettings::queue_url`), e.g. a `RedisSeenStore` shared by the crawler
+    /// processes of a distributed crawl. The store only serves a single crawl.
+    pub fn seen_store<T>(mut self, store: T) -> Self
+        where T: SeenStore + 'static
+    {
+        self.seen_store = Some(Box::new(store));
+        self
+    }
+
+    /// Resolve the links found on pages with `normalizer` instead of the
+    /// `DefaultNormalizer`, before they are filtered by the crawl rules and queued
+    pub fn url_normalizer<T>(mut self, normalizer: T) -> Self
+        where T: UrlNormalizer + 'static
+    {
+        self.url_normalizer = Some(Arc::new(normalizer));
+        self
+    }
+
+    /// Call `f` once the components of the crawl are set up, before the start `Requests`
+    /// are sent to the `Scheduler`
+    pub fn on_start<F>(mut self, f: F) -> Self
+        where F: Fn() + 'static
+    {
+        self.hooks.on_start = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` whenever the `Scheduler` runs out of `Requests`, with none in flight. The
+    /// `Requests` it returns are scheduled and keep the crawl going. If it returns `None`,
+    /// the spider is closed once `idle_timeout` has passed.
+    pub fn on_idle<F>(mut self, f: F) -> Self
+        where F: Fn() -> Option<RequestVec> + 'static
+    {
+        self.hooks.on_idle = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the summary of the crawl once the spider is closed
+    pub fn on_close<F>(mut self, f: F) -> Self
+        where F: Fn(&CrawlReport) + 'static
+    {
+        self.hooks.on_close = Some(Box::new(f));
+        self
+    }
+
     /// Final step in building a `Spider`. This will consume your `SpiderBuilder` and
     /// return a `Spider` will all parameters and instructions set for use in the crawler.
-    pub fn build(mut self) -> Spider {
+    ///
+    /// Panics if the `Spider` can't be built, see `try_build`.
+    pub fn build(self) -> Spider {
+        self.try_build().unwrap_or_else(|errors| {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            panic!("Failed to build spider: {}", errors.join("; "))
+        })
+    }
+
+    /// Same as `build`, but returns every problem found instead of panicking:
+    /// - invalid settings, see `Settings::validate`, which includes those of the enabled
+    ///   middleware and pipeline elements
+    /// - start URLs that can't be parsed
+    /// - an empty spider name
+    /// - regular expressions and CSS selectors of the crawl rules that can't be compiled,
+    ///   and patterns the `Parser` doesn't support
+    ///
+    /// A spider without crawl rules, which follows every link and extracts nothing, is
+    /// only logged as a warning. One without start URLs can still check sample pages (see
+    /// `Spider::check`), the crawl fails with `CrawlError::NoStartUrls`.
+    ///
+    /// The `start_urls`, `allow` and `deny` values of `SpiderSettings` are merged with
+    /// the ones set programmatically: the settings' start URLs are appended to the ones
+    /// from `start_urls`, and the settings' `allow`/`deny` condition is added as a
+    /// `ParseRule::FilterUrls` crawl rule that runs before all other crawl rules.
+    pub fn try_build(mut self) -> Result<Spider, Vec<BuildError>> {
+        let mut errors: Vec<BuildError> = self.errors.drain(..).collect();
+
+        if let Err(e) = self.settings.validate() {
+            errors.extend(e.into_iter().map(BuildError::Settings));
+        }
+
+        if self.settings.spider.name.trim().is_empty() {
+            errors.push(BuildError::EmptyName);
+        }
+
+        // Add start urls from settings
+        for url in &self.settings.spider.start_urls {
+            match Url::parse(url) {
+                Ok(u) => self.start_requests.requests.push(Request::new(u, 0, 1)),
+                Err(e) => errors.push(BuildError::InvalidUrl {
+                    url: url.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        // Start urls are popped in the order of the crawl strategy, like the links found
+        let priority = parser::Utils::calc_priority(&self.settings.parser, 0);
+        for req in &mut self.start_requests.requests {
+            req.priority = priority;
+            req.revisit_after = CrawlRule::revisit_interval(&self.crawl_rules, req);
+        }
+
+        // Add url filter from settings
+        let spider_settings = &self.settings.spider;
+        if !spider_settings.allow.is_empty() || !spider_settings.deny.is_empty() {
+            match Condition::from_strings(&spider_settings.allow, &spider_settings.deny) {
+                Ok(condition) => {
+                    let rule = CrawlRule::new(condition, ParseRule::FilterUrls);
+                    self.crawl_rules.insert(0, rule)
+                }
+                Err(e) => errors.extend(e),
+            }
+        }
+
+        // Check the patterns, they were compiled when the rules were created
+        if self.crawl_rules.is_empty() {
+            warn!("The spider has no crawl rules, every link is followed and no data is \
+                   extracted");
+        }
+        for rule in &self.crawl_rules {
+            let patterns = match &rule.parse_rule {
+                ParseRule::Pattern(pattern) => std::slice::from_ref(pattern),
+                ParseRule::List(list) => {
+                    if list.selectors.is_none() {
+                        let selector = list.container.to_string();
+                        errors.push(BuildError::InvalidSelector { selector });
+                    }
+                    &list.patterns[..]
+                }
+                _ => &[],
+            };
+            for pattern in patterns {
+                match pattern {
+                    ParsePattern { pattern: Pattern::Regex(exp), regex: None, .. } => {
+                        errors.extend(Utils::check_regexes(&[exp.to_string()]));
+                    }
+                    ParsePattern { pattern: Pattern::CssSelector(sel), selectors: None, .. } => {
+                        let selector = sel.to_string();
+                        errors.push(BuildError::InvalidSelector { selector });
+                    }
+                    ParsePattern { pattern: Pattern::Xpath(exp), .. } => {
+                        let pattern = format!("Xpath({:?})", exp);
+                        errors.push(BuildError::UnsupportedPattern { pattern });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
         // Add middleware from settings
         let middleware_list = self.settings.downloader.middleware_list.clone();
         for item in middleware_list {
@@ -215,6 +927,19 @@ impl SpiderBuilder {
                     let settings = self.settings.downloader.middleware.print.clone();
                     Box::new(Print::from_settings(settings))
                 }
+                DownloaderMiddlewareType::HeaderProfile => {
+                    let settings = self.settings.downloader.middleware.header_profile.clone();
+                    Box::new(HeaderProfile::from_settings(settings))
+                }
+                DownloaderMiddlewareType::Origin => {
+                    let settings = self.settings.downloader.middleware.origin.clone();
+                    Box::new(Origin::from_settings(settings))
+                }
+                DownloaderMiddlewareType::Jsonp => Box::new(Jsonp::new()),
+                DownloaderMiddlewareType::Cookies => {
+                    let settings = self.settings.downloader.middleware.cookies.clone();
+                    Box::new(Cookies::from_settings(settings))
+                }
             };
             self.middleware.push(middleware);
         }
@@ -231,17 +956,57 @@ impl SpiderBuilder {
                     let settings = self.settings.pipeline.element.print.clone();
                     Box::new(Print::from_settings(settings))
                 }
+                PipelineElementType::SchemaValidate => {
+                    let settings = self.settings.pipeline.element.schema_validate.clone();
+                    match SchemaValidate::try_from_settings(settings) {
+                        Ok(element) => Box::new(element),
+                        // Already reported by `Settings::validate`
+                        Err(_) => continue,
+                    }
+                }
+                PipelineElementType::Language => {
+                    let settings = self.settings.pipeline.element.language.clone();
+                    match Language::try_from_settings(settings) {
+                        Ok(element) => Box::new(element),
+                        // Already reported by `Settings::validate`
+                        Err(_) => continue,
+                    }
+                }
+                PipelineElementType::Pagination => {
+                    let settings = self.settings.pipeline.element.pagination.clone();
+                    Box::new(Pagination::from_settings(settings))
+                }
             };
             self.elements.push(pipeline);
         }
 
-        Spider {
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // Opened last, once the settings are known to be valid
+        let scheduler = &self.settings.scheduler;
+        let strategy = self.settings.parser.crawl_strategy.clone();
+        let queue = QueueBuilder::from_settings(scheduler, strategy, self.seen_store)
+            .map_err(|error| {
+                vec![BuildError::Queue { url: Settings::redact_url(&scheduler.queue_url), error }]
+            })?;
+
+        let url_policy = UrlPolicy::from_settings(self.settings.parser.url_policy.clone());
+        Ok(Spider {
             start_requests: self.start_requests,
+            sitemap_hosts: self.sitemap_hosts,
+            lazy_start_urls: RefCell::new(self.lazy_start_urls),
+            queue: RefCell::new(Some(queue)),
             settings: self.settings,
             crawl_rules: self.crawl_rules,
-            middleware: self.middleware,
+            middleware: Arc::new(self.middleware),
             elements: self.elements,
-        }
+            url_normalizer: self.url_normalizer,
+            url_policy,
+            settings_watch: self.settings_watch,
+            hooks: self.hooks,
+        })
     }
 }
 
@@ -252,17 +1017,39 @@ pub struct Spider {
     /// The URLs to initiate the crawl
     start_requests: RequestVec,
 
+    /// Hosts whose sitemaps seed the crawl
+    sitemap_hosts: Vec<Url>,
+
+    /// Start URLs read once the crawl has started, taken by the `Scheduler`
+    lazy_start_urls: RefCell<Option<StartUrls>>,
+
+    /// The queue of the crawl, with its `SeenStore`, taken by the `Scheduler`
+    queue: RefCell<Option<Box<dyn Queue>>>,
+
     /// The settings used for the crawl
     settings: Settings,
 
     /// The rules for filtering URLs and parsing `Responses`
     crawl_rules: Vec<CrawlRule>,
 
-    /// Enabled `middleware` in the `downloader` for `Request` modification
-    middleware: Vec<Box<dyn DownloaderMiddleware>>,
+    /// Enabled `middleware` in the `downloader` for `Request` modification, shared with the
+    /// threads `Requests` are fetched on
+    middleware: Arc<Vec<Box<dyn DownloaderMiddleware>>>,
 
     /// Enabled `pipeline` elements for post-processing
     elements: Vec<Box<dyn PipelineElement>>,
+
+    /// Resolves the links found on pages, `None` for the `DefaultNormalizer`
+    url_normalizer: Option<Arc<dyn UrlNormalizer>>,
+
+    /// The scheme and port policy of the links followed
+    url_policy: UrlPolicy,
+
+    /// Settings file that is watched for changes while the crawl is running
+    settings_watch: Option<PathBuf>,
+
+    /// Lifecycle callbacks
+    hooks: Hooks,
 }
 
 impl Spider {
@@ -281,11 +1068,44 @@ impl Spider {
         &self.start_requests
     }
 
+    /// Get the hosts whose sitemaps seed the crawl, see `SpiderBuilder::start_from_sitemaps`
+    pub fn sitemap_hosts(&self) -> &[Url] {
+        &self.sitemap_hosts
+    }
+
+    /// Whether the spider has something to start the crawl from: start URLs, sitemaps or
+    /// start URLs read lazily
+    pub(crate) fn has_start_urls(&self) -> bool {
+        !self.start_requests.requests.is_empty() || !self.sitemap_hosts.is_empty()
+            || self.lazy_start_urls.borrow().is_some()
+    }
+
+    /// Takes the start URLs read lazily, see `SpiderBuilder::start_urls_from_file`
+    pub(crate) fn take_lazy_start_urls(&self) -> Option<StartUrls> {
+        self.lazy_start_urls.borrow_mut().take()
+    }
+
+    /// Takes the queue of the crawl, see `SpiderBuilder::seen_store` and
+    /// `SchedulerSettings::queue_url`
+    pub(crate) fn take_queue(&self) -> Option<Box<dyn Queue>> {
+        self.queue.borrow_mut().take()
+    }
+
     /// Get the `settings`
     pub fn settings(&self) -> &Settings {
         &self.settings
     }
 
+    /// Get the settings file that is watched for changes, if any
+    pub fn settings_watch(&self) -> Option<&Path> {
+        self.settings_watch.as_deref()
+    }
+
+    /// The priority of the `Requests` at depth 0, e.g. the URLs submitted to a running crawl
+    pub(crate) fn start_priority(&self) -> u32 {
+        parser::Utils::calc_priority(&self.settings.parser, 0)
+    }
+
     /// Get a reference to the crawl rules
     pub fn crawl_rules(&self) -> &Vec<CrawlRule> {
         &self.crawl_rules
@@ -296,8 +1116,279 @@ impl Spider {
         &self.middleware
     }
 
+    /// Get a shared reference to the enabled `downloader` middleware, to hand it over to
+    /// other threads
+    pub(crate) fn shared_downloader_middleware(&self) -> Arc<Vec<Box<dyn DownloaderMiddleware>>> {
+        Arc::clone(&self.middleware)
+    }
+
     /// Get a reference to the enabled `pipeline` elements
     pub fn pipeline_elements(&self) -> &Vec<Box<dyn PipelineElement>> {
         &self.elements
     }
+
+    /// Get the `UrlNormalizer` that resolves the links found on pages. The URLs it returns
+    /// are canonicalized per `ParserSettings::canonicalize`
+    pub fn url_normalizer(&self) -> Arc<dyn UrlNormalizer> {
+        let normalizer = self.url_normalizer.clone()
+            .unwrap_or_else(|| Arc::new(DefaultNormalizer));
+        let settings = self.settings.parser.canonicalize.clone();
+        let canonicalizer = UrlCanonicalizer::from_settings(settings);
+        Arc::new(Canonicalized { normalizer, canonicalizer })
+    }
+
+    /// Applies the crawl rules to `html`, a saved sample of the page at `url`, without
+    /// making any network requests, and reports the fields that weren't extracted. See
+    /// `ContractResult`. Panics if `url` is not a valid URL.
+    pub fn check(&self, url: &str, html: &str) -> ContractResult {
+        dry_run::check(self, Url::parse(url).unwrap(), html)
+    }
+
+    /// Get the scheme and port policy of the links followed, whose clones share the state of
+    /// the https probes, see `UrlPolicy`
+    pub(crate) fn url_policy(&self) -> &UrlPolicy {
+        &self.url_policy
+    }
+
+    /// Get a reference to the lifecycle callbacks
+    pub(crate) fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+}
+
+struct Utils;
+
+impl Utils {
+    fn check_regexes(patterns: &[String]) -> Vec<BuildError> {
+        patterns.iter()
+            .filter_map(|p| {
+                Regex::new(p).err().map(|e| {
+                    BuildError::InvalidRegex { pattern: p.clone(), error: e.to_string() }
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_start_urls_and_condition() {
+        let mut settings = Settings::default();
+        settings.spider.start_urls = vec!["http://ru.wikipedia.org".to_string()];
+        settings.spider.deny = vec![r"\?".to_string()];
+
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://en.wikipedia.org"])
+            .crawl_rule(Condition::new(vec!["wiki"], vec![]), ParseRule::FilterUrls)
+            .settings(settings)
+            .build();
+
+        let urls: Vec<&str> = spider.start_requests().requests.iter()
+            .map(|r| r.url.as_str())
+            .collect();
+        assert_eq!(urls, vec!["http://en.wikipedia.org/", "http://ru.wikipedia.org/"]);
+
+        assert_eq!(spider.crawl_rules().len(), 2);
+        let condition = &spider.crawl_rules()[0].condition;
+        assert!(condition.allow.is_match("http://en.wikipedia.org/wiki"));
+        assert!(condition.deny.is_match("http://en.wikipedia.org/w/index.php?title=Rust"));
+
+        // An empty `allow` allows nothing, unless it comes from the settings
+        let url = "http://en.wikipedia.org/wiki/Rust";
+        assert!(!Condition::new(vec![], vec![]).allows(url));
+        assert!(!Condition::new(vec![], vec!["/w/"]).allows(url));
+        let built = Condition::from_strings(&[], &["/w/".to_string()]).unwrap();
+        for condition in &[Condition::any_except(vec!["/w/"]), built] {
+            assert!(condition.allows(url));
+            assert!(!condition.allows("http://en.wikipedia.org/w/index.php"));
+        }
+        assert!(Condition::any().allows(url));
+    }
+
+    #[test]
+    fn test_condition_hosts_and_path_prefixes() {
+        let condition = Condition::hosts(vec!["en.Wikipedia.org", "ru.wikipedia.org"]);
+        assert!(condition.allows("http://en.wikipedia.org/wiki/Rust"));
+        assert!(condition.allows("https://RU.wikipedia.org/"));
+        assert!(!condition.allows("http://de.wikipedia.org/wiki/Rust"));
+        assert!(!condition.allows("http://wikipedia.org/"));
+        assert!(!condition.allows("not a url"));
+
+        let condition = Condition::path_prefixes(vec!["/wiki/", "/w", "/wiki/Rust", "/docs/"]);
+        let allows = |path: &str| {
+            condition.allows_url(&Url::parse(&format!("http://site.test{}", path)).unwrap())
+        };
+        assert!(allows("/wiki/Rust"));
+        assert!(allows("/w"));
+        assert!(allows("/w/index.php"));
+        assert!(allows("/docs/"));
+        assert!(allows("/docs/a/b"));
+        assert!(!allows("/"));
+        assert!(!allows("/docs"));
+        assert!(!allows("/about"));
+        assert!(!allows("/a"));
+        assert!(!allows("/x"));
+    }
+
+    #[test]
+    fn test_rule_builder() {
+        let rule = CrawlRule::builder().build();
+        assert!(rule.name.is_none() && rule.follow && rule.max_depth.is_none());
+        assert!(matches!(rule.parse_rule, ParseRule::FilterUrls));
+        assert!(rule.condition.allows("http://site.test/"));
+        assert_eq!(rule.label(2), "#2");
+
+        let rule = CrawlRule::builder()
+            .name("news")
+            .condition(Condition::path_prefixes(vec!["/news"]))
+            .revisit_after(Duration::from_secs(60))
+            .max_depth(1)
+            .follow(false)
+            .build();
+        assert_eq!(rule.label(2), r#""news""#);
+        assert!(!rule.follow && !rule.condition.allows("http://site.test/"));
+
+        // The revisit interval of the rule applies up to its `max_depth`
+        let spider = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/news"])
+            .rule(rule)
+            .crawl_rule(Condition::any(), ParseRule::FilterUrls)
+            .build();
+        let mut req = spider.start_requests().requests[0].clone();
+        assert_eq!(req.revisit_after, Some(Duration::from_secs(60)));
+        req.depth = 2;
+        assert_eq!(CrawlRule::revisit_interval(spider.crawl_rules(), &req), None);
+        assert_eq!(spider.crawl_rules()[0].name.as_deref(), Some("news"));
+        assert!(spider.crawl_rules()[1].name.is_none());
+    }
+
+    struct Articles;
+
+    impl SpiderDef for Articles {
+        fn name(&self) -> &str {
+            "articles"
+        }
+
+        fn start_urls(&self) -> Vec<&str> {
+            vec!["http://site.test/"]
+        }
+
+        fn rules(&self) -> Vec<CrawlRule> {
+            vec![CrawlRule::builder().name("articles").build()]
+        }
+
+        fn configure(&self, builder: SpiderBuilder) -> SpiderBuilder {
+            let mut settings = Settings::default();
+            settings.scheduler.download_delay = 5;
+            builder.settings(settings)
+                .crawl_rule(Condition::any(), ParseRule::FilterUrls)
+        }
+    }
+
+    #[test]
+    fn test_spider_def() {
+        let registry = SpiderRegistry::default()
+            .register(Articles)
+            .register(Articles);
+        assert_eq!(registry.names(), vec!["articles"]);
+        assert!(registry.builder("news").is_none());
+
+        // The rules of the definition come first, its name overrides the one of the settings
+        let spider = registry.builder("articles").unwrap().build();
+        assert_eq!(spider.settings().spider.name, "articles");
+        assert_eq!(spider.settings().scheduler.download_delay, 5);
+        assert_eq!(spider.start_requests().requests[0].url.as_str(), "http://site.test/");
+        let names: Vec<_> = spider.crawl_rules().iter().map(|rule| rule.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("articles"), None]);
+    }
+
+    #[test]
+    fn test_settings_errors() {
+        let mut settings = Settings::default();
+        settings.spider.start_urls = vec!["not a url".to_string()];
+        settings.spider.allow = vec!["(unclosed".to_string()];
+
+        let errors = SpiderBuilder::default().settings(settings).try_build().err().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().contains("not a url"));
+        assert!(errors[1].to_string().contains("(unclosed"));
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let mut settings = Settings::default();
+        settings.spider.name = " ".to_string();
+        let text = |s: Vec<String>| Some(json!(s));
+        let cnd = || Condition::any();
+        let errors = SpiderBuilder::default()
+            .start_urls(vec!["http://site.test/", "not a url"])
+            .settings(settings)
+            .crawl_rule(cnd(), ParseRule::pattern("title", Pattern::CssSelector("h1 >"), text))
+            .crawl_rule(cnd(), ParseRule::pattern("path", Pattern::Xpath("//h1"), text))
+            .crawl_rule(cnd(), ParseRule::list("!product", vec![
+                ParsePattern::new("name", Pattern::CssSelector("h2"), text),
+                ParsePattern::new("sku", Pattern::CssSelector(".sku["), text),
+                ParsePattern::new("price", Pattern::Xpath("//span"), text),
+            ]))
+            .try_build().err().unwrap();
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec![
+            r#"invalid start url "not a url": relative URL without a base"#,
+            "the spider name is empty",
+            r#"invalid CSS selector "h1 >""#,
+            r#"unsupported pattern Xpath("//h1")"#,
+            r#"invalid CSS selector "!product""#,
+            r#"invalid CSS selector ".sku[""#,
+            r#"unsupported pattern Xpath("//span")"#,
+        ]);
+
+        let errors = SpiderBuilder::default()
+            .start_urls_from_file("/nonexistent/seeds.txt")
+            .try_build().err().unwrap();
+        assert_eq!(errors[0].to_string(), "can't open start urls file \"/nonexistent/seeds.txt\": \
+                                           No such file or directory (os error 2)");
+
+        // A spider without rules nor start urls is built, e.g. to check sample pages
+        assert!(SpiderBuilder::default().try_build().is_ok());
+
+        // The password of a queue that can't be opened isn't shown
+        #[cfg(feature = "redis")]
+        {
+            let mut settings = Settings::default();
+            settings.scheduler.queue_url = "redis://:s3cret@127.0.0.1:1/".to_string();
+            let errors = SpiderBuilder::default().settings(settings).try_build().err().unwrap();
+            let error = errors[0].to_string();
+            assert!(error.starts_with(r#"can't open the queue at "redis://:***@127.0.0.1:1/""#),
+                    "{}", error);
+            assert!(!error.contains("s3cret"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_regex_pattern() {
+        let rule = ParseRule::pattern("sku", Pattern::Regex(r"sku\d+"), |s| Some(json!(s)));
+        let regex = match &rule {
+            ParseRule::Pattern(p) => Arc::clone(p.regex.as_ref().unwrap()),
+            _ => unreachable!(),
+        };
+        let spider = SpiderBuilder::default()
+            .crawl_rule(Condition::any(), rule)
+            .build();
+
+        // The spider matches every page with the regex compiled when the rule was created
+        match &spider.crawl_rules()[0].parse_rule {
+            ParseRule::Pattern(p) => assert!(Arc::ptr_eq(p.regex.as_ref().unwrap(), &regex)),
+            _ => unreachable!(),
+        }
+
+        let rule = ParseRule::pattern("sku", Pattern::Regex("(unclosed"), |s| Some(json!(s)));
+        let errors = SpiderBuilder::default()
+            .crawl_rule(Condition::any(), rule)
+            .try_build().err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("(unclosed"));
+    }
 }
@@ -0,0 +1,141 @@
+//! Start URLs read lazily, from files or iterators, see `SpiderBuilder::start_urls_from_file`
+//! and `SpiderBuilder::start_urls_from_iter`.
+//!
+//! Seed lists can hold hundreds of thousands of URLs, so they aren't read when the spider is
+//! built: the `Scheduler` reads them a chunk at a time once the crawl has started, whenever
+//! its queue runs low.
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use reqwest::Url;
+
+use crate::crawler::Request;
+
+type Lines = Box<dyn Iterator<Item = String> + Send>;
+
+/// The start URLs of the files and iterators given to the `SpiderBuilder`, in that order.
+/// Blank lines and lines starting with `#` are ignored, lines that aren't valid URLs are
+/// skipped.
+pub(crate) struct StartUrls {
+    lines: Lines,
+    /// Names of the sources, for the logs
+    sources: Vec<String>,
+    /// Number of URLs read so far
+    parsed: usize,
+    /// Number of lines skipped so far, as they aren't valid URLs
+    skipped: usize,
+}
+
+impl StartUrls {
+    /// The URLs of `path`, one per line. A gzipped file is recognized by its first bytes.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let name = path.as_ref().display().to_string();
+        let mut file = BufReader::new(File::open(&path)?);
+        let reader: Box<dyn Read + Send> = if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            Box::new(MultiGzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+
+        // A read error ends the file, e.g. a truncated archive
+        let source = name.clone();
+        let lines = BufReader::new(reader).lines()
+            .map_while(move |line| {
+                line.map_err(|e| error!("Failed to read the start urls of {}: {}", source, e))
+                    .ok()
+            });
+        Ok(Self::new(Box::new(lines), name))
+    }
+
+    /// The URLs of `urls`
+    pub fn from_iter<I>(urls: I) -> Self
+        where
+            I: IntoIterator<Item = String>,
+            I::IntoIter: Send + 'static,
+    {
+        Self::new(Box::new(urls.into_iter()), "an iterator".to_string())
+    }
+
+    fn new(lines: Lines, source: String) -> Self {
+        Self { lines, sources: vec![source], parsed: 0, skipped: 0 }
+    }
+
+    /// The URLs of `other` are read once those of `self` are
+    pub fn chain(self, other: StartUrls) -> Self {
+        let mut sources = self.sources;
+        sources.extend(other.sources);
+        Self { lines: Box::new(self.lines.chain(other.lines)), sources, ..self }
+    }
+
+    /// The `Requests` of the next `n` URLs, at depth 0 with `priority`. Fewer once the
+    /// sources are exhausted.
+    pub fn next_requests(&mut self, n: usize, priority: u32) -> Vec<Request> {
+        let mut requests = Vec::new();
+        while requests.len() < n {
+            let line = match self.lines.next() {
+                Some(line) => line,
+                None => break,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Url::parse(line) {
+                Ok(url) => {
+                    self.parsed += 1;
+                    requests.push(Request::new(url, 0, priority));
+                }
+                Err(e) => {
+                    self.skipped += 1;
+                    debug!("Skipping the start url {:.100?}: {}", line, e);
+                }
+            }
+        }
+        requests
+    }
+
+    /// How many URLs were read and skipped, e.g. `"Read 3 start urls from urls.txt, skipped
+    /// 1 invalid"`
+    pub fn summary(&self) -> String {
+        format!("Read {} start urls from {}, skipped {} invalid", self.parsed,
+                self.sources.join(", "), self.skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+
+    #[test]
+    fn test_next_requests() {
+        let lines = "# Seeds\nhttp://site.test/a\n\n  http://site.test/b  \nnot a url\n\
+                     http://site.test/c\n";
+        let path = std::env::temp_dir().join(format!("vortex-seeds-{}.txt.gz", process::id()));
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(lines.as_bytes()).unwrap();
+        fs::write(&path, gz.finish().unwrap()).unwrap();
+
+        let from_iter = vec!["http://site.test/d".to_string()];
+        let mut start_urls = StartUrls::from_file(&path).unwrap()
+            .chain(StartUrls::from_iter(from_iter));
+        let paths = |requests: Vec<Request>| -> Vec<String> {
+            requests.iter().map(|req| req.url.path().to_string()).collect()
+        };
+        assert_eq!(paths(start_urls.next_requests(2, 7)), vec!["/a", "/b"]);
+        assert_eq!(paths(start_urls.next_requests(2, 7)), vec!["/c", "/d"]);
+        assert!(start_urls.next_requests(2, 7).is_empty());
+        assert_eq!(start_urls.summary(), format!(
+            "Read 4 start urls from {}, an iterator, skipped 1 invalid", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert!(StartUrls::from_file("/nonexistent/seeds.txt").is_err());
+    }
+}
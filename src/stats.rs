@@ -1,18 +1,558 @@
 //! Aggregates performance stats
-use actix::{Actor, ArbiterService, Context, Handler};
+use std::collections::{BTreeMap, HashMap};
+use std::{fmt, fs, io};
+use std::time::{Duration, Instant};
 
-use crate::downloader;
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, MessageResult};
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::crawler::{CloseSpider, CrawlEstimate, CrawlReport, CrawlStatus, GetStatus, HostStats,
+                     LatencyHistogram, StatsSnapshot, SubscribeStats};
+use crate::downloader::{self, RequestErrors};
+use crate::parser;
+use crate::pipeline;
 use crate::scheduler;
+use crate::settings::{SchedulerSettings, Settings, StatsSettings};
+
+/// Counters at the time a progress line was logged, to compute the deltas of the next line
+#[derive(Clone, Copy, Debug)]
+struct Snapshot {
+    at: Instant,
+    requests: usize,
+    errors: usize,
+    items: usize,
+    responses: usize,
+    /// `Responses` with a 4xx or 5xx status code
+    error_responses: usize,
+}
+
+/// Rates at which `Requests` are discovered and completed, smoothed over the last few
+/// samples so that the estimates don't jump around with every burst
+#[derive(Clone, Copy, Debug)]
+struct Rates {
+    at: Instant,
+    /// Number of `Requests` queued so far
+    discovered: usize,
+    /// Number of `Requests` processed by the `Downloader` so far
+    completed: usize,
+    discovered_rate: f64,
+    completed_rate: f64,
+    samples: usize,
+}
+
+impl Rates {
+    /// Interval at which the rates are sampled
+    const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Weight of the latest sample in the smoothed rates
+    const SMOOTHING: f64 = 0.3;
+
+    fn new(at: Instant) -> Self {
+        Self {
+            at,
+            discovered: 0,
+            completed: 0,
+            discovered_rate: 0.0,
+            completed_rate: 0.0,
+            samples: 0,
+        }
+    }
+
+    /// Adds the rates since the previous sample. The first sample is taken as is.
+    fn sample(&mut self, now: Instant, discovered: usize, completed: usize) {
+        let elapsed = now.duration_since(self.at);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+        if secs <= 0.0 {
+            return;
+        }
+        let discovered_rate = discovered.saturating_sub(self.discovered) as f64 / secs;
+        let completed_rate = completed.saturating_sub(self.completed) as f64 / secs;
+        let smooth = |previous: f64, latest: f64| {
+            previous + (latest - previous) * Rates::SMOOTHING
+        };
+        if self.samples == 0 {
+            self.discovered_rate = discovered_rate;
+            self.completed_rate = completed_rate;
+        } else {
+            self.discovered_rate = smooth(self.discovered_rate, discovered_rate);
+            self.completed_rate = smooth(self.completed_rate, completed_rate);
+        }
+        self.at = now;
+        self.discovered = discovered;
+        self.completed = completed;
+        self.samples += 1;
+    }
+}
+
+/// Estimates how far along the crawl is. A crawl with `max_requests` ends once that many
+/// `Requests` are completed; one with only `max_depth` ends once its `frontier` (the queued
+/// and in flight `Requests`) is drained. Without either, the rates are passed on.
+fn estimate(max_requests: usize, max_depth: u32, completed: usize, frontier: usize,
+            discovered_rate: f64, completed_rate: f64) -> CrawlEstimate
+{
+    let remaining = if max_requests > 0 {
+        max_requests.saturating_sub(completed)
+    } else if max_depth > 0 {
+        frontier
+    } else {
+        return CrawlEstimate::Unbounded { discovered_rate, completed_rate };
+    };
+
+    let total = completed + remaining;
+    let percent = if total > 0 { completed as f64 / total as f64 * 100.0 } else { 0.0 };
+    let eta_secs = if remaining == 0 {
+        Some(0)
+    } else if completed_rate > 0.0 {
+        Some((remaining as f64 / completed_rate).ceil() as u64)
+    } else {
+        None
+    };
+    CrawlEstimate::Bounded { percent, eta_secs }
+}
+
+/// The state of the crawl since the previous progress line, logged every
+/// `stats.log_interval_secs`
+#[derive(Clone, Debug, PartialEq)]
+struct Progress {
+    requests: usize,
+    new_requests: usize,
+    /// `Requests` per second since the previous line
+    request_rate: f64,
+    /// Share of failed `Requests` since the previous line, from 0.0 to 1.0
+    error_rate: f64,
+    /// Failed `Requests` since the start of the crawl, by category
+    request_errors: RequestErrors,
+    items: usize,
+    new_items: usize,
+    /// `Items` per second since the previous line
+    item_rate: f64,
+    queue_len: usize,
+    /// Links not followed because `max_depth` was reached
+    depth_limited: usize,
+    /// Number of `Responses` per status code since the start of the crawl, by status code
+    status_counts: Vec<(u16, usize)>,
+    /// Share of `Responses` with a 4xx or 5xx status code since the previous line
+    error_status_rate: f64,
+    /// Distribution of the latencies since the start of the crawl
+    latency: LatencyHistogram,
+    /// The `top_hosts` hosts with the most `Requests`
+    top_hosts: Vec<(String, HostStats)>,
+    estimate: CrawlEstimate,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} requests (+{}, {:.1} req/s, {:.1}% errors), {} items (+{}, {:.1} items/s), \
+                   {} queued, {} depth limited",
+               self.requests, self.new_requests, self.request_rate, self.error_rate * 100.0,
+               self.items, self.new_items, self.item_rate, self.queue_len, self.depth_limited)?;
+
+        // Nothing to tell of an unbounded crawl before the rates are sampled
+        if self.estimate != CrawlEstimate::default() {
+            write!(f, ", {}", self.estimate)?;
+        }
+
+        let errors = &self.request_errors;
+        let categories = [("connect", errors.connect), ("timeout", errors.timeout),
+                          ("tls", errors.tls), ("decode", errors.decode),
+                          ("http_status", errors.http_status), ("other", errors.other)];
+        let counts: Vec<String> = categories.iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(category, count)| format!("{}:{}", category, count))
+            .collect();
+        if !counts.is_empty() {
+            write!(f, ", errors {}", counts.join(" "))?;
+        }
+
+        if !self.status_counts.is_empty() {
+            let counts: Vec<String> = self.status_counts.iter()
+                .map(|(status, count)| format!("{}:{}", status, count))
+                .collect();
+            write!(f, ", statuses {}", counts.join(" "))?;
+        }
+
+        if self.latency != LatencyHistogram::default() {
+            write!(f, ", latency {}", self.latency)?;
+        }
+
+        if !self.top_hosts.is_empty() {
+            let hosts: Vec<String> = self.top_hosts.iter()
+                .map(|(host, s)| {
+                    format!("{} ({} requests, {} errors, {} bytes, {:?})", host, s.requests,
+                            s.errors, s.bytes_downloaded, s.average_latency)
+                })
+                .collect();
+            write!(f, ", top hosts: {}", hosts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Stats {
+    /// The name of the spider, used to tell the stats of spiders apart in the log
+    name: String,
+    settings: StatsSettings,
+    /// For `queue_warn_len` and `queue_warn_bytes`
+    scheduler_settings: SchedulerSettings,
+    started: Instant,
+    started_at: DateTime<Utc>,
+    /// The latest state of every component. They are dispatched on every change and only
+    /// logged every `log_interval_secs`.
+    scheduler: scheduler::State,
+    downloader: downloader::State,
+    parser: parser::State,
+    pipeline: pipeline::State,
+    previous: Snapshot,
+    rates: Rates,
+    status_counts: HashMap<u16, usize>,
+    latency_total: Duration,
+    latency_count: u32,
+    latency_histogram: LatencyHistogram,
+    hosts: HashMap<String, HostStats>,
+    rule_items: HashMap<String, usize>,
+    /// Callbacks of `SubscribeStats`, called with a `StatsSnapshot` on their interval
+    subscribers: Vec<Box<dyn Fn(StatsSnapshot) + Send>>,
+    on_close: Option<Box<dyn FnOnce(CrawlReport)>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        let started = Instant::now();
+        Self {
+            name: String::new(),
+            settings: StatsSettings { log_interval_secs: 0, ..Settings::default().stats },
+            scheduler_settings: Settings::default().scheduler,
+            started,
+            started_at: Utc::now(),
+            scheduler: scheduler::State::default(),
+            downloader: downloader::State::default(),
+            parser: parser::State::default(),
+            pipeline: pipeline::State::default(),
+            previous: Snapshot {
+                at: started,
+                requests: 0,
+                errors: 0,
+                items: 0,
+                responses: 0,
+                error_responses: 0,
+            },
+            rates: Rates::new(started),
+            status_counts: HashMap::new(),
+            latency_total: Duration::from_millis(0),
+            latency_count: 0,
+            latency_histogram: LatencyHistogram::default(),
+            hosts: HashMap::new(),
+            rule_items: HashMap::new(),
+            subscribers: Vec::new(),
+            on_close: None,
+        }
+    }
+}
+
+impl Stats {
+    /// `on_close` is called with the `CrawlReport` once the spider is closed
+    pub fn new(name: &str, settings: &Settings, on_close: Box<dyn FnOnce(CrawlReport)>)
+        -> Self
+    {
+        Self {
+            name: name.to_string(),
+            settings: settings.stats.clone(),
+            scheduler_settings: settings.scheduler.clone(),
+            on_close: Some(on_close),
+            ..Default::default()
+        }
+    }
+
+    /// The stats of the host of `url`. Once `max_hosts` hosts are tracked, the stats of
+    /// further hosts are those of `HostStats::OTHER_HOSTS`.
+    fn host(&mut self, url: &Url) -> &mut HostStats {
+        let mut host = url.host_str().unwrap_or_default().to_string();
+        let max_hosts = self.settings.max_hosts;
+        if max_hosts > 0 && !self.hosts.contains_key(&host) {
+            let other = self.hosts.contains_key(HostStats::OTHER_HOSTS) as usize;
+            let tracked = self.hosts.len() - other;
+            if tracked >= max_hosts {
+                host = HostStats::OTHER_HOSTS.to_string();
+            }
+        }
+        self.hosts.entry(host).or_default()
+    }
+
+    fn record_outcome(&mut self, outcome: &downloader::Outcome) {
+        if let Some(status) = outcome.status {
+            *self.status_counts.entry(status).or_insert(0) += 1;
+            self.latency_histogram.record(outcome.latency);
 
-#[derive(Default)]
-pub struct Stats;
+            let threshold = self.settings.slow_request_threshold_ms;
+            if threshold > 0 && outcome.latency > Duration::from_millis(threshold) {
+                warn!("[{}] Slow request: {} took {:?}", self.name, outcome.url, outcome.latency);
+            }
+        }
+        self.latency_total += outcome.latency;
+        self.latency_count += 1;
+
+        let host = self.host(&outcome.url);
+        host.requests += 1;
+        host.bytes_downloaded += outcome.bytes;
+        if outcome.status.is_none() {
+            host.errors += 1;
+        }
+        let n = host.requests as u32;
+        host.average_latency = (host.average_latency * (n - 1) + outcome.latency) / n;
+    }
+
+    /// Logs a warning when the queue of `state` crosses `queue_warn_len` or
+    /// `queue_warn_bytes`, compared to the latest `scheduler::State`
+    fn check_queue(&self, state: &scheduler::State) {
+        let (warn_len, warn_bytes) = (self.scheduler_settings.queue_warn_len,
+                                      self.scheduler_settings.queue_warn_bytes);
+        let previous = &self.scheduler;
+        if warn_len > 0 && previous.queue_len < warn_len && state.queue_len >= warn_len {
+            warn!("[{}] {} requests are queued, over queue_warn_len ({})",
+                  self.name, state.queue_len, warn_len);
+        }
+        if warn_bytes > 0 && previous.queue_bytes < warn_bytes && state.queue_bytes >= warn_bytes {
+            warn!("[{}] The queue takes about {} bytes, over queue_warn_bytes ({})",
+                  self.name, state.queue_bytes, warn_bytes);
+        }
+    }
+
+    fn record_item(&mut self, outcome: &pipeline::Outcome) {
+        self.host(&outcome.url).items += 1;
+        if let Some(rule) = &outcome.rule {
+            *self.rule_items.entry(rule.clone()).or_insert(0) += 1;
+        }
+    }
+
+    /// Samples the `Rates`
+    fn sample_rates(&mut self, now: Instant) {
+        let discovered = self.scheduler.visited + self.scheduler.queue_len;
+        self.rates.sample(now, discovered, self.downloader.request_total);
+    }
+
+    fn estimate(&self) -> CrawlEstimate {
+        let settings = &self.scheduler_settings;
+        estimate(settings.max_requests, settings.max_depth, self.downloader.request_total,
+                 self.scheduler.queue_len + self.scheduler.in_flight,
+                 self.rates.discovered_rate, self.rates.completed_rate)
+    }
+
+    /// Computes the `Progress` since the previous call (or the start of the crawl)
+    fn progress(&mut self, now: Instant) -> Progress {
+        let current = Snapshot {
+            at: now,
+            requests: self.downloader.request_total,
+            errors: self.downloader.request_error,
+            items: self.pipeline.item_total,
+            responses: self.status_counts.values().sum(),
+            error_responses: self.status_counts.iter()
+                .filter(|(status, _)| **status >= 400)
+                .map(|(_, count)| count)
+                .sum(),
+        };
+        let previous = std::mem::replace(&mut self.previous, current);
+
+        let elapsed = now.duration_since(previous.at);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+        let rate = |n: usize| if secs > 0.0 { n as f64 / secs } else { 0.0 };
+
+        let new_requests = current.requests.saturating_sub(previous.requests);
+        let new_errors = current.errors.saturating_sub(previous.errors);
+        let new_items = current.items.saturating_sub(previous.items);
+        let share = |part: usize, total: usize| {
+            if total > 0 { part as f64 / total as f64 } else { 0.0 }
+        };
+        let new_responses = current.responses.saturating_sub(previous.responses);
+        let new_error_responses = current.error_responses.saturating_sub(previous.error_responses);
+
+        let mut status_counts: Vec<(u16, usize)> = self.status_counts.iter()
+            .map(|(status, count)| (*status, *count))
+            .collect();
+        status_counts.sort();
+        Progress {
+            requests: current.requests,
+            new_requests,
+            request_rate: rate(new_requests),
+            error_rate: share(new_errors, new_requests),
+            request_errors: self.downloader.request_errors,
+            items: current.items,
+            new_items,
+            item_rate: rate(new_items),
+            queue_len: self.scheduler.queue_len,
+            depth_limited: self.parser.depth_limited,
+            status_counts,
+            error_status_rate: share(new_error_responses, new_responses),
+            latency: self.latency_histogram.clone(),
+            top_hosts: self.top_hosts(),
+            estimate: self.estimate(),
+        }
+    }
+
+    /// The `StatsSnapshot` handed to the subscribers at `now`
+    fn snapshot(&self, now: Instant, running: bool) -> StatsSnapshot {
+        let elapsed = now.duration_since(self.started);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+        let rate = |n: usize| if secs > 0.0 { n as f64 / secs } else { 0.0 };
+
+        StatsSnapshot {
+            spider: self.name.clone(),
+            elapsed_ms: Utils::millis(elapsed),
+            queue_len: self.scheduler.queue_len,
+            in_flight: self.scheduler.in_flight,
+            requests: self.downloader.request_total,
+            requests_failed: self.downloader.request_error,
+            request_errors: self.downloader.request_errors,
+            error_responses: self.status_counts.iter()
+                .filter(|(status, _)| **status >= 400)
+                .map(|(_, count)| count)
+                .sum(),
+            items: self.pipeline.item_total,
+            items_dropped: self.pipeline.item_dropped,
+            bytes_downloaded: self.downloader.bytes_downloaded,
+            request_rate: rate(self.downloader.request_total),
+            item_rate: rate(self.pipeline.item_total),
+            estimate: self.estimate(),
+            running,
+        }
+    }
+
+    /// The `top_hosts` hosts with the most `Requests`, ties broken by name
+    fn top_hosts(&self) -> Vec<(String, HostStats)> {
+        let mut hosts: Vec<(&String, &HostStats)> = self.hosts.iter().collect();
+        hosts.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(b.0)));
+        hosts.into_iter()
+            .take(self.settings.top_hosts)
+            .map(|(host, stats)| (host.clone(), stats.clone()))
+            .collect()
+    }
+
+    fn report(&self) -> CrawlReport {
+        let average_latency = if self.latency_count > 0 {
+            self.latency_total / self.latency_count
+        } else {
+            Duration::from_millis(0)
+        };
+
+        CrawlReport {
+            spider: self.name.clone(),
+            requests_total: self.downloader.request_total,
+            requests_success: self.downloader.request_success,
+            requests_failed: self.downloader.request_error,
+            request_errors: self.downloader.request_errors,
+            requests_retried: self.downloader.request_retried,
+            requests_refused: self.downloader.request_refused,
+            items_scraped: self.pipeline.item_total,
+            items_dropped: self.pipeline.item_dropped,
+            items_by_rule: self.rule_items.clone(),
+            bytes_downloaded: self.downloader.bytes_downloaded,
+            duration: self.started.elapsed(),
+            status_counts: self.status_counts.clone(),
+            average_latency,
+            latency_histogram: self.latency_histogram.clone(),
+            hosts: self.hosts.clone(),
+            max_queue_len: self.scheduler.max_queue_len,
+            max_queue_bytes: self.scheduler.max_queue_bytes,
+            visited: self.scheduler.visited,
+            ..Default::default()
+        }
+    }
+
+    /// The summary of a closed crawl that is written to `summary_path`
+    fn summary(&self, report: &CrawlReport, finished_at: DateTime<Utc>) -> Value {
+        let mut status_classes = BTreeMap::new();
+        for (status, count) in &report.status_counts {
+            *status_classes.entry(format!("{}xx", status / 100)).or_insert(0) += count;
+        }
+        let status_counts: BTreeMap<String, usize> = report.status_counts.iter()
+            .map(|(status, count)| (status.to_string(), *count))
+            .collect();
+        let items_by_rule: BTreeMap<&String, &usize> = report.items_by_rule.iter().collect();
+        let latency_histogram: BTreeMap<String, usize> =
+            report.latency_histogram.buckets().into_iter().collect();
+        let top_hosts: Vec<Value> = self.top_hosts().into_iter()
+            .map(|(host, stats)| json!({
+                "host": host,
+                "requests": stats.requests,
+                "errors": stats.errors,
+                "items": stats.items,
+                "bytes_downloaded": stats.bytes_downloaded,
+                "average_latency_ms": Utils::millis(stats.average_latency),
+            }))
+            .collect();
+
+        json!({
+            "spider": report.spider,
+            "started_at": self.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "finished_at": finished_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "duration_ms": Utils::millis(report.duration),
+            "stop_reason": format!("{:?}", report.stop_reason),
+            "clean": report.clean,
+            "requests": {
+                "total": report.requests_total,
+                "success": report.requests_success,
+                "failed": report.requests_failed,
+                "errors": report.request_errors,
+                "by_status_class": status_classes,
+                "by_status": status_counts,
+            },
+            "bytes_downloaded": report.bytes_downloaded,
+            "average_latency_ms": Utils::millis(report.average_latency),
+            "latency_histogram": latency_histogram,
+            "items": {
+                "scraped": report.items_scraped,
+                "dropped": report.items_dropped,
+                "by_rule": items_by_rule,
+            },
+            "queue": {
+                "max_len": report.max_queue_len,
+                "max_bytes": report.max_queue_bytes,
+                "visited": report.visited,
+            },
+            "top_hosts": top_hosts,
+        })
+    }
+}
+
+struct Utils;
+
+impl Utils {
+    fn millis(duration: Duration) -> u64 {
+        duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+    }
+
+    /// Writes `summary` to a temporary file next to `path`, which is then renamed to `path`,
+    /// so that readers never see a partly written file
+    fn write_summary(path: &str, summary: &Value) -> io::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_string_pretty(summary).unwrap())?;
+        fs::rename(&tmp, path)
+    }
+}
 
 /// Provide Actor implementation for `Stats`
 impl Actor for Stats {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
         info!("Stats is started");
+
+        ctx.run_interval(Rates::SAMPLE_INTERVAL, |act, _| act.sample_rates(Instant::now()));
+
+        let interval = self.settings.log_interval_secs;
+        if interval > 0 {
+            ctx.run_interval(Duration::from_secs(interval), |act, _| {
+                let progress = act.progress(Instant::now());
+                info!("[{}] {}", act.name, progress);
+
+                let threshold = act.settings.error_status_threshold;
+                if progress.error_status_rate > threshold {
+                    warn!("[{}] {:.1}% of the responses since the last progress line had a 4xx or \
+                           5xx status code", act.name, progress.error_status_rate * 100.0);
+                }
+            });
+        }
     }
 
     fn stopped(&mut self, _ctx: &mut Context<Self>) {
@@ -20,18 +560,13 @@ impl Actor for Stats {
     }
 }
 
-impl actix::Supervised for Stats {}
-
-impl ArbiterService for Stats {
-    fn service_started(&mut self, _ctx: &mut Context<Self>) {}
-}
-
 /// Define handler for `scheduler::State` message
 impl Handler<scheduler::State> for Stats {
     type Result = ();
 
     fn handle(&mut self, msg: scheduler::State, _ctx: &mut Context<Self>) {
-        info!("{:?}", msg);
+        self.check_queue(&msg);
+        self.scheduler = msg;
     }
 }
 
@@ -40,6 +575,299 @@ impl Handler<downloader::State> for Stats {
     type Result = ();
 
     fn handle(&mut self, msg: downloader::State, _ctx: &mut Context<Self>) {
-        info!("{:?}", msg);
+        self.downloader = msg;
+    }
+}
+
+/// Define handler for `downloader::Outcome` message
+impl Handler<downloader::Outcome> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: downloader::Outcome, _ctx: &mut Context<Self>) {
+        self.record_outcome(&msg);
+    }
+}
+
+/// Define handler for `parser::State` message
+impl Handler<parser::State> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: parser::State, _ctx: &mut Context<Self>) {
+        self.parser = msg;
+    }
+}
+
+/// Define handler for `pipeline::State` message
+impl Handler<pipeline::State> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: pipeline::State, _ctx: &mut Context<Self>) {
+        self.pipeline = msg;
+    }
+}
+
+/// Define handler for `pipeline::Outcome` message
+impl Handler<pipeline::Outcome> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: pipeline::Outcome, _ctx: &mut Context<Self>) {
+        self.record_item(&msg);
+    }
+}
+
+/// Define handler for `SubscribeStats` message
+impl Handler<SubscribeStats> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeStats, ctx: &mut Context<Self>) {
+        let index = self.subscribers.len();
+        self.subscribers.push(msg.callback);
+        ctx.run_interval(msg.interval, move |act, _| {
+            let snapshot = act.snapshot(Instant::now(), true);
+            (act.subscribers[index])(snapshot);
+        });
+    }
+}
+
+/// Define handler for `GetStatus` message. Only the number of `Items` is filled in.
+impl Handler<GetStatus> for Stats {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _msg: GetStatus, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(CrawlStatus { items: self.pipeline.item_total, ..Default::default() })
+    }
+}
+
+/// Define handler for `CloseSpider` message. This is the last step of closing a spider:
+/// the `CrawlReport` is handed over, and the `Stats` are stopped.
+impl Handler<CloseSpider> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSpider, ctx: &mut Context<Self>) {
+        ctx.stop();
+        let on_close = match self.on_close.take() {
+            Some(on_close) => on_close,
+            None => return,
+        };
+
+        let snapshot = self.snapshot(Instant::now(), false);
+        self.subscribers.iter().for_each(|callback| callback(snapshot.clone()));
+
+        let mut report = self.report();
+        report.stop_reason = msg.reason;
+        report.clean = msg.clean;
+        info!("[{}] {:?}", self.name, report);
+
+        let path = &self.settings.summary_path;
+        if !path.is_empty() {
+            let summary = self.summary(&report, Utc::now());
+            match Utils::write_summary(path, &summary) {
+                Ok(()) => info!("[{}] Wrote the crawl summary to {}", self.name, path),
+                Err(e) => error!("[{}] Failed to write the crawl summary to {}: {}",
+                                 self.name, path, e),
+            }
+        }
+        on_close(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(url: &str, status: Option<u16>, latency: u64, bytes: u64) -> downloader::Outcome {
+        downloader::Outcome {
+            url: Url::parse(url).unwrap(),
+            status,
+            latency: Duration::from_millis(latency),
+            bytes,
+        }
+    }
+
+    fn item(url: &str) -> pipeline::Outcome {
+        pipeline::Outcome { url: Url::parse(url).unwrap(), rule: None }
+    }
+
+    #[test]
+    fn test_host_stats() {
+        let mut stats = Stats::default();
+        stats.record_outcome(&outcome("http://en.wikipedia.org/wiki/A", Some(200), 100, 2000));
+        stats.record_outcome(&outcome("http://en.wikipedia.org/wiki/B", Some(404), 300, 500));
+        stats.record_outcome(&outcome("http://en.wikipedia.org/wiki/C", None, 200, 0));
+        stats.record_outcome(&outcome("http://ru.wikipedia.org/wiki/A", Some(200), 50, 1000));
+        stats.record_item(&item("http://en.wikipedia.org/wiki/A"));
+        stats.record_item(&item("http://ru.wikipedia.org/wiki/A"));
+        stats.record_item(&item("http://ru.wikipedia.org/wiki/A"));
+
+        let report = stats.report();
+        assert_eq!(report.hosts.len(), 2);
+        assert_eq!(report.hosts["en.wikipedia.org"], HostStats {
+            requests: 3,
+            errors: 1,
+            items: 1,
+            bytes_downloaded: 2500,
+            average_latency: Duration::from_millis(200),
+        });
+        assert_eq!(report.hosts["ru.wikipedia.org"], HostStats {
+            requests: 1,
+            errors: 0,
+            items: 2,
+            bytes_downloaded: 1000,
+            average_latency: Duration::from_millis(50),
+        });
+    }
+
+    #[test]
+    fn test_max_hosts() {
+        let mut stats = Stats::default();
+        stats.settings.max_hosts = 2;
+        stats.settings.top_hosts = 2;
+        for (host, requests) in &[("a.test", 1), ("b.test", 3), ("c.test", 2), ("d.test", 2)] {
+            for _ in 0..*requests {
+                let url = format!("http://{}/", host);
+                stats.record_outcome(&outcome(&url, Some(200), 10, 100));
+            }
+        }
+        stats.record_item(&item("http://a.test/"));
+        stats.record_item(&item("http://e.test/"));
+
+        let report = stats.report();
+        let mut hosts: Vec<&str> = report.hosts.keys().map(|h| h.as_str()).collect();
+        hosts.sort();
+        assert_eq!(hosts, vec!["(other)", "a.test", "b.test"]);
+        let other = &report.hosts[HostStats::OTHER_HOSTS];
+        assert_eq!((other.requests, other.items, other.bytes_downloaded), (4, 1, 400));
+
+        let top: Vec<String> = stats.top_hosts().into_iter().map(|(host, _)| host).collect();
+        assert_eq!(top, vec!["(other)", "b.test"]);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut stats = Stats::default();
+        let latencies = [0, 99, 100, 249, 250, 999, 1000, 4999, 5000, 60000];
+        for latency in latencies.iter() {
+            stats.record_outcome(&outcome("http://a.test/", Some(200), *latency, 100));
+        }
+        // Failed requests have no latency worth reporting
+        stats.record_outcome(&outcome("http://a.test/", None, 30000, 0));
+
+        let histogram = stats.report().latency_histogram;
+        assert_eq!(histogram.counts, [2, 2, 1, 1, 1, 1, 2]);
+        assert_eq!(histogram.buckets()[3], ("<1s".to_string(), 1));
+        assert_eq!(histogram.to_string(), "<100ms:2 <250ms:2 <500ms:1 <1s:1 <2s:1 <5s:1 >=5s:2");
+    }
+
+    #[test]
+    fn test_rates() {
+        let start = Instant::now();
+        let mut rates = Rates::new(start);
+        // (seconds, discovered, completed): a burst of 20 requests/s that settles at 10/s
+        let samples = [(1, 50, 20), (2, 90, 30), (3, 130, 40), (4, 170, 50), (5, 210, 60)];
+        for (secs, discovered, completed) in samples.iter() {
+            rates.sample(start + Duration::from_secs(*secs), *discovered, *completed);
+        }
+        assert_eq!(rates.samples, 5);
+        assert!((rates.discovered_rate - 42.40).abs() < 0.01);
+        assert!((rates.completed_rate - 12.40).abs() < 0.01);
+
+        // A sample without time passed is ignored
+        rates.sample(start + Duration::from_secs(5), 500, 500);
+        assert_eq!(rates.samples, 5);
+    }
+
+    #[test]
+    fn test_estimate() {
+        // max_requests: the budget left at the completed rate
+        assert_eq!(estimate(1000, 0, 250, 5000, 40.0, 10.0),
+                   CrawlEstimate::Bounded { percent: 25.0, eta_secs: Some(75) });
+        assert_eq!(estimate(1000, 3, 1000, 5000, 40.0, 10.0),
+                   CrawlEstimate::Bounded { percent: 100.0, eta_secs: Some(0) });
+        assert_eq!(estimate(1000, 0, 0, 1, 0.0, 0.0),
+                   CrawlEstimate::Bounded { percent: 0.0, eta_secs: None });
+
+        // max_depth only: the frontier left
+        assert_eq!(estimate(0, 2, 300, 100, 5.0, 4.0),
+                   CrawlEstimate::Bounded { percent: 75.0, eta_secs: Some(25) });
+
+        // Unbounded
+        let unbounded = estimate(0, 0, 300, 100, 5.0, 4.0);
+        assert_eq!(unbounded,
+                   CrawlEstimate::Unbounded { discovered_rate: 5.0, completed_rate: 4.0 });
+        assert_eq!(unbounded.to_string(), "frontier +5.0/s vs 4.0/s done");
+        assert_eq!(estimate(1000, 0, 250, 5000, 40.0, 10.0).to_string(),
+                   "25.0% done, ETA 1m15s");
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut stats = Stats::default();
+        let start = stats.previous.at;
+
+        stats.scheduler = scheduler::State { queue_len: 12, ..Default::default() };
+        let request_errors = RequestErrors { connect: 1, timeout: 1, ..Default::default() };
+        stats.downloader = downloader::State {
+            request_total: 20,
+            request_error: 2,
+            request_errors,
+            ..Default::default()
+        };
+        stats.pipeline = pipeline::State { item_total: 10, ..Default::default() };
+        stats.status_counts = vec![(200, 9), (404, 9)].into_iter().collect();
+        assert_eq!(stats.progress(start + Duration::from_secs(10)), Progress {
+            requests: 20,
+            new_requests: 20,
+            request_rate: 2.0,
+            error_rate: 0.1,
+            request_errors,
+            items: 10,
+            new_items: 10,
+            item_rate: 1.0,
+            queue_len: 12,
+            depth_limited: 0,
+            status_counts: vec![(200, 9), (404, 9)],
+            error_status_rate: 0.5,
+            latency: LatencyHistogram::default(),
+            top_hosts: vec![],
+            estimate: CrawlEstimate::default(),
+        });
+
+        // Rates are computed from the deltas since the previous line
+        stats.scheduler = scheduler::State { queue_len: 3, ..Default::default() };
+        let request_errors = RequestErrors { connect: 1, timeout: 4, tls: 2, ..Default::default() };
+        stats.downloader = downloader::State {
+            request_total: 30,
+            request_error: 7,
+            request_errors,
+            ..Default::default()
+        };
+        stats.pipeline = pipeline::State { item_total: 12, ..Default::default() };
+        stats.parser = parser::State { depth_limited: 4, ..Default::default() };
+        stats.status_counts = vec![(200, 10), (404, 9), (503, 4)].into_iter().collect();
+        assert_eq!(stats.progress(start + Duration::from_secs(15)), Progress {
+            requests: 30,
+            new_requests: 10,
+            request_rate: 2.0,
+            error_rate: 0.5,
+            request_errors,
+            items: 12,
+            new_items: 2,
+            item_rate: 0.4,
+            queue_len: 3,
+            depth_limited: 4,
+            status_counts: vec![(200, 10), (404, 9), (503, 4)],
+            error_status_rate: 0.8,
+            latency: LatencyHistogram::default(),
+            top_hosts: vec![],
+            estimate: CrawlEstimate::default(),
+        });
+
+        // Nothing happened
+        let progress = stats.progress(start + Duration::from_secs(20));
+        assert_eq!(progress.new_requests, 0);
+        assert_eq!((progress.request_rate, progress.error_rate), (0.0, 0.0));
+        assert_eq!(progress.error_status_rate, 0.0);
+        assert!(progress.to_string()
+            .ends_with("errors connect:1 timeout:4 tls:2, statuses 200:10 404:9 503:4"));
     }
 }